@@ -1,7 +1,8 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto/txn_rpc.proto");
     tonic_build::configure()
         .format(true)
-        .out_dir("src")
+        .out_dir("src/crdt")
         .compile(&["proto/txn_rpc.proto"], &["proto"])?;
     Ok(())
 }
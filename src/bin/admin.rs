@@ -0,0 +1,96 @@
+use clap::{Parser, Subcommand};
+use crdt_based_codoc::crdt::auth::with_token;
+use crdt_based_codoc::crdt::txn_rpc::admin_service_client::AdminServiceClient;
+use crdt_based_codoc::crdt::txn_rpc::{AdminRequest, BlockRangeRequest, ForceSyncRequest};
+use crdt_based_codoc::crdt::utils::{CRDTError, CRDTResult, ClientID};
+
+// Inspect a live replica (a peer or the relay) through its admin service
+#[derive(Parser, Debug)]
+#[clap(about = "Inspect and repair the replica of a doc")]
+struct Args {
+    // address of the peer or relay to inspect
+    #[clap(long, default_value = "127.0.0.1:4001")]
+    ip: String,
+    // name of the doc to inspect
+    #[clap(long, default_value = "doc")]
+    doc: String,
+    // token of a client owning the doc (required if the replica authenticates callers)
+    #[clap(long)]
+    token: Option<String>,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    // print the vector clock
+    Clock,
+    // print the peers and whether the replica is connected to them
+    Peers,
+    // print the blocks at [start, end) in spatial order, end 0 prints until the end of the doc
+    Blocks {
+        #[clap(long, default_value_t = 0)]
+        start: u32,
+        #[clap(long, default_value_t = 0)]
+        end: u32,
+    },
+    // print the pending updates and the origins they wait for
+    Pending,
+    // pull updates from the given peer now
+    Sync {
+        client_id: ClientID,
+    },
+    // merge blocks split from the same insertion
+    Compact,
+    // drop the content of the tombstones every peer has seen
+    Gc,
+}
+
+async fn run(args: Args) -> CRDTResult<String> {
+    let mut client = AdminServiceClient::connect(format!("http://{}", args.ip)).await?;
+    let doc_name = args.doc.clone();
+    let doc_request = || {
+        with_token(
+            AdminRequest {
+                doc_name: doc_name.clone(),
+            },
+            &args.token,
+        )
+    };
+    let resp = match args.command {
+        Command::Clock => client.get_vector_clock(doc_request()).await?,
+        Command::Peers => client.list_peers(doc_request()).await?,
+        Command::Blocks { start, end } => {
+            let req = BlockRangeRequest {
+                doc_name: args.doc.clone(),
+                start,
+                end,
+            };
+            client.dump_blocks(with_token(req, &args.token)).await?
+        }
+        Command::Pending => client.list_pending(doc_request()).await?,
+        Command::Sync { client_id } => {
+            let req = ForceSyncRequest {
+                doc_name: args.doc.clone(),
+                client_id,
+            };
+            client.force_sync(with_token(req, &args.token)).await?
+        }
+        Command::Compact => client.compact(doc_request()).await?,
+        Command::Gc => client.collect_tombstones(doc_request()).await?,
+    };
+    // pretty print the json result
+    let result: serde_json::Value = serde_json::from_str(&resp.into_inner().result)?;
+    serde_json::to_string_pretty(&result).map_err(CRDTError::from)
+}
+
+#[tokio::main]
+async fn main() {
+    match run(Args::parse()).await {
+        Ok(result) => println!("{}", result),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,367 @@
+use clap::Parser;
+use crdt_based_codoc::crdt::anti_entropy::{AntiEntropyConfig, PeerSelection};
+use crdt_based_codoc::crdt::auth::Permissions;
+use crdt_based_codoc::crdt::conn::ConnConfig;
+use crdt_based_codoc::crdt::doc::Doc;
+use crdt_based_codoc::crdt::e2e::KeyRing;
+use crdt_based_codoc::crdt::gossip::{Gossip, GossipConfig};
+use crdt_based_codoc::crdt::metrics::serve_metrics;
+use crdt_based_codoc::crdt::relay::{serve_relay, RelayServer};
+use crdt_based_codoc::crdt::signing::Signer;
+use crdt_based_codoc::crdt::snapshot::Bootstrap;
+use crdt_based_codoc::crdt::sync_txn::SyncTransaction;
+use crdt_based_codoc::crdt::tls::TlsConfig;
+use crdt_based_codoc::crdt::utils::{serve_rpc, ClientID, PeerDisplay};
+use crdt_based_codoc::crdt::ws::{serve_ws, sync_ws, WsServer};
+use crdt_based_codoc::tui::editor::Editor;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::Mutex;
+use tracing::{error, warn};
+use tracing_subscriber::EnvFilter;
+
+// A peer editing a shared doc
+#[derive(Parser, Debug)]
+#[clap(about = "Collaboratively edit a doc with other peers")]
+struct Args {
+    // name of the doc to edit
+    #[clap(long, default_value = "doc")]
+    doc: String,
+    // unique identifier of this client (randomly generated if not given)
+    #[clap(long)]
+    client_id: Option<ClientID>,
+    // address the rpc service (or the relay) listens on
+    #[clap(long, default_value = "127.0.0.1:4001")]
+    ip: String,
+    // open an interactive editor instead of printing the doc
+    #[clap(long)]
+    tui: bool,
+    // how often to pull updates from peers
+    #[clap(long, default_value_t = 500)]
+    sync_interval_ms: u64,
+    // sync through the relay at this address instead of connecting to peers
+    #[clap(long)]
+    relay: Option<String>,
+    // sync over a websocket with the server at this url (e.g. ws://127.0.0.1:4301)
+    #[clap(long)]
+    ws: Option<String>,
+    // run a relay server on ip instead of editing a doc
+    #[clap(long)]
+    serve_relay: bool,
+    // also accept websocket clients on this address when running a relay
+    #[clap(long)]
+    ws_ip: Option<String>,
+    // export prometheus metrics at http://<metrics-ip>/metrics
+    #[clap(long)]
+    metrics_ip: Option<String>,
+    // json file with the tokens and roles of clients allowed to call this service
+    #[clap(long)]
+    permissions: Option<String>,
+    // token sent with every rpc made by this client
+    #[clap(long)]
+    token: Option<String>,
+    // certificate (issued for "client-<client_id>") and key to serve and connect over tls
+    #[clap(long, requires_all = &["tls-key", "tls-ca"])]
+    tls_cert: Option<String>,
+    #[clap(long)]
+    tls_key: Option<String>,
+    // ca that signed the certificates of all peers
+    #[clap(long)]
+    tls_ca: Option<String>,
+    // require peers to present their certificate as well
+    #[clap(long, requires = "tls-cert")]
+    mtls: bool,
+    // sign insertions with the key in this file (created if missing),
+    // and only accept blocks signed by their client
+    #[clap(long)]
+    signing_key: Option<String>,
+    // json map from client id to public key, for peers not found through zookeeper
+    #[clap(long)]
+    public_keys: Option<String>,
+    // encrypt doc content end to end, the key is fetched from peers if --doc-key is not set
+    #[clap(long)]
+    e2e: bool,
+    // key ring of the doc (created if missing)
+    #[clap(long, requires = "e2e")]
+    doc_key: Option<String>,
+    // pull from peers in the background every interval, even without the editor syncing
+    #[clap(long)]
+    anti_entropy_interval_ms: Option<u64>,
+    // number of peers pulled from in every anti-entropy round
+    #[clap(long, default_value_t = 2)]
+    anti_entropy_fanout: usize,
+    // choose the peers of a round in turn instead of randomly
+    #[clap(long)]
+    anti_entropy_round_robin: bool,
+    // maximum number of blocks pulled from a peer in a round, 0 pulls the whole diff
+    #[clap(long, default_value_t = 1000)]
+    anti_entropy_page_size: u32,
+    // push updates to a few random peers which forward them, instead of pulling from every peer
+    #[clap(long)]
+    gossip: bool,
+    // number of peers an update is forwarded to
+    #[clap(long, default_value_t = 3)]
+    gossip_fanout: usize,
+    // number of times an update is forwarded
+    #[clap(long, default_value_t = 4)]
+    gossip_hops: u32,
+    // a peer not reachable within this timeout is retried later with a backoff
+    #[clap(long, default_value_t = 3000)]
+    connect_timeout_ms: u64,
+    // deadline of a pull from a peer
+    #[clap(long, default_value_t = 10000)]
+    pull_timeout_ms: u64,
+    // how often the channels to peers are checked, 0 disables health checks
+    #[clap(long, default_value_t = 5000)]
+    health_interval_ms: u64,
+    // load a snapshot of the doc when joining it instead of its whole history
+    #[clap(long)]
+    snapshot_bootstrap: bool,
+    // name shown to the other clients, e.g. in their blame view
+    #[clap(long)]
+    display_name: Option<String>,
+    // css color shown with the name
+    #[clap(long)]
+    color: Option<String>,
+}
+
+#[tokio::main(flavor = "multi_thread", worker_threads = 4)]
+async fn main() {
+    let args = Args::parse();
+    // logs go to stderr so the doc printed on stdout stays readable,
+    // the level is set with RUST_LOG (e.g. RUST_LOG=crdt_based_codoc=debug)
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .with_writer(std::io::stderr)
+        .init();
+    let sync_interval = Duration::from_millis(args.sync_interval_ms);
+    // never shut down unless the process is killed
+    let (_metrics_sender, metrics_receiver): (Sender<()>, Receiver<()>) = channel(1);
+    if let Some(metrics_ip) = args.metrics_ip.clone() {
+        let (metrics_init_sender, _metrics_init_receiver): (Sender<()>, Receiver<()>) = channel(1);
+        tokio::spawn(async move {
+            if let Err(e) = serve_metrics(metrics_ip, metrics_receiver, metrics_init_sender).await {
+                error!(error = %e, "metrics service failed");
+            }
+        });
+    }
+    let permissions = match args
+        .permissions
+        .as_ref()
+        .map(|path| Permissions::from_file(path))
+    {
+        Some(Ok(permissions)) => Some(Arc::new(permissions)),
+        Some(Err(e)) => {
+            error!(error = %e, "failed to load permissions");
+            return;
+        }
+        None => None,
+    };
+    let tls = match (
+        args.tls_cert.clone(),
+        args.tls_key.clone(),
+        args.tls_ca.clone(),
+    ) {
+        (Some(cert), Some(key), Some(ca)) => {
+            Some(Arc::new(TlsConfig::new(cert, key, ca, args.mtls)))
+        }
+        _ => None,
+    };
+
+    if args.serve_relay {
+        // never shut down unless the process is killed
+        let mut relay = RelayServer::new(args.ip.clone());
+        relay.auth = permissions;
+        relay.tls = tls;
+        let (_ws_sender, ws_receiver): (Sender<()>, Receiver<()>) = channel(1);
+        let (ws_init_sender, _ws_init_receiver): (Sender<()>, Receiver<()>) = channel(1);
+        if let Some(ws_ip) = args.ws_ip.clone() {
+            let ws_server = WsServer::new(ws_ip, relay.clone());
+            tokio::spawn(async move {
+                if let Err(e) = serve_ws(ws_server, ws_receiver, ws_init_sender).await {
+                    error!(error = %e, "websocket service failed");
+                }
+            });
+        }
+
+        let (_sender, receiver): (Sender<()>, Receiver<()>) = channel(1);
+        let (init_sender, _init_receiver): (Sender<()>, Receiver<()>) = channel(1);
+        if let Err(e) = serve_relay(relay, receiver, init_sender).await {
+            error!(error = %e, "relay service failed");
+        }
+        return;
+    }
+
+    let client_id = args.client_id.unwrap_or_else(rand::random);
+
+    // one transaction serves rpc, one is used by the editor, one watches zookeeper
+    let doc = Arc::new(Mutex::new(Doc::new(args.doc.clone(), client_id)));
+    let chan = Arc::new(Mutex::new(HashMap::new()));
+    let conn = ConnConfig {
+        connect_timeout: Duration::from_millis(args.connect_timeout_ms),
+        pull_timeout: Duration::from_millis(args.pull_timeout_ms),
+        health_interval: Duration::from_millis(args.health_interval_ms),
+        ..ConnConfig::default()
+    };
+    let new_txn = || {
+        let mut txn = SyncTransaction::new(
+            args.doc.clone(),
+            client_id,
+            doc.clone(),
+            chan.clone(),
+            args.ip.clone(),
+        );
+        txn.set_conn_config(conn.clone());
+        if args.snapshot_bootstrap {
+            txn.set_bootstrap(Bootstrap::Snapshot);
+        }
+        if let Some(name) = args.display_name.clone() {
+            txn.set_display(PeerDisplay {
+                name,
+                color: args.color.clone(),
+            });
+        }
+        if let Some(permissions) = permissions.clone() {
+            txn.set_permissions(permissions);
+        }
+        if let Some(token) = args.token.clone() {
+            txn.set_token(token);
+        }
+        if let Some(tls) = tls.clone() {
+            txn.set_tls(tls);
+        }
+        txn
+    };
+    let (mut txn_rpc, mut txn_service, txn_bg) = (new_txn(), new_txn(), new_txn());
+    if let Some(interval_ms) = args.anti_entropy_interval_ms {
+        txn_rpc.set_anti_entropy(AntiEntropyConfig {
+            interval: Duration::from_millis(interval_ms),
+            fanout: args.anti_entropy_fanout,
+            selection: if args.anti_entropy_round_robin {
+                PeerSelection::RoundRobin
+            } else {
+                PeerSelection::Random
+            },
+            page_size: args.anti_entropy_page_size,
+            ..AntiEntropyConfig::default()
+        });
+    }
+    if args.gossip {
+        let gossip = Arc::new(Gossip::new(GossipConfig {
+            fanout: args.gossip_fanout,
+            hops: args.gossip_hops,
+            ..GossipConfig::default()
+        }));
+        txn_rpc.set_gossip(gossip.clone());
+        txn_service.set_gossip(gossip);
+    }
+
+    if let Some(path) = args.signing_key.clone() {
+        match Signer::from_file(&path) {
+            Ok(signer) => txn_service.set_signer(Arc::new(signer)).await,
+            Err(e) => {
+                error!(error = %e, "failed to load signing key");
+                return;
+            }
+        }
+    }
+    if let Some(path) = args.public_keys.clone() {
+        let public_keys = std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| {
+                serde_json::from_str::<HashMap<ClientID, String>>(&content)
+                    .map_err(|e| e.to_string())
+            });
+        match public_keys {
+            Ok(public_keys) => {
+                let mut doc_lock = doc.lock().await;
+                for (client, public_key) in public_keys {
+                    doc_lock.signatures.add_public_key(client, public_key);
+                }
+            }
+            Err(e) => {
+                error!(error = %e, "failed to load public keys");
+                return;
+            }
+        }
+    }
+
+    if args.e2e {
+        let keys = match args.doc_key.as_ref().map(|path| KeyRing::from_file(path)) {
+            Some(Ok(keys)) => Some(keys),
+            Some(Err(e)) => {
+                error!(error = %e, "failed to load doc key");
+                return;
+            }
+            None => None,
+        };
+        txn_service.enable_encryption(keys).await;
+    }
+
+    // the websocket connection is kept open in the background by its own transaction
+    if let Some(url) = args.ws.clone() {
+        let (_ws_sender, ws_receiver): (Sender<()>, Receiver<()>) = channel(1);
+        tokio::spawn(async move {
+            if let Err(e) = sync_ws(&txn_bg, &url, sync_interval, ws_receiver).await {
+                error!(error = %e, "websocket sync failed");
+            }
+        });
+        run(txn_service, args.tui, sync_interval).await;
+        return;
+    }
+
+    // with a relay, there is no need to serve rpc or register to zookeeper
+    if let Some(relay) = args.relay.clone() {
+        txn_service.set_relay(relay);
+        run(txn_service, args.tui, sync_interval).await;
+        return;
+    }
+
+    let (sender, receiver): (Sender<()>, Receiver<()>) = channel(1);
+    let (init_sender, mut init_receiver): (Sender<()>, Receiver<()>) = channel(1);
+    tokio::spawn(async move {
+        if let Err(e) = serve_rpc(txn_rpc, txn_bg, receiver, init_sender).await {
+            error!(error = %e, "rpc service failed");
+        }
+    });
+    let _ = init_receiver.recv().await;
+
+    if let Err(e) = txn_service.register().await {
+        error!(client = client_id, doc = %args.doc, error = %e, "failed to register");
+        let _ = sender.send(()).await;
+        return;
+    }
+
+    run(txn_service, args.tui, sync_interval).await;
+
+    // shutdown rpc service
+    let _ = sender.send(()).await;
+}
+
+async fn run(txn: SyncTransaction, tui: bool, sync_interval: Duration) {
+    if tui {
+        let mut editor = Editor::new(txn, sync_interval);
+        if let Err(e) = editor.run().await {
+            error!(error = %e, "editor failed");
+        }
+    } else {
+        // headless: print the doc whenever it changes
+        let mut last = String::new();
+        loop {
+            // unreachable peers are retried at the next sync
+            if let Err(e) = txn.sync().await {
+                warn!(error = %e, "sync failed");
+            }
+            let curr = txn.doc.lock().await.to_string().await;
+            if curr != last {
+                println!("{}", curr);
+                last = curr;
+            }
+            tokio::time::sleep(sync_interval).await;
+        }
+    }
+}
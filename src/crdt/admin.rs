@@ -0,0 +1,302 @@
+use crate::crdt::auth::{check_role, Role};
+use crate::crdt::block::{Block, BlockID};
+use crate::crdt::relay::RelayServer;
+use crate::crdt::sync_txn::{respond, SyncTransaction};
+use crate::crdt::txn_rpc;
+use crate::crdt::txn_rpc::admin_service_server::AdminService;
+use crate::crdt::utils::{CRDTError, CRDTResult, Peer};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use tracing::{info, instrument};
+
+// PeerState is a peer of the doc and whether this client holds an rpc channel to it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PeerState {
+    pub peer: Peer,
+    pub connected: bool,
+    // consecutive failures to reach the peer
+    pub failures: u32,
+}
+
+// PendingUpdate is a remote block waiting to be integrated,
+// missing are its origins that haven't been seen yet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingUpdate {
+    pub block: Block,
+    pub missing: Vec<BlockID>,
+}
+
+// Introspection of a replica, used by the admin service to debug divergence between clients
+impl SyncTransaction {
+    // peers of the doc, the relay is listed if one is set
+    pub async fn peer_states(&self) -> Vec<PeerState> {
+        let mut peers = self.doc.lock().await.peers.clone();
+        if let Some(relay) = &self.relay {
+            peers.push(relay.clone());
+        }
+        let channels = self.channels.lock().await;
+        peers
+            .into_iter()
+            .map(|peer| {
+                let conn = channels.get(&peer.client_id);
+                PeerState {
+                    connected: matches!(conn, Some(conn) if conn.is_connected()),
+                    failures: conn.map_or(0, |conn| conn.failures),
+                    peer,
+                }
+            })
+            .collect()
+    }
+
+    // blocks at [start, end) in the spatial order of the doc, tombstones included
+    pub async fn blocks_in(&self, start: usize, end: usize) -> Vec<Block> {
+        let local_doc = self.doc.lock().await;
+        let store = local_doc.block_store.lock().await;
+        let end = end.min(store.total_store.list.len());
+        let mut blocks = vec![];
+        for block in store.total_store.list.iter().take(end).skip(start) {
+            blocks.push(block.lock().await.clone());
+        }
+        blocks
+    }
+
+    // pending updates together with the origins they are waiting for
+    pub async fn pending_updates(&self) -> Vec<PendingUpdate> {
+        let local_doc = self.doc.lock().await;
+        let store = local_doc.block_store.lock().await;
+        let mut pending = vec![];
+        for block in &local_doc.pending_updates {
+            let mut missing = vec![];
+            for origin in [&block.left_origin, &block.right_origin]
+                .into_iter()
+                .flatten()
+            {
+                if !store.contains(origin, 1).await {
+                    missing.push(origin.clone());
+                }
+            }
+            pending.push(PendingUpdate {
+                block: block.clone(),
+                missing,
+            });
+        }
+        pending
+    }
+
+    // merge blocks split from the same insertion, return the number of blocks removed
+    pub async fn compact_blocks(&self) -> usize {
+        let local_doc = self.doc.lock().await;
+        let mut store = local_doc.block_store.lock().await;
+        store.compact().await
+    }
+
+    // elide the tombstones every peer has seen, bounded by the vector clocks the peers reported,
+    // nothing is collected while a peer (or the relay) hasn't been pulled from
+    //
+    // return the number of tombstones collected
+    pub async fn collect_tombstones(&self) -> usize {
+        let local_doc = self.doc.lock().await;
+        let mut peers = local_doc.peers.clone();
+        if let Some(relay) = &self.relay {
+            peers.push(relay.clone());
+        }
+        let mut stable = local_doc.vector_clock.clone();
+        {
+            let peer_clocks = self.peer_clocks.lock().await;
+            for peer in peers.iter().filter(|peer| peer.client_id != self.client) {
+                match peer_clocks.get(&peer.client_id) {
+                    Some(clock) => stable = stable.meet(clock),
+                    None => return 0,
+                }
+            }
+        }
+        let mut store = local_doc.block_store.lock().await;
+        let collected = store.collect_tombstones(&stable).await;
+        // collected tombstones of the same insertion are merged again
+        store.compact().await;
+        collected
+    }
+
+    // check that the caller owns the doc named in the request
+    #[allow(clippy::result_large_err)]
+    fn check_admin<T>(
+        &self,
+        request: &tonic::Request<T>,
+        doc_name: &str,
+    ) -> Result<(), tonic::Status> {
+        check_role(&self.auth, request, &self.doc_name, Role::Owner)?;
+        if !doc_name.is_empty() && doc_name != self.doc_name {
+            return Err(tonic::Status::not_found("doc not found"));
+        }
+        Ok(())
+    }
+}
+
+// serialize the result of an admin rpc
+#[allow(clippy::result_large_err)]
+fn admin_response<T: Serialize>(
+    rpc: &str,
+    received: usize,
+    result: CRDTResult<T>,
+) -> Result<tonic::Response<txn_rpc::AdminResponse>, tonic::Status> {
+    let result =
+        serde_json::to_string(&result?).map_err(|e| tonic::Status::internal(e.to_string()))?;
+    respond(rpc, received, txn_rpc::AdminResponse { result })
+}
+
+// implement admin rpc interface, every call needs the caller to own the doc
+#[async_trait::async_trait]
+impl AdminService for SyncTransaction {
+    #[instrument(skip_all, fields(doc = %self.doc_name, client = self.client))]
+    async fn get_vector_clock(
+        &self,
+        request: tonic::Request<txn_rpc::AdminRequest>,
+    ) -> Result<tonic::Response<txn_rpc::AdminResponse>, tonic::Status> {
+        self.check_admin(&request, &request.get_ref().doc_name)?;
+        let received = request.get_ref().encoded_len();
+        let clock = self.doc.lock().await.vector_clock.clone();
+        admin_response("get_vector_clock", received, Ok(clock))
+    }
+
+    #[instrument(skip_all, fields(doc = %self.doc_name, client = self.client))]
+    async fn list_peers(
+        &self,
+        request: tonic::Request<txn_rpc::AdminRequest>,
+    ) -> Result<tonic::Response<txn_rpc::AdminResponse>, tonic::Status> {
+        self.check_admin(&request, &request.get_ref().doc_name)?;
+        let received = request.get_ref().encoded_len();
+        admin_response("list_peers", received, Ok(self.peer_states().await))
+    }
+
+    #[instrument(skip_all, fields(doc = %self.doc_name, client = self.client))]
+    async fn dump_blocks(
+        &self,
+        request: tonic::Request<txn_rpc::BlockRangeRequest>,
+    ) -> Result<tonic::Response<txn_rpc::AdminResponse>, tonic::Status> {
+        self.check_admin(&request, &request.get_ref().doc_name)?;
+        let received = request.get_ref().encoded_len();
+        let range = request.into_inner();
+        // an end of 0 dumps the blocks until the end of the doc
+        let end = match range.end {
+            0 => usize::MAX,
+            end => end as usize,
+        };
+        let blocks = self.blocks_in(range.start as usize, end).await;
+        admin_response("dump_blocks", received, Ok(blocks))
+    }
+
+    #[instrument(skip_all, fields(doc = %self.doc_name, client = self.client))]
+    async fn list_pending(
+        &self,
+        request: tonic::Request<txn_rpc::AdminRequest>,
+    ) -> Result<tonic::Response<txn_rpc::AdminResponse>, tonic::Status> {
+        self.check_admin(&request, &request.get_ref().doc_name)?;
+        let received = request.get_ref().encoded_len();
+        admin_response("list_pending", received, Ok(self.pending_updates().await))
+    }
+
+    #[instrument(skip_all, fields(doc = %self.doc_name, client = self.client, peer = request.get_ref().client_id))]
+    async fn force_sync(
+        &self,
+        request: tonic::Request<txn_rpc::ForceSyncRequest>,
+    ) -> Result<tonic::Response<txn_rpc::AdminResponse>, tonic::Status> {
+        self.check_admin(&request, &request.get_ref().doc_name)?;
+        let received = request.get_ref().encoded_len();
+        info!("forced sync");
+        // the doc is still partially synced when some blocks wait for their origins
+        let res = match self.sync_with(request.get_ref().client_id).await {
+            Ok(()) | Err(CRDTError::MissingDependency(_)) => {
+                Ok(self.doc.lock().await.vector_clock.clone())
+            }
+            Err(e) => Err(e),
+        };
+        admin_response("force_sync", received, res)
+    }
+
+    #[instrument(skip_all, fields(doc = %self.doc_name, client = self.client))]
+    async fn compact(
+        &self,
+        request: tonic::Request<txn_rpc::AdminRequest>,
+    ) -> Result<tonic::Response<txn_rpc::AdminResponse>, tonic::Status> {
+        self.check_admin(&request, &request.get_ref().doc_name)?;
+        let received = request.get_ref().encoded_len();
+        let removed = self.compact_blocks().await;
+        info!(removed, "compacted blocks");
+        admin_response("compact", received, Ok(removed))
+    }
+
+    #[instrument(skip_all, fields(doc = %self.doc_name, client = self.client))]
+    async fn collect_tombstones(
+        &self,
+        request: tonic::Request<txn_rpc::AdminRequest>,
+    ) -> Result<tonic::Response<txn_rpc::AdminResponse>, tonic::Status> {
+        self.check_admin(&request, &request.get_ref().doc_name)?;
+        let received = request.get_ref().encoded_len();
+        let collected = SyncTransaction::collect_tombstones(self).await;
+        info!(collected, "collected tombstones");
+        admin_response("collect_tombstones", received, Ok(collected))
+    }
+}
+
+// the relay dispatches admin requests to the replica named in the request,
+// docs that are not hosted are never created
+#[async_trait::async_trait]
+impl AdminService for RelayServer {
+    async fn get_vector_clock(
+        &self,
+        request: tonic::Request<txn_rpc::AdminRequest>,
+    ) -> Result<tonic::Response<txn_rpc::AdminResponse>, tonic::Status> {
+        let txn = self.find_doc(&request.get_ref().doc_name).await?;
+        AdminService::get_vector_clock(txn.as_ref(), request).await
+    }
+
+    async fn list_peers(
+        &self,
+        request: tonic::Request<txn_rpc::AdminRequest>,
+    ) -> Result<tonic::Response<txn_rpc::AdminResponse>, tonic::Status> {
+        let txn = self.find_doc(&request.get_ref().doc_name).await?;
+        AdminService::list_peers(txn.as_ref(), request).await
+    }
+
+    async fn dump_blocks(
+        &self,
+        request: tonic::Request<txn_rpc::BlockRangeRequest>,
+    ) -> Result<tonic::Response<txn_rpc::AdminResponse>, tonic::Status> {
+        let txn = self.find_doc(&request.get_ref().doc_name).await?;
+        AdminService::dump_blocks(txn.as_ref(), request).await
+    }
+
+    async fn list_pending(
+        &self,
+        request: tonic::Request<txn_rpc::AdminRequest>,
+    ) -> Result<tonic::Response<txn_rpc::AdminResponse>, tonic::Status> {
+        let txn = self.find_doc(&request.get_ref().doc_name).await?;
+        AdminService::list_pending(txn.as_ref(), request).await
+    }
+
+    async fn force_sync(
+        &self,
+        _request: tonic::Request<txn_rpc::ForceSyncRequest>,
+    ) -> Result<tonic::Response<txn_rpc::AdminResponse>, tonic::Status> {
+        Err(tonic::Status::unimplemented(
+            "relay only receives updates pushed by clients",
+        ))
+    }
+
+    async fn compact(
+        &self,
+        request: tonic::Request<txn_rpc::AdminRequest>,
+    ) -> Result<tonic::Response<txn_rpc::AdminResponse>, tonic::Status> {
+        let txn = self.find_doc(&request.get_ref().doc_name).await?;
+        AdminService::compact(txn.as_ref(), request).await
+    }
+
+    async fn collect_tombstones(
+        &self,
+        _request: tonic::Request<txn_rpc::AdminRequest>,
+    ) -> Result<tonic::Response<txn_rpc::AdminResponse>, tonic::Status> {
+        Err(tonic::Status::unimplemented(
+            "relay doesn't know which clients have seen the tombstones",
+        ))
+    }
+}
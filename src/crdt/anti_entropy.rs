@@ -0,0 +1,174 @@
+use crate::crdt::block::BlockID;
+use crate::crdt::sync_txn::SyncTransaction;
+use crate::crdt::utils::{ClientID, Peer};
+use rand::seq::SliceRandom;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, instrument, warn};
+
+// how the peers pulled from in a round are chosen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PeerSelection {
+    Random,
+    RoundRobin,
+}
+
+// AntiEntropyConfig configures the background sync started with the rpc service
+#[derive(Debug, Clone)]
+pub struct AntiEntropyConfig {
+    // time between two rounds
+    pub interval: Duration,
+    // number of peers pulled from in a round
+    pub fanout: usize,
+    pub selection: PeerSelection,
+    // maximum number of blocks pulled from a peer in a round (0 pulls the whole diff),
+    // the rest of the diff is pulled in the next rounds
+    pub page_size: u32,
+    // a failing peer is skipped for interval * 2^failures, at most max_backoff
+    pub max_backoff: Duration,
+}
+
+impl Default for AntiEntropyConfig {
+    fn default() -> Self {
+        AntiEntropyConfig {
+            interval: Duration::from_millis(500),
+            fanout: 2,
+            selection: PeerSelection::Random,
+            page_size: 1000,
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+// progress of the anti-entropy with one peer
+#[derive(Debug, Default)]
+struct PeerProgress {
+    // id the next page of the peer's diff starts after, None starts a new diff
+    after: Option<BlockID>,
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+// AntiEntropy pulls from a few peers every round,
+// so that replicas converge even if nobody calls sync
+pub struct AntiEntropy {
+    txn: SyncTransaction,
+    config: AntiEntropyConfig,
+    progress: HashMap<ClientID, PeerProgress>,
+    // index of the next peer when peers are chosen round-robin
+    next: usize,
+}
+
+impl AntiEntropy {
+    pub fn new(txn: SyncTransaction, config: AntiEntropyConfig) -> Self {
+        AntiEntropy {
+            txn,
+            config,
+            progress: HashMap::new(),
+            next: 0,
+        }
+    }
+
+    // run a round every interval, until the task running it is aborted
+    pub async fn run(mut self) {
+        info!(
+            doc = %self.txn.doc_name,
+            client = self.txn.client,
+            interval = ?self.config.interval,
+            "anti-entropy started"
+        );
+        let mut ticker = tokio::time::interval(self.config.interval);
+        loop {
+            ticker.tick().await;
+            self.round().await;
+        }
+    }
+
+    // pull a page of updates from the peers chosen for this round,
+    // return the number of peers that have been reached
+    #[instrument(skip_all, fields(doc = %self.txn.doc_name, client = self.txn.client))]
+    pub async fn round(&mut self) -> usize {
+        let now = Instant::now();
+        let peers = self.choose(now).await;
+        let mut reached = 0;
+        for peer in peers {
+            let progress = self.progress.entry(peer.client_id).or_default();
+            let res = self
+                .txn
+                .pull_page(&peer, progress.after.clone(), self.config.page_size)
+                .await;
+            match res {
+                Ok(next) => {
+                    progress.after = next;
+                    progress.failures = 0;
+                    progress.retry_at = None;
+                    reached += 1;
+                }
+                Err(e) => {
+                    progress.failures += 1;
+                    let delay = self
+                        .config
+                        .interval
+                        .saturating_mul(1 << progress.failures.min(16))
+                        .min(self.config.max_backoff);
+                    progress.retry_at = Some(now + delay);
+                    // the diff is computed again once the peer is back,
+                    // pull_page has already dropped a broken channel
+                    progress.after = None;
+                    warn!(
+                        peer = peer.client_id,
+                        failures = progress.failures,
+                        retry_in = ?delay,
+                        error = %e,
+                        "anti-entropy failed to pull from peer"
+                    );
+                }
+            }
+        }
+        self.txn.observe().await;
+        debug!(reached, "anti-entropy round done");
+        reached
+    }
+
+    // peers to pull from in this round, peers backing off are skipped
+    async fn choose(&mut self, now: Instant) -> Vec<Peer> {
+        let mut candidates: Vec<Peer> = {
+            let local_doc = self.txn.doc.lock().await;
+            local_doc
+                .peers
+                .iter()
+                .filter(|peer| peer.client_id != self.txn.client)
+                .filter(|peer| match self.progress.get(&peer.client_id) {
+                    Some(PeerProgress {
+                        retry_at: Some(retry_at),
+                        ..
+                    }) => *retry_at <= now,
+                    _ => true,
+                })
+                .cloned()
+                .collect()
+        };
+        if candidates.is_empty() {
+            return candidates;
+        }
+        let fanout = self.config.fanout.min(candidates.len());
+        match self.config.selection {
+            PeerSelection::Random => {
+                candidates.shuffle(&mut rand::thread_rng());
+                candidates.truncate(fanout);
+                candidates
+            }
+            PeerSelection::RoundRobin => {
+                candidates.sort_by_key(|peer| peer.client_id);
+                let start = self.next % candidates.len();
+                self.next = start + fanout;
+                candidates
+                    .into_iter()
+                    .cycle()
+                    .skip(start)
+                    .take(fanout)
+                    .collect()
+            }
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use crate::crdt::utils::{CRDTResult, ClientID, Updates};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tonic::{Request, Status};
+use tracing::warn;
+
+// metadata key carrying the token of the caller, e.g. "authorization: Bearer <token>"
+pub const AUTH_HEADER: &str = "authorization";
+
+// Role of a client on a doc, roles are ordered by what they are allowed to do
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Viewer,
+    Commenter,
+    Editor,
+    Owner,
+}
+
+impl Role {
+    // viewers and commenters can read the doc but cannot change the text
+    pub fn can_edit(&self) -> bool {
+        *self >= Role::Editor
+    }
+
+    // commenters can comment the doc without changing the text
+    pub fn can_comment(&self) -> bool {
+        *self >= Role::Commenter
+    }
+
+    // only owners accept or reject suggestions
+    pub fn can_resolve(&self) -> bool {
+        *self >= Role::Owner
+    }
+}
+
+// Identity of the caller of an rpc, attached to the request by the interceptor
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Caller(pub ClientID);
+
+// Permissions maps tokens to clients and gives every client a role per doc,
+// it can be loaded from a json file:
+//
+// {
+//     "tokens": { "secret-of-1": 1, "secret-of-2": 2 },
+//     "docs": { "doc": { "1": "owner", "2": "viewer" } }
+// }
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct Permissions {
+    pub tokens: HashMap<String, ClientID>,
+    pub docs: HashMap<String, HashMap<ClientID, Role>>,
+}
+
+impl Permissions {
+    pub fn from_file(path: &str) -> CRDTResult<Self> {
+        let content = std::fs::read_to_string(path)?;
+        let permissions = serde_json::from_str(&content)?;
+        Ok(permissions)
+    }
+
+    // Find the client owning token
+    pub fn authenticate(&self, token: &str) -> Option<ClientID> {
+        self.tokens.get(token).cloned()
+    }
+
+    pub fn role(&self, doc: &str, client: ClientID) -> Option<Role> {
+        self.docs
+            .get(doc)
+            .and_then(|roles| roles.get(&client).cloned())
+    }
+
+    pub fn can_edit(&self, doc: &str, client: ClientID) -> bool {
+        self.role(doc, client).is_some_and(|role| role.can_edit())
+    }
+
+    // Split updates into the blocks allowed on doc and the blocks refused,
+    // insertions and deletions must be made by clients allowed to edit the doc
+    // (a tombstone without its deleter is refused)
+    //
+    // if sender is the authenticated client that sent updates, it only writes its own
+    // insertions and deletions, whatever client the blocks claim to come from
+    //
+    // owner_of maps the client of a fork to the client it acts on behalf of (see SignatureStore::owner_of)
+    pub fn filter_updates(
+        &self,
+        doc: &str,
+        sender: Option<ClientID>,
+        updates: Updates,
+        owner_of: impl Fn(ClientID) -> ClientID,
+    ) -> (Updates, Updates) {
+        updates.into_iter().partition(|block| {
+            let written_by = if block.is_deleted {
+                block.deleted_by.map(&owner_of)
+            } else {
+                Some(owner_of(block.id.client))
+            };
+            let allowed = self.can_edit(doc, owner_of(block.id.client))
+                && written_by.is_some_and(|client| self.can_edit(doc, client))
+                && sender.is_none_or(|sender| written_by == Some(sender));
+            if !allowed {
+                warn!(block = ?block.id, sender = ?sender, "dropped block from a client without edit access");
+            }
+            allowed
+        })
+    }
+}
+
+// Build an interceptor that authenticates every rpc,
+// calls without a valid token are rejected before reaching the service
+//
+// if permissions is None, authentication is disabled and every call is accepted
+//
+// tonic interceptors and services fail with a Status by value
+#[allow(clippy::result_large_err)]
+pub fn interceptor(
+    permissions: Option<Arc<Permissions>>,
+) -> impl Fn(Request<()>) -> Result<Request<()>, Status> + Clone {
+    move |mut request: Request<()>| {
+        let permissions = match &permissions {
+            Some(permissions) => permissions,
+            None => return Ok(request),
+        };
+        let token = request
+            .metadata()
+            .get(AUTH_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim_start_matches("Bearer ").to_string());
+        match token.and_then(|token| permissions.authenticate(&token)) {
+            Some(client) => {
+                request.extensions_mut().insert(Caller(client));
+                Ok(request)
+            }
+            None => Err(Status::unauthenticated("invalid token")),
+        }
+    }
+}
+
+// Check that the caller of request has at least role required on doc
+#[allow(clippy::result_large_err)]
+pub fn check_role<T>(
+    permissions: &Option<Arc<Permissions>>,
+    request: &Request<T>,
+    doc: &str,
+    required: Role,
+) -> Result<(), Status> {
+    let permissions = match permissions {
+        Some(permissions) => permissions,
+        None => return Ok(()),
+    };
+    let caller = match request.extensions().get::<Caller>() {
+        Some(caller) => caller,
+        None => return Err(Status::unauthenticated("missing caller")),
+    };
+    match permissions.role(doc, caller.0) {
+        Some(role) if role >= required => Ok(()),
+        _ => Err(Status::permission_denied(format!(
+            "{:?} needs to be at least {:?} on {:?}",
+            caller.0, required, doc
+        ))),
+    }
+}
+
+// Attach token to an outgoing request
+pub fn with_token<T>(msg: T, token: &Option<String>) -> Request<T> {
+    let mut request = Request::new(msg);
+    if let Some(token) = token {
+        match format!("Bearer {}", token).parse() {
+            Ok(value) => {
+                request.metadata_mut().insert(AUTH_HEADER, value);
+            }
+            Err(_) => warn!("token cannot be sent as metadata"),
+        }
+    }
+    request
+}
@@ -0,0 +1,75 @@
+use crate::crdt::block::BlockID;
+use crate::crdt::utils::ClientID;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// CursorState is the ephemeral presence information of one client,
+// it is not part of the CRDT and is never persisted
+//
+// anchor is the id of the character right before the cursor (None = start of doc),
+// so that the cursor stays at the same place when other clients edit the doc
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct CursorState {
+    pub client_id: ClientID,
+    pub anchor: Option<BlockID>,
+    // bumped every time the local client moves its cursor,
+    // stale states received from other peers are ignored
+    pub version: u32,
+}
+
+// Awareness keeps the latest cursor of every client editing the doc
+#[derive(Clone, Debug, Default)]
+pub struct Awareness {
+    pub states: HashMap<ClientID, CursorState>,
+}
+
+impl Awareness {
+    pub fn new() -> Self {
+        Awareness {
+            states: HashMap::new(),
+        }
+    }
+
+    // Move the cursor of client to anchor
+    pub fn set_cursor(&mut self, client: ClientID, anchor: Option<BlockID>) {
+        let version = match self.states.get(&client) {
+            Some(state) => {
+                if state.anchor == anchor {
+                    return;
+                }
+                state.version + 1
+            }
+            None => 0,
+        };
+        self.states.insert(
+            client,
+            CursorState {
+                client_id: client,
+                anchor,
+                version,
+            },
+        );
+    }
+
+    // Merge cursor states received from a peer, keep the newer one
+    pub fn apply_remote(&mut self, states: Vec<CursorState>) {
+        for state in states {
+            let newer = match self.states.get(&state.client_id) {
+                Some(local) => state.version > local.version,
+                None => true,
+            };
+            if newer {
+                self.states.insert(state.client_id, state);
+            }
+        }
+    }
+
+    // Forget the cursor of a client (e.g. the client left the doc)
+    pub fn remove(&mut self, client: ClientID) {
+        self.states.remove(&client);
+    }
+
+    pub fn get_states(&self) -> Vec<CursorState> {
+        self.states.values().cloned().collect()
+    }
+}
@@ -0,0 +1,136 @@
+use crate::crdt::block::BlockID;
+use crate::crdt::doc::Doc;
+use crate::crdt::utils::{ClientID, PeerDisplay};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Span is a contiguous range of the visible text written by author, start counts bytes
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Span {
+    pub start: u32,
+    pub text: String,
+    pub author: ClientID,
+}
+
+// DeletedSpan is deleted text written by author, pos is where it used to be in the visible text
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DeletedSpan {
+    pub pos: u32,
+    pub id: BlockID,
+    pub text: String,
+    pub author: ClientID,
+    // None if the deletion came from a peer that doesn't record it
+    pub deleted_by: Option<ClientID>,
+}
+
+// BlameLine is a line of the text and the clients that wrote it, the ones that wrote most of it first
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BlameLine {
+    pub line: u32,
+    pub text: String,
+    pub authors: Vec<ClientID>,
+}
+
+impl Doc {
+    // Visible text split in spans of the same author, neighbouring blocks of an author are merged
+    pub async fn attributed_spans(&self) -> Vec<Span> {
+        let store = self.block_store.clone();
+        let store_lock = store.lock().await;
+        let mut spans: Vec<Span> = vec![];
+        let mut pos = 0;
+        for block in store_lock.total_store.list.iter() {
+            let block_lock = block.lock().await;
+            if block_lock.is_deleted || block_lock.content.is_empty() {
+                continue;
+            }
+            let content = block_lock.content.as_str();
+            match spans.last_mut() {
+                Some(span) if span.author == block_lock.id.client => span.text.push_str(content),
+                _ => spans.push(Span {
+                    start: pos,
+                    text: content.to_string(),
+                    author: block_lock.id.client,
+                }),
+            }
+            pos += content.len() as u32;
+        }
+        spans
+    }
+
+    // Deleted text with its author and the client that deleted it, in spatial order,
+    // text whose content has been left out of a snapshot is unknown and skipped
+    pub async fn deleted_spans(&self) -> Vec<DeletedSpan> {
+        let store = self.block_store.clone();
+        let store_lock = store.lock().await;
+        let mut spans: Vec<DeletedSpan> = vec![];
+        let mut pos = 0;
+        for block in store_lock.total_store.list.iter() {
+            let block_lock = block.lock().await;
+            let content = block_lock.content.as_str();
+            if !block_lock.is_deleted {
+                pos += content.len() as u32;
+                continue;
+            }
+            if block_lock.elided {
+                continue;
+            }
+            match spans.last_mut() {
+                Some(span)
+                    if span.pos == pos
+                        && span.author == block_lock.id.client
+                        && span.deleted_by == block_lock.deleted_by
+                        && span.id.clock + span.text.len() as u32 == block_lock.id.clock =>
+                {
+                    span.text.push_str(content)
+                }
+                _ => spans.push(DeletedSpan {
+                    pos,
+                    id: block_lock.id.clone(),
+                    text: content.to_string(),
+                    author: block_lock.id.client,
+                    deleted_by: block_lock.deleted_by,
+                }),
+            }
+        }
+        spans
+    }
+
+    // How client is shown, as published by the client through membership
+    pub fn display_of(&self, client: ClientID) -> Option<PeerDisplay> {
+        self.peers
+            .iter()
+            .find(|peer| peer.client_id == client)
+            .and_then(|peer| peer.display.clone())
+    }
+
+    // Lines of the visible text with their authors, like git blame
+    pub async fn blame(&self) -> Vec<BlameLine> {
+        let mut lines = vec![];
+        let (mut text, mut written) = (String::new(), HashMap::new());
+        let mut finish = |text: &mut String, written: &mut HashMap<ClientID, u32>| {
+            let mut authors: Vec<(ClientID, u32)> = written.drain().collect();
+            authors.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+            lines.push(BlameLine {
+                line: lines.len() as u32,
+                text: std::mem::take(text),
+                authors: authors.into_iter().map(|(client, _)| client).collect(),
+            });
+        };
+        for span in self.attributed_spans().await {
+            for part in span.text.split_inclusive('\n') {
+                *written.entry(span.author).or_insert(0) += part.len() as u32;
+                match part.strip_suffix('\n') {
+                    Some(part) => {
+                        text.push_str(part);
+                        finish(&mut text, &mut written);
+                    }
+                    None => text.push_str(part),
+                }
+            }
+        }
+        if !text.is_empty() {
+            finish(&mut text, &mut written);
+        }
+        lines
+    }
+}
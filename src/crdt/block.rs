@@ -1,10 +1,63 @@
+use crate::crdt::e2e::Sealed;
+use crate::crdt::embed::{Embed, EMBED_PLACEHOLDER};
+use crate::crdt::suggestion::Suggestion;
 use crate::crdt::utils::ClientID;
 use serde::{Deserialize, Serialize};
 use std::{cmp::Ordering, sync::Arc};
 use tokio::sync::Mutex;
-#[derive(Clone, Serialize, Deserialize, Debug, Default)]
-pub struct Content {
-    pub content: String,
+
+// Content of a block, a run of text or an embed
+//
+// an embed counts as the single character EMBED_PLACEHOLDER in clocks and positions,
+// it is the sealed embed once sealed, and filler text once elided
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Content {
+    Text(String),
+    Embed(Embed),
+}
+
+impl Default for Content {
+    fn default() -> Self {
+        Content::Text(String::new())
+    }
+}
+
+impl Content {
+    pub fn text(text: impl Into<String>) -> Self {
+        Content::Text(text.into())
+    }
+
+    // Text of the content, EMBED_PLACEHOLDER for an embed
+    pub fn as_str(&self) -> &str {
+        match self {
+            Content::Text(text) => text,
+            Content::Embed(_) => EMBED_PLACEHOLDER,
+        }
+    }
+
+    // Length in clocks (bytes of the text)
+    pub fn len(&self) -> usize {
+        self.as_str().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn embed(&self) -> Option<&Embed> {
+        match self {
+            Content::Text(_) => None,
+            Content::Embed(embed) => Some(embed),
+        }
+    }
+
+    // Append text split from the same insertion, embeds are never merged so they are left as they are
+    pub fn push_str(&mut self, text: &str) {
+        if let Content::Text(content) = self {
+            content.push_str(text);
+        }
+    }
 }
 
 #[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Hash, Debug, Default)]
@@ -41,6 +94,20 @@ impl BlockID {
 
 pub type BlockPtr = Arc<Mutex<Block>>;
 
+// ChangeID identifies a change made by client to blocks that already exist (e.g. a deletion),
+// seq counts the changes of the client from 0, like clocks count its characters
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Hash, Debug)]
+pub struct ChangeID {
+    pub client: ClientID,
+    pub seq: u32,
+}
+
+impl ChangeID {
+    pub fn new(client: ClientID, seq: u32) -> Self {
+        ChangeID { client, seq }
+    }
+}
+
 // Block is the basic building block of doc (e.g. text, xml element, etc.),
 // one block can be split to two blocks,
 // and two blocks can be merged into one
@@ -50,6 +117,24 @@ pub struct Block {
     pub left_origin: Option<BlockID>,
     pub right_origin: Option<BlockID>,
     pub is_deleted: bool,
+    // client that deleted the block, None if it is not deleted or the deletion came from a peer
+    // that doesn't record it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_by: Option<ClientID>,
+    // suggestion the block belongs to, None if the block is plain text
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suggestion: Option<Suggestion>,
+    // changes made to the block since it was inserted, sorted,
+    // a peer that hasn't seen one of them gets the block again
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<ChangeID>,
+    // set if the content is end-to-end encrypted, it is then SEALED_FILLER
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sealed: Option<Sealed>,
+    // set if the block is a tombstone whose content has been left out (of a snapshot or a yjs update),
+    // only its length is known and its content is TOMBSTONE_FILLER
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub elided: bool,
     pub content: Content,
 }
 
@@ -65,6 +150,11 @@ impl Block {
             left_origin,
             right_origin,
             is_deleted: false,
+            deleted_by: None,
+            suggestion: None,
+            changes: vec![],
+            sealed: None,
+            elided: false,
             content,
         }
     }
@@ -78,14 +168,67 @@ impl Block {
             left_origin: None,
             right_origin: None,
             is_deleted: false,
-            content: Content {
-                content: "".to_string(),
-            },
+            deleted_by: None,
+            suggestion: None,
+            changes: vec![],
+            sealed: None,
+            elided: false,
+            content: Content::default(),
         }
     }
 
     // Delete the current block (mark as deleted)
     pub fn delete(&mut self) {
+        self.delete_by(None);
+    }
+
+    // Delete the current block on behalf of by, concurrent deletions
+    // of the same block are attributed to the smallest client so that peers agree
+    pub fn delete_by(&mut self, by: Option<ClientID>) {
+        self.deleted_by = match (self.is_deleted, self.deleted_by, by) {
+            (false, _, by) => by,
+            (true, Some(a), Some(b)) => Some(a.min(b)),
+            (true, a, b) => a.or(b),
+        };
         self.is_deleted = true;
     }
+
+    // Record that change has been made to the block
+    pub fn stamp(&mut self, change: ChangeID) {
+        if let Err(i) = self.changes.binary_search(&change) {
+            self.changes.insert(i, change);
+        }
+    }
+
+    // Record suggestion on the block, merged with the suggestion it already has
+    // so that peers receiving the changes in any order agree
+    pub fn suggest(&mut self, suggestion: Suggestion) {
+        self.suggestion = Some(match self.suggestion {
+            Some(current) => current.merge(suggestion),
+            None => suggestion,
+        });
+    }
+
+    // Check if right is the part that splitting this block would have produced,
+    // whatever happened to either part since (embeds are never split)
+    pub fn splits_into(&self, right: &Block) -> bool {
+        self.content.embed().is_none()
+            && right.content.embed().is_none()
+            && right.id.client == self.id.client
+            && right.id.clock == self.id.clock + self.content.len() as u32
+            && right.left_origin.as_ref() == Some(&self.id)
+            && right.right_origin == self.right_origin
+    }
+
+    // Check if right is exactly what splitting this block would have produced,
+    // so that the two blocks can be merged back (embeds are never merged)
+    pub fn merges_with(&self, right: &Block) -> bool {
+        self.splits_into(right)
+            && right.is_deleted == self.is_deleted
+            && right.deleted_by == self.deleted_by
+            && right.suggestion == self.suggestion
+            && right.changes == self.changes
+            && right.sealed == self.sealed
+            && right.elided == self.elided
+    }
 }
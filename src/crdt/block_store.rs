@@ -1,8 +1,10 @@
 use tokio::sync::Mutex;
 
-use crate::crdt::block::{Block, BlockID, BlockPtr, Content};
-use crate::crdt::utils::ClientID;
-use std::collections::HashMap;
+use crate::crdt::block::{Block, BlockID, BlockPtr, ChangeID, Content};
+use crate::crdt::doc::{is_missing, VectorClock};
+use crate::crdt::snapshot::TOMBSTONE_FILLER;
+use crate::crdt::utils::{ClientID, Updates};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
 pub type BlockListPtr = Box<BlockList>;
@@ -22,6 +24,33 @@ impl BlockList {
     }
 }
 
+// StoreStats counts the blocks of a store as they are changed,
+// so they can be exported without walking the store
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct StoreStats {
+    pub blocks: i64,
+    pub tombstones: i64,
+    // length of the visible content
+    pub length: i64,
+}
+
+impl StoreStats {
+    fn add(&mut self, block: &Block) {
+        self.blocks += 1;
+        if block.is_deleted {
+            self.tombstones += 1;
+        } else {
+            self.length += block.content.len() as i64;
+        }
+    }
+
+    // the content of a deleted block is no longer visible
+    fn delete(&mut self, block: &Block) {
+        self.tombstones += 1;
+        self.length -= block.content.len() as i64;
+    }
+}
+
 // BlockStore is a collection of current blocks
 // 1. kvStore stores a mapping from client to the changes the client made
 // 2. totalStore stores the SPATIAL order of the blocks
@@ -33,6 +62,7 @@ pub struct BlockStore {
     pub block_map: HashMap<BlockID, BlockPtr>,
     pub kv_store: HashMap<ClientID, BlockList>,
     pub total_store: BlockList,
+    pub stats: StoreStats,
 }
 
 impl BlockStore {
@@ -41,7 +71,31 @@ impl BlockStore {
             block_map: HashMap::new(),
             kv_store: HashMap::new(),
             total_store: BlockList::new(),
+            stats: StoreStats::default(),
+        }
+    }
+
+    // Build a store from blocks in spatial order, without integrating them one by one
+    pub fn from_blocks(blocks: Vec<Block>) -> Self {
+        let mut store = BlockStore::new();
+        let mut by_client: HashMap<ClientID, Vec<(u32, BlockPtr)>> = HashMap::new();
+        for block in blocks {
+            store.stats.add(&block);
+            let (id, block_ptr) = (block.id.clone(), Arc::new(Mutex::new(block)));
+            store.total_store.list.push(block_ptr.clone());
+            store.block_map.insert(id.clone(), block_ptr.clone());
+            by_client
+                .entry(id.client)
+                .or_default()
+                .push((id.clock, block_ptr));
         }
+        // the blocks of a client are kept in clock order
+        for (client, mut blocks) in by_client {
+            blocks.sort_by_key(|(clock, _)| *clock);
+            let list = blocks.into_iter().map(|(_, block)| block).collect();
+            store.kv_store.insert(client, BlockList { list });
+        }
+        store
     }
 
     // Insert the new block to the position
@@ -53,6 +107,7 @@ impl BlockStore {
         // );
 
         let block_id = block.id.clone();
+        self.stats.add(&block);
         let block_ptr = Arc::new(Mutex::new(block));
 
         match left_id {
@@ -78,12 +133,163 @@ impl BlockStore {
         self.update_state(block_id, block_ptr.clone());
     }
 
-    // Delete the content of length len from pos
-    pub async fn delete(&mut self, block_id: BlockID) {
+    // Delete the content of length len from pos on behalf of by
+    pub async fn delete(&mut self, block_id: BlockID, by: Option<ClientID>) {
         let block = self.block_map.get_mut(&block_id);
         if let Some(block) = block {
             let mut block_lock = block.lock().await;
-            block_lock.delete();
+            if !block_lock.is_deleted {
+                self.stats.delete(&block_lock);
+            }
+            block_lock.delete_by(by);
+        }
+    }
+
+    // Delete the characters [id.clock, id.clock + len) inserted by id.client on behalf of by,
+    // as part of change if it is set, blocks are split so that only characters inside the range are deleted
+    //
+    // return false if none of the characters can be found
+    pub async fn delete_range(
+        &mut self,
+        id: BlockID,
+        len: u32,
+        by: Option<ClientID>,
+        change: Option<ChangeID>,
+    ) -> bool {
+        self.update_range(id, len, |block| {
+            block.delete_by(by);
+            if let Some(change) = change {
+                block.stamp(change);
+            }
+        })
+        .await
+    }
+
+    // Apply f to the characters [id.clock, id.clock + len) inserted by id.client,
+    // blocks are split so that only characters inside the range are changed
+    //
+    // return false if none of the characters can be found
+    pub async fn update_range<F>(&mut self, id: BlockID, len: u32, mut f: F) -> bool
+    where
+        F: FnMut(&mut Block),
+    {
+        let end = id.clock + len;
+        let mut found = false;
+
+        let mut i = 0;
+        while i < self.total_store.list.len() {
+            let (curr_id, curr_len) = {
+                let curr_lock = self.total_store.list[i].lock().await;
+                (curr_lock.id.clone(), curr_lock.content.len() as u32)
+            };
+            let curr_end = curr_id.clock + curr_len;
+            if curr_id.client != id.client || curr_end <= id.clock || curr_id.clock >= end {
+                i += 1;
+                continue;
+            }
+
+            found = true;
+            if curr_id.clock < id.clock {
+                // Keep the left part, the right part is checked in the next iteration
+                self.split(curr_id.clone(), id.clock - curr_id.clock).await;
+                i += 1;
+                continue;
+            }
+            if curr_end > end {
+                self.split(curr_id.clone(), end - curr_id.clock).await;
+            }
+            if let Some(block) = self.block_map.get(&curr_id) {
+                let mut block_lock = block.lock().await;
+                let was_deleted = block_lock.is_deleted;
+                f(&mut block_lock);
+                if !was_deleted && block_lock.is_deleted {
+                    self.stats.delete(&block_lock);
+                }
+            }
+            i += 1;
+        }
+        found
+    }
+
+    // Check if any character in [id.clock, id.clock + len) inserted by id.client exists
+    pub async fn contains(&self, id: &BlockID, len: u32) -> bool {
+        if let Some(list) = self.kv_store.get(&id.client) {
+            for block in &list.list {
+                let block_lock = block.lock().await;
+                let block_end = block_lock.id.clock + block_lock.content.len() as u32;
+                if block_lock.id.clock < id.clock + len.max(1) && id.clock < block_end {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    // Changes of updates that have been applied here,
+    // i.e. carried by every known block overlapping the block they were made to
+    pub async fn applied_changes(&self, updates: &Updates) -> Vec<ChangeID> {
+        let mut applied = HashSet::new();
+        let mut missing = HashSet::new();
+        for block in &self.total_store.list {
+            let block_lock = block.lock().await;
+            let start = block_lock.id.clock;
+            let end = start + block_lock.content.len() as u32;
+            for update in updates.iter().filter(|update| {
+                update.id.client == block_lock.id.client
+                    && update.id.clock < end
+                    && start < update.id.clock + update.content.len() as u32
+            }) {
+                for change in update.changes.iter() {
+                    if block_lock.changes.contains(change) {
+                        applied.insert(*change);
+                    } else {
+                        missing.insert(*change);
+                    }
+                }
+            }
+        }
+        applied.difference(&missing).copied().collect()
+    }
+
+    // Check that id is not inside a multi-byte character of a known block,
+    // i.e. the store can be split at id
+    pub async fn is_char_boundary(&self, id: &BlockID) -> bool {
+        for block in &self.total_store.list {
+            let block_lock = block.lock().await;
+            let content = block_lock.content.as_str();
+            if block_lock.id.client == id.client
+                && block_lock.id.clock <= id.clock
+                && id.clock < block_lock.id.clock + content.len() as u32
+            {
+                return content.is_char_boundary((id.clock - block_lock.id.clock) as usize);
+            }
+        }
+        true
+    }
+
+    // Split the block containing id so that the character at id is the last of a block,
+    // i.e. a block can be inserted right after id
+    pub async fn split_after(&mut self, id: &BlockID) {
+        let mut split = None;
+        if let Some(list) = self.kv_store.get(&id.client) {
+            for block in &list.list {
+                let block_lock = block.lock().await;
+                let content = block_lock.content.as_str();
+                let end = block_lock.id.clock + content.len() as u32;
+                if block_lock.id.clock <= id.clock && id.clock + 1 < end {
+                    let mut len = (id.clock + 1 - block_lock.id.clock) as usize;
+                    while !content.is_char_boundary(len) {
+                        len += 1;
+                    }
+                    if len < content.len() {
+                        split = Some((block_lock.id.clone(), len as u32));
+                    }
+                    break;
+                }
+            }
+        }
+        if let Some((start, len)) = split {
+            self.split(start, len).await;
         }
     }
 
@@ -110,16 +316,13 @@ impl BlockStore {
             let mut block_lock = block.lock().await;
 
             // It is impossible to split the block into a part
-            // that has a longer content than the original
-            if len > block_lock.content.content.len() {
-                return;
-            }
-
-            let left_content = Content {
-                content: String::from(&block_lock.content.content[..len]),
-            };
-            let right_content = Content {
-                content: String::from(&block_lock.content.content[len..]),
+            // that has a longer content than the original, and embeds are never split
+            let (left_content, right_content) = match &block_lock.content {
+                Content::Text(text) if len <= text.len() => (
+                    Content::Text(String::from(&text[..len])),
+                    Content::Text(String::from(&text[len..])),
+                ),
+                _ => return,
             };
 
             // Create a new block to hold right content
@@ -131,13 +334,24 @@ impl BlockStore {
                 id: right_block_id.clone(),
                 left_origin: Some(block_id.clone()),
                 right_origin: block_lock.right_origin.clone(),
-                is_deleted: false,
+                is_deleted: block_lock.is_deleted,
+                deleted_by: block_lock.deleted_by,
+                suggestion: block_lock.suggestion,
+                changes: block_lock.changes.clone(),
+                sealed: block_lock.sealed.clone(),
+                elided: block_lock.elided,
                 content: right_content,
             });
 
+            // the right part is counted again once it is inserted
+            if !block_lock.is_deleted {
+                self.stats.length -= (block_lock.content.len() - len) as i64;
+            }
+
             // Modify the left block
+            // (origins are kept as they were when the block was inserted,
+            // otherwise a peer that hasn't seen the block can never integrate either part)
             block_lock.content = left_content;
-            block_lock.right_origin = Some(right_block_id.clone());
         }
 
         if let Some(right_block) = right_block {
@@ -182,7 +396,7 @@ impl BlockStore {
             let middle_lock = self.total_store.list[middle].lock().await;
             (
                 block_id,
-                middle_lock.content.content.clone(),
+                middle_lock.content.as_str().to_string(),
                 middle_lock.right_origin.clone(),
             )
         };
@@ -206,7 +420,7 @@ impl BlockStore {
                 let left_lock = left_block.lock().await;
                 (
                     left_lock.id.clone(),
-                    left_lock.content.content.clone(),
+                    left_lock.content.as_str().to_string(),
                     left_lock.left_origin.clone(),
                     left_lock.is_deleted,
                 )
@@ -222,9 +436,7 @@ impl BlockStore {
                 {
                     let mut left_lock = left_block.lock().await;
                     left_content.push_str(&middle_content);
-                    left_lock.content = Content {
-                        content: left_content,
-                    };
+                    left_lock.content = Content::Text(left_content);
                     left_lock.right_origin = middle_right_origin;
                 }
 
@@ -239,7 +451,7 @@ impl BlockStore {
                     let right_lock = right_block.lock().await;
                     (
                         right_lock.id.clone(),
-                        right_lock.content.content.clone(),
+                        right_lock.content.as_str().to_string(),
                         right_lock.right_origin.clone(),
                         right_lock.is_deleted,
                     )
@@ -260,9 +472,7 @@ impl BlockStore {
                         let mut left_lock = left_block.lock().await;
                         left_content.push_str(&middle_content);
                         left_content.push_str(&right_content);
-                        left_lock.content = Content {
-                            content: left_content,
-                        };
+                        left_lock.content = Content::Text(left_content);
                         left_lock.left_origin = left_left_origin;
                         left_lock.right_origin = right_right_origin;
                     }
@@ -283,7 +493,7 @@ impl BlockStore {
                 let right_lock = right_block.lock().await;
                 (
                     right_lock.id.clone(),
-                    right_lock.content.content.clone(),
+                    right_lock.content.as_str().to_string(),
                     right_lock.right_origin.clone(),
                     right_lock.is_deleted,
                 )
@@ -293,9 +503,7 @@ impl BlockStore {
             {
                 let mut middle_lock = self.total_store.list[middle].lock().await;
                 middle_content.push_str(&right_content);
-                middle_lock.content = Content {
-                    content: middle_content,
-                };
+                middle_lock.content = Content::Text(middle_content);
                 middle_lock.right_origin = right_right_origin;
             }
 
@@ -304,6 +512,53 @@ impl BlockStore {
         }
     }
 
+    // Merge neighboring blocks that were split from the same insertion,
+    // i.e. the right block is exactly what split would have produced from the left block
+    //
+    // return the number of blocks removed
+    pub async fn compact(&mut self) -> usize {
+        let mut removed = 0;
+        let mut i = 0;
+        while i + 1 < self.total_store.list.len() {
+            let right = self.total_store.list[i + 1].lock().await.clone();
+            let mergeable = self.total_store.list[i].lock().await.merges_with(&right);
+            if !mergeable {
+                i += 1;
+                continue;
+            }
+
+            // Merge the right block into the left block, the next block is checked against it
+            {
+                let mut left_lock = self.total_store.list[i].lock().await;
+                left_lock.content.push_str(right.content.as_str());
+            }
+            self.remove_state(right.id).await;
+            removed += 1;
+        }
+        removed
+    }
+
+    // Drop the content of the tombstones whose insertion and deletion are both below stable,
+    // the clock every peer has reached, so no peer is sent them again
+    //
+    // the tombstones are kept (marked as elided) since later insertions can still use them as origins,
+    // return the number of tombstones collected
+    pub async fn collect_tombstones(&mut self, stable: &VectorClock) -> usize {
+        let mut collected = 0;
+        for block in &self.total_store.list {
+            let mut block_lock = block.lock().await;
+            if !block_lock.is_deleted || block_lock.elided || is_missing(&block_lock, stable) {
+                continue;
+            }
+            let len = block_lock.content.len();
+            block_lock.content = Content::Text(TOMBSTONE_FILLER.to_string().repeat(len));
+            block_lock.elided = true;
+            block_lock.sealed = None;
+            collected += 1;
+        }
+        collected
+    }
+
     // Form a string by connecting all elements in the current BlockList
     pub async fn to_string(&self) -> String {
         // println!(
@@ -317,7 +572,7 @@ impl BlockStore {
             if block_lock.is_deleted {
                 continue;
             }
-            res.push(block_lock.content.content.clone());
+            res.push(block_lock.content.as_str().to_string());
         }
         res.into_iter().collect()
     }
@@ -343,7 +598,8 @@ impl BlockStore {
         };
     }
 
-    // Remove state of the block from all BlockStore states
+    // Remove state of the block from all BlockStore states,
+    // its content has been merged into a neighbour so the length is unchanged
     async fn remove_state(&mut self, block_id: BlockID) {
         let mut i = 0;
         while i < self.total_store.list.len() {
@@ -352,7 +608,11 @@ impl BlockStore {
             }
             i += 1;
         }
-        self.total_store.list.remove(i);
+        let removed = self.total_store.list.remove(i);
+        self.stats.blocks -= 1;
+        if removed.lock().await.is_deleted {
+            self.stats.tombstones -= 1;
+        }
 
         self.block_map.remove(&block_id);
 
@@ -0,0 +1,468 @@
+use crate::crdt::auth::with_token;
+use crate::crdt::block::BlockID;
+use crate::crdt::doc::Doc;
+use crate::crdt::e2e::SealedText;
+use crate::crdt::metrics::metrics;
+use crate::crdt::sync_txn::SyncTransaction;
+use crate::crdt::txn_rpc::{self, txn_service_client::TxnServiceClient};
+use crate::crdt::utils::{CRDTResult, ClientID, Peer};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use tracing::{instrument, warn};
+
+// CommentID is the seq-th comment written by client
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CommentID {
+    pub client: ClientID,
+    pub seq: u32,
+}
+
+// Anchor is the text a comment is attached to, from the character start to the character end
+// (both included), so that it follows the text when other clients edit the doc
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Anchor {
+    pub start: BlockID,
+    pub end: BlockID,
+}
+
+// Resolution tells if a thread is resolved, the change with the largest (version, by) wins
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Resolution {
+    pub resolved: bool,
+    pub version: u32,
+    pub by: ClientID,
+}
+
+impl Resolution {
+    // resolution of a comment that has never been resolved or reopened
+    fn initial(author: ClientID) -> Self {
+        Resolution {
+            resolved: false,
+            version: 0,
+            by: author,
+        }
+    }
+
+    fn is_newer(&self, other: &Resolution) -> bool {
+        (self.version, self.by) > (other.version, other.by)
+    }
+}
+
+// Comment is written by id.client and never changes once written, except for its resolution,
+// a comment without parent starts a thread and is anchored to the text,
+// a reply answers its parent and belongs to the thread of the parent
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Comment {
+    pub id: CommentID,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<CommentID>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anchor: Option<Anchor>,
+    // empty while the text is sealed
+    pub text: String,
+    // only the resolution of the comment starting a thread is used
+    pub resolution: Resolution,
+    // set if the text is end-to-end encrypted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sealed: Option<SealedText>,
+}
+
+impl Comment {
+    pub fn author(&self) -> ClientID {
+        self.id.client
+    }
+}
+
+// Thread is a comment anchored to the text and its replies (a reply comes after the comment it answers),
+// range is the visible text [start, end) the comment is attached to,
+// None if the thread is orphaned (all of its text has been deleted)
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Thread {
+    pub comment: Comment,
+    pub range: Option<(u32, u32)>,
+    pub replies: Vec<Comment>,
+}
+
+impl Thread {
+    pub fn is_orphaned(&self) -> bool {
+        self.range.is_none()
+    }
+
+    pub fn is_resolved(&self) -> bool {
+        self.comment.resolution.resolved
+    }
+}
+
+// CommentClock tells which comments a doc has: the runs [start, end) of the seqs
+// of every client, and the resolution of every thread resolved or reopened
+//
+// peers exchange it so that only the comments the other side is missing are sent
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct CommentClock {
+    pub comments: HashMap<ClientID, Vec<(u32, u32)>>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub resolutions: HashMap<ClientID, HashMap<u32, Resolution>>,
+}
+
+impl CommentClock {
+    // check if the doc with this clock has comment and its resolution
+    pub fn knows(&self, comment: &Comment) -> bool {
+        let id = &comment.id;
+        let known = self.comments.get(&id.client).is_some_and(|runs| {
+            runs.iter()
+                .any(|(start, end)| *start <= id.seq && id.seq < *end)
+        });
+        let resolution = self
+            .resolutions
+            .get(&id.client)
+            .and_then(|resolutions| resolutions.get(&id.seq))
+            .cloned()
+            .unwrap_or(Resolution::initial(comment.author()));
+        known && !comment.resolution.is_newer(&resolution)
+    }
+}
+
+// Comments keeps every comment of the doc:
+// comments are only ever added and resolutions are merged by Resolution::is_newer,
+// so peers converge whatever order they exchange them in
+#[derive(Clone, Debug, Default)]
+pub struct Comments {
+    pub comments: HashMap<CommentID, Comment>,
+}
+
+impl Comments {
+    pub fn new() -> Self {
+        Comments {
+            comments: HashMap::new(),
+        }
+    }
+
+    // Id of the next comment of client
+    pub fn next_id(&self, client: ClientID) -> CommentID {
+        let seq = self
+            .comments
+            .keys()
+            .filter(|id| id.client == client)
+            .map(|id| id.seq + 1)
+            .max()
+            .unwrap_or(0);
+        CommentID { client, seq }
+    }
+
+    // Merge comments received from a peer
+    pub fn apply_remote(&mut self, comments: Vec<Comment>) {
+        for comment in comments {
+            match self.comments.get_mut(&comment.id) {
+                Some(local) => {
+                    if comment.resolution.is_newer(&local.resolution) {
+                        local.resolution = comment.resolution;
+                    }
+                }
+                None => {
+                    self.comments.insert(comment.id, comment);
+                }
+            }
+        }
+    }
+
+    // Comment starting the thread of id, None if a comment of the thread is missing
+    // or if the replies loop (parents are written before their replies, only forged comments loop)
+    pub fn thread_of(&self, id: &CommentID) -> Option<CommentID> {
+        let mut curr = self.comments.get(id)?;
+        let mut visited = HashSet::from([*id]);
+        while let Some(parent) = &curr.parent {
+            if !visited.insert(*parent) {
+                return None;
+            }
+            curr = self.comments.get(parent)?;
+        }
+        Some(curr.id)
+    }
+
+    // Resolve (or reopen) the thread of id on behalf of by, return false if it cannot be found
+    pub fn set_resolved(&mut self, id: &CommentID, resolved: bool, by: ClientID) -> bool {
+        let root = match self.thread_of(id) {
+            Some(root) => root,
+            None => return false,
+        };
+        let comment = self.comments.get_mut(&root).unwrap();
+        if comment.resolution.resolved != resolved {
+            comment.resolution = Resolution {
+                resolved,
+                version: comment.resolution.version + 1,
+                by,
+            };
+        }
+        true
+    }
+
+    pub fn get_comments(&self) -> Vec<Comment> {
+        let mut comments: Vec<Comment> = self.comments.values().cloned().collect();
+        comments.sort_unstable_by_key(|comment| comment.id);
+        comments
+    }
+
+    pub fn clock(&self) -> CommentClock {
+        let mut clock = CommentClock::default();
+        for comment in self.get_comments() {
+            let id = comment.id;
+            let runs = clock.comments.entry(id.client).or_default();
+            match runs.last_mut() {
+                Some((_, end)) if *end == id.seq => *end += 1,
+                _ => runs.push((id.seq, id.seq + 1)),
+            }
+            if comment.resolution != Resolution::initial(comment.author()) {
+                clock
+                    .resolutions
+                    .entry(id.client)
+                    .or_default()
+                    .insert(id.seq, comment.resolution);
+            }
+        }
+        clock
+    }
+
+    // Comments a doc with clock is missing, or whose resolution it is missing
+    pub fn missing_from(&self, clock: &CommentClock) -> Vec<Comment> {
+        self.get_comments()
+            .into_iter()
+            .filter(|comment| !clock.knows(comment))
+            .collect()
+    }
+}
+
+impl Doc {
+    // Comment the visible characters [pos, pos + len), return None if they are out of the doc
+    pub async fn add_comment(&mut self, pos: u32, len: u32, text: String) -> Option<CommentID> {
+        if len == 0 {
+            return None;
+        }
+        let start = self.id_at(pos).await?;
+        let end = self.id_at(pos + len - 1).await?;
+        let id = self.comments.next_id(self.client);
+        self.comments.comments.insert(
+            id,
+            Comment {
+                id,
+                parent: None,
+                anchor: Some(Anchor { start, end }),
+                text,
+                resolution: Resolution::initial(self.client),
+                sealed: None,
+            },
+        );
+        Some(id)
+    }
+
+    // Answer the comment parent, return None if it cannot be found
+    pub fn reply_to(&mut self, parent: &CommentID, text: String) -> Option<CommentID> {
+        if !self.comments.comments.contains_key(parent) {
+            return None;
+        }
+        let id = self.comments.next_id(self.client);
+        self.comments.comments.insert(
+            id,
+            Comment {
+                id,
+                parent: Some(*parent),
+                anchor: None,
+                text,
+                resolution: Resolution::initial(self.client),
+                sealed: None,
+            },
+        );
+        Some(id)
+    }
+
+    // Resolve (or reopen) the thread the comment id belongs to
+    pub fn resolve_comment(&mut self, id: &CommentID, resolved: bool) -> bool {
+        let client = self.client;
+        self.comments.set_resolved(id, resolved, client)
+    }
+
+    // Visible range [start, end) of the text anchor is attached to,
+    // None if all of it has been deleted or it hasn't been integrated yet
+    pub async fn anchor_range(&self, anchor: &Anchor) -> Option<(u32, u32)> {
+        let store = self.block_store.clone();
+        let store_lock = store.lock().await;
+
+        let mut range: Option<(u32, u32)> = None;
+        let mut pos = 0;
+        let mut inside = false;
+        for block in store_lock.total_store.list.iter() {
+            let block_lock = block.lock().await;
+            let clock = block_lock.id.clock;
+            let len = block_lock.content.len() as u32;
+            let contains = |id: &BlockID| {
+                id.client == block_lock.id.client && clock <= id.clock && id.clock < clock + len
+            };
+            let from = if !inside && contains(&anchor.start) {
+                inside = true;
+                anchor.start.clock - clock
+            } else {
+                0
+            };
+            if inside {
+                let last = contains(&anchor.end);
+                let to = if last {
+                    anchor.end.clock - clock + 1
+                } else {
+                    len
+                };
+                if !block_lock.is_deleted && from < to {
+                    range = match range {
+                        Some((start, _)) => Some((start, pos + to)),
+                        None => Some((pos + from, pos + to)),
+                    };
+                }
+                if last {
+                    return range;
+                }
+            }
+            if !block_lock.is_deleted {
+                pos += len;
+            }
+        }
+        None
+    }
+
+    // Threads of the doc ordered by where they are attached, orphaned threads come last,
+    // replies whose thread is incomplete are left out
+    pub async fn comment_threads(&self) -> Vec<Thread> {
+        let mut replies: HashMap<CommentID, Vec<Comment>> = HashMap::new();
+        let mut threads = vec![];
+        for comment in self.comments.get_comments() {
+            match (&comment.parent, &comment.anchor) {
+                (Some(parent), _) => replies.entry(*parent).or_default().push(comment),
+                (None, Some(anchor)) => {
+                    let range = self.anchor_range(anchor).await;
+                    threads.push(Thread {
+                        comment,
+                        range,
+                        replies: vec![],
+                    });
+                }
+                (None, None) => {}
+            }
+        }
+        for thread in threads.iter_mut() {
+            // depth first, so that a reply comes right after the comment it answers
+            let mut stack = vec![thread.comment.id];
+            while let Some(id) = stack.pop() {
+                let mut children = replies.remove(&id).unwrap_or_default();
+                if id != thread.comment.id {
+                    thread.replies.push(self.comments.comments[&id].clone());
+                }
+                children.reverse();
+                stack.extend(children.into_iter().map(|reply| reply.id));
+            }
+        }
+        threads.sort_by_key(|thread| (thread.range.is_none(), thread.range, thread.comment.id));
+        threads
+    }
+}
+
+impl SyncTransaction {
+    // push the comments of the doc to all peers and merge the comments they know of,
+    // only the comments a peer is missing (since the last sync with it) are sent both ways
+    //
+    // the first error is returned once all peers have been tried
+    #[instrument(skip_all, fields(doc = %self.doc_name, client = self.client))]
+    pub async fn sync_comments(&self) -> CRDTResult<()> {
+        let peers = {
+            let local_doc = self.doc.lock().await;
+            match &self.relay {
+                Some(relay) => vec![relay.clone()],
+                None => local_doc.peers.clone(),
+            }
+        };
+
+        let mut res = Ok(());
+        for peer in peers.into_iter() {
+            if peer.client_id == self.client {
+                continue;
+            }
+            let peer_res = self.exchange_comments(&peer).await;
+            if let Err(e) = peer_res {
+                warn!(peer = peer.client_id, error = %e, "failed to sync comments with peer");
+                if res.is_ok() {
+                    res = Err(e);
+                }
+            }
+        }
+        res
+    }
+
+    async fn exchange_comments(&self, peer: &Peer) -> CRDTResult<()> {
+        let peer_clock = self
+            .comment_clocks
+            .lock()
+            .await
+            .get(&peer.client_id)
+            .cloned()
+            .unwrap_or_default();
+        let (comments, known) = {
+            let local_doc = self.doc.lock().await;
+            let comments = local_doc.comments.missing_from(&peer_clock);
+            (
+                serde_json::to_string(&local_doc.encryption.seal_comments(comments))?,
+                serde_json::to_string(&local_doc.comments.clock())?,
+            )
+        };
+        let mut client = TxnServiceClient::new(self.connect(peer).await?);
+        let req = with_token(
+            txn_rpc::CommentsRequest {
+                client_id: self.client,
+                comments,
+                doc_name: self.doc_name.clone(),
+                known,
+            },
+            &self.token,
+        );
+        let sent = req.get_ref().encoded_len();
+        let resp = client.sync_comments(req).await?.into_inner();
+        metrics().observe_rpc("sync_comments", sent, resp.encoded_len());
+        let remote_comments: Vec<Comment> = serde_json::from_str(&resp.comments)?;
+        let remote_clock: CommentClock = serde_json::from_str(&resp.known).unwrap_or_default();
+        self.apply_comments(None, remote_comments).await;
+        self.comment_clocks
+            .lock()
+            .await
+            .insert(peer.client_id, remote_clock);
+        Ok(())
+    }
+
+    // merge comments of a peer, the ones written or resolved by clients
+    // that are not allowed to comment the doc are dropped
+    //
+    // if sender is the authenticated client that sent comments, it only adds its own comments
+    // and its own resolutions, whatever client they claim to come from
+    pub(crate) async fn apply_comments(&self, sender: Option<ClientID>, comments: Vec<Comment>) {
+        let mut local_doc = self.doc.lock().await;
+        let comments: Vec<Comment> = comments
+            .into_iter()
+            .filter_map(|mut comment| {
+                if let Some(sender) = sender {
+                    if comment.author() != sender
+                        && !local_doc.comments.comments.contains_key(&comment.id)
+                    {
+                        warn!(comment = ?comment.id, sender, "dropped comment not written by the sender");
+                        return None;
+                    }
+                    if comment.resolution.by != sender {
+                        comment.resolution = Resolution::initial(comment.author());
+                    }
+                }
+                let allowed =
+                    self.can_comment(comment.author()) && self.can_comment(comment.resolution.by);
+                if !allowed {
+                    warn!(comment = ?comment.id, "dropped comment from a client without comment access");
+                }
+                allowed.then_some(comment)
+            })
+            .collect();
+        let comments = local_doc.encryption.open_comments(comments);
+        local_doc.comments.apply_remote(comments);
+    }
+}
@@ -0,0 +1,243 @@
+use crate::crdt::auth::with_token;
+use crate::crdt::doc::VectorClock;
+use crate::crdt::sync_txn::SyncTransaction;
+use crate::crdt::tls::endpoint;
+use crate::crdt::txn_rpc::{self, txn_service_client::TxnServiceClient};
+use crate::crdt::utils::{CRDTError, CRDTResult, ClientID, Peer, Updates};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tonic::transport::Channel;
+use tonic::Request;
+use tracing::{debug, info, instrument, warn};
+
+// ConnConfig configures the channels to peers
+#[derive(Debug, Clone)]
+pub struct ConnConfig {
+    // a peer not reachable within connect_timeout is considered down
+    pub connect_timeout: Duration,
+    // deadline of a get_remote_updates call
+    pub pull_timeout: Duration,
+    // how often open channels are pinged while the rpc service runs (0 disables health checks)
+    pub health_interval: Duration,
+    // a peer that is down is reconnected after min_backoff * 2^(failures - 1), at most max_backoff
+    pub min_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for ConnConfig {
+    fn default() -> Self {
+        ConnConfig {
+            connect_timeout: Duration::from_secs(3),
+            pull_timeout: Duration::from_secs(10),
+            health_interval: Duration::from_secs(5),
+            min_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+// Connection is the channel to a peer, connected lazily on first use
+#[derive(Debug, Clone, Default)]
+pub struct Connection {
+    pub channel: Option<Channel>,
+    // address the channel has been opened to, a peer moving gets a new channel
+    pub ip_addr: String,
+    // number of consecutive failures
+    pub failures: u32,
+    // the peer is not reconnected before retry_at
+    pub retry_at: Option<Instant>,
+}
+
+// channels to the peers of a doc, shared by the transactions of a client
+pub type Connections = HashMap<ClientID, Connection>;
+
+impl Connection {
+    pub fn is_connected(&self) -> bool {
+        self.channel.is_some()
+    }
+
+    // forget the channel after a failure, the next connection waits for the backoff
+    pub fn failed(&mut self, config: &ConnConfig) -> Duration {
+        self.channel = None;
+        self.failures += 1;
+        let delay = config
+            .min_backoff
+            .saturating_mul(1 << (self.failures - 1).min(16))
+            .min(config.max_backoff);
+        self.retry_at = Some(Instant::now() + delay);
+        delay
+    }
+
+    fn connected(&mut self, channel: Channel) {
+        self.channel = Some(channel);
+        self.failures = 0;
+        self.retry_at = None;
+    }
+}
+
+impl SyncTransaction {
+    // channel to peer, connecting if there is no healthy channel to it,
+    // fails without connecting while the peer is backing off
+    //
+    // the channels are not locked while connecting, a peer that is slow to answer
+    // doesn't block the rpcs to the others
+    pub(crate) async fn connect(&self, peer: &Peer) -> CRDTResult<Channel> {
+        {
+            let mut channels = self.channels.lock().await;
+            let conn = channels.entry(peer.client_id).or_default();
+            if conn.ip_addr != peer.ip_addr {
+                // first connection, or the peer has moved
+                *conn = Connection {
+                    ip_addr: peer.ip_addr.clone(),
+                    ..Default::default()
+                };
+            }
+            if let Some(ch) = &conn.channel {
+                return Ok(ch.clone());
+            }
+            if let Some(retry_at) = conn.retry_at {
+                let now = Instant::now();
+                if now < retry_at {
+                    return Err(CRDTError::Transport(format!(
+                        "{} is down, retrying in {:?}",
+                        peer.client_id,
+                        retry_at - now
+                    )));
+                }
+            }
+        }
+        let ep = endpoint(&peer.ip_addr, &self.tls, peer.client_id)?
+            .connect_timeout(self.conn.connect_timeout);
+        let res = ep.connect().await;
+        let mut channels = self.channels.lock().await;
+        let conn = channels.entry(peer.client_id).or_default();
+        match res {
+            // the peer has moved (or left) while connecting
+            _ if conn.ip_addr != peer.ip_addr => Err(CRDTError::Transport(format!(
+                "{} moved while connecting",
+                peer.client_id
+            ))),
+            Ok(ch) => {
+                // another transaction connected first, its channel is shared
+                if let Some(ch) = &conn.channel {
+                    return Ok(ch.clone());
+                }
+                debug!(peer = peer.client_id, addr = %peer.ip_addr, "connected to peer");
+                conn.connected(ch.clone());
+                Ok(ch)
+            }
+            Err(e) => {
+                let delay = conn.failed(&self.conn);
+                debug!(peer = peer.client_id, failures = conn.failures, retry_in = ?delay, "failed to connect to peer");
+                Err(e.into())
+            }
+        }
+    }
+
+    // drop the channel to peer if an rpc failed in transport,
+    // the peer is reconnected after a backoff
+    pub(crate) async fn disconnect(&self, peer: ClientID, error: &CRDTError) {
+        if !matches!(error, CRDTError::Transport(_)) {
+            return;
+        }
+        let mut channels = self.channels.lock().await;
+        // a failed connection has already been dropped
+        if let Some(conn) = channels.get_mut(&peer).filter(|conn| conn.is_connected()) {
+            let delay = conn.failed(&self.conn);
+            warn!(peer, failures = conn.failures, retry_in = ?delay, error = %error, "dropped channel to peer");
+        }
+    }
+
+    // get_remote_updates of peer failing with a transport error once the pull timeout is reached
+    pub(crate) async fn pull_with_deadline(
+        &self,
+        peer: &Peer,
+        client: &mut TxnServiceClient<Channel>,
+        mut req: Request<txn_rpc::PullRequest>,
+    ) -> CRDTResult<txn_rpc::PullResponse> {
+        let deadline = self.conn.pull_timeout;
+        let is_relay = self
+            .relay
+            .as_ref()
+            .is_some_and(|relay| relay.client_id == peer.client_id);
+        // the peer stops working on the request once the deadline is reached
+        req.set_timeout(deadline);
+        match tokio::time::timeout(deadline, client.get_remote_updates(req)).await {
+            Ok(Ok(resp)) => Ok(resp.into_inner()),
+            // a relay that doesn't host the doc yet has seen nothing,
+            // a peer without the doc is an error
+            Ok(Err(e)) if is_relay && e.code() == tonic::Code::NotFound => {
+                Ok(txn_rpc::PullResponse {
+                    updates: serde_json::to_string(&Updates::new())?,
+                    vector_clock: serde_json::to_string(&VectorClock::new())?,
+                    ..Default::default()
+                })
+            }
+            Ok(Err(e)) => Err(e.into()),
+            Err(_) => Err(CRDTError::Transport(format!(
+                "get_remote_updates timed out after {:?}",
+                deadline
+            ))),
+        }
+    }
+
+    // ping every open channel, channels that don't answer within the connect timeout
+    // and channels to clients that are no longer peers of the doc are dropped,
+    // return the number of healthy channels
+    #[instrument(skip_all, fields(doc = %self.doc_name, client = self.client))]
+    pub async fn check_health(&self) -> usize {
+        let peers: Vec<ClientID> = {
+            let local_doc = self.doc.lock().await;
+            local_doc.peers.iter().map(|peer| peer.client_id).collect()
+        };
+        let open: Vec<(ClientID, Channel)> = {
+            let mut channels = self.channels.lock().await;
+            let relay = self.relay.as_ref().map(|relay| relay.client_id);
+            channels.retain(|client, _| peers.contains(client) || Some(*client) == relay);
+            channels
+                .iter()
+                .filter_map(|(client, conn)| Some((*client, conn.channel.clone()?)))
+                .collect()
+        };
+        // ping without holding the channels, a slow peer doesn't block the rpcs to the others
+        let mut healthy = 0;
+        for (client, channel) in open {
+            match self.ping(channel).await {
+                Ok(()) => healthy += 1,
+                Err(e) => {
+                    warn!(peer = client, error = %e, "peer failed health check");
+                    self.disconnect(client, &e).await;
+                }
+            }
+        }
+        self.observe().await;
+        healthy
+    }
+
+    async fn ping(&self, channel: Channel) -> CRDTResult<()> {
+        let mut client = TxnServiceClient::new(channel);
+        let mut req = with_token(
+            txn_rpc::PingRequest {
+                doc_name: self.doc_name.clone(),
+                client_id: self.client,
+            },
+            &self.token,
+        );
+        req.set_timeout(self.conn.connect_timeout);
+        match tokio::time::timeout(self.conn.connect_timeout, client.ping(req)).await {
+            Ok(resp) => resp.map(|_| ()).map_err(CRDTError::from),
+            Err(_) => Err(CRDTError::Transport("ping timed out".to_string())),
+        }
+    }
+
+    // check the health of the channels every health interval,
+    // until the task running it is aborted
+    pub async fn run_health_checks(self) {
+        info!(doc = %self.doc_name, client = self.client, interval = ?self.conn.health_interval, "health checks started");
+        let mut ticker = tokio::time::interval(self.conn.health_interval);
+        loop {
+            ticker.tick().await;
+            self.check_health().await;
+        }
+    }
+}
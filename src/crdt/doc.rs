@@ -1,23 +1,37 @@
+use crate::crdt::awareness::Awareness;
+use crate::crdt::block::ChangeID;
+use crate::crdt::comment::Comments;
+use crate::crdt::e2e::Encryption;
+use crate::crdt::metrics::metrics;
+use crate::crdt::signing::SignatureStore;
 use crate::crdt::utils::{ClientID, Peer, Updates};
 use crate::crdt::{block::Content, block_store::BlockStore, Block, BlockID};
-use std::cmp::min;
+use std::time::Instant;
 use std::{collections::HashMap, sync::Arc};
 use tokio::sync::Mutex;
+use tracing::{debug, instrument, trace, warn};
 
 // VectorClock represents the latest clocks of all clients,
 // it is used during synchronization to find the missing changes
+//
+// the clock of a client is the next clock the client will use,
+// i.e. every character the client inserted below that clock has been seen,
+// and the changes of a client is the next seq the client will use for a change
 
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::convert::FromWasmAbi;
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
 pub struct VectorClock {
     pub clock_map: HashMap<ClientID, u32>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub change_map: HashMap<ClientID, u32>,
 }
 
 impl VectorClock {
     pub fn new() -> VectorClock {
         VectorClock {
             clock_map: HashMap::new(),
+            change_map: HashMap::new(),
         }
     }
 
@@ -25,11 +39,47 @@ impl VectorClock {
         todo!()
     }
 
-    fn increment(&mut self, client: ClientID) {
-        self.clock_map.insert(
-            client,
-            self.clock_map.get(&client).cloned().unwrap_or(0) + 1,
-        );
+    pub fn get(&self, client: ClientID) -> u32 {
+        self.clock_map.get(&client).cloned().unwrap_or(0)
+    }
+
+    // Record that all clocks of client below clock have been seen
+    pub fn advance(&mut self, client: ClientID, clock: u32) {
+        if clock > self.get(client) {
+            self.clock_map.insert(client, clock);
+        }
+    }
+
+    pub fn changes(&self, client: ClientID) -> u32 {
+        self.change_map.get(&client).cloned().unwrap_or(0)
+    }
+
+    pub fn has_change(&self, change: &ChangeID) -> bool {
+        change.seq < self.changes(change.client)
+    }
+
+    // Clocks and changes that both self and other have seen
+    pub fn meet(&self, other: &VectorClock) -> VectorClock {
+        let mut res = VectorClock::new();
+        for (client, clock) in self.clock_map.iter() {
+            res.clock_map
+                .insert(*client, (*clock).min(other.get(*client)));
+        }
+        for (client, changes) in self.change_map.iter() {
+            res.change_map
+                .insert(*client, (*changes).min(other.changes(*client)));
+        }
+        res
+    }
+
+    // Record that the changes seen by other have been seen,
+    // only once every block other sent has been integrated
+    pub fn merge_changes(&mut self, other: &VectorClock) {
+        for (client, changes) in other.change_map.iter() {
+            if *changes > self.changes(*client) {
+                self.change_map.insert(*client, *changes);
+            }
+        }
     }
 }
 
@@ -50,6 +100,14 @@ pub struct Doc {
     // TODO: states: vector clock, pending updates, delete set, etc.
     pub vector_clock: VectorClock,
     pub latest_clock: Arc<Mutex<Option<u32>>>, // Largest clock that has been synchronized
+    // cursors of all clients editing the doc (not part of the CRDT)
+    pub awareness: Awareness,
+    // comments anchored to the text, synchronized separately from the blocks
+    pub comments: Comments,
+    // signatures of insertions, used to check the author of remote blocks
+    pub signatures: SignatureStore,
+    // encrypts content leaving the doc (end-to-end encryption is off by default)
+    pub encryption: Encryption,
 }
 
 impl Doc {
@@ -60,21 +118,38 @@ impl Doc {
             block_store: Arc::new(Mutex::new(BlockStore::new())),
             peers: vec![],
             pending_updates: vec![],
-            vector_clock: VectorClock {
-                clock_map: HashMap::new(),
-            },
+            vector_clock: VectorClock::new(),
             latest_clock: Arc::new(Mutex::new(None)),
+            awareness: Awareness::new(),
+            comments: Comments::new(),
+            signatures: SignatureStore::new(),
+            encryption: Encryption::new(),
         }
     }
 
     /* Local operations */
     // TODO: local operations should also grab mutex of the whole doc (as in SyncTransaction) to avoid concurrency issue
+    #[instrument(level = "debug", skip_all, fields(doc = %self.name, client = self.client, updates = update.len()))]
     pub async fn insert_remote(&mut self, update: Updates) {
+        let mut update = self.encryption.decrypt(update);
+        // the content of an elided block is filler, only a tombstone may be sent without it
+        update.retain(|block| {
+            let filler = block.elided && !block.is_deleted;
+            if filler {
+                warn!(block = ?block.id, "dropped live block without content");
+            }
+            !filler
+        });
         for block in update.iter() {
             // Try insert pending updates
             self.flush_pending_updates().await; // TODO: flush every time an insersion happens? Is it possible that current insersion and remote update interleave?
                                                 // Try insert current updates
+            let start = Instant::now();
             let success = self.insert_single_block(block).await;
+            metrics()
+                .integration_time
+                .with_label_values(&[&self.name])
+                .observe(start.elapsed().as_secs_f64());
             if !success {
                 self.pending_updates.push(block.clone());
             } else {
@@ -84,8 +159,42 @@ impl Doc {
     }
 
     pub async fn insert_single_block(&mut self, block: &Block) -> bool {
-        println!("insert single block");
+        trace!(block = ?block.id, "integrating block");
         // Try insert, return false if failed, return true if success
+        let len = block.content.len() as u32;
+
+        // such a block can never be integrated, so it is dropped instead of kept pending
+        if !self.on_char_boundaries(block).await {
+            warn!(block = ?block.id, "dropped block that splits a character");
+            return true;
+        }
+
+        // The block has been integrated before (maybe split differently),
+        // only its deletion, suggestion and the changes that made them can be new
+        let known = {
+            let store_lock = self.block_store.lock().await;
+            store_lock.contains(&block.id, len).await
+        };
+        if known {
+            if block.is_deleted || block.suggestion.is_some() || !block.changes.is_empty() {
+                let mut store_lock = self.block_store.lock().await;
+                store_lock
+                    .update_range(block.id.clone(), len, |known| {
+                        if let Some(suggestion) = block.suggestion {
+                            known.suggest(suggestion);
+                        }
+                        if block.is_deleted {
+                            known.delete_by(block.deleted_by);
+                        }
+                        for change in block.changes.iter() {
+                            known.stamp(*change);
+                        }
+                    })
+                    .await;
+            }
+            return true;
+        }
+
         // First find the block corresponding the left_origin and right_origin
 
         let left_res = self
@@ -161,6 +270,8 @@ impl Doc {
                     continue;
                 }
             } else {
+                // curr is inserted after a block between left and curr, keep scanning
+                i += 1;
                 continue;
             }
         }
@@ -176,38 +287,56 @@ impl Doc {
             left_id = Some(dest_lock.id.clone());
         }
         store_lock.insert(new_block, left_id).await;
+        self.vector_clock
+            .advance(block.id.client, block.id.clock + len);
         true
     }
 
+    // Delete all characters covered by block, return false if none of them has been integrated
     pub async fn delete_single_block(&mut self, block: &Block) -> bool {
+        if !self.on_char_boundaries(block).await {
+            warn!(block = ?block.id, "dropped deletion that splits a character");
+            return true;
+        }
         let store = self.block_store.clone();
         let mut store_lock = store.lock().await;
+        let len = block.content.len() as u32;
+        store_lock
+            .update_range(block.id.clone(), len, |known| {
+                known.delete_by(block.deleted_by);
+                for change in block.changes.iter() {
+                    known.stamp(*change);
+                }
+            })
+            .await
+    }
 
-        let mut i = 0;
-        let id = block.id.clone();
-        while i < store_lock.total_store.list.len() {
-            let (curr_id, curr_content) = {
-                let curr_lock = store_lock.total_store.list[i].lock().await;
-                (curr_lock.id.clone(), curr_lock.content.clone())
-            };
-
-            if curr_id == id {
-                store_lock.delete(block.id.clone()).await;
-                return true;
-            } else if curr_id.client == id.client
-                && curr_id.clock < id.clock
-                && curr_id.clock + curr_content.content.len() as u32 - 1 >= id.clock
-            {
-                let block_to_split = curr_id.clone();
-                let len = id.clock - curr_id.clock;
-                store_lock.split(block_to_split, len).await;
-                store_lock.delete(block.id.clone()).await;
-                return true;
+    // Check that the range and origins of a remote block don't fall inside a character
+    // of a known block, splitting the store there would cut the character in two
+    async fn on_char_boundaries(&self, block: &Block) -> bool {
+        let end = BlockID::new(block.id.client, block.id.clock + block.content.len() as u32);
+        let store_lock = self.block_store.lock().await;
+        let ids = [
+            Some(&block.id),
+            Some(&end),
+            block.left_origin.as_ref(),
+            block.right_origin.as_ref(),
+        ];
+        for id in ids.into_iter().flatten() {
+            if !store_lock.is_char_boundary(id).await {
+                return false;
             }
-            i += 1;
         }
+        true
+    }
 
-        return false;
+    // Id of a new change made by the local client
+    pub(crate) fn next_change(&mut self) -> ChangeID {
+        let change = ChangeID::new(self.client, self.vector_clock.changes(self.client));
+        self.vector_clock
+            .change_map
+            .insert(self.client, change.seq + 1);
+        change
     }
 
     async fn find_block_idx(
@@ -220,7 +349,8 @@ impl Doc {
         let mut store_lock = store.lock().await;
         match block_id {
             Some(id) => {
-                let mut i: usize = start_idx as usize;
+                // start_idx is -1 if the left origin is the start of the doc
+                let mut i: usize = start_idx.max(0) as usize;
                 while i < store_lock.total_store.list.len() {
                     let (curr_id, curr_content) = {
                         let curr_lock = store_lock.total_store.list[i].lock().await;
@@ -231,7 +361,7 @@ impl Doc {
                         return Ok(i as i64);
                     } else if curr_id.client == id.client
                         && curr_id.clock < id.clock
-                        && curr_id.clock + curr_content.content.len() as u32 - 1 >= id.clock
+                        && curr_id.clock + curr_content.len() as u32 - 1 >= id.clock
                     {
                         let block_to_split = curr_id.clone();
                         let len = id.clock - curr_id.clock;
@@ -255,7 +385,7 @@ impl Doc {
     // Insert the content into pos in BlockStore
     // TODO: Arc<Mutex<BlockList>>
     pub async fn insert_local(&mut self, content: Content, pos: u32) {
-        println!("INsert - local, content: {:?}, pos: {}", &content, &pos);
+        trace!(pos, len = content.len(), "local insertion");
         let store = self.block_store.clone();
         let mut store_lock = store.lock().await;
 
@@ -271,23 +401,14 @@ impl Doc {
             };
 
             if !curr_is_deleted {
-                prev_char_cnt += curr_content.content.len() as u32;
+                prev_char_cnt += curr_content.len() as u32;
             }
             idx += 1;
         }
 
-        let new_block_clk;
-        if store_lock.kv_store.contains_key(&self.client) {
-            let list = &store_lock.kv_store.get(&self.client).unwrap().list;
-            if list.len() == 0 {
-                new_block_clk = 0;
-            } else {
-                let last_lock = list.last().unwrap().lock().await;
-                new_block_clk = last_lock.id.clock + last_lock.content.content.len() as u32;
-            }
-        } else {
-            new_block_clk = 0;
-        }
+        // The last block in kv_store may be the right half of a split,
+        // so the next clock has to come from the vector clock
+        let new_block_clk = self.vector_clock.get(self.client);
 
         // Create a new block and insert it to total_store
         let new_block_id = BlockID {
@@ -299,6 +420,11 @@ impl Doc {
             left_origin: None,
             right_origin: None,
             is_deleted: false,
+            deleted_by: None,
+            suggestion: None,
+            changes: vec![],
+            sealed: None,
+            elided: false,
             content: content.clone(),
         };
 
@@ -337,7 +463,7 @@ impl Doc {
             store_lock.insert(new_block, left_id).await;
         } else {
             // Have to split total_store[i-1]
-            let left_content_len = left_content.content.len() as u32;
+            let left_content_len = left_content.len() as u32;
             new_block.left_origin = left_id.clone();
             new_block.right_origin = Some(BlockID::new(
                 left_id.clone().unwrap().client,
@@ -354,12 +480,35 @@ impl Doc {
             store_lock.insert(new_block, left_id).await;
         }
 
+        // the origins the block was integrated with are signed along with its content
+        let (left_origin, right_origin) = {
+            let block = store_lock.block_map[&new_block_id].lock().await;
+            (block.left_origin.clone(), block.right_origin.clone())
+        };
+
         // Squash neighboring blocks
         let latest_clock = self.latest_clock.lock().await;
         // store_lock.squash(new_block_id, *latest_clock).await;
 
         // Update vector clock
-        self.vector_clock.increment(self.client);
+        self.vector_clock
+            .advance(self.client, new_block_clk + content.len() as u32);
+        // signatures cover the sealed insertion and embed, so nodes without the key can verify them
+        self.encryption.adopt(self.client, new_block_clk);
+        let sealed = self.encryption.seal(&new_block_id, content.as_str());
+        let embed = content.embed().map(|embed| {
+            self.encryption
+                .seal_embed(&new_block_id, embed)
+                .unwrap_or_else(|| embed.clone())
+        });
+        self.signatures.sign(
+            &new_block_id,
+            left_origin,
+            right_origin,
+            content.as_str(),
+            embed.as_ref().map(|embed| embed.encode()),
+            sealed,
+        );
     }
 
     // Delete the content of length len from pos
@@ -370,173 +519,247 @@ impl Doc {
             // Try insert current updates
             let success = self.delete_single_block(block).await;
             if !success {
-                println!("delete failed");
+                debug!(block = ?block.id, "deleted block is not integrated yet");
                 self.pending_updates.push(block.clone());
             }
         }
     }
 
+    // Retry pending updates until none of them can be integrated,
+    // a deleted block that has never been seen is integrated as a tombstone
+    #[instrument(level = "debug", skip_all, fields(doc = %self.name, pending = self.pending_updates.len()))]
     async fn flush_pending_updates(&mut self) {
-        let mut new_pending = vec![];
-        for pending in self.pending_updates.clone().iter() {
-            let success;
-            if pending.is_deleted {
-                success = self.delete_single_block(pending).await;
-            } else {
-                success = self.insert_single_block(pending).await;
+        if self.pending_updates.is_empty() {
+            return;
+        }
+        let before = self.pending_updates.len();
+        loop {
+            let mut progress = false;
+            let mut new_pending = vec![];
+            for pending in self.pending_updates.clone().iter() {
+                if self.insert_single_block(pending).await {
+                    progress = true;
+                } else {
+                    new_pending.push(pending.clone());
+                }
             }
-            if !success {
-                new_pending.push(pending.clone()); // TODO: does it take effect?
+            self.pending_updates = new_pending;
+            if !progress {
+                break;
             }
         }
+        if self.pending_updates.len() < before {
+            debug!(
+                integrated = before - self.pending_updates.len(),
+                remaining = self.pending_updates.len(),
+                "flushed pending updates"
+            );
+        }
     }
 
+    // Delete the content of length len from pos, as one change of the local client
     pub async fn delete_local(&mut self, pos: u32, len: u32) {
+        let runs = self.visible_runs(pos, len).await;
+        if runs.is_empty() {
+            return;
+        }
+        let change = self.next_change();
         let store = self.block_store.clone();
         let mut store_lock = store.lock().await;
-
-        // Pos out of range, no effect
-        let doc_len = store_lock.to_string().await.len() as u32;
-        if pos >= doc_len {
-            return;
+        for (id, len) in runs {
+            store_lock
+                .delete_range(id.clone(), len, Some(self.client), Some(change))
+                .await;
+            self.signatures.sign_delete(&id, len, self.client);
         }
+    }
+
+    pub async fn to_string(&self) -> String {
+        let store = self.block_store.clone();
+        let store_lock = store.lock().await;
+        store_lock.to_string().await
+    }
 
-        // Find the correct blocks to delete
-        // The block may need to be splitted
-        // TODO: empty doc -> raise error
-        let mut i_start = 0 as usize;
-        let mut curr_start = -1;
+    // Find all blocks that a peer with vector clock remote_clocks hasn't seen,
+    // or that have been changed by changes it hasn't seen
+    // (a tombstone without changes comes from a peer that doesn't record them and is always included)
+    //
+    // blocks are returned in spatial order so that their origins are likely to be integrated first
+    // content is encrypted if end-to-end encryption is enabled
+    pub async fn compute_diff(&self, remote_clocks: &VectorClock) -> Updates {
+        let store = self.block_store.clone();
+        let store_lock = store.lock().await;
 
-        loop {
-            if i_start >= store_lock.total_store.list.len() {
-                break;
+        let mut res: Updates = vec![];
+        for block in &store_lock.total_store.list {
+            let block_lock = block.lock().await;
+            if is_missing(&block_lock, remote_clocks) {
+                res.push(block_lock.clone());
             }
+        }
+        self.encryption.encrypt(res)
+    }
 
-            // TODO: what if pos < curr_left and the loop already break?
-            let (curr_is_deleted, curr_content) = {
-                let curr_lock = store_lock.total_store.list[i_start].lock().await;
-                (curr_lock.is_deleted, curr_lock.content.clone())
-            };
+    // Same as compute_diff, but blocks are scanned in (client, clock) order from the one
+    // right after after (from the first one if None) and at most limit blocks are returned
+    //
+    // ids don't move when the doc is edited between pages, unlike spatial positions,
+    // so no block is skipped: blocks inserted since the first page are in the next diff
+    //
+    // also return the id the next page starts after, None if the whole diff has been returned
+    pub async fn compute_diff_page(
+        &self,
+        remote_clocks: &VectorClock,
+        after: Option<BlockID>,
+        limit: usize,
+    ) -> (Updates, Option<BlockID>) {
+        let store = self.block_store.clone();
+        let store_lock = store.lock().await;
 
-            if !curr_is_deleted {
-                curr_start += curr_content.content.len() as i32;
-            }
-            if curr_start < pos as i32 {
-                i_start += 1;
-            } else {
+        let mut ids: Vec<&BlockID> = store_lock
+            .block_map
+            .keys()
+            .filter(|id| after.as_ref().is_none_or(|after| *id > after))
+            .collect();
+        ids.sort_unstable();
+        let mut res: Updates = vec![];
+        let mut next = None;
+        for id in ids {
+            if res.len() >= limit {
                 break;
             }
+            let block_lock = store_lock.block_map[id].lock().await;
+            if is_missing(&block_lock, remote_clocks) {
+                res.push(block_lock.clone());
+            }
+            next = Some(id.clone());
         }
-        // println!("-------Current start is : {:?}", i_start);
+        if res.len() < limit {
+            next = None;
+        }
+        (self.encryption.encrypt(res), next)
+    }
 
-        let mut i_end = i_start;
-        let mut curr_end = curr_start;
-        let pos_end = min(pos + len - 1, doc_len - 1);
-        loop {
-            if i_end >= store_lock.total_store.list.len() {
-                break;
+    // Find the id of the character at pos (only visible characters are counted)
+    pub async fn id_at(&self, pos: u32) -> Option<BlockID> {
+        let store = self.block_store.clone();
+        let store_lock = store.lock().await;
+
+        let mut start = 0;
+        for block in &store_lock.total_store.list {
+            let block_lock = block.lock().await;
+            if block_lock.is_deleted {
+                continue;
             }
+            let len = block_lock.content.len() as u32;
+            if pos < start + len {
+                return Some(BlockID::new(
+                    block_lock.id.client,
+                    block_lock.id.clock + (pos - start),
+                ));
+            }
+            start += len;
+        }
+        None
+    }
 
-            let (curr_is_deleted, curr_content) = {
-                let curr_lock = store_lock.total_store.list[i_end].lock().await;
-                (curr_lock.is_deleted, curr_lock.content.clone())
-            };
+    // Visible characters [pos, pos + len) as runs of (id of the first character, length),
+    // one per block so that every run can be deleted or marked on its own
+    pub(crate) async fn visible_runs(&self, pos: u32, len: u32) -> Vec<(BlockID, u32)> {
+        let store = self.block_store.clone();
+        let store_lock = store.lock().await;
 
-            if curr_end < pos_end as i32 {
-                i_end += 1;
-            } else {
-                break;
+        let mut runs = vec![];
+        let end = pos + len;
+        let mut start = 0;
+        for block in &store_lock.total_store.list {
+            let block_lock = block.lock().await;
+            if block_lock.is_deleted {
+                continue;
             }
-            if !curr_is_deleted {
-                curr_end += curr_content.content.len() as i32;
+            let block_len = block_lock.content.len() as u32;
+            let (from, to) = (start.max(pos), (start + block_len).min(end));
+            if from < to {
+                let id = BlockID::new(block_lock.id.client, block_lock.id.clock + from - start);
+                runs.push((id, to - from));
             }
+            start += block_len;
         }
-        // println!("-------Current end is : {:?}", i_end);
+        runs
+    }
 
-        // Delete all blocks in (i_start, i_end) directly
-        // Delete i_start and i_end according to the position
-        let (start_id, start_content) = {
-            let start_lock = store_lock.total_store.list[i_start].lock().await;
-            (start_lock.id.clone(), start_lock.content.clone())
-        };
-        let (end_id, end_content) = {
-            let end_lock = store_lock.total_store.list[i_end].lock().await;
-            (end_lock.id.clone(), end_lock.content.clone())
-        };
+    // Find the position of the character with id,
+    // a deleted character is mapped to the position it used to occupy
+    pub async fn pos_of(&self, id: &BlockID) -> Option<u32> {
+        self.locate(id).await.map(|(pos, _)| pos)
+    }
 
-        if i_start == i_end {
-            // All texts to be deleted are in the same block
-            let block_id = start_id.clone();
-            let length = start_content.content.len() as u32;
-            // split into three Blocks
-            // the middle one will be deleted
-            let left_length = length - (curr_start as u32 - pos + 1); // TODO: ?
-            let new_block_id;
-            if left_length != 0 {
-                store_lock.split(block_id.clone(), left_length).await;
-                new_block_id = BlockID::new(
-                    block_id.client.clone(),
-                    block_id.clock.clone() + left_length,
-                );
-            } else {
-                new_block_id = block_id.clone();
-            }
-            // println!("----- Block id: {:?}", block_id);
-            // println!("----- NEW Block id: {:?}", new_blockID);
+    // Find the position of the character with id and whether it is deleted
+    async fn locate(&self, id: &BlockID) -> Option<(u32, bool)> {
+        let store = self.block_store.clone();
+        let store_lock = store.lock().await;
 
-            if pos_end as i32 == curr_start {
-                store_lock.delete(new_block_id).await;
-            } else {
-                let mid_length = len;
-                store_lock.split(new_block_id.clone(), mid_length).await;
-                store_lock.delete(new_block_id).await;
+        let mut start = 0;
+        for block in &store_lock.total_store.list {
+            let block_lock = block.lock().await;
+            let len = block_lock.content.len() as u32;
+            if block_lock.id.client == id.client
+                && block_lock.id.clock <= id.clock
+                && id.clock < block_lock.id.clock + len
+            {
+                if block_lock.is_deleted {
+                    return Some((start, true));
+                }
+                return Some((start + (id.clock - block_lock.id.clock), false));
             }
-        } else {
-            // Delete all blocks in between
-            let i = i_start + 1;
-            while i < i_end {
-                let curr_id = {
-                    let curr_lock = store_lock.total_store.list[i].lock().await;
-                    curr_lock.id.clone()
-                };
-
-                let block_id = curr_id.clone();
-                store_lock.delete(block_id).await;
+            if !block_lock.is_deleted {
+                start += len;
             }
+        }
+        None
+    }
 
-            // Delete left blocks
-            let length_start = start_content.content.len() as u32;
-            let block_id_start = start_id.clone();
-            let left_length = length_start - (curr_start as u32 - pos + 1);
-            let new_block_id;
-            if left_length != 0 {
-                store_lock.split(block_id_start.clone(), left_length).await;
-                new_block_id = BlockID::new(
-                    block_id_start.client.clone(),
-                    block_id_start.clock.clone() + left_length,
-                );
+    // Move the local cursor to pos
+    pub async fn set_cursor(&mut self, pos: u32) {
+        let anchor = {
+            if pos == 0 {
+                None
             } else {
-                new_block_id = block_id_start.clone();
+                self.id_at(pos - 1).await
             }
-            store_lock.delete(new_block_id).await;
-
-            // Delete right blocks
-            let length_end = end_content.content.len() as u32;
-            let block_id_end = end_id.clone();
-            let right_length = length_end - (curr_end as u32 - pos_end);
-            if curr_end != pos_end as i32 {
-                store_lock.split(block_id_end.clone(), right_length).await;
+        };
+        self.awareness.set_cursor(self.client, anchor);
+    }
+
+    // Resolve the cursors of all clients to positions in the current doc
+    pub async fn cursor_positions(&self) -> HashMap<ClientID, u32> {
+        let mut res = HashMap::new();
+        for state in self.awareness.get_states() {
+            let pos = match &state.anchor {
+                None => Some(0),
+                Some(anchor) => match self.locate(anchor).await {
+                    // the cursor sits right after its anchor
+                    Some((pos, false)) => Some(pos + 1),
+                    Some((pos, true)) => Some(pos),
+                    None => None,
+                },
+            };
+            if let Some(pos) = pos {
+                res.insert(state.client_id, pos);
             }
-            store_lock.delete(block_id_end).await;
         }
-
-        // Update vector clock
-        self.vector_clock.increment(self.client);
+        res
     }
+}
 
-    pub async fn to_string(&self) -> String {
-        let store = self.block_store.clone();
-        let store_lock = store.lock().await;
-        store_lock.to_string().await
-    }
+// Check if a peer with vector clock remote_clocks is missing the insertion of block
+// or one of the changes made to it
+pub(crate) fn is_missing(block: &Block, remote_clocks: &VectorClock) -> bool {
+    let end = block.id.clock + block.content.len() as u32;
+    end > remote_clocks.get(block.id.client)
+        || (block.is_deleted && block.changes.is_empty())
+        || block
+            .changes
+            .iter()
+            .any(|change| !remote_clocks.has_change(change))
 }
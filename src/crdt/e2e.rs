@@ -0,0 +1,503 @@
+use crate::crdt::block::{Block, BlockID, Content};
+use crate::crdt::comment::{Comment, CommentID};
+use crate::crdt::embed::{Embed, Payload, SEALED_EMBED};
+use crate::crdt::snapshot::TOMBSTONE_FILLER;
+use crate::crdt::utils::{CRDTResult, ClientID, Updates};
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use tracing::warn;
+
+// content of sealed blocks outside of the docs that can open them,
+// it only keeps the length of the text so the blocks can be merged and split as usual
+pub const SEALED_FILLER: char = '*';
+
+// KeyRing holds every version of the symmetric key of a doc,
+// new content is encrypted with the current version
+//
+// old versions are kept so content written before a rotation can still be read,
+// peers removed before a rotation never get the new version
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct KeyRing {
+    // hex encoded 32 byte keys by version
+    pub keys: BTreeMap<u32, String>,
+    pub current: u32,
+}
+
+impl KeyRing {
+    pub fn generate() -> Self {
+        let mut keys = BTreeMap::new();
+        keys.insert(0, hex::encode(rand::random::<[u8; 32]>()));
+        KeyRing { keys, current: 0 }
+    }
+
+    // Load the key ring from path, a new one is generated and saved if the file doesn't exist
+    pub fn from_file(path: &str) -> CRDTResult<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Ok(serde_json::from_str(&content)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let keys = KeyRing::generate();
+                keys.save(path)?;
+                Ok(keys)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> CRDTResult<()> {
+        std::fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    // Add a new version of the key and make it current
+    pub fn rotate(&mut self) -> u32 {
+        let version = self.keys.keys().next_back().map_or(0, |v| v + 1);
+        self.keys
+            .insert(version, hex::encode(rand::random::<[u8; 32]>()));
+        self.current = version;
+        version
+    }
+
+    // Add the versions only known by other
+    pub fn merge(&mut self, other: KeyRing) {
+        for (version, key) in other.keys {
+            self.keys.entry(version).or_insert(key);
+        }
+        self.current = self.current.max(other.current);
+    }
+}
+
+// KeyEpochs records the clock from which every client encrypts with a key version,
+// i.e. epochs[client] maps a clock to the version used from that clock on
+//
+// it is sent together with updates, so readers know which version to decrypt with
+pub type KeyEpochs = HashMap<ClientID, BTreeMap<u32, u32>>;
+
+// Sealed is an insertion encrypted with ChaCha20-Poly1305 under the key version of its epoch,
+// the nonce is derived from the id of its first character, which is never reused
+//
+// every block split from the insertion carries the whole of it
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Sealed {
+    pub id: BlockID,
+    #[serde(serialize_with = "to_hex", deserialize_with = "from_hex")]
+    pub ciphertext: Vec<u8>,
+}
+
+impl Sealed {
+    // length of the text of the insertion
+    pub fn text_len(&self) -> u32 {
+        self.ciphertext
+            .len()
+            .saturating_sub(CHACHA20_POLY1305.tag_len()) as u32
+    }
+
+    // check if the character at id belongs to the insertion
+    pub fn covers(&self, id: &BlockID) -> bool {
+        id.client == self.id.client
+            && self.id.clock <= id.clock
+            && id.clock < self.id.clock + self.text_len()
+    }
+}
+
+// SealedText is the text of a comment encrypted with ChaCha20-Poly1305 under key version,
+// the nonce is derived from the id of the comment (whose text never changes)
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct SealedText {
+    pub version: u32,
+    #[serde(serialize_with = "to_hex", deserialize_with = "from_hex")]
+    pub ciphertext: Vec<u8>,
+}
+
+pub fn to_hex<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hex::encode(bytes))
+}
+
+pub fn from_hex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let encoded = String::deserialize(deserializer)?;
+    hex::decode(encoded).map_err(serde::de::Error::custom)
+}
+
+fn nonce(id: &BlockID) -> Nonce {
+    let mut nonce = [0u8; aead::NONCE_LEN];
+    nonce[..4].copy_from_slice(&id.client.to_be_bytes());
+    nonce[4..8].copy_from_slice(&id.clock.to_be_bytes());
+    Nonce::assume_unique_for_key(nonce)
+}
+
+// the last byte keeps the nonces of comments apart from the ones of insertions
+fn comment_nonce(id: &CommentID) -> Nonce {
+    let mut nonce = [0u8; aead::NONCE_LEN];
+    nonce[..4].copy_from_slice(&id.client.to_be_bytes());
+    nonce[4..8].copy_from_slice(&id.seq.to_be_bytes());
+    nonce[aead::NONCE_LEN - 1] = 1;
+    Nonce::assume_unique_for_key(nonce)
+}
+
+// the last byte keeps the nonces of embeds apart from the ones of insertions and comments
+fn embed_nonce(id: &BlockID) -> Nonce {
+    let mut nonce = [0u8; aead::NONCE_LEN];
+    nonce[..4].copy_from_slice(&id.client.to_be_bytes());
+    nonce[4..8].copy_from_slice(&id.clock.to_be_bytes());
+    nonce[aead::NONCE_LEN - 1] = 2;
+    Nonce::assume_unique_for_key(nonce)
+}
+
+// Encryption seals the insertions of the local client and opens incoming ones,
+// ids, origins and deletion flags stay in the clear so any node can merge and relay
+//
+// blocks leaving the doc carry the sealed insertion they belong to and SEALED_FILLER
+// as content (an embed its sealed embed), a block is opened by slicing its characters out of the insertion
+//
+// content written by a client without an epoch is not encrypted
+#[derive(Clone, Default)]
+pub struct Encryption {
+    pub enabled: bool,
+    pub keys: KeyRing,
+    pub epochs: KeyEpochs,
+    // versions referenced by epochs but missing from keys
+    pub missing: HashSet<u32>,
+    // sealed insertions of every client by clock, to send along with their blocks
+    sealed: HashMap<ClientID, BTreeMap<u32, Sealed>>,
+}
+
+impl Encryption {
+    pub fn new() -> Self {
+        Encryption::default()
+    }
+
+    // Turn on encryption, keys can be None until they are received from a peer
+    pub fn enable(&mut self, keys: Option<KeyRing>) {
+        self.enabled = true;
+        if let Some(keys) = keys {
+            self.add_keys(keys);
+        }
+    }
+
+    pub fn has_key(&self) -> bool {
+        !self.keys.keys.is_empty()
+    }
+
+    // check if some received content cannot be decrypted yet
+    pub fn needs_keys(&self) -> bool {
+        self.enabled && (!self.has_key() || !self.missing.is_empty())
+    }
+
+    pub fn add_keys(&mut self, keys: KeyRing) {
+        self.keys.merge(keys);
+        let keys = &self.keys.keys;
+        self.missing.retain(|version| !keys.contains_key(version));
+    }
+
+    pub fn merge_epochs(&mut self, epochs: KeyEpochs) {
+        for (client, client_epochs) in epochs {
+            let local = self.epochs.entry(client).or_default();
+            for (clock, version) in client_epochs {
+                local.entry(clock).or_insert(version);
+            }
+        }
+    }
+
+    // Start using the current key version for the local client from clock
+    pub fn adopt(&mut self, client: ClientID, clock: u32) {
+        if !self.enabled || !self.has_key() {
+            return;
+        }
+        let current = self.keys.current;
+        let epochs = self.epochs.entry(client).or_default();
+        if epochs.values().next_back() != Some(&current) {
+            epochs.insert(clock, current);
+        }
+    }
+
+    fn version_of(&self, id: &BlockID) -> Option<u32> {
+        let (_, version) = self
+            .epochs
+            .get(&id.client)?
+            .range(..=id.clock)
+            .next_back()?;
+        Some(*version)
+    }
+
+    fn key(&self, version: u32) -> Option<LessSafeKey> {
+        let key = hex::decode(self.keys.keys.get(&version)?).ok()?;
+        Some(LessSafeKey::new(
+            UnboundKey::new(&CHACHA20_POLY1305, &key).ok()?,
+        ))
+    }
+
+    // Seal the characters inserted at id by the local client,
+    // return None if the insertion is not encrypted
+    pub fn seal(&mut self, id: &BlockID, content: &str) -> Option<Sealed> {
+        if !self.enabled {
+            return None;
+        }
+        // the key of a local epoch is always known
+        let key = self.key(self.version_of(id)?)?;
+        let mut ciphertext = content.as_bytes().to_vec();
+        key.seal_in_place_append_tag(nonce(id), Aad::empty(), &mut ciphertext)
+            .ok()?;
+        let sealed = Sealed {
+            id: id.clone(),
+            ciphertext,
+        };
+        self.record_sealed(sealed.clone());
+        Some(sealed)
+    }
+
+    // Text of the insertion, None if the key version is unknown or the ciphertext is forged
+    fn open(&self, sealed: &Sealed) -> Option<Vec<u8>> {
+        let key = self.key(self.version_of(&sealed.id)?)?;
+        let mut in_out = sealed.ciphertext.clone();
+        let text = key
+            .open_in_place(nonce(&sealed.id), Aad::empty(), &mut in_out)
+            .ok()?;
+        Some(text.to_vec())
+    }
+
+    // Seal the embed inserted at id (its kind, alt text and payload),
+    // return None if the insertion is not encrypted
+    //
+    // sealing is deterministic, so the embed can be sealed again whenever it leaves the doc
+    pub fn seal_embed(&self, id: &BlockID, embed: &Embed) -> Option<Embed> {
+        if !self.enabled {
+            return None;
+        }
+        let key = self.key(self.version_of(id)?)?;
+        let mut ciphertext = embed.encode().into_bytes();
+        key.seal_in_place_append_tag(embed_nonce(id), Aad::empty(), &mut ciphertext)
+            .ok()?;
+        Some(Embed {
+            kind: SEALED_EMBED.to_string(),
+            alt: None,
+            payload: Payload::Sealed(ciphertext),
+        })
+    }
+
+    // Embed sealed at id, None if the ciphertext is forged
+    fn open_embed(&self, id: &BlockID, embed: Embed) -> Option<Embed> {
+        let mut in_out = match embed.payload {
+            Payload::Sealed(ciphertext) => ciphertext,
+            _ => return Some(embed),
+        };
+        let key = self.key(self.version_of(id)?)?;
+        let text = key
+            .open_in_place(embed_nonce(id), Aad::empty(), &mut in_out)
+            .ok()?;
+        serde_json::from_slice(text).ok()
+    }
+
+    fn record_sealed(&mut self, sealed: Sealed) {
+        self.sealed
+            .entry(sealed.id.client)
+            .or_default()
+            .entry(sealed.id.clock)
+            .or_insert(sealed);
+    }
+
+    fn find_sealed(&self, id: &BlockID) -> Option<&Sealed> {
+        let (_, sealed) = self
+            .sealed
+            .get(&id.client)?
+            .range(..=id.clock)
+            .next_back()?;
+        sealed.covers(id).then_some(sealed)
+    }
+
+    // Seal updates leaving the doc
+    //
+    // a tombstone whose insertion has never been sealed here only keeps its length and is marked as elided,
+    // a live block that cannot be sealed (of a client without an epoch, e.g. imported from yjs)
+    // or an embed whose key is gone is held back
+    pub fn encrypt(&self, updates: Updates) -> Updates {
+        if !self.enabled {
+            return updates;
+        }
+        updates
+            .into_iter()
+            .filter_map(|mut block| {
+                if block.sealed.is_some() || block.elided {
+                    return Some(block);
+                }
+                let len = block.content.len();
+                block.sealed = self.find_sealed(&block.id).cloned();
+                if block.sealed.is_none() {
+                    if !block.is_deleted {
+                        warn!(block = ?block.id, "held back block that cannot be sealed");
+                        return None;
+                    }
+                    block.elided = true;
+                    block.content = Content::Text(TOMBSTONE_FILLER.to_string().repeat(len));
+                    return Some(block);
+                }
+                block.content = match block.content {
+                    Content::Embed(embed) => match self.seal_embed(&block.id, &embed) {
+                        Some(embed) => Content::Embed(embed),
+                        // never sent in the clear
+                        None => {
+                            warn!(block = ?block.id, "held back embed that cannot be sealed");
+                            return None;
+                        }
+                    },
+                    Content::Text(_) => Content::Text(SEALED_FILLER.to_string().repeat(len)),
+                };
+                Some(block)
+            })
+            .collect()
+    }
+
+    // Open updates entering the doc,
+    // blocks sealed with an unknown key version are left out, as are forged ones
+    pub fn decrypt(&mut self, updates: Updates) -> Updates {
+        if !self.enabled {
+            return updates;
+        }
+        let mut opened: HashMap<BlockID, Vec<u8>> = HashMap::new();
+        let mut res: Updates = vec![];
+        for mut block in updates {
+            let sealed = match block.sealed.take() {
+                Some(sealed) => sealed,
+                // written before the client turned on encryption
+                None if self.version_of(&block.id).is_none() || block.elided => {
+                    res.push(block);
+                    continue;
+                }
+                None => {
+                    warn!(block = ?block.id, "dropped block of an epoch that is not sealed");
+                    continue;
+                }
+            };
+            if !sealed.covers(&block.id) {
+                warn!(block = ?block.id, "dropped block outside of its sealed insertion");
+                continue;
+            }
+            let start = (block.id.clock - sealed.id.clock) as usize;
+            let end = start + block.content.len();
+            if end > sealed.text_len() as usize {
+                warn!(block = ?block.id, "dropped block outside of its sealed insertion");
+                continue;
+            }
+            if !opened.contains_key(&sealed.id) {
+                match self.open(&sealed) {
+                    Some(text) => {
+                        opened.insert(sealed.id.clone(), text);
+                    }
+                    None if self
+                        .version_of(&sealed.id)
+                        .is_some_and(|version| !self.keys.keys.contains_key(&version)) =>
+                    {
+                        self.record_missing(&block);
+                        continue;
+                    }
+                    None => {
+                        warn!(block = ?block.id, "dropped block that cannot be opened");
+                        continue;
+                    }
+                }
+            }
+            block.content = match block.content {
+                Content::Embed(embed) => match self.open_embed(&block.id, embed) {
+                    Some(embed) => Content::Embed(embed),
+                    None => {
+                        warn!(block = ?block.id, "dropped embed that cannot be opened");
+                        continue;
+                    }
+                },
+                Content::Text(_) => {
+                    match String::from_utf8(opened[&sealed.id][start..end].to_vec()) {
+                        Ok(text) => Content::Text(text),
+                        Err(_) => {
+                            warn!(block = ?block.id, "dropped block split inside a character");
+                            continue;
+                        }
+                    }
+                }
+            };
+            self.record_sealed(sealed);
+            res.push(block);
+        }
+        res
+    }
+
+    // Seal the texts of comments leaving the doc with the current key version,
+    // comments already sealed (e.g. kept by a relay) are sent as they are
+    // and the others are held back until the key is known
+    pub fn seal_comments(&self, comments: Vec<Comment>) -> Vec<Comment> {
+        if !self.enabled {
+            return comments;
+        }
+        let version = self.keys.current;
+        let key = self.key(version);
+        comments
+            .into_iter()
+            .filter_map(|mut comment| {
+                if comment.sealed.is_some() {
+                    return Some(comment);
+                }
+                let mut ciphertext = std::mem::take(&mut comment.text).into_bytes();
+                key.as_ref()?
+                    .seal_in_place_append_tag(
+                        comment_nonce(&comment.id),
+                        Aad::empty(),
+                        &mut ciphertext,
+                    )
+                    .ok()?;
+                comment.sealed = Some(SealedText {
+                    version,
+                    ciphertext,
+                });
+                Some(comment)
+            })
+            .collect()
+    }
+
+    // Open the texts of comments entering the doc,
+    // comments sealed with an unknown key version are left out, as are forged ones
+    pub fn open_comments(&mut self, comments: Vec<Comment>) -> Vec<Comment> {
+        if !self.enabled {
+            return comments;
+        }
+        let mut res = vec![];
+        for mut comment in comments {
+            let sealed = match comment.sealed.take() {
+                Some(sealed) => sealed,
+                // written before the client turned on encryption
+                None => {
+                    res.push(comment);
+                    continue;
+                }
+            };
+            let key = match self.key(sealed.version) {
+                Some(key) => key,
+                None => {
+                    self.missing.insert(sealed.version);
+                    continue;
+                }
+            };
+            let mut in_out = sealed.ciphertext;
+            let text = key
+                .open_in_place(comment_nonce(&comment.id), Aad::empty(), &mut in_out)
+                .ok()
+                .and_then(|text| String::from_utf8(text.to_vec()).ok());
+            match text {
+                Some(text) => {
+                    comment.text = text;
+                    res.push(comment);
+                }
+                None => warn!(comment = ?comment.id, "dropped comment that cannot be opened"),
+            }
+        }
+        res
+    }
+
+    fn record_missing(&mut self, block: &Block) {
+        if let Some(epochs) = self.epochs.get(&block.id.client) {
+            let end = block.id.clock + block.content.len() as u32;
+            for (_, version) in epochs.range(..end) {
+                if !self.keys.keys.contains_key(version) {
+                    self.missing.insert(*version);
+                }
+            }
+        }
+    }
+}
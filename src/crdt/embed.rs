@@ -0,0 +1,151 @@
+use crate::crdt::block::{BlockID, Content};
+use crate::crdt::doc::Doc;
+use crate::crdt::utils::{CRDTError, CRDTResult};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// content of an embed block, a single byte so that an embed counts as one character
+// in clocks and positions like any other character
+pub const EMBED_PLACEHOLDER: &str = "\u{1A}";
+
+// kind of an embed whose kind, alt text and payload are end-to-end encrypted
+pub const SEALED_EMBED: &str = "sealed";
+
+// Payload carried by an embed, binary payloads are hex encoded on the wire
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum Payload {
+    Json(serde_json::Value),
+    Binary(#[serde(with = "hex_bytes")] Vec<u8>),
+    // the encoded embed sealed by Encryption::seal_embed
+    Sealed(#[serde(with = "hex_bytes")] Vec<u8>),
+}
+
+mod hex_bytes {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        hex::decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+// Embed is non-text content of the doc (an image, a mention, a widget...),
+// it is a block of length 1 that is ordered and deleted like text but never split or merged
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Embed {
+    // e.g. "image", "mention", "table"
+    pub kind: String,
+    // text shown in place of the embed by EmbedPolicy::Alt, e.g. "@alice"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alt: Option<String>,
+    pub payload: Payload,
+}
+
+impl Embed {
+    // Serialized embed, covered by the signature of its insertion
+    pub fn encode(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+// How embeds are rendered by Doc::to_string_with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EmbedPolicy {
+    // every embed is EMBED_PLACEHOLDER, positions in the text are positions of the doc
+    Placeholder,
+    // embeds are left out
+    Skip,
+    // embeds are replaced by their alt text, "[kind]" if they have none
+    Alt,
+}
+
+// DeltaInsert is a run of text or an embed, {"insert": "text"} or {"insert": {"kind", "payload"}}
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum DeltaInsert {
+    Text(String),
+    Embed(Embed),
+}
+
+// DeltaOp is an insertion of the delta of the doc, applying them in order to an empty doc
+// gives the current doc with its embeds (like a Quill delta)
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct DeltaOp {
+    pub insert: DeltaInsert,
+}
+
+impl Doc {
+    // Insert embed at pos, return the id of the embed
+    //
+    // the embed leaves the doc sealed once encryption is enabled, so sealed payloads are refused
+    pub async fn insert_embed(&mut self, embed: Embed, pos: u32) -> CRDTResult<BlockID> {
+        if matches!(embed.payload, Payload::Sealed(_)) {
+            return Err(CRDTError::InvalidKey(format!(
+                "cannot embed a sealed payload into {}",
+                self.name
+            )));
+        }
+        let id = BlockID::new(self.client, self.vector_clock.get(self.client));
+        self.insert_local(Content::Embed(embed), pos).await;
+        Ok(id)
+    }
+
+    // Visible text with embeds rendered according to policy
+    pub async fn to_string_with(&self, policy: EmbedPolicy) -> String {
+        let store = self.block_store.clone();
+        let store_lock = store.lock().await;
+        let mut res = String::new();
+        for block in store_lock.total_store.list.iter() {
+            let block_lock = block.lock().await;
+            if block_lock.is_deleted {
+                continue;
+            }
+            match (block_lock.content.embed(), policy) {
+                (None, _) | (Some(_), EmbedPolicy::Placeholder) => {
+                    res.push_str(block_lock.content.as_str())
+                }
+                (Some(_), EmbedPolicy::Skip) => {}
+                (Some(embed), EmbedPolicy::Alt) => match &embed.alt {
+                    Some(alt) => res.push_str(alt),
+                    None => res.push_str(&format!("[{}]", embed.kind)),
+                },
+            }
+        }
+        res
+    }
+
+    // Visible content as a delta, neighbouring text is merged into one insertion
+    pub async fn delta(&self) -> Vec<DeltaOp> {
+        let store = self.block_store.clone();
+        let store_lock = store.lock().await;
+        let mut ops: Vec<DeltaOp> = vec![];
+        for block in store_lock.total_store.list.iter() {
+            let block_lock = block.lock().await;
+            if block_lock.is_deleted || block_lock.content.is_empty() {
+                continue;
+            }
+            let text = match &block_lock.content {
+                Content::Embed(embed) => {
+                    ops.push(DeltaOp {
+                        insert: DeltaInsert::Embed(embed.clone()),
+                    });
+                    continue;
+                }
+                Content::Text(text) => text,
+            };
+            match ops.last_mut() {
+                Some(DeltaOp {
+                    insert: DeltaInsert::Text(delta),
+                }) => delta.push_str(text),
+                _ => ops.push(DeltaOp {
+                    insert: DeltaInsert::Text(text.clone()),
+                }),
+            }
+        }
+        ops
+    }
+}
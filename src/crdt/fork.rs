@@ -0,0 +1,104 @@
+use crate::crdt::awareness::Awareness;
+use crate::crdt::block_store::BlockStore;
+use crate::crdt::doc::Doc;
+use crate::crdt::relay::RELAY_CLIENT;
+use crate::crdt::utils::{CRDTError, CRDTResult, ClientID, Updates};
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::{debug, info, instrument, warn};
+
+impl Doc {
+    // Independent copy of the doc named new_name, sharing its history up to now,
+    // edited by a new random client so that insertions on both sides never share a clock
+    pub async fn fork(&self, new_name: String) -> Doc {
+        let client = loop {
+            let client: ClientID = rand::random();
+            if client != self.client
+                && client != RELAY_CLIENT
+                && !self.vector_clock.clock_map.contains_key(&client)
+            {
+                break client;
+            }
+        };
+        self.fork_as(new_name, client).await
+    }
+
+    // Same as fork, edited by client
+    pub async fn fork_as(&self, new_name: String, client: ClientID) -> Doc {
+        let store = self.block_store.clone();
+        let store_lock = store.lock().await;
+        let mut blocks = vec![];
+        for block in store_lock.total_store.list.iter() {
+            blocks.push(block.lock().await.clone());
+        }
+        drop(store_lock);
+        info!(doc = %self.name, fork = %new_name, client, blocks = blocks.len(), "forked doc");
+        // the fork seals its insertions with the current key version from its first one on
+        let mut encryption = self.encryption.clone();
+        encryption.adopt(client, 0);
+        Doc {
+            name: new_name,
+            client,
+            block_store: Arc::new(Mutex::new(BlockStore::from_blocks(blocks))),
+            // a fork is not shared until it is merged back
+            peers: vec![],
+            pending_updates: self.pending_updates.clone(),
+            vector_clock: self.vector_clock.clone(),
+            latest_clock: Arc::new(Mutex::new(None)),
+            awareness: Awareness::new(),
+            comments: self.comments.clone(),
+            signatures: self.signatures.fork(self.client, client),
+            encryption,
+        }
+    }
+
+    // Integrate the updates and comments of other that are missing here, e.g. a fork being merged back
+    // (or the original being merged into a fork to catch up), the diff is computed like for a peer
+    //
+    // the diff goes through the same checks as the updates of a peer: it is sealed by other,
+    // carries the signatures and key epochs it needs and is opened here, blocks with a missing
+    // or invalid signature are quarantined and reported as Conflict
+    #[instrument(skip_all, fields(doc = %self.name, from = %other.name))]
+    pub async fn merge_from(&mut self, other: &Doc) -> CRDTResult<()> {
+        let diff = other.compute_diff(&self.vector_clock).await;
+        self.signatures.add(other.signatures.signatures_for(&diff));
+        self.encryption
+            .merge_epochs(other.encryption.epochs.clone());
+        let mut rejected = vec![];
+        let diff: Updates = if self.signatures.verifying() {
+            let (trusted, forged): (Updates, Updates) = diff
+                .into_iter()
+                .partition(|block| self.signatures.verify_block(block));
+            for block in forged {
+                warn!(block = ?block.id, "quarantined block with a missing or invalid signature");
+                rejected.push(block.id.clone());
+                self.signatures.quarantine_block(block);
+            }
+            trusted
+        } else {
+            diff
+        };
+        let ids: HashSet<_> = diff.iter().map(|block| block.id.clone()).collect();
+        debug!(updates = diff.len(), "merging");
+        self.insert_remote(diff).await;
+        self.comments.apply_remote(other.comments.get_comments());
+        if !rejected.is_empty() {
+            return Err(CRDTError::Conflict(rejected));
+        }
+        // every change of other is now integrated (or pending), unless a block couldn't be opened
+        if !self.encryption.needs_keys() {
+            self.vector_clock.merge_changes(&other.vector_clock);
+        }
+        let pending: Vec<_> = self
+            .pending_updates
+            .iter()
+            .filter(|block| ids.contains(&block.id))
+            .map(|block| block.id.clone())
+            .collect();
+        if !pending.is_empty() {
+            return Err(CRDTError::MissingDependency(pending));
+        }
+        Ok(())
+    }
+}
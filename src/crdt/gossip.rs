@@ -0,0 +1,278 @@
+use crate::crdt::auth::with_token;
+use crate::crdt::block::{Block, BlockID, ChangeID};
+use crate::crdt::doc::{is_missing, VectorClock};
+use crate::crdt::e2e::KeyEpochs;
+use crate::crdt::metrics::metrics;
+use crate::crdt::signing::Signatures;
+use crate::crdt::sync_txn::SyncTransaction;
+use crate::crdt::txn_rpc::{self, txn_service_client::TxnServiceClient};
+use crate::crdt::utils::{CRDTError, CRDTResult, ClientID, Peer, Updates};
+use prost::Message;
+use rand::seq::SliceRandom;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::{debug, info, instrument, warn};
+
+// GossipConfig configures the epidemic dissemination of updates
+#[derive(Debug, Clone)]
+pub struct GossipConfig {
+    // number of peers an update is forwarded to by every peer receiving it for the first time
+    pub fanout: usize,
+    // number of times an update is forwarded,
+    // fanout^hops should be larger than the number of peers
+    pub hops: u32,
+    // how often local updates are disseminated
+    pub interval: Duration,
+    // how often vector clocks are exchanged with a random peer to repair missed updates
+    pub digest_interval: Duration,
+}
+
+impl Default for GossipConfig {
+    fn default() -> Self {
+        GossipConfig {
+            fanout: 3,
+            hops: 4,
+            interval: Duration::from_millis(100),
+            digest_interval: Duration::from_secs(2),
+        }
+    }
+}
+
+// an update as received by gossip: the insertion of a block, or its deletion and changes
+type UpdateKey = (BlockID, bool, Vec<ChangeID>);
+
+fn key(block: &Block) -> UpdateKey {
+    (block.id.clone(), block.is_deleted, block.changes.clone())
+}
+
+// Gossip is the state shared by the transactions of a client when updates are gossiped,
+// instead of pulling from every peer, each update is pushed to a few random peers which forward it
+pub struct Gossip {
+    pub config: GossipConfig,
+    // updates received but not covered by the vector clock of the doc yet
+    // (e.g. blocks waiting for their origins), the others are recognised by the clock
+    seen: Mutex<HashSet<UpdateKey>>,
+    // vector clock of the doc when updates were last disseminated to each peer
+    sent: Mutex<HashMap<ClientID, VectorClock>>,
+}
+
+impl Gossip {
+    pub fn new(config: GossipConfig) -> Self {
+        Gossip {
+            config,
+            seen: Mutex::new(HashSet::new()),
+            sent: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // keep the updates a doc with vector clock clock is missing and that haven't been
+    // received yet, and mark them as seen
+    pub async fn fresh(&self, updates: Updates, clock: &VectorClock) -> Updates {
+        let mut seen = self.seen.lock().await;
+        seen.retain(|(id, _, changes)| {
+            id.clock >= clock.get(id.client)
+                || changes.iter().any(|change| !clock.has_change(change))
+        });
+        updates
+            .into_iter()
+            .filter(|block| is_missing(block, clock) && seen.insert(key(block)))
+            .collect()
+    }
+
+    // number of updates remembered as seen
+    pub async fn remembered(&self) -> usize {
+        self.seen.lock().await.len()
+    }
+}
+
+impl SyncTransaction {
+    // push updates to a random fanout of peers (except the client they came from),
+    // return the number of peers reached, unreachable peers are skipped
+    #[instrument(skip_all, fields(doc = %self.doc_name, client = self.client, updates = updates.len(), hops))]
+    pub async fn gossip(
+        &self,
+        updates: &Updates,
+        signatures: &Signatures,
+        epochs: &KeyEpochs,
+        hops: u32,
+        from: Option<ClientID>,
+    ) -> CRDTResult<usize> {
+        let fanout = match &self.gossip {
+            Some(gossip) => gossip.config.fanout,
+            None => return Ok(0),
+        };
+        let peers = self.gossip_peers(fanout, from).await;
+        let req = txn_rpc::GossipRequest {
+            doc_name: self.doc_name.clone(),
+            client_id: self.client,
+            updates: serde_json::to_string(updates)?,
+            signatures: serde_json::to_string(signatures)?,
+            key_epochs: serde_json::to_string(epochs)?,
+            hops,
+        };
+
+        let mut reached = 0;
+        for peer in peers {
+            match self.gossip_to(&peer, req.clone()).await {
+                Ok(()) => reached += 1,
+                // missed updates are repaired by the digest exchange
+                Err(e) => warn!(peer = peer.client_id, error = %e, "failed to gossip to peer"),
+            }
+        }
+        Ok(reached)
+    }
+
+    // a random fanout of peers, except the client updates came from
+    async fn gossip_peers(&self, fanout: usize, from: Option<ClientID>) -> Vec<Peer> {
+        let local_doc = self.doc.lock().await;
+        let candidates: Vec<&Peer> = local_doc
+            .peers
+            .iter()
+            .filter(|peer| peer.client_id != self.client && Some(peer.client_id) != from)
+            .collect();
+        candidates
+            .choose_multiple(&mut rand::thread_rng(), fanout)
+            .map(|peer| (*peer).clone())
+            .collect()
+    }
+
+    async fn gossip_to(&self, peer: &Peer, req: txn_rpc::GossipRequest) -> CRDTResult<()> {
+        let mut client = TxnServiceClient::new(self.connect(peer).await?);
+        let req = with_token(req, &self.token);
+        let sent = req.get_ref().encoded_len();
+        let resp = match client.gossip_updates(req).await {
+            Ok(resp) => resp.into_inner(),
+            Err(status) => {
+                let e = CRDTError::from(status);
+                self.disconnect(peer.client_id, &e).await;
+                return Err(e);
+            }
+        };
+        metrics().observe_rpc("gossip_updates", sent, resp.encoded_len());
+        Ok(())
+    }
+
+    // gossip to a random fanout of peers the updates made since they were last disseminated
+    // to each of them (mostly local ones, remote updates have been forwarded when they were received),
+    // peers the doc has not changed for since are skipped without computing a diff
+    pub async fn disseminate(&self) -> CRDTResult<usize> {
+        let gossip = match &self.gossip {
+            Some(gossip) => gossip.clone(),
+            None => return Ok(0),
+        };
+        let mut reached = 0;
+        for peer in self.gossip_peers(gossip.config.fanout, None).await {
+            let sent = gossip.sent.lock().await.get(&peer.client_id).cloned();
+            let (updates, clock) = {
+                let local_doc = self.doc.lock().await;
+                if sent.as_ref() == Some(&local_doc.vector_clock) {
+                    continue;
+                }
+                let sent = sent.unwrap_or_default();
+                (
+                    local_doc.compute_diff(&sent).await,
+                    local_doc.vector_clock.clone(),
+                )
+            };
+            if !updates.is_empty() {
+                let req = txn_rpc::GossipRequest {
+                    doc_name: self.doc_name.clone(),
+                    client_id: self.client,
+                    updates: serde_json::to_string(&updates)?,
+                    signatures: serde_json::to_string(&self.signatures_for(&updates).await)?,
+                    key_epochs: serde_json::to_string(&self.key_epochs().await)?,
+                    hops: gossip.config.hops,
+                };
+                debug!(
+                    peer = peer.client_id,
+                    updates = updates.len(),
+                    "disseminating updates"
+                );
+                if let Err(e) = self.gossip_to(&peer, req).await {
+                    // missed updates are repaired by the digest exchange
+                    warn!(peer = peer.client_id, error = %e, "failed to gossip to peer");
+                    continue;
+                }
+                reached += 1;
+            }
+            gossip.sent.lock().await.insert(peer.client_id, clock);
+        }
+        Ok(reached)
+    }
+
+    // exchange vector clocks with a random peer, each side receives what it is missing
+    //
+    // what the peer is missing is not all written by this client, so it is sent as gossip
+    // (checked against the client that wrote every block) that the peer doesn't forward
+    pub async fn exchange_digest(&self) -> CRDTResult<()> {
+        let peer = {
+            let local_doc = self.doc.lock().await;
+            let candidates: Vec<&Peer> = local_doc
+                .peers
+                .iter()
+                .filter(|peer| peer.client_id != self.client)
+                .collect();
+            candidates
+                .choose(&mut rand::thread_rng())
+                .map(|peer| (*peer).clone())
+        };
+        let peer = match peer {
+            Some(peer) => peer,
+            None => return Ok(()),
+        };
+        let (peer_clock, res) = self.pull_diff(&peer).await?;
+        let updates = self.compute_diff(peer_clock).await;
+        if !updates.is_empty() {
+            let req = txn_rpc::GossipRequest {
+                doc_name: self.doc_name.clone(),
+                client_id: self.client,
+                updates: serde_json::to_string(&updates)?,
+                signatures: serde_json::to_string(&self.signatures_for(&updates).await)?,
+                key_epochs: serde_json::to_string(&self.key_epochs().await)?,
+                hops: 1,
+            };
+            debug!(
+                peer = peer.client_id,
+                updates = updates.len(),
+                "repairing updates"
+            );
+            self.gossip_to(&peer, req).await?;
+        }
+        match res {
+            // the rest of the updates is integrated once their origins arrive
+            Err(CRDTError::MissingDependency(_)) => Ok(()),
+            res => res,
+        }
+    }
+
+    // disseminate local updates every interval and exchange digests every digest interval,
+    // until the task running it is aborted
+    pub async fn run_gossip(self) {
+        let config = match &self.gossip {
+            Some(gossip) => gossip.config.clone(),
+            None => return,
+        };
+        info!(doc = %self.doc_name, client = self.client, fanout = config.fanout, "gossip started");
+        let mut updates = tokio::time::interval(config.interval);
+        // the first digest is exchanged after one interval, updates are gossiped first
+        let mut digests = tokio::time::interval_at(
+            tokio::time::Instant::now() + config.digest_interval,
+            config.digest_interval,
+        );
+        loop {
+            tokio::select! {
+                _ = updates.tick() => {
+                    if let Err(e) = self.disseminate().await {
+                        warn!(error = %e, "failed to disseminate updates");
+                    }
+                }
+                _ = digests.tick() => {
+                    if let Err(e) = self.exchange_digest().await {
+                        warn!(error = %e, "failed to exchange digest");
+                    }
+                }
+            }
+        }
+    }
+}
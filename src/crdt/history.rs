@@ -0,0 +1,221 @@
+use crate::crdt::block::{Block, BlockID, Content};
+use crate::crdt::doc::Doc;
+use crate::crdt::embed::Embed;
+use crate::crdt::snapshot::delete_set;
+use crate::crdt::stream::causal_order;
+use crate::crdt::utils::{ClientID, Updates};
+use serde::{Deserialize, Serialize};
+
+// version of the change log format, bumped on any incompatible change
+pub const HISTORY_VERSION: u32 = 1;
+
+// Change is an entry of the change log
+//
+// {"op": "insert", "id": {"client", "clock"}, "left_origin": id | null, "right_origin": id | null, "content": string}
+// is text inserted by id.client, whose characters have the clocks [id.clock, id.clock + content.len()),
+// between the block starting at left_origin and the character right_origin,
+// an embed also has "embed": {"kind", "payload"} and EMBED_PLACEHOLDER as content
+//
+// {"op": "delete", "id": {"client", "clock"}, "len": n}
+// deletes the characters [id.clock, id.clock + n) of id.client
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Change {
+    Insert {
+        id: BlockID,
+        left_origin: Option<BlockID>,
+        right_origin: Option<BlockID>,
+        content: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        embed: Option<Embed>,
+    },
+    Delete {
+        id: BlockID,
+        len: u32,
+    },
+}
+
+// History is the change log of a doc: {"version": 1, "doc": name, "changes": [change]}
+//
+// insertions come first in causal order (origins and earlier insertions of the same client
+// before an insertion, ties broken by id), then deletions ordered by id,
+// since deletions don't record when they happened
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct History {
+    pub version: u32,
+    pub doc: String,
+    pub changes: Vec<Change>,
+}
+
+// TextOp is a positional operation on the text, indices count bytes like positions of Doc
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum TextOp {
+    Insert { index: u32, text: String },
+    Delete { index: u32, len: u32 },
+}
+
+impl TextOp {
+    pub fn apply(&self, text: &mut String) {
+        match self {
+            TextOp::Insert {
+                index,
+                text: inserted,
+            } => text.insert_str(*index as usize, inserted),
+            TextOp::Delete { index, len } => {
+                text.replace_range(*index as usize..(*index + *len) as usize, "")
+            }
+        }
+    }
+}
+
+impl Doc {
+    // Change log of the doc, blocks of every client are walked in clock order
+    // and the ones split from the same insertion are merged back
+    pub async fn history(&self) -> History {
+        let store = self.block_store.clone();
+        let store_lock = store.lock().await;
+        let mut clients: Vec<&ClientID> = store_lock.kv_store.keys().collect();
+        clients.sort_unstable();
+        let mut blocks: Updates = vec![];
+        for client in clients {
+            let mut list = vec![];
+            for block in store_lock.kv_store[client].list.iter() {
+                list.push(block.lock().await.clone());
+            }
+            list.sort_by_key(|block| block.id.clock);
+            blocks.extend(list);
+        }
+        drop(store_lock);
+
+        let deleted = delete_set(&blocks);
+        // a block split from the previous one is part of the same insertion,
+        // which keeps the origins of its first part
+        let mut insertions: Updates = vec![];
+        let mut last: Option<Block> = None;
+        for block in blocks {
+            match insertions.last_mut() {
+                Some(insertion) if last.as_ref().is_some_and(|last| last.splits_into(&block)) => {
+                    insertion.content.push_str(block.content.as_str())
+                }
+                _ => insertions.push(block.clone()),
+            }
+            last = Some(block);
+        }
+
+        let mut changes: Vec<Change> = causal_order(insertions)
+            .into_iter()
+            .map(|block| Change::Insert {
+                id: block.id,
+                left_origin: block.left_origin,
+                right_origin: block.right_origin,
+                content: block.content.as_str().to_string(),
+                embed: block.content.embed().cloned(),
+            })
+            .collect();
+        let mut deleted: Vec<_> = deleted.into_iter().collect();
+        deleted.sort_unstable_by_key(|(client, _)| *client);
+        for (client, ranges) in deleted {
+            for (start, end) in ranges {
+                changes.push(Change::Delete {
+                    id: BlockID::new(client, start),
+                    len: end - start,
+                });
+            }
+        }
+        History {
+            version: HISTORY_VERSION,
+            doc: self.name.clone(),
+            changes,
+        }
+    }
+
+    // Delete the characters [id.clock, id.clock + len) of id.client
+    // and return the deletions of the visible ones as text operations
+    async fn delete_as_text_ops(&mut self, id: &BlockID, len: u32) -> Vec<TextOp> {
+        let end = id.clock + len;
+        // visible runs of the range as (index, len), neighbouring runs are merged
+        let mut runs: Vec<(u32, u32)> = vec![];
+        {
+            let store_lock = self.block_store.lock().await;
+            let mut pos = 0;
+            for block in store_lock.total_store.list.iter() {
+                let block_lock = block.lock().await;
+                if block_lock.is_deleted {
+                    continue;
+                }
+                let block_len = block_lock.content.len() as u32;
+                let start = block_lock.id.clock.max(id.clock);
+                let stop = (block_lock.id.clock + block_len).min(end);
+                if block_lock.id.client == id.client && start < stop {
+                    let index = pos + start - block_lock.id.clock;
+                    match runs.last_mut() {
+                        Some((i, l)) if *i + *l == index => *l += stop - start,
+                        _ => runs.push((index, stop - start)),
+                    }
+                }
+                pos += block_len;
+            }
+        }
+        self.block_store
+            .lock()
+            .await
+            .delete_range(id.clone(), len, None, None)
+            .await;
+        // every run shifts the runs after it once it is deleted
+        let mut deleted = 0;
+        runs.into_iter()
+            .map(|(index, len)| {
+                let op = TextOp::Delete {
+                    index: index - deleted,
+                    len,
+                };
+                deleted += len;
+                op
+            })
+            .collect()
+    }
+}
+
+impl History {
+    // Replay the change log into positional text operations, applying them in order
+    // to an empty string gives the text of the doc
+    //
+    // insertions whose origins are missing from the log are left out
+    pub async fn text_ops(&self) -> Vec<TextOp> {
+        let mut doc = Doc::new(self.doc.clone(), 0);
+        let mut ops = vec![];
+        for change in self.changes.iter() {
+            match change {
+                Change::Insert {
+                    id,
+                    left_origin,
+                    right_origin,
+                    content,
+                    embed,
+                } => {
+                    let block = Block::new(
+                        id.clone(),
+                        left_origin.clone(),
+                        right_origin.clone(),
+                        match embed {
+                            Some(embed) => Content::Embed(embed.clone()),
+                            None => Content::Text(content.clone()),
+                        },
+                    );
+                    if !doc.insert_single_block(&block).await {
+                        continue;
+                    }
+                    if let Some(index) = doc.pos_of(id).await {
+                        ops.push(TextOp::Insert {
+                            index,
+                            text: content.clone(),
+                        });
+                    }
+                }
+                Change::Delete { id, len } => ops.extend(doc.delete_as_text_ops(id, *len).await),
+            }
+        }
+        ops
+    }
+}
@@ -0,0 +1,171 @@
+use crate::crdt::doc::Doc;
+use crate::crdt::utils::{resolve, CRDTError, CRDTResult, ClientID};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::convert::Infallible;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tracing::info;
+
+// Metrics holds every metric exported by the process,
+// metrics of a doc are labeled with its name so a relay can export all of its replicas
+//
+// doc gauges are refreshed after remote updates are integrated and after every sync round,
+// so they lag behind local edits until the next sync
+pub struct Metrics {
+    pub registry: Registry,
+    // blocks in total_store, including tombstones
+    pub blocks: IntGaugeVec,
+    pub tombstones: IntGaugeVec,
+    // visible characters
+    pub doc_length: IntGaugeVec,
+    pub pending_updates: IntGaugeVec,
+    // peers with an open rpc channel
+    pub connected_peers: IntGaugeVec,
+    pub sync_rtt: HistogramVec,
+    pub integration_time: HistogramVec,
+    // protobuf encoded size of rpc messages, by rpc name
+    pub rpc_bytes_sent: IntCounterVec,
+    pub rpc_bytes_received: IntCounterVec,
+}
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+// Metrics of the process, registered the first time they are used
+pub fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(|| Metrics::new().expect("metrics are registered once"))
+}
+
+impl Metrics {
+    fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new();
+        let gauge = |name: &str, help: &str| -> prometheus::Result<IntGaugeVec> {
+            let gauge = IntGaugeVec::new(Opts::new(name, help), &["doc"])?;
+            registry.register(Box::new(gauge.clone()))?;
+            Ok(gauge)
+        };
+        let counter = |name: &str, help: &str| -> prometheus::Result<IntCounterVec> {
+            let counter = IntCounterVec::new(Opts::new(name, help), &["rpc"])?;
+            registry.register(Box::new(counter.clone()))?;
+            Ok(counter)
+        };
+        let histogram = |name: &str,
+                         help: &str,
+                         labels: &[&str],
+                         buckets: Vec<f64>|
+         -> prometheus::Result<HistogramVec> {
+            let histogram =
+                HistogramVec::new(HistogramOpts::new(name, help).buckets(buckets), labels)?;
+            registry.register(Box::new(histogram.clone()))?;
+            Ok(histogram)
+        };
+
+        Ok(Metrics {
+            blocks: gauge("crdt_blocks", "blocks in the doc, including tombstones")?,
+            tombstones: gauge("crdt_tombstones", "deleted blocks kept in the doc")?,
+            doc_length: gauge("crdt_doc_length", "visible characters in the doc")?,
+            pending_updates: gauge(
+                "crdt_pending_updates",
+                "remote blocks waiting for their origins",
+            )?,
+            connected_peers: gauge("crdt_connected_peers", "peers with an open rpc channel")?,
+            sync_rtt: histogram(
+                "crdt_sync_rtt_seconds",
+                "round trip time of pulling updates from a peer",
+                &["doc", "peer"],
+                prometheus::exponential_buckets(0.001, 2.0, 14)?,
+            )?,
+            integration_time: histogram(
+                "crdt_block_integration_seconds",
+                "time to integrate one remote block",
+                &["doc"],
+                prometheus::exponential_buckets(0.00001, 2.0, 16)?,
+            )?,
+            rpc_bytes_sent: counter("crdt_rpc_bytes_sent_total", "bytes sent by rpc")?,
+            rpc_bytes_received: counter("crdt_rpc_bytes_received_total", "bytes received by rpc")?,
+            registry,
+        })
+    }
+
+    // Refresh the gauges of doc from the counters of its store
+    pub async fn observe_doc(&self, doc: &Doc) {
+        let stats = doc.block_store.lock().await.stats;
+        let name = doc.name.as_str();
+        self.blocks.with_label_values(&[name]).set(stats.blocks);
+        self.tombstones
+            .with_label_values(&[name])
+            .set(stats.tombstones);
+        self.doc_length.with_label_values(&[name]).set(stats.length);
+        self.pending_updates
+            .with_label_values(&[name])
+            .set(doc.pending_updates.len() as i64);
+    }
+
+    pub fn observe_rtt(&self, doc: &str, peer: ClientID, rtt: Duration) {
+        self.sync_rtt
+            .with_label_values(&[doc, &peer.to_string()])
+            .observe(rtt.as_secs_f64());
+    }
+
+    pub fn observe_rpc(&self, rpc: &str, sent: usize, received: usize) {
+        self.rpc_bytes_sent
+            .with_label_values(&[rpc])
+            .inc_by(sent as u64);
+        self.rpc_bytes_received
+            .with_label_values(&[rpc])
+            .inc_by(received as u64);
+    }
+
+    // Metrics in the prometheus text format
+    pub fn export(&self) -> CRDTResult<String> {
+        let mut buf = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buf)
+            .map_err(|e| CRDTError::Serialization(e.to_string()))?;
+        String::from_utf8(buf).map_err(|e| CRDTError::Serialization(e.to_string()))
+    }
+}
+
+async fn handle(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let res = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/metrics") => match metrics().export() {
+            Ok(text) => Response::builder()
+                .header("Content-Type", TextEncoder::new().format_type())
+                .body(Body::from(text)),
+            Err(e) => Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(e.to_string())),
+        },
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty()),
+    };
+    Ok(res.unwrap_or_default())
+}
+
+// start the http service exporting metrics at /metrics, returns once receiver gets a message
+pub async fn serve_metrics(
+    ip: String,
+    mut receiver: Receiver<()>,
+    sender: Sender<()>,
+) -> CRDTResult<()> {
+    let addr = resolve(&ip)?;
+    let make_service = make_service_fn(|_conn| async { Ok::<_, Infallible>(service_fn(handle)) });
+    let server = hyper::Server::try_bind(&addr)
+        .map_err(|e| CRDTError::Transport(e.to_string()))?
+        .serve(make_service);
+    info!(addr = %addr, "started metrics service");
+    let _ = sender.send(()).await;
+    server
+        .with_graceful_shutdown(async move {
+            receiver.recv().await;
+        })
+        .await
+        .map_err(|e| CRDTError::Transport(e.to_string()))?;
+    info!("shut down metrics service");
+    Ok(())
+}
@@ -1,9 +1,32 @@
+pub mod admin;
+pub mod anti_entropy;
+pub mod auth;
+pub mod awareness;
+pub mod blame;
 pub mod block;
 pub mod block_store;
+pub mod comment;
+pub mod conn;
 pub mod doc;
+pub mod e2e;
+pub mod embed;
+pub mod fork;
+pub mod gossip;
+pub mod history;
+pub mod metrics;
+pub mod relay;
+pub mod signing;
+pub mod snapshot;
+pub mod stream;
+pub mod suggestion;
 pub mod sync_txn;
+pub mod tls;
 pub mod txn_rpc;
 pub mod utils;
+pub mod version;
+pub mod ws;
+pub mod xml;
+pub mod yjs;
 pub mod zk_conn;
 
 pub use crate::crdt::block::Block;
@@ -0,0 +1,209 @@
+use crate::crdt::auth::{check_role, interceptor, Permissions, Role};
+use crate::crdt::doc::Doc;
+use crate::crdt::sync_txn::SyncTransaction;
+use crate::crdt::tls::{server_builder, TlsConfig};
+use crate::crdt::txn_rpc;
+use crate::crdt::txn_rpc::admin_service_server::AdminServiceServer;
+use crate::crdt::txn_rpc::txn_service_server::{TxnService, TxnServiceServer};
+use crate::crdt::utils::{resolve, CRDTResult, ClientID};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::Mutex;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::info;
+
+// client id used by the replicas hosted on the relay,
+// the relay never inserts anything so it never shows up in a vector clock
+pub const RELAY_CLIENT: ClientID = ClientID::MAX;
+
+// RelayServer hosts many docs and keeps a merged replica of each of them,
+// clients push their updates to the relay and pull everything they are missing,
+// so every client only needs one outbound connection
+//
+// the relay speaks the same TxnService protocol as peers,
+// requests are dispatched to the replica named in the request
+#[derive(Clone)]
+pub struct RelayServer {
+    pub ip: String,
+    // if set, clients need a role on a doc to read or update it
+    pub auth: Option<Arc<Permissions>>,
+    // if set, clients connect over tls, the relay's certificate is issued for RELAY_CLIENT
+    pub tls: Option<Arc<TlsConfig>>,
+    docs: Arc<Mutex<HashMap<String, Arc<SyncTransaction>>>>,
+}
+
+impl RelayServer {
+    pub fn new(ip: String) -> Self {
+        RelayServer {
+            ip,
+            auth: None,
+            tls: None,
+            docs: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // Get the replica of the doc, a new replica is created the first time a doc is seen
+    pub async fn get_doc(&self, doc_name: &str) -> Arc<SyncTransaction> {
+        let mut docs = self.docs.lock().await;
+        if let Some(txn) = docs.get(doc_name) {
+            return txn.clone();
+        }
+
+        info!(doc = doc_name, "relay started hosting doc");
+        let doc = Arc::new(Mutex::new(Doc::new(doc_name.to_string(), RELAY_CLIENT)));
+        let mut txn = SyncTransaction::new(
+            doc_name.to_string(),
+            RELAY_CLIENT,
+            doc,
+            Arc::new(Mutex::new(HashMap::new())),
+            self.ip.clone(),
+        );
+        txn.auth = self.auth.clone();
+        txn.tls = self.tls.clone();
+        let txn = Arc::new(txn);
+        docs.insert(doc_name.to_string(), txn.clone());
+        txn
+    }
+
+    // Get the replica of a doc hosted on the relay, without creating it
+    pub async fn find_doc(&self, doc_name: &str) -> Result<Arc<SyncTransaction>, tonic::Status> {
+        let docs = self.docs.lock().await;
+        docs.get(doc_name)
+            .cloned()
+            .ok_or_else(|| tonic::Status::not_found("doc not hosted on the relay"))
+    }
+
+    // check if client is allowed to change doc (always true without permissions)
+    pub fn can_edit(&self, doc_name: &str, client: ClientID) -> bool {
+        self.auth
+            .as_ref()
+            .is_none_or(|permissions| permissions.can_edit(doc_name, client))
+    }
+
+    // Names of all docs hosted on the relay
+    pub async fn doc_names(&self) -> Vec<String> {
+        let docs = self.docs.lock().await;
+        docs.keys().cloned().collect()
+    }
+}
+
+#[async_trait::async_trait]
+impl TxnService for RelayServer {
+    async fn get_remote_updates(
+        &self,
+        request: tonic::Request<txn_rpc::PullRequest>,
+    ) -> Result<tonic::Response<txn_rpc::PullResponse>, tonic::Status> {
+        let txn = self.find_doc(&request.get_ref().doc_name).await?;
+        TxnService::get_remote_updates(txn.as_ref(), request).await
+    }
+
+    async fn sync_peer_list(
+        &self,
+        _request: tonic::Request<txn_rpc::RegisterRequest>,
+    ) -> Result<tonic::Response<txn_rpc::Status>, tonic::Status> {
+        Err(tonic::Status::unimplemented(
+            "relay does not keep a peer list",
+        ))
+    }
+
+    async fn sync_awareness(
+        &self,
+        request: tonic::Request<txn_rpc::AwarenessRequest>,
+    ) -> Result<tonic::Response<txn_rpc::AwarenessResponse>, tonic::Status> {
+        let txn = self.find_doc(&request.get_ref().doc_name).await?;
+        TxnService::sync_awareness(txn.as_ref(), request).await
+    }
+
+    async fn sync_comments(
+        &self,
+        request: tonic::Request<txn_rpc::CommentsRequest>,
+    ) -> Result<tonic::Response<txn_rpc::CommentsResponse>, tonic::Status> {
+        let txn = self.find_doc(&request.get_ref().doc_name).await?;
+        TxnService::sync_comments(txn.as_ref(), request).await
+    }
+
+    async fn push_updates(
+        &self,
+        request: tonic::Request<txn_rpc::PushRequest>,
+    ) -> Result<tonic::Response<txn_rpc::Status>, tonic::Status> {
+        // only editors may create a replica on the relay, reads never do
+        check_role(
+            &self.auth,
+            &request,
+            &request.get_ref().doc_name,
+            Role::Editor,
+        )?;
+        let txn = self.get_doc(&request.get_ref().doc_name).await;
+        TxnService::push_updates(txn.as_ref(), request).await
+    }
+
+    async fn get_doc_key(
+        &self,
+        _request: tonic::Request<txn_rpc::KeyRequest>,
+    ) -> Result<tonic::Response<txn_rpc::KeyResponse>, tonic::Status> {
+        Err(tonic::Status::not_found(
+            "relay only stores encrypted content",
+        ))
+    }
+
+    async fn gossip_updates(
+        &self,
+        _request: tonic::Request<txn_rpc::GossipRequest>,
+    ) -> Result<tonic::Response<txn_rpc::Status>, tonic::Status> {
+        Err(tonic::Status::unimplemented(
+            "clients of a relay push their updates",
+        ))
+    }
+
+    async fn get_snapshot(
+        &self,
+        request: tonic::Request<txn_rpc::SnapshotRequest>,
+    ) -> Result<tonic::Response<txn_rpc::SnapshotResponse>, tonic::Status> {
+        let txn = self.find_doc(&request.get_ref().doc_name).await?;
+        TxnService::get_snapshot(txn.as_ref(), request).await
+    }
+
+    type StreamUpdatesStream = ReceiverStream<Result<txn_rpc::StreamChunk, tonic::Status>>;
+
+    async fn stream_updates(
+        &self,
+        request: tonic::Request<txn_rpc::StreamRequest>,
+    ) -> Result<tonic::Response<Self::StreamUpdatesStream>, tonic::Status> {
+        let txn = self.find_doc(&request.get_ref().doc_name).await?;
+        TxnService::stream_updates(txn.as_ref(), request).await
+    }
+
+    async fn ping(
+        &self,
+        request: tonic::Request<txn_rpc::PingRequest>,
+    ) -> Result<tonic::Response<txn_rpc::Status>, tonic::Status> {
+        // the relay is healthy even if the doc is not hosted yet
+        let succ = self.find_doc(&request.get_ref().doc_name).await.is_ok();
+        Ok(tonic::Response::new(txn_rpc::Status { succ }))
+    }
+}
+
+// start relay service, returns once receiver gets a message
+pub async fn serve_relay(
+    relay: RelayServer,
+    mut receiver: Receiver<()>,
+    sender: Sender<()>,
+) -> CRDTResult<()> {
+    let ip = relay.ip.clone();
+    let mut builder = server_builder(&relay.tls)?;
+    let auth = interceptor(relay.auth.clone());
+    let admin_rpc = AdminServiceServer::with_interceptor(relay.clone(), auth.clone());
+    let relay_rpc = TxnServiceServer::with_interceptor(relay, auth);
+    let server = builder.add_service(relay_rpc).add_service(admin_rpc);
+    let resolved_addr = resolve(&ip)?;
+    server
+        .serve_with_shutdown(resolved_addr, async move {
+            info!(addr = %resolved_addr, "started relay");
+            let _ = sender.send(()).await;
+            receiver.recv().await;
+            info!("shut down relay service");
+        })
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,797 @@
+use crate::crdt::block::{Block, BlockID};
+use crate::crdt::e2e::{Sealed, SEALED_FILLER};
+use crate::crdt::suggestion::SuggestionState;
+use crate::crdt::utils::{CRDTError, CRDTResult, ClientID, Updates};
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use tracing::warn;
+
+// SignedInsert is the signature of a client over the characters it inserted at id,
+// between its origins
+//
+// blocks are split and squashed after they are inserted, so the signature covers
+// the original insertion and any block inside it can be checked against it
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct SignedInsert {
+    pub id: BlockID,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub left_origin: Option<BlockID>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub right_origin: Option<BlockID>,
+    pub content: String,
+    // encoded embed if the insertion is an embed, its payload is signed with the content
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub embed: Option<String>,
+    // set if the insertion is end-to-end encrypted, content is then SEALED_FILLER
+    // (or EMBED_PLACEHOLDER for an embed, which only gives away that it is one)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sealed: Option<Sealed>,
+    pub signature: String,
+}
+
+// SignedDelete is the signature of deleter over the characters
+// [target.clock, target.clock + len) of target.client it deleted
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct SignedDelete {
+    pub target: BlockID,
+    pub len: u32,
+    pub deleter: ClientID,
+    pub signature: String,
+}
+
+// SignedResolution is the signature of resolver over accepting (or rejecting) the suggestions
+// of the characters [target.clock, target.clock + len) of target.client
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct SignedResolution {
+    pub target: BlockID,
+    pub len: u32,
+    pub resolver: ClientID,
+    pub accept: bool,
+    pub signature: String,
+}
+
+// SignedFork is the signature of parent over the client editing a fork of the doc,
+// the fork signs its updates with the key of parent and acts on its behalf
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct SignedFork {
+    pub parent: ClientID,
+    pub fork: ClientID,
+    pub signature: String,
+}
+
+// Signatures are sent together with the blocks they cover
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Signatures {
+    pub inserts: Vec<SignedInsert>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deletes: Vec<SignedDelete>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub resolutions: Vec<SignedResolution>,
+    // forks of the clients that wrote the blocks
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub forks: Vec<SignedFork>,
+}
+
+// Signer holds the Ed25519 key of the local client
+pub struct Signer {
+    seed: [u8; 32],
+    key_pair: Ed25519KeyPair,
+}
+
+impl Signer {
+    pub fn generate() -> Self {
+        // a 32 byte seed is always a valid key
+        Signer::from_seed(rand::random()).unwrap()
+    }
+
+    pub fn from_seed(seed: [u8; 32]) -> CRDTResult<Self> {
+        let key_pair = Ed25519KeyPair::from_seed_unchecked(&seed)
+            .map_err(|e| CRDTError::InvalidKey(format!("invalid signing key {:?}", e)))?;
+        Ok(Signer { seed, key_pair })
+    }
+
+    // Load the key (hex encoded seed) from path,
+    // a new key is generated and saved if the file doesn't exist
+    pub fn from_file(path: &str) -> CRDTResult<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => {
+                let mut seed = [0u8; 32];
+                hex::decode_to_slice(content.trim(), &mut seed)?;
+                Signer::from_seed(seed)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let signer = Signer::generate();
+                std::fs::write(path, hex::encode(signer.seed))?;
+                Ok(signer)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // hex encoded public key, published through zookeeper
+    pub fn public_key(&self) -> String {
+        hex::encode(self.key_pair.public_key().as_ref())
+    }
+
+    // Sign an insertion without origins (at the start of an empty doc)
+    pub fn sign(&self, id: &BlockID, content: &str) -> SignedInsert {
+        self.sign_insert(id, None, None, content, None, None)
+    }
+
+    // a sealed insertion is signed as it is sent, so nodes without the key can verify it
+    pub fn sign_insert(
+        &self,
+        id: &BlockID,
+        left_origin: Option<BlockID>,
+        right_origin: Option<BlockID>,
+        content: &str,
+        embed: Option<String>,
+        sealed: Option<Sealed>,
+    ) -> SignedInsert {
+        let content = match &sealed {
+            Some(_) if embed.is_none() => SEALED_FILLER.to_string().repeat(content.len()),
+            _ => content.to_string(),
+        };
+        let mut insert = SignedInsert {
+            id: id.clone(),
+            left_origin,
+            right_origin,
+            content,
+            embed,
+            sealed,
+            signature: String::new(),
+        };
+        insert.signature = hex::encode(self.key_pair.sign(&insert_message(&insert)).as_ref());
+        insert
+    }
+
+    pub fn sign_delete(&self, target: &BlockID, len: u32, deleter: ClientID) -> SignedDelete {
+        let mut delete = SignedDelete {
+            target: target.clone(),
+            len,
+            deleter,
+            signature: String::new(),
+        };
+        delete.signature = hex::encode(self.key_pair.sign(&delete_message(&delete)).as_ref());
+        delete
+    }
+
+    pub fn sign_resolution(
+        &self,
+        target: &BlockID,
+        len: u32,
+        resolver: ClientID,
+        accept: bool,
+    ) -> SignedResolution {
+        let mut resolution = SignedResolution {
+            target: target.clone(),
+            len,
+            resolver,
+            accept,
+            signature: String::new(),
+        };
+        resolution.signature = hex::encode(
+            self.key_pair
+                .sign(&resolution_message(&resolution))
+                .as_ref(),
+        );
+        resolution
+    }
+
+    pub fn sign_fork(&self, parent: ClientID, fork: ClientID) -> SignedFork {
+        let mut signed = SignedFork {
+            parent,
+            fork,
+            signature: String::new(),
+        };
+        signed.signature = hex::encode(self.key_pair.sign(&fork_message(&signed)).as_ref());
+        signed
+    }
+}
+
+fn push_id(msg: &mut Vec<u8>, id: &Option<BlockID>) {
+    match id {
+        Some(id) => {
+            msg.push(1);
+            msg.extend_from_slice(&id.client.to_be_bytes());
+            msg.extend_from_slice(&id.clock.to_be_bytes());
+        }
+        None => msg.push(0),
+    }
+}
+
+// bytes covered by the signature of an insertion
+fn insert_message(insert: &SignedInsert) -> Vec<u8> {
+    let mut msg = b"insert".to_vec();
+    push_id(&mut msg, &Some(insert.id.clone()));
+    push_id(&mut msg, &insert.left_origin);
+    push_id(&mut msg, &insert.right_origin);
+    msg.extend_from_slice(insert.content.as_bytes());
+    // the content of an embed is a single byte, so the embed cannot be taken for content
+    if let Some(embed) = &insert.embed {
+        msg.extend_from_slice(embed.as_bytes());
+    }
+    if let Some(sealed) = &insert.sealed {
+        msg.extend_from_slice(&sealed.ciphertext);
+    }
+    msg
+}
+
+// bytes covered by the signature of a deletion
+fn delete_message(delete: &SignedDelete) -> Vec<u8> {
+    let mut msg = b"delete".to_vec();
+    push_id(&mut msg, &Some(delete.target.clone()));
+    msg.extend_from_slice(&delete.len.to_be_bytes());
+    msg.extend_from_slice(&delete.deleter.to_be_bytes());
+    msg
+}
+
+// bytes covered by the signature of a resolution
+fn resolution_message(resolution: &SignedResolution) -> Vec<u8> {
+    let mut msg = b"resolve".to_vec();
+    push_id(&mut msg, &Some(resolution.target.clone()));
+    msg.extend_from_slice(&resolution.len.to_be_bytes());
+    msg.extend_from_slice(&resolution.resolver.to_be_bytes());
+    msg.push(resolution.accept as u8);
+    msg
+}
+
+// bytes covered by the signature of a fork
+fn fork_message(fork: &SignedFork) -> Vec<u8> {
+    let mut msg = b"fork".to_vec();
+    msg.extend_from_slice(&fork.parent.to_be_bytes());
+    msg.extend_from_slice(&fork.fork.to_be_bytes());
+    msg
+}
+
+fn verify_message(public_key: &str, msg: &[u8], signature: &str) -> bool {
+    match (hex::decode(public_key), hex::decode(signature)) {
+        (Ok(public_key), Ok(signature)) => UnparsedPublicKey::new(&ED25519, public_key)
+            .verify(msg, &signature)
+            .is_ok(),
+        _ => false,
+    }
+}
+
+// Check the signature of insert against the hex encoded public key
+pub fn verify(public_key: &str, insert: &SignedInsert) -> bool {
+    verify_message(public_key, &insert_message(insert), &insert.signature)
+}
+
+// Check the signature of delete against the hex encoded public key of its deleter
+pub fn verify_delete(public_key: &str, delete: &SignedDelete) -> bool {
+    verify_message(public_key, &delete_message(delete), &delete.signature)
+}
+
+// Check the signature of resolution against the hex encoded public key of its resolver
+pub fn verify_resolution(public_key: &str, resolution: &SignedResolution) -> bool {
+    verify_message(
+        public_key,
+        &resolution_message(resolution),
+        &resolution.signature,
+    )
+}
+
+// Check the signature of fork against the hex encoded public key of its parent
+pub fn verify_fork(public_key: &str, fork: &SignedFork) -> bool {
+    verify_message(public_key, &fork_message(fork), &fork.signature)
+}
+
+// Unverified holds the signatures whose key is not known yet,
+// every candidate is kept since a forged one cannot be told apart from the real one
+#[derive(Clone, Default)]
+struct Unverified {
+    // by client and clock of the insertion
+    inserts: HashMap<ClientID, BTreeMap<u32, Vec<SignedInsert>>>,
+    // by client of the characters
+    deletes: HashMap<ClientID, Vec<SignedDelete>>,
+    resolutions: HashMap<ClientID, Vec<SignedResolution>>,
+    // by fork client
+    forks: HashMap<ClientID, Vec<SignedFork>>,
+}
+
+impl Unverified {
+    fn into_signatures(self) -> Signatures {
+        Signatures {
+            inserts: self
+                .inserts
+                .into_values()
+                .flat_map(|inserts| inserts.into_values().flatten())
+                .collect(),
+            deletes: self.deletes.into_values().flatten().collect(),
+            resolutions: self.resolutions.into_values().flatten().collect(),
+            forks: self.forks.into_values().flatten().collect(),
+        }
+    }
+}
+
+// Push value to values unless it is already there
+fn push_new<T: PartialEq>(values: &mut Vec<T>, value: T) {
+    if !values.contains(&value) {
+        values.push(value);
+    }
+}
+
+// SignatureStore keeps the signatures of all insertions known to the doc,
+// so they can be forwarded together with the blocks they cover
+//
+// updates are only verified if the local client signs its own updates,
+// signatures are stored once they are checked against the key of their client,
+// until the key is known they are kept aside and forwarded as they are (e.g. by a relay)
+#[derive(Clone, Default)]
+pub struct SignatureStore {
+    signer: Option<Arc<Signer>>,
+    pub public_keys: HashMap<ClientID, String>,
+    // signed insertions of every client, ordered by clock
+    inserts: HashMap<ClientID, BTreeMap<u32, SignedInsert>>,
+    // signed deletions of the characters of every client
+    deletes: HashMap<ClientID, Vec<SignedDelete>>,
+    // signed resolutions of the suggestions of the characters of every client
+    resolutions: HashMap<ClientID, Vec<SignedResolution>>,
+    // signed forks by fork client
+    forks: HashMap<ClientID, SignedFork>,
+    // signatures whose key is not known yet
+    unverified: Unverified,
+    // blocks rejected because their signature is missing or invalid
+    pub quarantine: Updates,
+}
+
+impl SignatureStore {
+    pub fn new() -> Self {
+        SignatureStore::default()
+    }
+
+    pub fn set_signer(&mut self, client: ClientID, signer: Arc<Signer>) {
+        self.signer = Some(signer.clone());
+        self.add_public_key(client, signer.public_key());
+    }
+
+    // Signatures of a fork of the doc edited by client, its updates are signed with the key of parent,
+    // which signs the fork so that other nodes know the key (and role) of client
+    pub fn fork(&self, parent: ClientID, client: ClientID) -> Self {
+        let mut store = self.clone();
+        if let Some(signer) = &self.signer {
+            store.public_keys.insert(client, signer.public_key());
+            store.forks.insert(client, signer.sign_fork(parent, client));
+        }
+        store
+    }
+
+    // Client on whose behalf client acts: the client that forked it (through every fork of a fork)
+    // if the forks are signed with its known key, client itself otherwise
+    pub fn owner_of(&self, client: ClientID) -> ClientID {
+        let mut chain = vec![];
+        let mut owner = client;
+        let mut visited = HashSet::from([client]);
+        while let Some(fork) = self.forks.get(&owner) {
+            if !visited.insert(fork.parent) {
+                break;
+            }
+            chain.push(fork);
+            owner = fork.parent;
+        }
+        match self.public_keys.get(&owner) {
+            Some(public_key) if chain.iter().all(|fork| verify_fork(public_key, fork)) => owner,
+            _ => client,
+        }
+    }
+
+    // Public key client signs with
+    fn key_of(&self, client: ClientID) -> Option<&String> {
+        self.public_keys
+            .get(&client)
+            .or_else(|| self.public_keys.get(&self.owner_of(client)))
+    }
+
+    pub fn verifying(&self) -> bool {
+        self.signer.is_some()
+    }
+
+    // Sign a local insertion, embed is the encoded embed if the insertion is one
+    pub fn sign(
+        &mut self,
+        id: &BlockID,
+        left_origin: Option<BlockID>,
+        right_origin: Option<BlockID>,
+        content: &str,
+        embed: Option<String>,
+        sealed: Option<Sealed>,
+    ) {
+        if let Some(signer) = &self.signer {
+            let insert = signer.sign_insert(id, left_origin, right_origin, content, embed, sealed);
+            self.insert(insert);
+        }
+    }
+
+    // Sign the local deletion of the characters [id.clock, id.clock + len) of id.client
+    pub fn sign_delete(&mut self, id: &BlockID, len: u32, deleter: ClientID) {
+        if let Some(signer) = &self.signer {
+            let delete = signer.sign_delete(id, len, deleter);
+            self.insert_delete(delete);
+        }
+    }
+
+    // Sign the local resolution of the suggestions of the characters [id.clock, id.clock + len) of id.client
+    pub fn sign_resolution(&mut self, id: &BlockID, len: u32, resolver: ClientID, accept: bool) {
+        if let Some(signer) = &self.signer {
+            let resolution = signer.sign_resolution(id, len, resolver, accept);
+            self.insert_resolution(resolution);
+        }
+    }
+
+    pub fn add_public_key(&mut self, client: ClientID, public_key: String) {
+        self.public_keys.insert(client, public_key);
+        self.check_unverified();
+    }
+
+    // Store signatures received from a peer,
+    // signatures that don't match the known key of their client are dropped
+    // and those whose key is not known yet are kept aside until it is
+    pub fn add(&mut self, signatures: Signatures) {
+        let forks = self.forks.len();
+        for fork in signatures.forks {
+            self.add_fork(fork);
+        }
+        // a fork tells the key of its client
+        if self.forks.len() > forks {
+            self.check_unverified();
+        }
+        for insert in signatures.inserts {
+            match self.key_of(insert.id.client) {
+                Some(public_key) if verify(public_key, &insert) => self.insert(insert),
+                Some(_) => warn!(block = ?insert.id, "dropped invalid signature"),
+                None => push_new(
+                    self.unverified
+                        .inserts
+                        .entry(insert.id.client)
+                        .or_default()
+                        .entry(insert.id.clock)
+                        .or_default(),
+                    insert,
+                ),
+            }
+        }
+        for delete in signatures.deletes {
+            match self.key_of(delete.deleter) {
+                Some(public_key) if verify_delete(public_key, &delete) => {
+                    self.insert_delete(delete)
+                }
+                Some(_) => {
+                    warn!(block = ?delete.target, "dropped invalid signature of a deletion")
+                }
+                None => push_new(
+                    self.unverified
+                        .deletes
+                        .entry(delete.target.client)
+                        .or_default(),
+                    delete,
+                ),
+            }
+        }
+        for resolution in signatures.resolutions {
+            match self.key_of(resolution.resolver) {
+                Some(public_key) if verify_resolution(public_key, &resolution) => {
+                    self.insert_resolution(resolution)
+                }
+                Some(_) => {
+                    warn!(block = ?resolution.target, "dropped invalid signature of a resolution")
+                }
+                None => push_new(
+                    self.unverified
+                        .resolutions
+                        .entry(resolution.target.client)
+                        .or_default(),
+                    resolution,
+                ),
+            }
+        }
+    }
+
+    // Store a fork signed with the key of its parent, the first one stored for a client is kept
+    fn add_fork(&mut self, fork: SignedFork) {
+        match self.key_of(fork.parent) {
+            Some(public_key) if verify_fork(public_key, &fork) => {
+                self.forks.entry(fork.fork).or_insert(fork);
+            }
+            Some(_) => warn!(fork = fork.fork, "dropped invalid signature of a fork"),
+            None => push_new(self.unverified.forks.entry(fork.fork).or_default(), fork),
+        }
+    }
+
+    // Check the signatures kept aside again, once a key is known
+    fn check_unverified(&mut self) {
+        let unverified = std::mem::take(&mut self.unverified);
+        self.add(unverified.into_signatures());
+    }
+
+    // Store a checked insertion, it replaces the one stored for the same id
+    // only if that one doesn't match the key of the client (e.g. the key changed)
+    fn insert(&mut self, insert: SignedInsert) {
+        let stored = self
+            .inserts
+            .get(&insert.id.client)
+            .and_then(|inserts| inserts.get(&insert.id.clock));
+        let keep = stored.is_some_and(|stored| {
+            *stored == insert
+                || self
+                    .key_of(insert.id.client)
+                    .is_some_and(|public_key| verify(public_key, stored))
+        });
+        if !keep {
+            self.inserts
+                .entry(insert.id.client)
+                .or_default()
+                .insert(insert.id.clock, insert);
+        }
+    }
+
+    fn insert_delete(&mut self, delete: SignedDelete) {
+        let deletes = self.deletes.entry(delete.target.client).or_default();
+        if !deletes.contains(&delete) {
+            deletes.push(delete);
+        }
+    }
+
+    fn insert_resolution(&mut self, resolution: SignedResolution) {
+        let resolutions = self
+            .resolutions
+            .entry(resolution.target.client)
+            .or_default();
+        if !resolutions.contains(&resolution) {
+            resolutions.push(resolution);
+        }
+    }
+
+    // Signed resolutions of the suggestions of the characters [id.clock, id.clock + len) of id.client
+    fn find_resolutions(&self, id: &BlockID, len: u32) -> impl Iterator<Item = &SignedResolution> {
+        overlapping(&self.resolutions, id, len)
+    }
+
+    // Signed deletions of the characters [id.clock, id.clock + len) of id.client
+    fn find_deletes(&self, id: &BlockID, len: u32) -> impl Iterator<Item = &SignedDelete> {
+        overlapping(&self.deletes, id, len)
+    }
+
+    // The signed insertion containing the character at id
+    fn find(&self, id: &BlockID) -> Option<&SignedInsert> {
+        let (clock, insert) = self
+            .inserts
+            .get(&id.client)?
+            .range(..=id.clock)
+            .next_back()?;
+        if id.clock < clock + insert.content.len() as u32 {
+            Some(insert)
+        } else {
+            None
+        }
+    }
+
+    // Check that every character of block was inserted and signed by its client,
+    // between the origins it was signed with, if block is deleted that every character
+    // was deleted and signed by the client the deletion is attributed to, and if its
+    // suggestion is resolved that every character was resolved and signed by its resolver
+    pub fn verify_block(&self, block: &Block) -> bool {
+        let public_key = match self.key_of(block.id.client) {
+            Some(public_key) => public_key,
+            None => return false,
+        };
+        let content = block.content.as_str().as_bytes();
+        let embed = block.content.embed().map(|embed| embed.encode());
+        let mut offset = 0;
+        while offset < content.len() {
+            let id = BlockID::new(block.id.client, block.id.clock + offset as u32);
+            let insert = match self.find(&id) {
+                Some(insert) => insert,
+                None => return false,
+            };
+            if offset == 0 && !self.within_origins(block, insert) {
+                return false;
+            }
+            let start = (id.clock - insert.id.clock) as usize;
+            let signed = &insert.content.as_bytes()[start..];
+            let len = signed.len().min(content.len() - offset);
+            if signed[..len] != content[offset..offset + len]
+                || insert.embed != embed
+                || insert.sealed != block.sealed
+                || !verify(public_key, insert)
+            {
+                return false;
+            }
+            offset += len;
+        }
+        (!block.is_deleted || self.verify_deletion(block)) && self.verify_resolution(block)
+    }
+
+    // A block starting an insertion has its origins, a block split from it
+    // follows a character of the same insertion, in both cases before its right origin
+    fn within_origins(&self, block: &Block, insert: &SignedInsert) -> bool {
+        let left_ok = if block.id.clock == insert.id.clock {
+            block.left_origin == insert.left_origin
+        } else {
+            block.left_origin.as_ref().is_some_and(|left| {
+                left.client == block.id.client
+                    && insert.id.clock <= left.clock
+                    && left.clock < block.id.clock
+            })
+        };
+        left_ok && block.right_origin == insert.right_origin
+    }
+
+    // Check that every character of the tombstone block was deleted by its deleter,
+    // an accepted suggestion is deleted by the owner that resolved it
+    fn verify_deletion(&self, block: &Block) -> bool {
+        let resolved_by = block
+            .suggestion
+            .and_then(|suggestion| suggestion.resolved_by);
+        let deleters = [block.deleted_by, resolved_by];
+        let len = block.content.len() as u32;
+        let mut covered = vec![false; len as usize];
+        for delete in self.find_deletes(&block.id, len) {
+            let public_key = match self.key_of(delete.deleter) {
+                Some(public_key) => public_key,
+                None => continue,
+            };
+            if !deleters.contains(&Some(delete.deleter)) || !verify_delete(public_key, delete) {
+                continue;
+            }
+            let start = delete.target.clock.max(block.id.clock) - block.id.clock;
+            let end = (delete.target.clock + delete.len).min(block.id.clock + len) - block.id.clock;
+            covered[start as usize..end as usize].fill(true);
+        }
+        covered.into_iter().all(|c| c)
+    }
+
+    // Check that every character of a block with a resolved suggestion was resolved
+    // that way by the client the resolution is attributed to
+    fn verify_resolution(&self, block: &Block) -> bool {
+        let suggestion = match block.suggestion {
+            Some(suggestion) if !suggestion.is_pending() => suggestion,
+            _ => return true,
+        };
+        let (resolver, public_key) = match suggestion
+            .resolved_by
+            .and_then(|resolver| Some((resolver, self.key_of(resolver)?)))
+        {
+            Some(resolver) => resolver,
+            None => return false,
+        };
+        let accept = suggestion.state == SuggestionState::Accepted;
+        let len = block.content.len() as u32;
+        let mut covered = vec![false; len as usize];
+        for resolution in self.find_resolutions(&block.id, len) {
+            if resolution.resolver != resolver
+                || resolution.accept != accept
+                || !verify_resolution(public_key, resolution)
+            {
+                continue;
+            }
+            let start = resolution.target.clock.max(block.id.clock) - block.id.clock;
+            let end = (resolution.target.clock + resolution.len).min(block.id.clock + len)
+                - block.id.clock;
+            covered[start as usize..end as usize].fill(true);
+        }
+        covered.into_iter().all(|c| c)
+    }
+
+    pub fn quarantine_block(&mut self, block: Block) {
+        if !self.quarantine.iter().any(|b| b.id == block.id) {
+            self.quarantine.push(block);
+        }
+    }
+
+    // Signatures of all insertions and deletions covering updates,
+    // along with the ones that cannot be checked here yet
+    pub fn signatures_for(&self, updates: &Updates) -> Signatures {
+        let mut res: BTreeMap<BlockID, SignedInsert> = BTreeMap::new();
+        let mut candidates: Vec<SignedInsert> = vec![];
+        let mut deletes: Vec<SignedDelete> = vec![];
+        let mut resolutions: Vec<SignedResolution> = vec![];
+        let mut forks: Vec<SignedFork> = vec![];
+        let mut fork_clients: HashSet<ClientID> = HashSet::new();
+        for block in updates {
+            let mut clients: Vec<ClientID> = [Some(block.id.client), block.deleted_by]
+                .into_iter()
+                .flatten()
+                .collect();
+            while let Some(client) = clients.pop() {
+                if !fork_clients.insert(client) {
+                    continue;
+                }
+                let unverified = self.unverified.forks.get(&client).into_iter().flatten();
+                for fork in self.forks.get(&client).into_iter().chain(unverified) {
+                    push_new(&mut forks, fork.clone());
+                    clients.push(fork.parent);
+                }
+            }
+            let len = block.content.len() as u32;
+            if block.is_deleted {
+                let unverified = overlapping(&self.unverified.deletes, &block.id, len);
+                for delete in self.find_deletes(&block.id, len).chain(unverified) {
+                    push_new(&mut deletes, delete.clone());
+                }
+            }
+            if block
+                .suggestion
+                .is_some_and(|suggestion| !suggestion.is_pending())
+            {
+                let unverified = overlapping(&self.unverified.resolutions, &block.id, len);
+                for resolution in self.find_resolutions(&block.id, len).chain(unverified) {
+                    push_new(&mut resolutions, resolution.clone());
+                }
+            }
+            let mut clock = block.id.clock;
+            let end = clock + len;
+            while clock < end {
+                match self.find(&BlockID::new(block.id.client, clock)) {
+                    Some(insert) => {
+                        clock = insert.id.clock + insert.content.len() as u32;
+                        res.insert(insert.id.clone(), insert.clone());
+                    }
+                    None => break,
+                }
+            }
+            if let Some(unverified) = self.unverified.inserts.get(&block.id.client) {
+                // the candidates starting before the block that cover its first character,
+                // and all those starting inside it
+                let before = unverified.range(..=block.id.clock).next_back();
+                let inside = unverified.range(block.id.clock + 1..end.max(block.id.clock + 1));
+                for insert in before
+                    .into_iter()
+                    .chain(inside)
+                    .flat_map(|(_, inserts)| inserts)
+                {
+                    if block.id.clock < insert.id.clock + insert.content.len() as u32 {
+                        push_new(&mut candidates, insert.clone());
+                    }
+                }
+            }
+        }
+        let mut inserts: Vec<SignedInsert> = res.into_values().collect();
+        for insert in candidates {
+            push_new(&mut inserts, insert);
+        }
+        Signatures {
+            inserts,
+            deletes,
+            resolutions,
+            forks,
+        }
+    }
+}
+
+// Signatures (of deletions or resolutions, by client of their characters)
+// overlapping the characters [id.clock, id.clock + len) of id.client
+fn overlapping<'a, T: Ranged>(
+    values: &'a HashMap<ClientID, Vec<T>>,
+    id: &BlockID,
+    len: u32,
+) -> impl Iterator<Item = &'a T> {
+    let (start, end) = (id.clock, id.clock + len);
+    values
+        .get(&id.client)
+        .into_iter()
+        .flatten()
+        .filter(move |value| {
+            let (target, value_len) = value.target();
+            target.clock < end && start < target.clock + value_len
+        })
+}
+
+// Ranged is a signature over a range of characters
+trait Ranged {
+    fn target(&self) -> (&BlockID, u32);
+}
+
+impl Ranged for SignedDelete {
+    fn target(&self) -> (&BlockID, u32) {
+        (&self.target, self.len)
+    }
+}
+
+impl Ranged for SignedResolution {
+    fn target(&self) -> (&BlockID, u32) {
+        (&self.target, self.len)
+    }
+}
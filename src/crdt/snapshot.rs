@@ -0,0 +1,284 @@
+use crate::crdt::auth::with_token;
+use crate::crdt::block::{Block, BlockID, ChangeID, Content};
+use crate::crdt::block_store::BlockStore;
+use crate::crdt::doc::{Doc, VectorClock};
+use crate::crdt::e2e::{KeyEpochs, Sealed};
+use crate::crdt::embed::Embed;
+use crate::crdt::metrics::metrics;
+use crate::crdt::signing::Signatures;
+use crate::crdt::suggestion::Suggestion;
+use crate::crdt::sync_txn::SyncTransaction;
+use crate::crdt::txn_rpc::{self, txn_service_client::TxnServiceClient};
+use crate::crdt::utils::{CRDTError, CRDTResult, ClientID, Peer, Updates};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::{info, instrument};
+
+// content given to deleted blocks whose content has been left out of a snapshot
+pub const TOMBSTONE_FILLER: char = '\u{0}';
+
+// DeleteSet is the deleted clocks of every client, as sorted [start, end) ranges
+pub type DeleteSet = HashMap<ClientID, Vec<(u32, u32)>>;
+
+// Deleted clocks of blocks, adjacent ranges are merged
+pub fn delete_set(blocks: &[Block]) -> DeleteSet {
+    let mut delete_set = DeleteSet::new();
+    for block in blocks.iter().filter(|block| block.is_deleted) {
+        let start = block.id.clock;
+        let end = start + block.content.len() as u32;
+        delete_set
+            .entry(block.id.client)
+            .or_default()
+            .push((start, end));
+    }
+    for ranges in delete_set.values_mut() {
+        ranges.sort_unstable();
+        let mut merged: Vec<(u32, u32)> = vec![];
+        for (start, end) in ranges.drain(..) {
+            match merged.last_mut() {
+                Some(last) if last.1 == start => last.1 = end,
+                _ => merged.push((start, end)),
+            }
+        }
+        *ranges = merged;
+    }
+    delete_set
+}
+
+// how a peer joining a doc gets its current state
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bootstrap {
+    // stream every block ever created, tombstones included
+    Stream,
+    // load a snapshot of the current state, then pull the updates made since
+    Snapshot,
+}
+
+// SnapshotBlock is a block of a snapshot, deleted blocks only keep their length
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SnapshotBlock {
+    pub id: BlockID,
+    pub left_origin: Option<BlockID>,
+    pub right_origin: Option<BlockID>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub content: String,
+    pub len: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_by: Option<ClientID>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suggestion: Option<Suggestion>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub embed: Option<Embed>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<ChangeID>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sealed: Option<Sealed>,
+}
+
+// Snapshot is the current state of a doc: its blocks in spatial order
+// (neighbours split from the same insertion are merged back), the clocks they cover
+// and the ranges that have been deleted
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Snapshot {
+    pub blocks: Vec<SnapshotBlock>,
+    pub vector_clock: VectorClock,
+    pub delete_set: DeleteSet,
+}
+
+impl Snapshot {
+    // Encode blocks in spatial order, the content of deleted blocks is only kept
+    // if keep_deleted is set (a signed tombstone is verified like any other block)
+    pub fn encode(blocks: Updates, keep_deleted: bool) -> Self {
+        let mut merged: Updates = vec![];
+        for block in blocks {
+            match merged.last_mut() {
+                Some(left) if left.merges_with(&block) => {
+                    left.content.push_str(block.content.as_str())
+                }
+                _ => merged.push(block),
+            }
+        }
+
+        let mut snapshot = Snapshot {
+            delete_set: delete_set(&merged),
+            ..Default::default()
+        };
+        for block in merged {
+            let len = block.content.len() as u32;
+            snapshot
+                .vector_clock
+                .advance(block.id.client, block.id.clock + len);
+            let (content, embed, sealed) = if block.is_deleted && (!keep_deleted || block.elided) {
+                (String::new(), None, None)
+            } else {
+                let embed = block.content.embed().cloned();
+                (block.content.as_str().to_string(), embed, block.sealed)
+            };
+            snapshot.blocks.push(SnapshotBlock {
+                content,
+                id: block.id,
+                left_origin: block.left_origin,
+                right_origin: block.right_origin,
+                len,
+                deleted_by: block.deleted_by,
+                suggestion: block.suggestion,
+                embed,
+                changes: block.changes,
+                sealed,
+            });
+        }
+        snapshot
+    }
+
+    // check if the characters [id.clock, id.clock + len) have been deleted
+    pub fn is_deleted(&self, id: &BlockID, len: u32) -> bool {
+        self.delete_set.get(&id.client).is_some_and(|ranges| {
+            ranges
+                .iter()
+                .any(|(start, end)| *start <= id.clock && id.clock + len <= *end)
+        })
+    }
+
+    // Blocks of the snapshot in spatial order, deleted content that has been left out
+    // is filled with TOMBSTONE_FILLER so that the clocks still add up, and marked as elided
+    pub fn decode(&self) -> Updates {
+        self.blocks
+            .iter()
+            .map(|block| {
+                let elided = block.content.is_empty() && block.len > 0;
+                let content = if elided {
+                    Content::Text(TOMBSTONE_FILLER.to_string().repeat(block.len as usize))
+                } else {
+                    match &block.embed {
+                        Some(embed) => Content::Embed(embed.clone()),
+                        None => Content::Text(block.content.clone()),
+                    }
+                };
+                Block {
+                    id: block.id.clone(),
+                    left_origin: block.left_origin.clone(),
+                    right_origin: block.right_origin.clone(),
+                    is_deleted: self.is_deleted(&block.id, block.len),
+                    deleted_by: block.deleted_by,
+                    suggestion: block.suggestion,
+                    changes: block.changes.clone(),
+                    sealed: block.sealed.clone(),
+                    elided,
+                    content,
+                }
+            })
+            .collect()
+    }
+}
+
+impl Doc {
+    // Snapshot of the current state of the doc, content leaving the doc is encrypted
+    pub async fn snapshot(&self) -> Snapshot {
+        let store = self.block_store.clone();
+        let store_lock = store.lock().await;
+        let mut blocks: Updates = vec![];
+        for block in store_lock.total_store.list.iter() {
+            blocks.push(block.lock().await.clone());
+        }
+        drop(store_lock);
+        let mut snapshot =
+            Snapshot::encode(self.encryption.encrypt(blocks), self.signatures.verifying());
+        // every change seen so far has been made to the blocks of the snapshot
+        snapshot.vector_clock.merge_changes(&self.vector_clock);
+        snapshot
+    }
+
+    // Replace the state of an empty doc with a snapshot,
+    // fails without changing the doc if some content cannot be decrypted
+    pub async fn load_snapshot(&mut self, snapshot: Snapshot) -> CRDTResult<()> {
+        if !self.vector_clock.clock_map.is_empty() || !self.pending_updates.is_empty() {
+            return Err(CRDTError::Storage(format!(
+                "cannot load a snapshot into {}, it is not empty",
+                self.name
+            )));
+        }
+        let mut blocks = vec![];
+        for (encoded, block) in snapshot.blocks.iter().zip(snapshot.decode()) {
+            // content that has been left out was never encrypted
+            if encoded.content.is_empty() {
+                blocks.push(block);
+                continue;
+            }
+            let id = block.id.clone();
+            match self.encryption.decrypt(vec![block]).pop() {
+                Some(block) => blocks.push(block),
+                None => {
+                    return Err(CRDTError::InvalidKey(format!(
+                        "cannot decrypt block {:?} of the snapshot",
+                        id
+                    )))
+                }
+            }
+        }
+        *self.block_store.lock().await = BlockStore::from_blocks(blocks);
+        self.vector_clock = snapshot.vector_clock;
+        Ok(())
+    }
+}
+
+impl SyncTransaction {
+    // load the snapshot of peer into the empty doc, the blocks are checked
+    // like the updates of update_remote and the snapshot is rejected as a whole
+    #[instrument(skip_all, fields(doc = %self.doc_name, client = self.client, peer = peer.client_id))]
+    pub async fn load_snapshot_from(&self, peer: &Peer) -> CRDTResult<()> {
+        let mut client = TxnServiceClient::new(self.connect(peer).await?);
+        let req = with_token(
+            txn_rpc::SnapshotRequest {
+                doc_name: self.doc_name.clone(),
+                client_id: self.client,
+            },
+            &self.token,
+        );
+        let sent = req.get_ref().encoded_len();
+        let resp = client.get_snapshot(req).await?.into_inner();
+        metrics().observe_rpc("get_snapshot", sent, resp.encoded_len());
+        let snapshot: Snapshot = serde_json::from_str(&resp.snapshot)?;
+        let signatures: Signatures = serde_json::from_str(&resp.signatures).unwrap_or_default();
+        let epochs: KeyEpochs = serde_json::from_str(&resp.key_epochs).unwrap_or_default();
+
+        let blocks = snapshot.decode();
+        let mut local_doc = self.doc.lock().await;
+        // the snapshot is checked against a scratch copy of the signatures,
+        // the doc is only changed once every check has passed
+        let mut scratch = local_doc.signatures.clone();
+        scratch.add(signatures);
+        if let Some(permissions) = &self.auth {
+            let (_, refused) =
+                permissions.filter_updates(&self.doc_name, None, blocks.clone(), |client| {
+                    scratch.owner_of(client)
+                });
+            if !refused.is_empty() {
+                return Err(CRDTError::PermissionDenied(
+                    "snapshot holds blocks of clients without edit access".to_string(),
+                ));
+            }
+        }
+        if scratch.verifying() {
+            let forged: Vec<BlockID> = blocks
+                .iter()
+                .filter(|block| !scratch.verify_block(block))
+                .map(|block| block.id.clone())
+                .collect();
+            if !forged.is_empty() {
+                return Err(CRDTError::Conflict(forged));
+            }
+        }
+        // the epochs are needed to open the blocks, they are dropped if one cannot be opened
+        let encryption = local_doc.encryption.clone();
+        local_doc.encryption.merge_epochs(epochs);
+        if let Err(e) = local_doc.load_snapshot(snapshot).await {
+            local_doc.encryption = encryption;
+            return Err(e);
+        }
+        local_doc.signatures = scratch;
+        metrics().observe_doc(&local_doc).await;
+        info!(blocks = blocks.len(), "loaded snapshot");
+        Ok(())
+    }
+}
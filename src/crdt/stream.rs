@@ -0,0 +1,279 @@
+use crate::crdt::auth::with_token;
+use crate::crdt::block::BlockID;
+use crate::crdt::doc::VectorClock;
+use crate::crdt::e2e::KeyEpochs;
+use crate::crdt::metrics::metrics;
+use crate::crdt::signing::Signatures;
+use crate::crdt::sync_txn::SyncTransaction;
+use crate::crdt::txn_rpc::{self, txn_service_client::TxnServiceClient};
+use crate::crdt::utils::{CRDTError, CRDTResult, ClientID, Peer, Updates};
+use prost::Message;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::time::Instant;
+use tokio::sync::mpsc::Sender;
+use tonic::transport::Channel;
+use tracing::{debug, info, instrument, warn};
+
+// number of blocks in a chunk requested by a joining peer
+pub const STREAM_CHUNK_SIZE: u32 = 500;
+// a chunk is closed once it holds this much content, even if it has fewer blocks
+pub const MAX_CHUNK_BYTES: usize = 1 << 20;
+// number of times an interrupted stream is resumed before giving up
+pub const STREAM_RETRIES: u32 = 5;
+
+// Order blocks so that the origins of a block and the previous blocks of its client
+// come before it, ties are broken by id so the order doesn't depend on the spatial one
+pub fn causal_order(blocks: Updates) -> Updates {
+    // blocks of every client sorted by clock, to find the block containing an id
+    let mut by_client: HashMap<ClientID, Vec<usize>> = HashMap::new();
+    for (i, block) in blocks.iter().enumerate() {
+        by_client.entry(block.id.client).or_default().push(i);
+    }
+    for indices in by_client.values_mut() {
+        indices.sort_by_key(|i| blocks[*i].id.clock);
+    }
+    let containing = |id: &BlockID| -> Option<usize> {
+        let indices = by_client.get(&id.client)?;
+        let pos = indices.partition_point(|i| blocks[*i].id.clock <= id.clock);
+        let i = *indices.get(pos.checked_sub(1)?)?;
+        let end = blocks[i].id.clock + blocks[i].content.len() as u32;
+        if id.clock < end {
+            Some(i)
+        } else {
+            None
+        }
+    };
+
+    let mut waiting = vec![0; blocks.len()];
+    let mut dependents: Vec<Vec<usize>> = vec![vec![]; blocks.len()];
+    for indices in by_client.values() {
+        for pair in indices.windows(2) {
+            waiting[pair[1]] += 1;
+            dependents[pair[0]].push(pair[1]);
+        }
+    }
+    for (i, block) in blocks.iter().enumerate() {
+        for origin in [&block.left_origin, &block.right_origin]
+            .into_iter()
+            .flatten()
+        {
+            // origins missing from blocks are already known to the receiver
+            if let Some(dep) = containing(origin).filter(|dep| *dep != i) {
+                waiting[i] += 1;
+                dependents[dep].push(i);
+            }
+        }
+    }
+
+    let mut ready: BinaryHeap<Reverse<(BlockID, usize)>> = (0..blocks.len())
+        .filter(|i| waiting[*i] == 0)
+        .map(|i| Reverse((blocks[i].id.clone(), i)))
+        .collect();
+    let mut order = Vec::with_capacity(blocks.len());
+    let mut sent = vec![false; blocks.len()];
+    while let Some(Reverse((_, i))) = ready.pop() {
+        order.push(i);
+        sent[i] = true;
+        for dep in &dependents[i] {
+            waiting[*dep] -= 1;
+            if waiting[*dep] == 0 {
+                ready.push(Reverse((blocks[*dep].id.clone(), *dep)));
+            }
+        }
+    }
+    // origins never form a cycle, but a corrupted store shouldn't lose blocks
+    let mut rest: Vec<usize> = (0..blocks.len()).filter(|i| !sent[*i]).collect();
+    rest.sort_by(|a, b| blocks[*a].id.cmp(&blocks[*b].id));
+    order.extend(rest);
+
+    let mut blocks: Vec<Option<_>> = blocks.into_iter().map(Some).collect();
+    order.into_iter().filter_map(|i| blocks[i].take()).collect()
+}
+
+// Split updates in chunks of at most size blocks and MAX_CHUNK_BYTES of content
+pub fn chunks(updates: Updates, size: usize) -> Vec<Updates> {
+    let mut chunks = vec![];
+    let (mut chunk, mut bytes): (Updates, usize) = (vec![], 0);
+    for block in updates {
+        if !chunk.is_empty() && (chunk.len() >= size || bytes >= MAX_CHUNK_BYTES) {
+            chunks.push(std::mem::take(&mut chunk));
+            bytes = 0;
+        }
+        bytes += block.content.len();
+        chunk.push(block);
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+impl SyncTransaction {
+    // Blocks with content missing from remote_clocks in causal order,
+    // and the other blocks changed by changes missing from remote_clocks after the id after
+    // (tombstones), ordered by id
+    //
+    // the doc is only locked while a page of the diff is computed, never while it is ordered
+    pub async fn causal_diff(
+        &self,
+        remote_clocks: &VectorClock,
+        after: Option<&BlockID>,
+    ) -> (Updates, Updates) {
+        let (mut blocks, mut tombstones) = (vec![], vec![]);
+        let mut page_after = None;
+        loop {
+            let (page, next) = {
+                let local_doc = self.doc.lock().await;
+                local_doc
+                    .compute_diff_page(remote_clocks, page_after, STREAM_CHUNK_SIZE as usize)
+                    .await
+            };
+            for block in page {
+                let end = block.id.clock + block.content.len() as u32;
+                if end > remote_clocks.get(block.id.client) {
+                    blocks.push(block);
+                } else if after.is_none_or(|after| block.id > *after) {
+                    tombstones.push(block);
+                }
+            }
+            match next {
+                Some(next) => page_after = Some(next),
+                None => break,
+            }
+        }
+        tombstones.sort_by(|a, b| a.id.cmp(&b.id));
+        (causal_order(blocks), tombstones)
+    }
+
+    // send the diff against remote_clocks in chunks through sender,
+    // the doc is only locked while a page of the diff is computed, not while it is encoded and sent
+    pub(crate) async fn send_stream(
+        &self,
+        remote_clocks: VectorClock,
+        after: Option<BlockID>,
+        chunk_size: u32,
+        sender: Sender<Result<txn_rpc::StreamChunk, tonic::Status>>,
+    ) -> CRDTResult<usize> {
+        let (blocks, tombstones) = self.causal_diff(&remote_clocks, after.as_ref()).await;
+        debug!(
+            blocks = blocks.len(),
+            tombstones = tombstones.len(),
+            "streaming diff"
+        );
+        let size = match chunk_size {
+            0 => STREAM_CHUNK_SIZE as usize,
+            size => size as usize,
+        };
+        let epochs = serde_json::to_string(&self.key_epochs().await)?;
+        let mut sent = 0;
+        let tagged = chunks(blocks, size)
+            .into_iter()
+            .map(|chunk| (chunk, false))
+            .chain(
+                chunks(tombstones, size)
+                    .into_iter()
+                    .map(|chunk| (chunk, true)),
+            );
+        for (updates, is_tombstones) in tagged {
+            // a resumed stream skips the tombstones up to the last one received
+            let after = match updates.last() {
+                Some(last) if is_tombstones => serde_json::to_string(&last.id)?,
+                _ => String::new(),
+            };
+            let chunk = txn_rpc::StreamChunk {
+                signatures: serde_json::to_string(&self.signatures_for(&updates).await)?,
+                updates: serde_json::to_string(&updates)?,
+                key_epochs: epochs.clone(),
+                after,
+            };
+            sent += chunk.encoded_len();
+            if sender.send(Ok(chunk)).await.is_err() {
+                return Err(CRDTError::Transport(
+                    "stream closed by the receiver".to_string(),
+                ));
+            }
+        }
+        Ok(sent)
+    }
+
+    // pull the whole diff of peer in chunks, an interrupted stream is resumed
+    // from the last chunk received if the peer is back after the connection backoff
+    #[instrument(skip_all, fields(doc = %self.doc_name, client = self.client, peer = peer.client_id))]
+    pub async fn stream_from(&self, peer: &Peer) -> CRDTResult<()> {
+        let mut after = String::new();
+        let mut retries = 0;
+        loop {
+            // a peer that is down fails the pull right away
+            let channel = self.connect(peer).await?;
+            match self.stream_once(channel, &mut after).await {
+                Err(e @ CRDTError::Transport(_)) if retries < STREAM_RETRIES => {
+                    retries += 1;
+                    self.disconnect(peer.client_id, &e).await;
+                    let delay = self
+                        .channels
+                        .lock()
+                        .await
+                        .get(&peer.client_id)
+                        .and_then(|conn| conn.retry_at)
+                        .map(|at| at.saturating_duration_since(Instant::now()))
+                        .unwrap_or_default();
+                    warn!(retries, retry_in = ?delay, error = %e, "stream interrupted, resuming");
+                    tokio::time::sleep(delay).await;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    // stream the diff from the current vector clock, the blocks received so far are not
+    // requested again and after is moved past every tombstone received
+    async fn stream_once(&self, channel: Channel, after: &mut String) -> CRDTResult<()> {
+        let mut client = TxnServiceClient::new(channel);
+        let clock_serialized = {
+            let local_doc = self.doc.lock().await;
+            serde_json::to_string(&local_doc.vector_clock)?
+        };
+        let req = with_token(
+            txn_rpc::StreamRequest {
+                doc_name: self.doc_name.clone(),
+                client_id: self.client,
+                vector_clock: clock_serialized,
+                chunk_size: STREAM_CHUNK_SIZE,
+                after: after.clone(),
+            },
+            &self.token,
+        );
+        let sent = req.get_ref().encoded_len();
+        let mut stream = client.stream_updates(req).await?.into_inner();
+        let (mut received, mut res, mut refused) = (0, Ok(()), None);
+        while let Some(chunk) = stream.message().await? {
+            received += chunk.encoded_len();
+            let updates: Updates = serde_json::from_str(&chunk.updates)?;
+            let signatures: Signatures =
+                serde_json::from_str(&chunk.signatures).unwrap_or_default();
+            let epochs: KeyEpochs = serde_json::from_str(&chunk.key_epochs).unwrap_or_default();
+            debug!(updates = updates.len(), "received chunk");
+            res = match self.update_remote(updates, signatures, epochs).await {
+                // pending blocks are integrated once their origins arrive
+                Err(e @ CRDTError::MissingDependency(_)) => Err(e),
+                // the rest of the stream is still integrated, the error is returned once it is over
+                Err(e) => {
+                    warn!(error = %e, "refused chunk");
+                    refused = Some(e);
+                    Ok(())
+                }
+                Ok(()) => Ok(()),
+            };
+            if !chunk.after.is_empty() {
+                *after = chunk.after;
+            }
+        }
+        metrics().observe_rpc("stream_updates", sent, received);
+        info!(received, "streamed diff");
+        match refused {
+            Some(e) => Err(e),
+            None => res,
+        }
+    }
+}
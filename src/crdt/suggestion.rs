@@ -0,0 +1,356 @@
+use crate::crdt::block::{Block, BlockID, Content};
+use crate::crdt::doc::Doc;
+use crate::crdt::sync_txn::SyncTransaction;
+use crate::crdt::utils::{CRDTError, CRDTResult, ClientID};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+
+// markers around pending suggestions in the rendered text
+pub const INSERT_MARKERS: (&str, &str) = ("{++", "++}");
+pub const DELETE_MARKERS: (&str, &str) = ("{--", "--}");
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum SuggestionKind {
+    Insert,
+    Delete,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum SuggestionState {
+    Pending,
+    Accepted,
+    Rejected,
+}
+
+// Suggestion marks a block as suggested by author instead of committed:
+// a suggested insertion is visible until it is rejected (then it is deleted),
+// a suggested deletion keeps the text visible until it is accepted (then it is deleted)
+//
+// suggestions only move forward (pending, then resolved) and concurrent changes are merged
+// by Suggestion::merge, so peers converge whatever order they receive them in
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Suggestion {
+    pub kind: SuggestionKind,
+    pub author: ClientID,
+    pub state: SuggestionState,
+    // client that accepted or rejected the suggestion
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolved_by: Option<ClientID>,
+}
+
+impl Suggestion {
+    pub fn new(kind: SuggestionKind, author: ClientID) -> Self {
+        Suggestion {
+            kind,
+            author,
+            state: SuggestionState::Pending,
+            resolved_by: None,
+        }
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.state == SuggestionState::Pending
+    }
+
+    // check if the suggestion has been resolved in a way that removes its text
+    pub fn removes_text(&self) -> bool {
+        matches!(
+            (self.kind, self.state),
+            (SuggestionKind::Insert, SuggestionState::Rejected)
+                | (SuggestionKind::Delete, SuggestionState::Accepted)
+        )
+    }
+
+    // total order of suggestions, merging keeps the largest one:
+    // 1. a deletion can only be suggested on text whose insertion is no longer pending
+    // 2. a resolution wins over pending, and the one removing text wins over the other
+    //    since the deletion it comes with cannot be undone
+    // 3. ties go to the smallest author, then the smallest resolver
+    fn rank(
+        &self,
+    ) -> (
+        SuggestionKind,
+        u8,
+        Reverse<ClientID>,
+        Reverse<Option<ClientID>>,
+    ) {
+        let state = match self.state {
+            SuggestionState::Pending => 0,
+            _ if self.removes_text() => 2,
+            _ => 1,
+        };
+        (
+            self.kind,
+            state,
+            Reverse(self.author),
+            Reverse(self.resolved_by),
+        )
+    }
+
+    pub fn merge(self, other: Suggestion) -> Suggestion {
+        if other.rank() > self.rank() {
+            other
+        } else {
+            self
+        }
+    }
+}
+
+// SuggestionSpan is a contiguous pending suggestion of the visible text,
+// its characters are [id.clock, id.clock + len) of id.client and pos counts bytes
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SuggestionSpan {
+    pub id: BlockID,
+    pub len: u32,
+    pub pos: u32,
+    pub kind: SuggestionKind,
+    pub author: ClientID,
+    pub text: String,
+}
+
+// Accept or reject the pending suggestion of block on behalf of by,
+// return true if the block got deleted
+fn resolve(block: &mut Block, accept: bool, by: ClientID) -> bool {
+    let mut suggestion = match block.suggestion {
+        Some(suggestion) if suggestion.is_pending() => suggestion,
+        _ => return false,
+    };
+    suggestion.state = if accept {
+        SuggestionState::Accepted
+    } else {
+        SuggestionState::Rejected
+    };
+    suggestion.resolved_by = Some(by);
+    block.suggest(suggestion);
+    if suggestion.removes_text() {
+        // an accepted deletion is attributed to the client that suggested it
+        let deleted_by = match suggestion.kind {
+            SuggestionKind::Delete => suggestion.author,
+            SuggestionKind::Insert => by,
+        };
+        block.delete_by(Some(deleted_by));
+    }
+    suggestion.removes_text()
+}
+
+// Characters of block as a range to sign the deletion of
+fn range_of(block: &Block) -> (BlockID, u32) {
+    (block.id.clone(), block.content.len() as u32)
+}
+
+impl Doc {
+    // Insert content at pos as a suggestion, return the id of its first character
+    pub async fn suggest_insert(&mut self, content: Content, pos: u32) -> BlockID {
+        let id = BlockID::new(self.client, self.vector_clock.get(self.client));
+        let len = content.len() as u32;
+        let client = self.client;
+        self.insert_local(content, pos).await;
+        self.block_store
+            .lock()
+            .await
+            .update_range(id.clone(), len, |block| {
+                block.suggest(Suggestion::new(SuggestionKind::Insert, client))
+            })
+            .await;
+        id
+    }
+
+    // Suggest deleting the visible characters [pos, pos + len), they stay visible until accepted
+    //
+    // suggested insertions of this client in the range are withdrawn (deleted),
+    // the ones of other clients and text whose deletion has already been resolved are left as is
+    //
+    // the suggestion is one change of the local client, so that peers that have seen the text get it
+    pub async fn suggest_delete(&mut self, pos: u32, len: u32) {
+        let client = self.client;
+        let runs = self.visible_runs(pos, len).await;
+        if runs.is_empty() {
+            return;
+        }
+        let change = self.next_change();
+        let mut store_lock = self.block_store.lock().await;
+        let mut withdrawn = vec![];
+        for (id, len) in runs {
+            store_lock
+                .update_range(id, len, |block| match block.suggestion {
+                    Some(suggestion)
+                        if suggestion.kind == SuggestionKind::Insert && suggestion.is_pending() =>
+                    {
+                        if suggestion.author == client {
+                            block.delete_by(Some(client));
+                            block.stamp(change);
+                            withdrawn.push(range_of(block));
+                        }
+                    }
+                    Some(suggestion)
+                        if suggestion.kind == SuggestionKind::Delete
+                            && !suggestion.is_pending() => {}
+                    _ => {
+                        block.suggest(Suggestion::new(SuggestionKind::Delete, client));
+                        block.stamp(change);
+                    }
+                })
+                .await;
+        }
+        for (id, len) in withdrawn {
+            self.signatures.sign_delete(&id, len, client);
+        }
+    }
+
+    // Accept the pending suggestions of the characters [id.clock, id.clock + len) of id.client:
+    // suggested insertions become plain text, suggested deletions are deleted
+    //
+    // return false if none of the characters can be found
+    pub async fn accept_suggestion(&mut self, id: &BlockID, len: u32) -> bool {
+        self.resolve_range(id, len, true).await
+    }
+
+    // Reject the pending suggestions of the characters [id.clock, id.clock + len) of id.client:
+    // suggested insertions are deleted, suggested deletions become plain text again
+    //
+    // return false if none of the characters can be found
+    pub async fn reject_suggestion(&mut self, id: &BlockID, len: u32) -> bool {
+        self.resolve_range(id, len, false).await
+    }
+
+    // the resolutions and the deletions they cause are signed by the resolver,
+    // resolving is one change of the local client so that peers that have seen the suggestions get it
+    async fn resolve_range(&mut self, id: &BlockID, len: u32, accept: bool) -> bool {
+        let client = self.client;
+        let change = self.next_change();
+        let mut resolved = vec![];
+        let mut deleted = vec![];
+        let found = self
+            .block_store
+            .lock()
+            .await
+            .update_range(id.clone(), len, |block| {
+                if !block
+                    .suggestion
+                    .is_some_and(|suggestion| suggestion.is_pending())
+                {
+                    return;
+                }
+                if resolve(block, accept, client) {
+                    deleted.push(range_of(block));
+                }
+                block.stamp(change);
+                resolved.push(range_of(block));
+            })
+            .await;
+        for (id, len) in resolved {
+            self.signatures.sign_resolution(&id, len, client, accept);
+        }
+        for (id, len) in deleted {
+            self.signatures.sign_delete(&id, len, client);
+        }
+        found
+    }
+
+    // Pending suggestions of the visible text in spatial order,
+    // neighbouring blocks of the same suggestion are merged
+    pub async fn suggestions(&self) -> Vec<SuggestionSpan> {
+        let store = self.block_store.clone();
+        let store_lock = store.lock().await;
+        let mut spans: Vec<SuggestionSpan> = vec![];
+        let mut pos = 0;
+        for block in store_lock.total_store.list.iter() {
+            let block_lock = block.lock().await;
+            if block_lock.is_deleted {
+                continue;
+            }
+            let content = block_lock.content.as_str();
+            let len = content.len() as u32;
+            match block_lock.suggestion {
+                Some(suggestion) if suggestion.is_pending() => match spans.last_mut() {
+                    Some(span)
+                        if span.pos + span.len == pos
+                            && span.kind == suggestion.kind
+                            && span.author == suggestion.author
+                            && span.id.client == block_lock.id.client
+                            && span.id.clock + span.len == block_lock.id.clock =>
+                    {
+                        span.len += len;
+                        span.text.push_str(content);
+                    }
+                    _ => spans.push(SuggestionSpan {
+                        id: block_lock.id.clone(),
+                        len,
+                        pos,
+                        kind: suggestion.kind,
+                        author: suggestion.author,
+                        text: content.to_string(),
+                    }),
+                },
+                _ => {}
+            }
+            pos += len;
+        }
+        spans
+    }
+
+    // Visible text with pending suggestions marked,
+    // e.g. "a {++new++} text {--old--}" (INSERT_MARKERS and DELETE_MARKERS)
+    pub async fn render_suggestions(&self) -> String {
+        let store = self.block_store.clone();
+        let store_lock = store.lock().await;
+        let mut res = String::new();
+        let mut open: Option<SuggestionKind> = None;
+        for block in store_lock.total_store.list.iter() {
+            let block_lock = block.lock().await;
+            if block_lock.is_deleted || block_lock.content.is_empty() {
+                continue;
+            }
+            let kind = block_lock
+                .suggestion
+                .filter(|suggestion| suggestion.is_pending())
+                .map(|suggestion| suggestion.kind);
+            if kind != open {
+                if let Some(kind) = open {
+                    res.push_str(markers(kind).1);
+                }
+                if let Some(kind) = kind {
+                    res.push_str(markers(kind).0);
+                }
+                open = kind;
+            }
+            res.push_str(block_lock.content.as_str());
+        }
+        if let Some(kind) = open {
+            res.push_str(markers(kind).1);
+        }
+        res
+    }
+}
+
+fn markers(kind: SuggestionKind) -> (&'static str, &'static str) {
+    match kind {
+        SuggestionKind::Insert => INSERT_MARKERS,
+        SuggestionKind::Delete => DELETE_MARKERS,
+    }
+}
+
+impl SyncTransaction {
+    // Accept (or reject) the pending suggestions of the characters [id.clock, id.clock + len) of id.client,
+    // only owners of the doc can resolve suggestions
+    pub async fn resolve_suggestion(&self, id: &BlockID, len: u32, accept: bool) -> CRDTResult<()> {
+        if !self.can_resolve(self.client) {
+            return Err(CRDTError::PermissionDenied(format!(
+                "client {} cannot resolve suggestions of {}",
+                self.client, self.doc_name
+            )));
+        }
+        let mut local_doc = self.doc.lock().await;
+        let found = if accept {
+            local_doc.accept_suggestion(id, len).await
+        } else {
+            local_doc.reject_suggestion(id, len).await
+        };
+        if !found {
+            return Err(CRDTError::MissingDependency(vec![id.clone()]));
+        }
+        Ok(())
+    }
+}
@@ -1,30 +1,67 @@
+use crate::crdt::anti_entropy::AntiEntropyConfig;
+use crate::crdt::auth::{check_role, with_token, Caller, Permissions, Role};
+use crate::crdt::awareness::CursorState;
+use crate::crdt::block::BlockID;
+use crate::crdt::comment::{Comment, CommentClock};
+use crate::crdt::conn::{ConnConfig, Connections};
 use crate::crdt::doc::Doc;
 use crate::crdt::doc::VectorClock;
+use crate::crdt::e2e::{KeyEpochs, KeyRing};
+use crate::crdt::gossip::Gossip;
+use crate::crdt::metrics::metrics;
+use crate::crdt::relay::RELAY_CLIENT;
+use crate::crdt::signing::{Signatures, Signer};
+use crate::crdt::snapshot::Bootstrap;
+use crate::crdt::tls::{check_peer, TlsConfig};
 use crate::crdt::txn_rpc;
 use crate::crdt::txn_rpc::txn_service_client::TxnServiceClient;
 use crate::crdt::txn_rpc::txn_service_server::TxnService;
-use crate::crdt::utils::Peer;
-use crate::crdt::utils::{ClientID, Updates};
+use crate::crdt::utils::{CRDTError, CRDTResult, ClientID, Updates};
+use crate::crdt::utils::{Peer, PeerDisplay};
 use crate::crdt::zk_conn::ZooKeeperConnection;
-use std::collections::HashMap;
+use prost::Message;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::Mutex;
-use tonic::transport::Channel;
-use tonic::transport::Endpoint;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{debug, info, instrument, warn};
 
 // SyncTransaction is used to sync updates (insertion and deletion) among different clients
 //
 // IMPORTANT: SyncTransaction will take in a created Doc and modify its states
+#[derive(Clone)]
 pub struct SyncTransaction {
     // local copy of the doc
     pub doc_name: String,
     pub doc: Arc<Mutex<Doc>>,
-    pub channels: Arc<Mutex<HashMap<ClientID, Channel>>>,
+    pub channels: Arc<Mutex<Connections>>,
+    // timeouts and backoff of the channels
+    pub conn: ConnConfig,
     // zookeeper utils
     pub zk: ZooKeeperConnection,
     // unique identifier for this client
     pub client: ClientID,
     pub client_ip: String,
+    // if set, all updates are exchanged through the relay instead of peers
+    pub relay: Option<Peer>,
+    // if set, rpc callers are authenticated and need a role on the doc
+    pub auth: Option<Arc<Permissions>>,
+    // token sent with every rpc made by this client
+    pub token: Option<String>,
+    // if set, rpc channels are encrypted (and callers verified with mutual tls)
+    pub tls: Option<Arc<TlsConfig>>,
+    // if set, peers are pulled from in the background while the rpc service runs
+    pub anti_entropy: Option<AntiEntropyConfig>,
+    // if set, updates are gossiped instead of pulled from every peer
+    pub gossip: Option<Arc<Gossip>>,
+    // how the state of the doc is pulled when joining it
+    pub bootstrap: Bootstrap,
+    // comments every peer had at the end of the last sync with it
+    pub comment_clocks: Arc<Mutex<HashMap<ClientID, CommentClock>>>,
+    // vector clock every peer reported at the end of the last pull from it,
+    // tombstones are only collected once every peer has seen them
+    pub peer_clocks: Arc<Mutex<HashMap<ClientID, VectorClock>>>,
 }
 
 impl SyncTransaction {
@@ -32,26 +69,138 @@ impl SyncTransaction {
         doc_name: String,
         client: ClientID,
         doc: Arc<Mutex<Doc>>,
-        channels: Arc<Mutex<HashMap<ClientID, Channel>>>,
+        channels: Arc<Mutex<Connections>>,
         client_ip: String,
     ) -> Self {
         SyncTransaction {
-            doc_name: doc_name,
-            doc: doc,
-            channels: channels,
-            client: client,
+            doc_name,
+            doc,
+            channels,
+            conn: ConnConfig::default(),
+            client,
             client_ip: client_ip.clone(),
             zk: ZooKeeperConnection {
-                client_ip: client_ip,
+                client_ip,
+                client,
+                token: None,
+                tls: None,
+                public_key: None,
+                display: None,
             },
+            relay: None,
+            auth: None,
+            token: None,
+            tls: None,
+            anti_entropy: None,
+            gossip: None,
+            bootstrap: Bootstrap::Stream,
+            comment_clocks: Arc::new(Mutex::new(HashMap::new())),
+            peer_clocks: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
+    // Authenticate rpc callers with permissions
+    pub fn set_permissions(&mut self, permissions: Arc<Permissions>) {
+        self.auth = Some(permissions);
+    }
+
+    // Send token with every rpc (including the ones made by the zookeeper watcher)
+    pub fn set_token(&mut self, token: String) {
+        self.token = Some(token.clone());
+        self.zk.token = Some(token);
+    }
+
+    // Serve and connect over tls (including the zookeeper watcher)
+    pub fn set_tls(&mut self, tls: Arc<TlsConfig>) {
+        self.tls = Some(tls.clone());
+        self.zk.tls = Some(tls);
+    }
+
+    // Sign local insertions with signer and only accept remote blocks signed by their client,
+    // the public key is published through zookeeper when registering
+    pub async fn set_signer(&mut self, signer: Arc<Signer>) {
+        self.zk.public_key = Some(signer.public_key());
+        let mut local_doc = self.doc.lock().await;
+        local_doc.signatures.set_signer(self.client, signer);
+    }
+
+    // Show the client to its peers with display, published through zookeeper when registering
+    pub fn set_display(&mut self, display: PeerDisplay) {
+        self.zk.display = Some(display);
+    }
+
+    // Encrypt content leaving the doc with keys,
+    // if keys is None they are requested from peers during the next sync
+    pub async fn enable_encryption(&self, keys: Option<KeyRing>) {
+        let mut local_doc = self.doc.lock().await;
+        local_doc.encryption.enable(keys);
+    }
+
+    // Encrypt new content with a new version of the doc key,
+    // only peers allowed to read the doc can fetch the new version
+    pub async fn rotate_key(&self) -> u32 {
+        let mut local_doc = self.doc.lock().await;
+        local_doc.encryption.keys.rotate()
+    }
+
+    // Connect to peers and check the health of channels with config
+    pub fn set_conn_config(&mut self, config: ConnConfig) {
+        self.conn = config;
+    }
+
+    // Pull from peers in the background with config once the rpc service is started
+    pub fn set_anti_entropy(&mut self, config: AntiEntropyConfig) {
+        self.anti_entropy = Some(config);
+    }
+
+    // Gossip updates to a few random peers instead of pulling from every peer,
+    // the transactions of a client need to share the same gossip state
+    pub fn set_gossip(&mut self, gossip: Arc<Gossip>) {
+        self.gossip = Some(gossip);
+    }
+
+    // Load a snapshot of a peer instead of every block ever created when joining the doc
+    pub fn set_bootstrap(&mut self, bootstrap: Bootstrap) {
+        self.bootstrap = bootstrap;
+    }
+
+    // Exchange updates through the relay server at relay_ip only,
+    // the client doesn't need to be reachable by other peers
+    pub fn set_relay(&mut self, relay_ip: String) {
+        self.relay = Some(Peer {
+            client_id: RELAY_CLIENT,
+            ip_addr: relay_ip,
+            public_key: None,
+            display: None,
+        });
+    }
+
     // request all updates from its peers and deduplicate
     // resolve all conflicts
-    pub async fn sync(&self) {
+    //
+    // a failing peer doesn't stop the others from being synced,
+    // the first error is returned once all peers have been tried
+    #[instrument(skip_all, fields(doc = %self.doc_name, client = self.client))]
+    pub async fn sync(&self) -> CRDTResult<()> {
+        let mut res = Ok(());
+        if self.doc.lock().await.encryption.needs_keys() {
+            // updates are still pulled, they are decrypted once the keys arrive
+            res = self.fetch_keys().await;
+        }
+        let round = match (self.relay.clone(), &self.gossip) {
+            (Some(relay), _) => self.push_pull(&relay).await,
+            // updates are pushed by peers, one digest exchange repairs what has been missed
+            (None, Some(_)) => self.exchange_digest().await,
+            (None, None) => self.sync_peers().await,
+        };
+        self.observe().await;
+        res.and(round)
+    }
+
+    // pull updates from every peer
+    async fn sync_peers(&self) -> CRDTResult<()> {
+        let mut res = Ok(());
         // get all the peers that are editing the same doc
-        let mut real_channel = self.channels.lock().await;
         let peers;
         {
             let real_doc = self.doc.lock().await;
@@ -60,221 +209,910 @@ impl SyncTransaction {
 
         // for all peers call on rpc to get all updates
         for client in peers.into_iter() {
-            if client.client_id == client.client_id {
+            if client.client_id == self.client {
                 continue;
             }
-            // if connection already established, reuse the connection
-            let conn = real_channel.get(&client.client_id);
-            match conn {
-                Some(_) => {
-                    // just reuse
-                }
-                None => {
-                    let http_path = format!("http://{}", client.ip_addr);
-                    let endpoint = Endpoint::from_shared(http_path);
-                    if let Ok(ep) = endpoint {
-                        let temp = ep.connect().await;
-                        if let Ok(ch) = temp {
-                            (*real_channel).insert(client.client_id, ch);
-                        }
-                    }
+            if let Err(e) = self.pull(&client).await {
+                warn!(peer = client.client_id, error = %e, "failed to sync with peer");
+                if res.is_ok() {
+                    res = Err(e);
                 }
             }
+        }
+        res
+    }
 
-            if let Some(new_channel) = real_channel.get(&client.client_id) {
-                let mut client = TxnServiceClient::new(new_channel.clone());
-                let clock_serialized;
-                {
+    // pull updates from one peer only (from the relay if one is set)
+    pub async fn sync_with(&self, client: ClientID) -> CRDTResult<()> {
+        let round = match self.relay.clone() {
+            Some(relay) => self.push_pull(&relay).await,
+            None => {
+                let peer = {
                     let local_doc = self.doc.lock().await;
-                    clock_serialized = serde_json::to_string(&local_doc.vector_clock);
-                }
-                match clock_serialized {
-                    // serialize the local vector clock send our through rpc
-                    Ok(clock_serialized) => {
-                        let req = tonic::Request::new(txn_rpc::PullRequest {
-                            client_id: self.client,
-                            vector_clock: clock_serialized,
-                        });
-                        let resp = client.get_remote_updates(req).await;
-                        match resp {
-                            Ok(value) => {
-                                let remote_updates: Result<Updates, serde_json::Error> =
-                                    serde_json::from_str(&value.into_inner().updates);
-                                match remote_updates {
-                                    Ok(remote_updates) => {
-                                        self.update_remote(remote_updates).await;
-                                    }
-                                    Err(_) => println!("serde deserialization error"),
-                                }
-                            }
-                            Err(_) => println!("rpc error"),
-                        };
+                    local_doc
+                        .peers
+                        .iter()
+                        .find(|peer| peer.client_id == client)
+                        .cloned()
+                };
+                let peer = peer.ok_or_else(|| {
+                    CRDTError::Membership(format!("{} is not a peer of {}", client, self.doc_name))
+                })?;
+                self.pull(&peer).await
+            }
+        };
+        self.observe().await;
+        round
+    }
+
+    // refresh the metrics of the doc and its connections
+    pub(crate) async fn observe(&self) {
+        let connected = {
+            let channels = self.channels.lock().await;
+            channels.values().filter(|conn| conn.is_connected()).count() as i64
+        };
+        metrics()
+            .connected_peers
+            .with_label_values(&[&self.doc_name])
+            .set(connected);
+        let local_doc = self.doc.lock().await;
+        metrics().observe_doc(&local_doc).await;
+    }
+
+    // pull the updates of peer that are missing locally
+    async fn pull(&self, peer: &Peer) -> CRDTResult<()> {
+        // a peer joining the doc streams the whole history in chunks,
+        // or loads the current state and only pulls what changed since
+        if self.doc.lock().await.vector_clock.clock_map.is_empty() {
+            match self.bootstrap {
+                Bootstrap::Stream => return self.stream_from(peer).await,
+                Bootstrap::Snapshot => {
+                    if let Err(e) = self.load_snapshot_from(peer).await {
+                        warn!(error = %e, "failed to load snapshot, streaming history instead");
+                        return self.stream_from(peer).await;
                     }
-                    Err(_) => println!("serde serialization error"),
                 }
             }
         }
+        self.pull_page(peer, None, 0).await.map(|_| ())
     }
 
-    // update peers' modifications on local copy
-    // don't need to deal with conflicts
-    pub async fn update_remote(&self, updates: Updates) {
-        let mut delete_list: Updates = vec![];
-        let mut update_list: Updates = vec![];
-        for update in updates {
-            if update.is_deleted {
-                delete_list.push(update);
-            } else {
-                update_list.push(update);
+    // pull one page of at most limit updates of peer (the whole diff if limit is 0),
+    // starting after the block id after, return the id the next page starts after,
+    // None once the whole diff has been pulled
+    #[instrument(skip_all, fields(doc = %self.doc_name, client = self.client, peer = peer.client_id, after = ?after))]
+    pub(crate) async fn pull_page(
+        &self,
+        peer: &Peer,
+        after: Option<BlockID>,
+        limit: u32,
+    ) -> CRDTResult<Option<BlockID>> {
+        // if connection already established, reuse the connection
+        let mut client = TxnServiceClient::new(self.connect(peer).await?);
+        // serialize the local vector clock send our through rpc
+        let clock_serialized = {
+            let local_doc = self.doc.lock().await;
+            serde_json::to_string(&local_doc.vector_clock)?
+        };
+        let req = with_token(
+            txn_rpc::PullRequest {
+                client_id: self.client,
+                vector_clock: clock_serialized,
+                doc_name: self.doc_name.clone(),
+                after: match &after {
+                    Some(after) => serde_json::to_string(after)?,
+                    None => String::new(),
+                },
+                limit,
+            },
+            &self.token,
+        );
+        let sent = req.get_ref().encoded_len();
+        let start = Instant::now();
+        let resp = match self.pull_with_deadline(peer, &mut client, req).await {
+            Ok(resp) => resp,
+            Err(e) => {
+                self.disconnect(peer.client_id, &e).await;
+                return Err(e);
             }
+        };
+        metrics().observe_rtt(&self.doc_name, peer.client_id, start.elapsed());
+        metrics().observe_rpc("get_remote_updates", sent, resp.encoded_len());
+        let remote_updates: Updates = serde_json::from_str(&resp.updates)?;
+        let next: Option<BlockID> = match resp.after.as_str() {
+            "" => None,
+            next => Some(serde_json::from_str(next)?),
+        };
+        debug!(updates = remote_updates.len(), next = ?next, "pulled updates");
+        let signatures: Signatures = serde_json::from_str(&resp.signatures).unwrap_or_default();
+        let epochs: KeyEpochs = serde_json::from_str(&resp.key_epochs).unwrap_or_default();
+        // the changes seen by the peer are only seen here once its whole diff has been integrated
+        let res = if after.is_none() && next.is_none() {
+            let peer_clock: VectorClock = serde_json::from_str(&resp.vector_clock)?;
+            self.record_peer_clock(peer.client_id, &peer_clock).await;
+            self.integrate_diff(None, remote_updates, signatures, epochs, &peer_clock)
+                .await
+        } else {
+            self.update_remote(remote_updates, signatures, epochs).await
+        };
+        match res {
+            // the origins can still be in the next pages
+            Err(CRDTError::MissingDependency(_)) if next.is_some() => Ok(next),
+            res => res.map(|_| next),
         }
+    }
 
-        let mut local_doc = self.doc.lock().await;
-        local_doc.insert_remote(update_list).await;
-        local_doc.delete_remote(delete_list).await;
+    // pull updates from the relay (or a peer), then push the updates it hasn't seen
+    #[instrument(skip_all, fields(doc = %self.doc_name, client = self.client, peer = peer.client_id))]
+    pub(crate) async fn push_pull(&self, peer: &Peer) -> CRDTResult<()> {
+        let (peer_clock, res) = self.pull_diff(peer).await?;
+
+        // the peer has integrated everything it sent,
+        // so the diff only contains what the peer is missing
+        let local_clock = self.doc.lock().await.vector_clock.clone();
+        let updates = self.compute_diff(peer_clock).await;
+        if updates.is_empty() {
+            return res;
+        }
+        let mut client = TxnServiceClient::new(self.connect(peer).await?);
+        let signatures = self.signatures_for(&updates).await;
+        let epochs = self.key_epochs().await;
+        let req = with_token(
+            txn_rpc::PushRequest {
+                doc_name: self.doc_name.clone(),
+                client_id: self.client,
+                updates: serde_json::to_string(&updates)?,
+                signatures: serde_json::to_string(&signatures)?,
+                key_epochs: serde_json::to_string(&epochs)?,
+                vector_clock: serde_json::to_string(&local_clock)?,
+            },
+            &self.token,
+        );
+        let sent = req.get_ref().encoded_len();
+        let resp = client.push_updates(req).await?.into_inner();
+        metrics().observe_rpc("push_updates", sent, resp.encoded_len());
+        debug!(updates = updates.len(), "pushed updates");
+        res
     }
 
-    // takes in a vector clock, compare with its own vector clock,
-    // compute updates that need to be send
-    pub async fn compute_diff(&self, remote_clocks: VectorClock) -> Updates {
-        // get its own state vector
-        let local_clocks;
-        {
+    // pull the whole diff of peer, return the vector clock the peer reported
+    // together with the result of integrating the diff
+    pub(crate) async fn pull_diff(&self, peer: &Peer) -> CRDTResult<(VectorClock, CRDTResult<()>)> {
+        let mut client = TxnServiceClient::new(self.connect(peer).await?);
+
+        let clock_serialized = {
             let local_doc = self.doc.lock().await;
-            local_clocks = local_doc.vector_clock.clone();
-        }
-        let mut res: Updates = vec![];
-
-        // compute the difference
-        for (client_id, local_clock) in local_clocks.clock_map.into_iter() {
-            let remote_clock = remote_clocks.clock_map.get(&client_id);
-            match remote_clock {
-                Some(remote_clock) => {
-                    if *remote_clock < local_clock {
-                        // need to send the remaining part to the counterpart
-                        res.extend(self.construct_updates(*remote_clock + 1, client_id).await);
+            serde_json::to_string(&local_doc.vector_clock)?
+        };
+        let req = with_token(
+            txn_rpc::PullRequest {
+                client_id: self.client,
+                vector_clock: clock_serialized,
+                doc_name: self.doc_name.clone(),
+                after: String::new(),
+                limit: 0,
+            },
+            &self.token,
+        );
+        let sent = req.get_ref().encoded_len();
+        let start = Instant::now();
+        let resp = match self.pull_with_deadline(peer, &mut client, req).await {
+            Ok(resp) => resp,
+            Err(e) => {
+                self.disconnect(peer.client_id, &e).await;
+                return Err(e);
+            }
+        };
+        metrics().observe_rtt(&self.doc_name, peer.client_id, start.elapsed());
+        metrics().observe_rpc("get_remote_updates", sent, resp.encoded_len());
+
+        let remote_updates: Updates = serde_json::from_str(&resp.updates)?;
+        debug!(updates = remote_updates.len(), "pulled updates");
+        let peer_clock: VectorClock = serde_json::from_str(&resp.vector_clock)?;
+        self.record_peer_clock(peer.client_id, &peer_clock).await;
+        let signatures: Signatures = serde_json::from_str(&resp.signatures).unwrap_or_default();
+        let epochs: KeyEpochs = serde_json::from_str(&resp.key_epochs).unwrap_or_default();
+        // blocks waiting for their origins don't prevent sending the peer what it is missing
+        let res = self
+            .integrate_diff(None, remote_updates, signatures, epochs, &peer_clock)
+            .await;
+        Ok((peer_clock, res))
+    }
+
+    // request the key ring of the doc from peers (the relay never has it),
+    // fails with the last error if no peer could provide the missing keys
+    #[instrument(skip_all, fields(doc = %self.doc_name, client = self.client))]
+    pub async fn fetch_keys(&self) -> CRDTResult<()> {
+        let peers = self.doc.lock().await.peers.clone();
+        let mut res = Ok(());
+        for peer in peers.into_iter() {
+            if peer.client_id == self.client {
+                continue;
+            }
+            match self.fetch_keys_from(&peer).await {
+                Ok(keys) => {
+                    let mut local_doc = self.doc.lock().await;
+                    local_doc.encryption.add_keys(keys);
+                    info!(peer = peer.client_id, "received doc key");
+                    if !local_doc.encryption.needs_keys() {
+                        return Ok(());
                     }
                 }
-                None => {
-                    // need to forword all they have to the requester
-                    res.extend(self.construct_updates(0, client_id).await);
+                Err(e) => {
+                    warn!(peer = peer.client_id, error = %e, "failed to get doc key");
+                    res = Err(e);
                 }
             }
         }
+        res
+    }
 
-        return res;
+    async fn fetch_keys_from(&self, peer: &Peer) -> CRDTResult<KeyRing> {
+        let mut client = TxnServiceClient::new(self.connect(peer).await?);
+        let req = with_token(
+            txn_rpc::KeyRequest {
+                doc_name: self.doc_name.clone(),
+                client_id: self.client,
+            },
+            &self.token,
+        );
+        let sent = req.get_ref().encoded_len();
+        let resp = client.get_doc_key(req).await?.into_inner();
+        metrics().observe_rpc("get_doc_key", sent, resp.encoded_len());
+        Ok(serde_json::from_str(&resp.keys)?)
     }
 
-    // given a diff range, consult the block store and find all the updates
-    // need to send to the counterpart
-    async fn construct_updates(&self, start: u32, client: ClientID) -> Updates {
-        // go to block store and get the updates
-        let local_doc = self.doc.lock().await;
-        let block_store = local_doc.block_store.lock().await;
-        let list = block_store.kv_store.get(&client).clone();
-
-        let mut res: Updates = vec![];
-        match list {
-            Some(list) => {
-                let block = list.list.get((start as usize)..list.list.len());
-                match block {
-                    Some(block) => {
-                        let vec = block.iter();
-                        for b in vec {
-                            let b_lock = b.lock().await;
-                            res.push(b_lock.clone());
-                        }
-                    }
-                    None => {
-                        // should not happen
-                    }
+    // push the local cursor to all peers and merge the cursors they know of,
+    // the first error is returned once all peers have been tried
+    #[instrument(skip_all, fields(doc = %self.doc_name, client = self.client))]
+    pub async fn sync_awareness(&self) -> CRDTResult<()> {
+        let (peers, states_serialized) = {
+            let real_doc = self.doc.lock().await;
+            let peers = match &self.relay {
+                Some(relay) => vec![relay.clone()],
+                None => real_doc.peers.clone(),
+            };
+            (
+                peers,
+                serde_json::to_string(&real_doc.awareness.get_states())?,
+            )
+        };
+
+        let mut res = Ok(());
+        for peer in peers.into_iter() {
+            if peer.client_id == self.client {
+                continue;
+            }
+            let peer_res = self
+                .exchange_awareness(&peer, states_serialized.clone())
+                .await;
+            if let Err(e) = peer_res {
+                warn!(peer = peer.client_id, error = %e, "failed to sync cursors with peer");
+                if res.is_ok() {
+                    res = Err(e);
                 }
             }
-            None => {
-                // should not happen
+        }
+        res
+    }
+
+    async fn exchange_awareness(&self, peer: &Peer, states: String) -> CRDTResult<()> {
+        let mut client = TxnServiceClient::new(self.connect(peer).await?);
+        let req = with_token(
+            txn_rpc::AwarenessRequest {
+                client_id: self.client,
+                states,
+                doc_name: self.doc_name.clone(),
+            },
+            &self.token,
+        );
+        let sent = req.get_ref().encoded_len();
+        let resp = client.sync_awareness(req).await?.into_inner();
+        metrics().observe_rpc("sync_awareness", sent, resp.encoded_len());
+        let remote_states: Vec<CursorState> = serde_json::from_str(&resp.states)?;
+        let mut local_doc = self.doc.lock().await;
+        local_doc.awareness.apply_remote(remote_states);
+        Ok(())
+    }
+
+    // check if client is allowed to comment the doc (always true without permissions)
+    pub(crate) fn can_comment(&self, client: ClientID) -> bool {
+        match &self.auth {
+            Some(permissions) => permissions
+                .role(&self.doc_name, client)
+                .map(|role| role.can_comment())
+                .unwrap_or(false),
+            None => true,
+        }
+    }
+
+    // check if client is allowed to accept or reject suggestions (always true without permissions)
+    pub(crate) fn can_resolve(&self, client: ClientID) -> bool {
+        match &self.auth {
+            Some(permissions) => permissions
+                .role(&self.doc_name, client)
+                .map(|role| role.can_resolve())
+                .unwrap_or(false),
+            None => true,
+        }
+    }
+
+    // update peers' modifications on local copy
+    // don't need to deal with conflicts
+    //
+    // signatures are the signed insertions covering updates,
+    // epochs tell which key version the content of updates is encrypted with
+    //
+    // every block that can be integrated is, the others are reported as
+    // Conflict (quarantined) or MissingDependency (buffered until their origins arrive)
+    #[instrument(skip_all, fields(doc = %self.doc_name, client = self.client, updates = updates.len()))]
+    pub async fn update_remote(
+        &self,
+        updates: Updates,
+        signatures: Signatures,
+        epochs: KeyEpochs,
+    ) -> CRDTResult<()> {
+        self.update_from(None, updates, signatures, epochs).await
+    }
+
+    // same as update_remote, sender is the authenticated client that sent updates (if known),
+    // the blocks it is not allowed to write are dropped and reported as PermissionDenied
+    pub(crate) async fn update_from(
+        &self,
+        sender: Option<ClientID>,
+        updates: Updates,
+        signatures: Signatures,
+        epochs: KeyEpochs,
+    ) -> CRDTResult<()> {
+        let mut local_doc = self.doc.lock().await;
+        local_doc.signatures.add(signatures);
+        local_doc.encryption.merge_epochs(epochs);
+        // blocks written by clients without edit access are never integrated
+        let (mut updates, refused) = match &self.auth {
+            Some(permissions) => {
+                permissions.filter_updates(&self.doc_name, sender, updates, |client| {
+                    local_doc.signatures.owner_of(client)
+                })
             }
+            None => (updates, vec![]),
+        };
+        // suggestions resolved by clients that don't own the doc are never integrated,
+        // the resolver is checked against the signature of the resolution below (if verifying)
+        updates.retain(|block| match block.suggestion.and_then(|s| s.resolved_by) {
+            Some(by) if !self.can_resolve(local_doc.signatures.owner_of(by)) => {
+                warn!(block = ?block.id, "dropped suggestion resolved by a client that doesn't own the doc");
+                false
+            }
+            _ => true,
+        });
+
+        // blocks that are not signed by their client are quarantined instead of integrated,
+        // they are sent again by the next diff and accepted once the signature is known
+        let mut rejected = vec![];
+        if local_doc.signatures.verifying() {
+            let (trusted, forged): (Updates, Updates) = updates
+                .into_iter()
+                .partition(|block| local_doc.signatures.verify_block(block));
+            for block in forged {
+                warn!(block = ?block.id, "quarantined block with a missing or invalid signature");
+                rejected.push(block.id.clone());
+                local_doc.signatures.quarantine_block(block);
+            }
+            updates = trusted;
+        }
+
+        // deleted blocks that have never been seen are integrated as tombstones,
+        // so insertions and deletions go through the same path
+        let ids: HashSet<BlockID> = updates.iter().map(|block| block.id.clone()).collect();
+        local_doc.insert_remote(updates).await;
+        metrics().observe_doc(&local_doc).await;
+
+        if sender.is_some() && !refused.is_empty() {
+            return Err(CRDTError::PermissionDenied(format!(
+                "{} block(s) not written by the sender or without edit access",
+                refused.len()
+            )));
         }
-        return res;
+        if !rejected.is_empty() {
+            return Err(CRDTError::Conflict(rejected));
+        }
+        let pending: Vec<BlockID> = local_doc
+            .pending_updates
+            .iter()
+            .filter(|block| ids.contains(&block.id))
+            .map(|block| block.id.clone())
+            .collect();
+        if !pending.is_empty() {
+            debug!(pending = pending.len(), "blocks wait for their origins");
+            return Err(CRDTError::MissingDependency(pending));
+        }
+        Ok(())
     }
 
-    // consult zookeeper and sync with other peers when started
-    pub async fn register(&self) -> bool {
-        let reg_res = self.zk.register(self.doc_name.clone(), self.client).await;
-        if let Err(e) = reg_res {
-            println!("{:?} register user failed because of {:?}", self.client, e);
-            return false;
+    // integrate the whole diff a peer computed when its vector clock was remote_clock,
+    // the changes the peer had seen are then seen here too, unless some blocks have been
+    // quarantined or refused (blocks waiting for their origins keep the changes made to them)
+    pub(crate) async fn integrate_diff(
+        &self,
+        sender: Option<ClientID>,
+        updates: Updates,
+        signatures: Signatures,
+        epochs: KeyEpochs,
+        remote_clock: &VectorClock,
+    ) -> CRDTResult<()> {
+        let res = self.update_from(sender, updates, signatures, epochs).await;
+        if matches!(res, Ok(()) | Err(CRDTError::MissingDependency(_))) {
+            let mut local_doc = self.doc.lock().await;
+            local_doc.vector_clock.merge_changes(remote_clock);
         }
-        true
+        res
+    }
+
+    async fn record_peer_clock(&self, peer: ClientID, clock: &VectorClock) {
+        self.peer_clocks.lock().await.insert(peer, clock.clone());
+    }
+
+    // signatures of the insertions covering updates
+    pub async fn signatures_for(&self, updates: &Updates) -> Signatures {
+        let local_doc = self.doc.lock().await;
+        local_doc.signatures.signatures_for(updates)
+    }
+
+    // key versions used by every client, sent together with updates
+    pub async fn key_epochs(&self) -> KeyEpochs {
+        let local_doc = self.doc.lock().await;
+        local_doc.encryption.epochs.clone()
+    }
+
+    // takes in a vector clock, compare with its own vector clock,
+    // compute updates that need to be send
+    pub async fn compute_diff(&self, remote_clocks: VectorClock) -> Updates {
+        let local_doc = self.doc.lock().await;
+        local_doc.compute_diff(&remote_clocks).await
+    }
+
+    // page of the updates that need to be sent, see Doc::compute_diff_page
+    pub async fn compute_diff_page(
+        &self,
+        remote_clocks: VectorClock,
+        after: Option<BlockID>,
+        limit: usize,
+    ) -> (Updates, Option<BlockID>) {
+        let local_doc = self.doc.lock().await;
+        local_doc
+            .compute_diff_page(&remote_clocks, after, limit)
+            .await
+    }
+
+    // consult zookeeper and sync with other peers when started
+    #[instrument(skip_all, fields(doc = %self.doc_name, client = self.client))]
+    pub async fn register(&self) -> CRDTResult<()> {
+        self.zk.register(self.doc_name.clone(), self.client).await
     }
 }
 
 // implement rpc interface
 #[async_trait::async_trait]
 impl TxnService for SyncTransaction {
+    #[instrument(skip_all, fields(doc = %self.doc_name, client = self.client, caller = request.get_ref().client_id))]
     async fn get_remote_updates(
         &self,
         request: tonic::Request<txn_rpc::PullRequest>,
     ) -> Result<tonic::Response<txn_rpc::PullResponse>, tonic::Status> {
+        check_role(&self.auth, &request, &self.doc_name, Role::Viewer)?;
+        check_peer(&self.tls, &request, request.get_ref().client_id)?;
         let temp_request = request.into_inner();
+        let received = temp_request.encoded_len();
+        if !temp_request.doc_name.is_empty() && temp_request.doc_name != self.doc_name {
+            return Err(tonic::Status::not_found("doc not found"));
+        }
         let vector_string = temp_request.vector_clock;
 
         let vector_clock = serde_json::from_str::<VectorClock>(&vector_string);
         match vector_clock {
             Ok(vector_clock) => {
-                let updates = self.compute_diff(vector_clock).await;
+                let after: Option<BlockID> =
+                    match temp_request.after.as_str() {
+                        "" => None,
+                        after => Some(serde_json::from_str(after).map_err(|_| {
+                            tonic::Status::invalid_argument("deserialized rpc error")
+                        })?),
+                    };
+                // every change seen before the diff is computed has been made to its blocks
+                let clock_serialized = serde_json::to_string(&self.doc.lock().await.vector_clock);
+                // a limit of 0 requests the whole diff
+                let (updates, next) = match temp_request.limit {
+                    0 => (self.compute_diff(vector_clock).await, None),
+                    limit => {
+                        self.compute_diff_page(vector_clock, after, limit as usize)
+                            .await
+                    }
+                };
+                let next_serialized = match &next {
+                    Some(next) => serde_json::to_string(next),
+                    None => Ok(String::new()),
+                };
                 let updates_serialized = serde_json::to_string(&updates);
-                match updates_serialized {
-                    Ok(updates_serialized) => {
+                let doc_lock = self.doc.lock().await;
+                let signatures_serialized =
+                    serde_json::to_string(&doc_lock.signatures.signatures_for(&updates));
+                let epochs_serialized = serde_json::to_string(&doc_lock.encryption.epochs);
+                match (
+                    updates_serialized,
+                    clock_serialized,
+                    signatures_serialized,
+                    epochs_serialized,
+                    next_serialized,
+                ) {
+                    (
+                        Ok(updates_serialized),
+                        Ok(clock_serialized),
+                        Ok(signatures_serialized),
+                        Ok(epochs_serialized),
+                        Ok(next_serialized),
+                    ) => {
                         // Update current latest clock
-                        let doc_lock = self.doc.lock().await;
                         let store_lock = doc_lock.block_store.lock().await;
-                        let last_block_lock = store_lock.kv_store[&self.client]
-                            .list
-                            .last()
-                            .unwrap()
-                            .lock()
-                            .await;
-                        let mut latest_clock_lock = doc_lock.latest_clock.lock().await;
-                        *latest_clock_lock = Some(last_block_lock.id.clock);
-
-                        return Ok(tonic::Response::new(txn_rpc::PullResponse {
-                            updates: updates_serialized,
-                        }));
+                        if let Some(list) = store_lock.kv_store.get(&self.client) {
+                            if let Some(last_block) = list.list.last() {
+                                let last_block_lock = last_block.lock().await;
+                                let mut latest_clock_lock = doc_lock.latest_clock.lock().await;
+                                *latest_clock_lock = Some(last_block_lock.id.clock);
+                            }
+                        }
+
+                        return respond(
+                            "get_remote_updates",
+                            received,
+                            txn_rpc::PullResponse {
+                                updates: updates_serialized,
+                                vector_clock: clock_serialized,
+                                signatures: signatures_serialized,
+                                key_epochs: epochs_serialized,
+                                after: next_serialized,
+                            },
+                        );
                     }
-                    Err(_) => return Err(tonic::Status::invalid_argument("serialized rpc error")),
+                    _ => return Err(tonic::Status::invalid_argument("serialized rpc error")),
                 }
             }
             Err(_) => return Err(tonic::Status::invalid_argument("deserialized rpc error")),
         }
     }
 
+    #[instrument(skip_all, fields(doc = %self.doc_name, client = self.client))]
     async fn sync_peer_list(
         &self,
         request: tonic::Request<txn_rpc::RegisterRequest>,
     ) -> Result<tonic::Response<txn_rpc::Status>, tonic::Status> {
-        println!("{:?} received new node added notification", self.client);
+        info!("received new node added notification");
+        // the peer list is only sent by the zookeeper watcher of this client
+        if self.auth.is_some() && request.extensions().get::<Caller>() != Some(&Caller(self.client))
+        {
+            return Err(tonic::Status::permission_denied(
+                "peer list can only be updated by this client",
+            ));
+        }
+        check_peer(&self.tls, &request, self.client)?;
         let temp_request = request.into_inner();
+        let received = temp_request.encoded_len();
         let peers_remote_res: Result<Vec<Peer>, serde_json::Error> =
             serde_json::from_str(&temp_request.peer_list);
         if let Ok(peers_remote) = peers_remote_res {
-            println!(
-                "{:?} successfully received up-to-dated peer list {:?}",
-                self.client, peers_remote
-            );
-            let mut local_doc = self.doc.lock().await;
-            let peers_local = local_doc.peers.clone();
-            for client in peers_remote {
-                if !peers_local.contains(&client) {
-                    // this is the new user
-                    local_doc.peers.push(Peer {
-                        client_id: client.client_id,
-                        ip_addr: client.ip_addr,
-                    });
+            info!(peers = peers_remote.len(), "received up-to-date peer list");
+            let left: Vec<ClientID> = {
+                let mut local_doc = self.doc.lock().await;
+                for client in &peers_remote {
+                    if let Some(public_key) = client.public_key.clone() {
+                        local_doc
+                            .signatures
+                            .add_public_key(client.client_id, public_key);
+                    }
+                }
+                // the list is complete, peers missing from it have left the doc
+                let left = local_doc
+                    .peers
+                    .iter()
+                    .filter(|peer| !peers_remote.iter().any(|p| p.client_id == peer.client_id))
+                    .map(|peer| peer.client_id)
+                    .collect();
+                local_doc.peers = peers_remote;
+                left
+            };
+            // channels to peers that moved are replaced on their next use
+            let mut channels = self.channels.lock().await;
+            for client in left {
+                if channels.remove(&client).is_some() {
+                    info!(peer = client, "peer left, closed its channel");
                 }
             }
-            return Ok(tonic::Response::new(txn_rpc::Status { succ: true }));
+            return respond("sync_peer_list", received, txn_rpc::Status { succ: true });
         } else {
             return Err(tonic::Status::invalid_argument("rpc error"));
         }
     }
+
+    #[instrument(skip_all, fields(doc = %self.doc_name, client = self.client, caller = request.get_ref().client_id))]
+    async fn sync_awareness(
+        &self,
+        request: tonic::Request<txn_rpc::AwarenessRequest>,
+    ) -> Result<tonic::Response<txn_rpc::AwarenessResponse>, tonic::Status> {
+        check_role(&self.auth, &request, &self.doc_name, Role::Viewer)?;
+        check_peer(&self.tls, &request, request.get_ref().client_id)?;
+        let temp_request = request.into_inner();
+        let received = temp_request.encoded_len();
+        let states_remote_res: Result<Vec<CursorState>, serde_json::Error> =
+            serde_json::from_str(&temp_request.states);
+        if let Ok(states_remote) = states_remote_res {
+            let mut local_doc = self.doc.lock().await;
+            local_doc.awareness.apply_remote(states_remote);
+            let states_serialized = serde_json::to_string(&local_doc.awareness.get_states());
+            match states_serialized {
+                Ok(states) => {
+                    return respond(
+                        "sync_awareness",
+                        received,
+                        txn_rpc::AwarenessResponse { states },
+                    );
+                }
+                Err(_) => return Err(tonic::Status::invalid_argument("serialized rpc error")),
+            }
+        } else {
+            return Err(tonic::Status::invalid_argument("deserialized rpc error"));
+        }
+    }
+
+    #[instrument(skip_all, fields(doc = %self.doc_name, client = self.client, caller = request.get_ref().client_id))]
+    async fn sync_comments(
+        &self,
+        request: tonic::Request<txn_rpc::CommentsRequest>,
+    ) -> Result<tonic::Response<txn_rpc::CommentsResponse>, tonic::Status> {
+        check_role(&self.auth, &request, &self.doc_name, Role::Commenter)?;
+        check_peer(&self.tls, &request, request.get_ref().client_id)?;
+        // the caller only adds what it wrote itself
+        let sender = request.extensions().get::<Caller>().map(|caller| caller.0);
+        let temp_request = request.into_inner();
+        let received = temp_request.encoded_len();
+        let remote_comments: Vec<Comment> = serde_json::from_str(&temp_request.comments)
+            .map_err(|_| tonic::Status::invalid_argument("deserialized rpc error"))?;
+        let remote_clock: CommentClock =
+            serde_json::from_str(&temp_request.known).unwrap_or_default();
+        self.apply_comments(sender, remote_comments).await;
+        let (comments, known) = {
+            let local_doc = self.doc.lock().await;
+            let comments = local_doc.comments.missing_from(&remote_clock);
+            (
+                serde_json::to_string(&local_doc.encryption.seal_comments(comments)),
+                serde_json::to_string(&local_doc.comments.clock()),
+            )
+        };
+        match (comments, known) {
+            (Ok(comments), Ok(known)) => respond(
+                "sync_comments",
+                received,
+                txn_rpc::CommentsResponse { comments, known },
+            ),
+            _ => Err(tonic::Status::invalid_argument("serialized rpc error")),
+        }
+    }
+
+    #[instrument(skip_all, fields(doc = %self.doc_name, client = self.client, caller = request.get_ref().client_id))]
+    async fn push_updates(
+        &self,
+        request: tonic::Request<txn_rpc::PushRequest>,
+    ) -> Result<tonic::Response<txn_rpc::Status>, tonic::Status> {
+        check_role(&self.auth, &request, &self.doc_name, Role::Editor)?;
+        check_peer(&self.tls, &request, request.get_ref().client_id)?;
+        // the caller only pushes what it wrote itself
+        let sender = request.extensions().get::<Caller>().map(|caller| caller.0);
+        let temp_request = request.into_inner();
+        let received = temp_request.encoded_len();
+        if !temp_request.doc_name.is_empty() && temp_request.doc_name != self.doc_name {
+            return Err(tonic::Status::not_found("doc not found"));
+        }
+        let updates_res: Result<Updates, serde_json::Error> =
+            serde_json::from_str(&temp_request.updates);
+        let signatures: Signatures =
+            serde_json::from_str(&temp_request.signatures).unwrap_or_default();
+        let epochs: KeyEpochs = serde_json::from_str(&temp_request.key_epochs).unwrap_or_default();
+        let remote_clock: VectorClock =
+            serde_json::from_str(&temp_request.vector_clock).unwrap_or_default();
+        match updates_res {
+            Ok(updates) => match self
+                .integrate_diff(sender, updates, signatures, epochs, &remote_clock)
+                .await
+            {
+                // buffered blocks are integrated once their origins are pushed
+                Ok(()) | Err(CRDTError::MissingDependency(_)) => {
+                    return respond("push_updates", received, txn_rpc::Status { succ: true });
+                }
+                Err(e @ CRDTError::PermissionDenied(_)) => return Err(e.into()),
+                Err(e) => return Err(tonic::Status::invalid_argument(e.to_string())),
+            },
+            Err(_) => return Err(tonic::Status::invalid_argument("deserialized rpc error")),
+        }
+    }
+
+    #[instrument(skip_all, fields(doc = %self.doc_name, client = self.client, caller = request.get_ref().client_id))]
+    async fn get_doc_key(
+        &self,
+        request: tonic::Request<txn_rpc::KeyRequest>,
+    ) -> Result<tonic::Response<txn_rpc::KeyResponse>, tonic::Status> {
+        // without authentication, anyone reaching the port could read the doc
+        if self.auth.is_none() {
+            return Err(tonic::Status::permission_denied(
+                "doc key is only shared with authenticated peers",
+            ));
+        }
+        check_role(&self.auth, &request, &self.doc_name, Role::Viewer)?;
+        check_peer(&self.tls, &request, request.get_ref().client_id)?;
+        let temp_request = request.into_inner();
+        let received = temp_request.encoded_len();
+        if !temp_request.doc_name.is_empty() && temp_request.doc_name != self.doc_name {
+            return Err(tonic::Status::not_found("doc not found"));
+        }
+        let local_doc = self.doc.lock().await;
+        if !local_doc.encryption.has_key() {
+            return Err(tonic::Status::not_found("no key for doc"));
+        }
+        match serde_json::to_string(&local_doc.encryption.keys) {
+            Ok(keys) => respond("get_doc_key", received, txn_rpc::KeyResponse { keys }),
+            Err(_) => Err(tonic::Status::internal("serialized rpc error")),
+        }
+    }
+
+    #[instrument(skip_all, fields(doc = %self.doc_name, client = self.client, caller = request.get_ref().client_id, hops = request.get_ref().hops))]
+    async fn gossip_updates(
+        &self,
+        request: tonic::Request<txn_rpc::GossipRequest>,
+    ) -> Result<tonic::Response<txn_rpc::Status>, tonic::Status> {
+        // only editors relay gossip, every block is also checked against the client that wrote it
+        check_role(&self.auth, &request, &self.doc_name, Role::Editor)?;
+        check_peer(&self.tls, &request, request.get_ref().client_id)?;
+        let gossip = match &self.gossip {
+            Some(gossip) => gossip.clone(),
+            None => return Err(tonic::Status::unimplemented("gossip is disabled")),
+        };
+        let temp_request = request.into_inner();
+        let received = temp_request.encoded_len();
+        if !temp_request.doc_name.is_empty() && temp_request.doc_name != self.doc_name {
+            return Err(tonic::Status::not_found("doc not found"));
+        }
+        let updates: Updates = serde_json::from_str(&temp_request.updates)
+            .map_err(|_| tonic::Status::invalid_argument("deserialized rpc error"))?;
+        let signatures: Signatures =
+            serde_json::from_str(&temp_request.signatures).unwrap_or_default();
+        let epochs: KeyEpochs = serde_json::from_str(&temp_request.key_epochs).unwrap_or_default();
+
+        let (updates, clock) = {
+            let mut local_doc = self.doc.lock().await;
+            // forks are known before the blocks of their clients are filtered
+            local_doc.signatures.add(signatures.clone());
+            let updates = match &self.auth {
+                Some(permissions) => {
+                    permissions
+                        .filter_updates(&self.doc_name, None, updates, |client| {
+                            local_doc.signatures.owner_of(client)
+                        })
+                        .0
+                }
+                None => updates,
+            };
+            (updates, local_doc.vector_clock.clone())
+        };
+        // updates already integrated or received from another peer are neither integrated nor forwarded again
+        let updates = gossip.fresh(updates, &clock).await;
+        if updates.is_empty() {
+            return respond("gossip_updates", received, txn_rpc::Status { succ: true });
+        }
+        match self
+            .update_remote(updates.clone(), signatures.clone(), epochs.clone())
+            .await
+        {
+            Ok(()) | Err(CRDTError::MissingDependency(_)) => {}
+            Err(e) => return Err(tonic::Status::invalid_argument(e.to_string())),
+        }
+
+        // forward in the background so the sender doesn't wait for the whole epidemic
+        if temp_request.hops > 1 {
+            let txn = self.clone();
+            let from = temp_request.client_id;
+            tokio::spawn(async move {
+                let hops = temp_request.hops - 1;
+                if let Err(e) = txn
+                    .gossip(&updates, &signatures, &epochs, hops, Some(from))
+                    .await
+                {
+                    warn!(error = %e, "failed to forward gossip");
+                }
+            });
+        }
+        respond("gossip_updates", received, txn_rpc::Status { succ: true })
+    }
+
+    #[instrument(skip_all, fields(doc = %self.doc_name, client = self.client, caller = request.get_ref().client_id))]
+    async fn get_snapshot(
+        &self,
+        request: tonic::Request<txn_rpc::SnapshotRequest>,
+    ) -> Result<tonic::Response<txn_rpc::SnapshotResponse>, tonic::Status> {
+        check_role(&self.auth, &request, &self.doc_name, Role::Viewer)?;
+        check_peer(&self.tls, &request, request.get_ref().client_id)?;
+        let received = request.get_ref().encoded_len();
+        if !request.get_ref().doc_name.is_empty() && request.get_ref().doc_name != self.doc_name {
+            return Err(tonic::Status::not_found("doc not found"));
+        }
+        let local_doc = self.doc.lock().await;
+        let snapshot = local_doc.snapshot().await;
+        let signatures = local_doc.signatures.signatures_for(&snapshot.decode());
+        let resp = txn_rpc::SnapshotResponse {
+            snapshot: serde_json::to_string(&snapshot).map_err(CRDTError::from)?,
+            signatures: serde_json::to_string(&signatures).map_err(CRDTError::from)?,
+            key_epochs: serde_json::to_string(&local_doc.encryption.epochs)
+                .map_err(CRDTError::from)?,
+        };
+        debug!(blocks = snapshot.blocks.len(), "sending snapshot");
+        respond("get_snapshot", received, resp)
+    }
+
+    type StreamUpdatesStream = ReceiverStream<Result<txn_rpc::StreamChunk, tonic::Status>>;
+
+    #[instrument(skip_all, fields(doc = %self.doc_name, client = self.client, caller = request.get_ref().client_id))]
+    async fn stream_updates(
+        &self,
+        request: tonic::Request<txn_rpc::StreamRequest>,
+    ) -> Result<tonic::Response<Self::StreamUpdatesStream>, tonic::Status> {
+        check_role(&self.auth, &request, &self.doc_name, Role::Viewer)?;
+        check_peer(&self.tls, &request, request.get_ref().client_id)?;
+        let temp_request = request.into_inner();
+        let received = temp_request.encoded_len();
+        if !temp_request.doc_name.is_empty() && temp_request.doc_name != self.doc_name {
+            return Err(tonic::Status::not_found("doc not found"));
+        }
+        let vector_clock: VectorClock = serde_json::from_str(&temp_request.vector_clock)
+            .map_err(|_| tonic::Status::invalid_argument("deserialized rpc error"))?;
+        let after: Option<BlockID> = match temp_request.after.as_str() {
+            "" => None,
+            after => Some(
+                serde_json::from_str(after)
+                    .map_err(|_| tonic::Status::invalid_argument("deserialized rpc error"))?,
+            ),
+        };
+        // a few chunks are buffered, the diff is sent as fast as the receiver integrates it
+        let (sender, receiver) = tokio::sync::mpsc::channel(4);
+        let txn = self.clone();
+        tokio::spawn(async move {
+            match txn
+                .send_stream(vector_clock, after, temp_request.chunk_size, sender.clone())
+                .await
+            {
+                Ok(sent) => metrics().observe_rpc("stream_updates", sent, received),
+                Err(e) => {
+                    warn!(error = %e, "failed to stream updates");
+                    let _ = sender.send(Err(e.into())).await;
+                }
+            }
+        });
+        Ok(tonic::Response::new(ReceiverStream::new(receiver)))
+    }
+
+    // health check, only authenticated by the interceptor so that any peer can check the channel
+    async fn ping(
+        &self,
+        request: tonic::Request<txn_rpc::PingRequest>,
+    ) -> Result<tonic::Response<txn_rpc::Status>, tonic::Status> {
+        let received = request.get_ref().encoded_len();
+        let succ = request.get_ref().doc_name == self.doc_name;
+        respond("ping", received, txn_rpc::Status { succ })
+    }
+}
+
+// record the size of an rpc handled by this client and build its response
+#[allow(clippy::result_large_err)]
+pub(crate) fn respond<T: Message>(
+    rpc: &str,
+    received: usize,
+    resp: T,
+) -> Result<tonic::Response<T>, tonic::Status> {
+    metrics().observe_rpc(rpc, resp.encoded_len(), received);
+    Ok(tonic::Response::new(resp))
 }
@@ -0,0 +1,114 @@
+use crate::crdt::utils::{CRDTResult, ClientID};
+use std::sync::Arc;
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint, Identity, Server, ServerTlsConfig};
+use tonic::{Request, Status};
+use x509_parser::extensions::GeneralName;
+
+// TlsConfig holds the paths of the pem files used to encrypt rpc channels
+//
+// every client certificate carries the dns name given by peer_name(client_id),
+// so a peer connecting to client 1 only accepts a certificate for "client-1"
+//
+// if mutual is set, callers must present a certificate signed by ca as well,
+// and every request is checked against the client id in that certificate
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+    pub ca_path: String,
+    pub mutual: bool,
+}
+
+impl TlsConfig {
+    pub fn new(cert_path: String, key_path: String, ca_path: String, mutual: bool) -> Self {
+        TlsConfig {
+            cert_path,
+            key_path,
+            ca_path,
+            mutual,
+        }
+    }
+
+    fn identity(&self) -> CRDTResult<Identity> {
+        let cert = std::fs::read(&self.cert_path)?;
+        let key = std::fs::read(&self.key_path)?;
+        Ok(Identity::from_pem(cert, key))
+    }
+
+    fn ca(&self) -> CRDTResult<Certificate> {
+        let ca = std::fs::read(&self.ca_path)?;
+        Ok(Certificate::from_pem(ca))
+    }
+
+    pub fn server_config(&self) -> CRDTResult<ServerTlsConfig> {
+        let mut config = ServerTlsConfig::new().identity(self.identity()?);
+        if self.mutual {
+            config = config.client_ca_root(self.ca()?);
+        }
+        Ok(config)
+    }
+
+    // Config used to connect to peer, its certificate must be issued for peer_name(peer)
+    pub fn client_config(&self, peer: ClientID) -> CRDTResult<ClientTlsConfig> {
+        Ok(ClientTlsConfig::new()
+            .ca_certificate(self.ca()?)
+            .identity(self.identity()?)
+            .domain_name(peer_name(peer)))
+    }
+}
+
+// Name certificates of client are issued for
+pub fn peer_name(client: ClientID) -> String {
+    format!("client-{}", client)
+}
+
+// Build the endpoint of peer at ip, using https if tls is set
+pub fn endpoint(ip: &str, tls: &Option<Arc<TlsConfig>>, peer: ClientID) -> CRDTResult<Endpoint> {
+    match tls {
+        Some(tls) => {
+            let endpoint = Endpoint::from_shared(format!("https://{}", ip))?;
+            Ok(endpoint.tls_config(tls.client_config(peer)?)?)
+        }
+        None => Ok(Endpoint::from_shared(format!("http://{}", ip))?),
+    }
+}
+
+// Build the rpc server, serving over tls if tls is set
+pub fn server_builder(tls: &Option<Arc<TlsConfig>>) -> CRDTResult<Server> {
+    let builder = Server::builder();
+    match tls {
+        Some(tls) => Ok(builder.tls_config(tls.server_config()?)?),
+        None => Ok(builder),
+    }
+}
+
+// Client id in the certificate the caller of request presented (mutual tls only)
+pub fn cert_client_id<T>(request: &Request<T>) -> Option<ClientID> {
+    let certs = request.peer_certs()?;
+    let (_, cert) = x509_parser::parse_x509_certificate(certs.first()?.get_ref()).ok()?;
+    let san = cert.tbs_certificate.subject_alternative_name().ok()??;
+    san.value.general_names.iter().find_map(|name| match name {
+        GeneralName::DNSName(name) => name.strip_prefix("client-")?.parse().ok(),
+        _ => None,
+    })
+}
+
+// Check that the caller of request is the client it claims to be
+#[allow(clippy::result_large_err)]
+pub fn check_peer<T>(
+    tls: &Option<Arc<TlsConfig>>,
+    request: &Request<T>,
+    claimed: ClientID,
+) -> Result<(), Status> {
+    match tls {
+        Some(tls) if tls.mutual => match cert_client_id(request) {
+            Some(client) if client == claimed => Ok(()),
+            Some(client) => Err(Status::permission_denied(format!(
+                "certificate of {:?} cannot be used by {:?}",
+                client, claimed
+            ))),
+            None => Err(Status::unauthenticated("missing client certificate")),
+        },
+        _ => Ok(()),
+    }
+}
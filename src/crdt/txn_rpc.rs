@@ -1,14 +1,47 @@
+/// a limit of 0 requests the whole diff in spatial order,
+/// otherwise at most limit blocks are returned in (client, clock) order,
+/// starting after the block id in after (json, empty for the first page)
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct PullRequest {
     #[prost(uint32, tag = "1")]
     pub client_id: u32,
     #[prost(string, tag = "2")]
     pub vector_clock: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub doc_name: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "5")]
+    pub limit: u32,
+    #[prost(string, tag = "6")]
+    pub after: ::prost::alloc::string::String,
 }
+/// after is the block id the next page starts after, empty once the whole diff has been returned
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct PullResponse {
     #[prost(string, tag = "1")]
     pub updates: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub vector_clock: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub signatures: ::prost::alloc::string::String,
+    #[prost(string, tag = "4")]
+    pub key_epochs: ::prost::alloc::string::String,
+    #[prost(string, tag = "6")]
+    pub after: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PushRequest {
+    #[prost(string, tag = "1")]
+    pub doc_name: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "2")]
+    pub client_id: u32,
+    #[prost(string, tag = "3")]
+    pub updates: ::prost::alloc::string::String,
+    #[prost(string, tag = "4")]
+    pub signatures: ::prost::alloc::string::String,
+    #[prost(string, tag = "5")]
+    pub key_epochs: ::prost::alloc::string::String,
+    #[prost(string, tag = "6")]
+    pub vector_clock: ::prost::alloc::string::String,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct RegisterRequest {
@@ -20,6 +53,149 @@ pub struct Status {
     #[prost(bool, tag = "1")]
     pub succ: bool,
 }
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AwarenessRequest {
+    #[prost(uint32, tag = "1")]
+    pub client_id: u32,
+    #[prost(string, tag = "2")]
+    pub states: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub doc_name: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AwarenessResponse {
+    #[prost(string, tag = "1")]
+    pub states: ::prost::alloc::string::String,
+}
+/// comments of a doc as json, only the ones the other side is missing are sent:
+/// known is the CommentClock of the sender, the comments it already has
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CommentsRequest {
+    #[prost(uint32, tag = "1")]
+    pub client_id: u32,
+    #[prost(string, tag = "2")]
+    pub comments: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub doc_name: ::prost::alloc::string::String,
+    #[prost(string, tag = "4")]
+    pub known: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CommentsResponse {
+    #[prost(string, tag = "1")]
+    pub comments: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub known: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct KeyRequest {
+    #[prost(string, tag = "1")]
+    pub doc_name: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "2")]
+    pub client_id: u32,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct KeyResponse {
+    #[prost(string, tag = "1")]
+    pub keys: ::prost::alloc::string::String,
+}
+/// updates forwarded from peer to peer,
+/// hops is the number of times the updates can still be forwarded
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GossipRequest {
+    #[prost(string, tag = "1")]
+    pub doc_name: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "2")]
+    pub client_id: u32,
+    #[prost(string, tag = "3")]
+    pub updates: ::prost::alloc::string::String,
+    #[prost(string, tag = "4")]
+    pub signatures: ::prost::alloc::string::String,
+    #[prost(string, tag = "5")]
+    pub key_epochs: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "6")]
+    pub hops: u32,
+}
+/// pull of the whole diff in chunks of at most chunk_size blocks,
+/// after is the json id of the last tombstone received when resuming an interrupted stream
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StreamRequest {
+    #[prost(string, tag = "1")]
+    pub doc_name: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "2")]
+    pub client_id: u32,
+    #[prost(string, tag = "3")]
+    pub vector_clock: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "4")]
+    pub chunk_size: u32,
+    #[prost(string, tag = "5")]
+    pub after: ::prost::alloc::string::String,
+}
+/// chunk of a stream, new blocks come in causal order before the tombstones
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StreamChunk {
+    #[prost(string, tag = "1")]
+    pub updates: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub signatures: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub key_epochs: ::prost::alloc::string::String,
+    #[prost(string, tag = "4")]
+    pub after: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SnapshotRequest {
+    #[prost(string, tag = "1")]
+    pub doc_name: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "2")]
+    pub client_id: u32,
+}
+/// current state of a doc, loaded by peers joining it
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SnapshotResponse {
+    #[prost(string, tag = "1")]
+    pub snapshot: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub signatures: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub key_epochs: ::prost::alloc::string::String,
+}
+/// health check of the channel to a peer
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PingRequest {
+    #[prost(string, tag = "1")]
+    pub doc_name: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "2")]
+    pub client_id: u32,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AdminRequest {
+    #[prost(string, tag = "1")]
+    pub doc_name: ::prost::alloc::string::String,
+}
+/// blocks at [start, end) in the spatial order of the doc
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct BlockRangeRequest {
+    #[prost(string, tag = "1")]
+    pub doc_name: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "2")]
+    pub start: u32,
+    #[prost(uint32, tag = "3")]
+    pub end: u32,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ForceSyncRequest {
+    #[prost(string, tag = "1")]
+    pub doc_name: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "2")]
+    pub client_id: u32,
+}
+/// result is json
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AdminResponse {
+    #[prost(string, tag = "1")]
+    pub result: ::prost::alloc::string::String,
+}
 #[doc = r" Generated client implementations."]
 pub mod txn_service_client {
     #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
@@ -39,102 +215,841 @@ pub mod txn_service_client {
             Ok(Self::new(conn))
         }
     }
-    impl<T> TxnServiceClient<T>
-    where
-        T: tonic::client::GrpcService<tonic::body::BoxBody>,
-        T::ResponseBody: Body + Send + 'static,
-        T::Error: Into<StdError>,
-        <T::ResponseBody as Body>::Error: Into<StdError> + Send,
-    {
-        pub fn new(inner: T) -> Self {
-            let inner = tonic::client::Grpc::new(inner);
-            Self { inner }
-        }
-        pub fn with_interceptor<F>(
-            inner: T,
-            interceptor: F,
-        ) -> TxnServiceClient<InterceptedService<T, F>>
-        where
-            F: tonic::service::Interceptor,
-            T: tonic::codegen::Service<
-                http::Request<tonic::body::BoxBody>,
-                Response = http::Response<
-                    <T as tonic::client::GrpcService<tonic::body::BoxBody>>::ResponseBody,
-                >,
-            >,
-            <T as tonic::codegen::Service<http::Request<tonic::body::BoxBody>>>::Error:
-                Into<StdError> + Send + Sync,
-        {
-            TxnServiceClient::new(InterceptedService::new(inner, interceptor))
-        }
-        #[doc = r" Compress requests with `gzip`."]
-        #[doc = r""]
-        #[doc = r" This requires the server to support it otherwise it might respond with an"]
-        #[doc = r" error."]
-        pub fn send_gzip(mut self) -> Self {
-            self.inner = self.inner.send_gzip();
-            self
-        }
-        #[doc = r" Enable decompressing responses with `gzip`."]
-        pub fn accept_gzip(mut self) -> Self {
-            self.inner = self.inner.accept_gzip();
-            self
+    impl<T> TxnServiceClient<T>
+    where
+        T: tonic::client::GrpcService<tonic::body::BoxBody>,
+        T::ResponseBody: Body + Send + 'static,
+        T::Error: Into<StdError>,
+        <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+    {
+        pub fn new(inner: T) -> Self {
+            let inner = tonic::client::Grpc::new(inner);
+            Self { inner }
+        }
+        pub fn with_interceptor<F>(
+            inner: T,
+            interceptor: F,
+        ) -> TxnServiceClient<InterceptedService<T, F>>
+        where
+            F: tonic::service::Interceptor,
+            T: tonic::codegen::Service<
+                http::Request<tonic::body::BoxBody>,
+                Response = http::Response<
+                    <T as tonic::client::GrpcService<tonic::body::BoxBody>>::ResponseBody,
+                >,
+            >,
+            <T as tonic::codegen::Service<http::Request<tonic::body::BoxBody>>>::Error:
+                Into<StdError> + Send + Sync,
+        {
+            TxnServiceClient::new(InterceptedService::new(inner, interceptor))
+        }
+        #[doc = r" Compress requests with `gzip`."]
+        #[doc = r""]
+        #[doc = r" This requires the server to support it otherwise it might respond with an"]
+        #[doc = r" error."]
+        pub fn send_gzip(mut self) -> Self {
+            self.inner = self.inner.send_gzip();
+            self
+        }
+        #[doc = r" Enable decompressing responses with `gzip`."]
+        pub fn accept_gzip(mut self) -> Self {
+            self.inner = self.inner.accept_gzip();
+            self
+        }
+        pub async fn get_remote_updates(
+            &mut self,
+            request: impl tonic::IntoRequest<super::PullRequest>,
+        ) -> Result<tonic::Response<super::PullResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path =
+                http::uri::PathAndQuery::from_static("/txn_rpc.TxnService/get_remote_updates");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn sync_peer_list(
+            &mut self,
+            request: impl tonic::IntoRequest<super::RegisterRequest>,
+        ) -> Result<tonic::Response<super::Status>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/txn_rpc.TxnService/sync_peer_list");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn sync_awareness(
+            &mut self,
+            request: impl tonic::IntoRequest<super::AwarenessRequest>,
+        ) -> Result<tonic::Response<super::AwarenessResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/txn_rpc.TxnService/sync_awareness");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn push_updates(
+            &mut self,
+            request: impl tonic::IntoRequest<super::PushRequest>,
+        ) -> Result<tonic::Response<super::Status>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/txn_rpc.TxnService/push_updates");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn get_doc_key(
+            &mut self,
+            request: impl tonic::IntoRequest<super::KeyRequest>,
+        ) -> Result<tonic::Response<super::KeyResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/txn_rpc.TxnService/get_doc_key");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn gossip_updates(
+            &mut self,
+            request: impl tonic::IntoRequest<super::GossipRequest>,
+        ) -> Result<tonic::Response<super::Status>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/txn_rpc.TxnService/gossip_updates");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn ping(
+            &mut self,
+            request: impl tonic::IntoRequest<super::PingRequest>,
+        ) -> Result<tonic::Response<super::Status>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/txn_rpc.TxnService/ping");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn stream_updates(
+            &mut self,
+            request: impl tonic::IntoRequest<super::StreamRequest>,
+        ) -> Result<tonic::Response<tonic::codec::Streaming<super::StreamChunk>>, tonic::Status>
+        {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/txn_rpc.TxnService/StreamUpdates");
+            self.inner
+                .server_streaming(request.into_request(), path, codec)
+                .await
+        }
+        pub async fn get_snapshot(
+            &mut self,
+            request: impl tonic::IntoRequest<super::SnapshotRequest>,
+        ) -> Result<tonic::Response<super::SnapshotResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/txn_rpc.TxnService/get_snapshot");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn sync_comments(
+            &mut self,
+            request: impl tonic::IntoRequest<super::CommentsRequest>,
+        ) -> Result<tonic::Response<super::CommentsResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/txn_rpc.TxnService/sync_comments");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+    }
+}
+#[doc = r" Generated client implementations."]
+pub mod admin_service_client {
+    #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
+    use tonic::codegen::*;
+    #[doc = " introspection of a live replica, only owners of the doc can call it"]
+    #[derive(Debug, Clone)]
+    pub struct AdminServiceClient<T> {
+        inner: tonic::client::Grpc<T>,
+    }
+    impl AdminServiceClient<tonic::transport::Channel> {
+        #[doc = r" Attempt to create a new client by connecting to a given endpoint."]
+        pub async fn connect<D>(dst: D) -> Result<Self, tonic::transport::Error>
+        where
+            D: std::convert::TryInto<tonic::transport::Endpoint>,
+            D::Error: Into<StdError>,
+        {
+            let conn = tonic::transport::Endpoint::new(dst)?.connect().await?;
+            Ok(Self::new(conn))
+        }
+    }
+    impl<T> AdminServiceClient<T>
+    where
+        T: tonic::client::GrpcService<tonic::body::BoxBody>,
+        T::ResponseBody: Body + Send + 'static,
+        T::Error: Into<StdError>,
+        <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+    {
+        pub fn new(inner: T) -> Self {
+            let inner = tonic::client::Grpc::new(inner);
+            Self { inner }
+        }
+        pub fn with_interceptor<F>(
+            inner: T,
+            interceptor: F,
+        ) -> AdminServiceClient<InterceptedService<T, F>>
+        where
+            F: tonic::service::Interceptor,
+            T: tonic::codegen::Service<
+                http::Request<tonic::body::BoxBody>,
+                Response = http::Response<
+                    <T as tonic::client::GrpcService<tonic::body::BoxBody>>::ResponseBody,
+                >,
+            >,
+            <T as tonic::codegen::Service<http::Request<tonic::body::BoxBody>>>::Error:
+                Into<StdError> + Send + Sync,
+        {
+            AdminServiceClient::new(InterceptedService::new(inner, interceptor))
+        }
+        #[doc = r" Compress requests with `gzip`."]
+        #[doc = r""]
+        #[doc = r" This requires the server to support it otherwise it might respond with an"]
+        #[doc = r" error."]
+        pub fn send_gzip(mut self) -> Self {
+            self.inner = self.inner.send_gzip();
+            self
+        }
+        #[doc = r" Enable decompressing responses with `gzip`."]
+        pub fn accept_gzip(mut self) -> Self {
+            self.inner = self.inner.accept_gzip();
+            self
+        }
+        pub async fn get_vector_clock(
+            &mut self,
+            request: impl tonic::IntoRequest<super::AdminRequest>,
+        ) -> Result<tonic::Response<super::AdminResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path =
+                http::uri::PathAndQuery::from_static("/txn_rpc.AdminService/get_vector_clock");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn list_peers(
+            &mut self,
+            request: impl tonic::IntoRequest<super::AdminRequest>,
+        ) -> Result<tonic::Response<super::AdminResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/txn_rpc.AdminService/list_peers");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn dump_blocks(
+            &mut self,
+            request: impl tonic::IntoRequest<super::BlockRangeRequest>,
+        ) -> Result<tonic::Response<super::AdminResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/txn_rpc.AdminService/dump_blocks");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn list_pending(
+            &mut self,
+            request: impl tonic::IntoRequest<super::AdminRequest>,
+        ) -> Result<tonic::Response<super::AdminResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/txn_rpc.AdminService/list_pending");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn force_sync(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ForceSyncRequest>,
+        ) -> Result<tonic::Response<super::AdminResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/txn_rpc.AdminService/force_sync");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn compact(
+            &mut self,
+            request: impl tonic::IntoRequest<super::AdminRequest>,
+        ) -> Result<tonic::Response<super::AdminResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/txn_rpc.AdminService/compact");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn collect_tombstones(
+            &mut self,
+            request: impl tonic::IntoRequest<super::AdminRequest>,
+        ) -> Result<tonic::Response<super::AdminResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path =
+                http::uri::PathAndQuery::from_static("/txn_rpc.AdminService/collect_tombstones");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+    }
+}
+#[doc = r" Generated server implementations."]
+pub mod txn_service_server {
+    #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
+    use tonic::codegen::*;
+    #[doc = "Generated trait containing gRPC methods that should be implemented for use with TxnServiceServer."]
+    #[async_trait]
+    pub trait TxnService: Send + Sync + 'static {
+        async fn get_remote_updates(
+            &self,
+            request: tonic::Request<super::PullRequest>,
+        ) -> Result<tonic::Response<super::PullResponse>, tonic::Status>;
+        async fn sync_peer_list(
+            &self,
+            request: tonic::Request<super::RegisterRequest>,
+        ) -> Result<tonic::Response<super::Status>, tonic::Status>;
+        async fn sync_awareness(
+            &self,
+            request: tonic::Request<super::AwarenessRequest>,
+        ) -> Result<tonic::Response<super::AwarenessResponse>, tonic::Status>;
+        async fn push_updates(
+            &self,
+            request: tonic::Request<super::PushRequest>,
+        ) -> Result<tonic::Response<super::Status>, tonic::Status>;
+        async fn get_doc_key(
+            &self,
+            request: tonic::Request<super::KeyRequest>,
+        ) -> Result<tonic::Response<super::KeyResponse>, tonic::Status>;
+        async fn gossip_updates(
+            &self,
+            request: tonic::Request<super::GossipRequest>,
+        ) -> Result<tonic::Response<super::Status>, tonic::Status>;
+        async fn ping(
+            &self,
+            request: tonic::Request<super::PingRequest>,
+        ) -> Result<tonic::Response<super::Status>, tonic::Status>;
+        #[doc = "Server streaming response type for the StreamUpdates method."]
+        type StreamUpdatesStream: futures_core::Stream<Item = Result<super::StreamChunk, tonic::Status>>
+            + Send
+            + 'static;
+        async fn stream_updates(
+            &self,
+            request: tonic::Request<super::StreamRequest>,
+        ) -> Result<tonic::Response<Self::StreamUpdatesStream>, tonic::Status>;
+        async fn get_snapshot(
+            &self,
+            request: tonic::Request<super::SnapshotRequest>,
+        ) -> Result<tonic::Response<super::SnapshotResponse>, tonic::Status>;
+        async fn sync_comments(
+            &self,
+            request: tonic::Request<super::CommentsRequest>,
+        ) -> Result<tonic::Response<super::CommentsResponse>, tonic::Status>;
+    }
+    #[derive(Debug)]
+    pub struct TxnServiceServer<T: TxnService> {
+        inner: _Inner<T>,
+        accept_compression_encodings: (),
+        send_compression_encodings: (),
+    }
+    struct _Inner<T>(Arc<T>);
+    impl<T: TxnService> TxnServiceServer<T> {
+        pub fn new(inner: T) -> Self {
+            let inner = Arc::new(inner);
+            let inner = _Inner(inner);
+            Self {
+                inner,
+                accept_compression_encodings: Default::default(),
+                send_compression_encodings: Default::default(),
+            }
+        }
+        pub fn with_interceptor<F>(inner: T, interceptor: F) -> InterceptedService<Self, F>
+        where
+            F: tonic::service::Interceptor,
+        {
+            InterceptedService::new(Self::new(inner), interceptor)
+        }
+    }
+    impl<T, B> tonic::codegen::Service<http::Request<B>> for TxnServiceServer<T>
+    where
+        T: TxnService,
+        B: Body + Send + 'static,
+        B::Error: Into<StdError> + Send + 'static,
+    {
+        type Response = http::Response<tonic::body::BoxBody>;
+        type Error = Never;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+        fn call(&mut self, req: http::Request<B>) -> Self::Future {
+            let inner = self.inner.clone();
+            match req.uri().path() {
+                "/txn_rpc.TxnService/get_remote_updates" => {
+                    #[allow(non_camel_case_types)]
+                    struct get_remote_updatesSvc<T: TxnService>(pub Arc<T>);
+                    impl<T: TxnService> tonic::server::UnaryService<super::PullRequest> for get_remote_updatesSvc<T> {
+                        type Response = super::PullResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::PullRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).get_remote_updates(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = get_remote_updatesSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/txn_rpc.TxnService/sync_peer_list" => {
+                    #[allow(non_camel_case_types)]
+                    struct sync_peer_listSvc<T: TxnService>(pub Arc<T>);
+                    impl<T: TxnService> tonic::server::UnaryService<super::RegisterRequest> for sync_peer_listSvc<T> {
+                        type Response = super::Status;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::RegisterRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).sync_peer_list(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = sync_peer_listSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/txn_rpc.TxnService/sync_awareness" => {
+                    #[allow(non_camel_case_types)]
+                    struct sync_awarenessSvc<T: TxnService>(pub Arc<T>);
+                    impl<T: TxnService> tonic::server::UnaryService<super::AwarenessRequest> for sync_awarenessSvc<T> {
+                        type Response = super::AwarenessResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::AwarenessRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).sync_awareness(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = sync_awarenessSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/txn_rpc.TxnService/push_updates" => {
+                    #[allow(non_camel_case_types)]
+                    struct push_updatesSvc<T: TxnService>(pub Arc<T>);
+                    impl<T: TxnService> tonic::server::UnaryService<super::PushRequest> for push_updatesSvc<T> {
+                        type Response = super::Status;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::PushRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).push_updates(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = push_updatesSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/txn_rpc.TxnService/get_doc_key" => {
+                    #[allow(non_camel_case_types)]
+                    struct get_doc_keySvc<T: TxnService>(pub Arc<T>);
+                    impl<T: TxnService> tonic::server::UnaryService<super::KeyRequest> for get_doc_keySvc<T> {
+                        type Response = super::KeyResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::KeyRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).get_doc_key(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = get_doc_keySvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/txn_rpc.TxnService/gossip_updates" => {
+                    #[allow(non_camel_case_types)]
+                    struct gossip_updatesSvc<T: TxnService>(pub Arc<T>);
+                    impl<T: TxnService> tonic::server::UnaryService<super::GossipRequest> for gossip_updatesSvc<T> {
+                        type Response = super::Status;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::GossipRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).gossip_updates(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = gossip_updatesSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/txn_rpc.TxnService/ping" => {
+                    #[allow(non_camel_case_types)]
+                    struct pingSvc<T: TxnService>(pub Arc<T>);
+                    impl<T: TxnService> tonic::server::UnaryService<super::PingRequest> for pingSvc<T> {
+                        type Response = super::Status;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::PingRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).ping(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = pingSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/txn_rpc.TxnService/StreamUpdates" => {
+                    #[allow(non_camel_case_types)]
+                    struct StreamUpdatesSvc<T: TxnService>(pub Arc<T>);
+                    impl<T: TxnService> tonic::server::ServerStreamingService<super::StreamRequest>
+                        for StreamUpdatesSvc<T>
+                    {
+                        type Response = super::StreamChunk;
+                        type ResponseStream = T::StreamUpdatesStream;
+                        type Future =
+                            BoxFuture<tonic::Response<Self::ResponseStream>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::StreamRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).stream_updates(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = StreamUpdatesSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.server_streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/txn_rpc.TxnService/get_snapshot" => {
+                    #[allow(non_camel_case_types)]
+                    struct get_snapshotSvc<T: TxnService>(pub Arc<T>);
+                    impl<T: TxnService> tonic::server::UnaryService<super::SnapshotRequest> for get_snapshotSvc<T> {
+                        type Response = super::SnapshotResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::SnapshotRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).get_snapshot(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = get_snapshotSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/txn_rpc.TxnService/sync_comments" => {
+                    #[allow(non_camel_case_types)]
+                    struct sync_commentsSvc<T: TxnService>(pub Arc<T>);
+                    impl<T: TxnService> tonic::server::UnaryService<super::CommentsRequest> for sync_commentsSvc<T> {
+                        type Response = super::CommentsResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::CommentsRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).sync_comments(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = sync_commentsSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                _ => Box::pin(async move {
+                    Ok(http::Response::builder()
+                        .status(200)
+                        .header("grpc-status", "12")
+                        .header("content-type", "application/grpc")
+                        .body(empty_body())
+                        .unwrap())
+                }),
+            }
+        }
+    }
+    impl<T: TxnService> Clone for TxnServiceServer<T> {
+        fn clone(&self) -> Self {
+            let inner = self.inner.clone();
+            Self {
+                inner,
+                accept_compression_encodings: self.accept_compression_encodings,
+                send_compression_encodings: self.send_compression_encodings,
+            }
         }
-        pub async fn get_remote_updates(
-            &mut self,
-            request: impl tonic::IntoRequest<super::PullRequest>,
-        ) -> Result<tonic::Response<super::PullResponse>, tonic::Status> {
-            self.inner.ready().await.map_err(|e| {
-                tonic::Status::new(
-                    tonic::Code::Unknown,
-                    format!("Service was not ready: {}", e.into()),
-                )
-            })?;
-            let codec = tonic::codec::ProstCodec::default();
-            let path =
-                http::uri::PathAndQuery::from_static("/txn_rpc.TxnService/get_remote_updates");
-            self.inner.unary(request.into_request(), path, codec).await
+    }
+    impl<T: TxnService> Clone for _Inner<T> {
+        fn clone(&self) -> Self {
+            Self(self.0.clone())
         }
-        pub async fn sync_peer_list(
-            &mut self,
-            request: impl tonic::IntoRequest<super::RegisterRequest>,
-        ) -> Result<tonic::Response<super::Status>, tonic::Status> {
-            self.inner.ready().await.map_err(|e| {
-                tonic::Status::new(
-                    tonic::Code::Unknown,
-                    format!("Service was not ready: {}", e.into()),
-                )
-            })?;
-            let codec = tonic::codec::ProstCodec::default();
-            let path = http::uri::PathAndQuery::from_static("/txn_rpc.TxnService/sync_peer_list");
-            self.inner.unary(request.into_request(), path, codec).await
+    }
+    impl<T: std::fmt::Debug> std::fmt::Debug for _Inner<T> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{:?}", self.0)
         }
     }
+    impl<T: TxnService> tonic::transport::NamedService for TxnServiceServer<T> {
+        const NAME: &'static str = "txn_rpc.TxnService";
+    }
 }
 #[doc = r" Generated server implementations."]
-pub mod txn_service_server {
+pub mod admin_service_server {
     #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
     use tonic::codegen::*;
-    #[doc = "Generated trait containing gRPC methods that should be implemented for use with TxnServiceServer."]
+    #[doc = "Generated trait containing gRPC methods that should be implemented for use with AdminServiceServer."]
     #[async_trait]
-    pub trait TxnService: Send + Sync + 'static {
-        async fn get_remote_updates(
+    pub trait AdminService: Send + Sync + 'static {
+        async fn get_vector_clock(
             &self,
-            request: tonic::Request<super::PullRequest>,
-        ) -> Result<tonic::Response<super::PullResponse>, tonic::Status>;
-        async fn sync_peer_list(
+            request: tonic::Request<super::AdminRequest>,
+        ) -> Result<tonic::Response<super::AdminResponse>, tonic::Status>;
+        async fn list_peers(
             &self,
-            request: tonic::Request<super::RegisterRequest>,
-        ) -> Result<tonic::Response<super::Status>, tonic::Status>;
+            request: tonic::Request<super::AdminRequest>,
+        ) -> Result<tonic::Response<super::AdminResponse>, tonic::Status>;
+        async fn dump_blocks(
+            &self,
+            request: tonic::Request<super::BlockRangeRequest>,
+        ) -> Result<tonic::Response<super::AdminResponse>, tonic::Status>;
+        async fn list_pending(
+            &self,
+            request: tonic::Request<super::AdminRequest>,
+        ) -> Result<tonic::Response<super::AdminResponse>, tonic::Status>;
+        async fn force_sync(
+            &self,
+            request: tonic::Request<super::ForceSyncRequest>,
+        ) -> Result<tonic::Response<super::AdminResponse>, tonic::Status>;
+        async fn compact(
+            &self,
+            request: tonic::Request<super::AdminRequest>,
+        ) -> Result<tonic::Response<super::AdminResponse>, tonic::Status>;
+        async fn collect_tombstones(
+            &self,
+            request: tonic::Request<super::AdminRequest>,
+        ) -> Result<tonic::Response<super::AdminResponse>, tonic::Status>;
     }
+    #[doc = " introspection of a live replica, only owners of the doc can call it"]
     #[derive(Debug)]
-    pub struct TxnServiceServer<T: TxnService> {
+    pub struct AdminServiceServer<T: AdminService> {
         inner: _Inner<T>,
         accept_compression_encodings: (),
         send_compression_encodings: (),
     }
     struct _Inner<T>(Arc<T>);
-    impl<T: TxnService> TxnServiceServer<T> {
+    impl<T: AdminService> AdminServiceServer<T> {
         pub fn new(inner: T) -> Self {
             let inner = Arc::new(inner);
             let inner = _Inner(inner);
@@ -151,9 +1066,9 @@ pub mod txn_service_server {
             InterceptedService::new(Self::new(inner), interceptor)
         }
     }
-    impl<T, B> tonic::codegen::Service<http::Request<B>> for TxnServiceServer<T>
+    impl<T, B> tonic::codegen::Service<http::Request<B>> for AdminServiceServer<T>
     where
-        T: TxnService,
+        T: AdminService,
         B: Body + Send + 'static,
         B::Error: Into<StdError> + Send + 'static,
     {
@@ -166,18 +1081,18 @@ pub mod txn_service_server {
         fn call(&mut self, req: http::Request<B>) -> Self::Future {
             let inner = self.inner.clone();
             match req.uri().path() {
-                "/txn_rpc.TxnService/get_remote_updates" => {
+                "/txn_rpc.AdminService/get_vector_clock" => {
                     #[allow(non_camel_case_types)]
-                    struct get_remote_updatesSvc<T: TxnService>(pub Arc<T>);
-                    impl<T: TxnService> tonic::server::UnaryService<super::PullRequest> for get_remote_updatesSvc<T> {
-                        type Response = super::PullResponse;
+                    struct get_vector_clockSvc<T: AdminService>(pub Arc<T>);
+                    impl<T: AdminService> tonic::server::UnaryService<super::AdminRequest> for get_vector_clockSvc<T> {
+                        type Response = super::AdminResponse;
                         type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
                         fn call(
                             &mut self,
-                            request: tonic::Request<super::PullRequest>,
+                            request: tonic::Request<super::AdminRequest>,
                         ) -> Self::Future {
                             let inner = self.0.clone();
-                            let fut = async move { (*inner).get_remote_updates(request).await };
+                            let fut = async move { (*inner).get_vector_clock(request).await };
                             Box::pin(fut)
                         }
                     }
@@ -186,7 +1101,7 @@ pub mod txn_service_server {
                     let inner = self.inner.clone();
                     let fut = async move {
                         let inner = inner.0;
-                        let method = get_remote_updatesSvc(inner);
+                        let method = get_vector_clockSvc(inner);
                         let codec = tonic::codec::ProstCodec::default();
                         let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
                             accept_compression_encodings,
@@ -197,18 +1112,18 @@ pub mod txn_service_server {
                     };
                     Box::pin(fut)
                 }
-                "/txn_rpc.TxnService/sync_peer_list" => {
+                "/txn_rpc.AdminService/list_peers" => {
                     #[allow(non_camel_case_types)]
-                    struct sync_peer_listSvc<T: TxnService>(pub Arc<T>);
-                    impl<T: TxnService> tonic::server::UnaryService<super::RegisterRequest> for sync_peer_listSvc<T> {
-                        type Response = super::Status;
+                    struct list_peersSvc<T: AdminService>(pub Arc<T>);
+                    impl<T: AdminService> tonic::server::UnaryService<super::AdminRequest> for list_peersSvc<T> {
+                        type Response = super::AdminResponse;
                         type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
                         fn call(
                             &mut self,
-                            request: tonic::Request<super::RegisterRequest>,
+                            request: tonic::Request<super::AdminRequest>,
                         ) -> Self::Future {
                             let inner = self.0.clone();
-                            let fut = async move { (*inner).sync_peer_list(request).await };
+                            let fut = async move { (*inner).list_peers(request).await };
                             Box::pin(fut)
                         }
                     }
@@ -217,7 +1132,164 @@ pub mod txn_service_server {
                     let inner = self.inner.clone();
                     let fut = async move {
                         let inner = inner.0;
-                        let method = sync_peer_listSvc(inner);
+                        let method = list_peersSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/txn_rpc.AdminService/dump_blocks" => {
+                    #[allow(non_camel_case_types)]
+                    struct dump_blocksSvc<T: AdminService>(pub Arc<T>);
+                    impl<T: AdminService> tonic::server::UnaryService<super::BlockRangeRequest> for dump_blocksSvc<T> {
+                        type Response = super::AdminResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::BlockRangeRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).dump_blocks(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = dump_blocksSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/txn_rpc.AdminService/list_pending" => {
+                    #[allow(non_camel_case_types)]
+                    struct list_pendingSvc<T: AdminService>(pub Arc<T>);
+                    impl<T: AdminService> tonic::server::UnaryService<super::AdminRequest> for list_pendingSvc<T> {
+                        type Response = super::AdminResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::AdminRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).list_pending(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = list_pendingSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/txn_rpc.AdminService/force_sync" => {
+                    #[allow(non_camel_case_types)]
+                    struct force_syncSvc<T: AdminService>(pub Arc<T>);
+                    impl<T: AdminService> tonic::server::UnaryService<super::ForceSyncRequest> for force_syncSvc<T> {
+                        type Response = super::AdminResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ForceSyncRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).force_sync(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = force_syncSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/txn_rpc.AdminService/compact" => {
+                    #[allow(non_camel_case_types)]
+                    struct compactSvc<T: AdminService>(pub Arc<T>);
+                    impl<T: AdminService> tonic::server::UnaryService<super::AdminRequest> for compactSvc<T> {
+                        type Response = super::AdminResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::AdminRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).compact(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = compactSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/txn_rpc.AdminService/collect_tombstones" => {
+                    #[allow(non_camel_case_types)]
+                    struct collect_tombstonesSvc<T: AdminService>(pub Arc<T>);
+                    impl<T: AdminService> tonic::server::UnaryService<super::AdminRequest>
+                        for collect_tombstonesSvc<T>
+                    {
+                        type Response = super::AdminResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::AdminRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).collect_tombstones(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = collect_tombstonesSvc(inner);
                         let codec = tonic::codec::ProstCodec::default();
                         let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
                             accept_compression_encodings,
@@ -239,7 +1311,7 @@ pub mod txn_service_server {
             }
         }
     }
-    impl<T: TxnService> Clone for TxnServiceServer<T> {
+    impl<T: AdminService> Clone for AdminServiceServer<T> {
         fn clone(&self) -> Self {
             let inner = self.inner.clone();
             Self {
@@ -249,7 +1321,7 @@ pub mod txn_service_server {
             }
         }
     }
-    impl<T: TxnService> Clone for _Inner<T> {
+    impl<T: AdminService> Clone for _Inner<T> {
         fn clone(&self) -> Self {
             Self(self.0.clone())
         }
@@ -259,7 +1331,7 @@ pub mod txn_service_server {
             write!(f, "{:?}", self.0)
         }
     }
-    impl<T: TxnService> tonic::transport::NamedService for TxnServiceServer<T> {
-        const NAME: &'static str = "txn_rpc.TxnService";
+    impl<T: AdminService> tonic::transport::NamedService for AdminServiceServer<T> {
+        const NAME: &'static str = "txn_rpc.AdminService";
     }
 }
@@ -1,28 +1,56 @@
 use serde::{Deserialize, Serialize};
-use std::net::ToSocketAddrs;
+use std::net::{SocketAddr, ToSocketAddrs};
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 
 use crate::crdt::{
-    block::Block, sync_txn::SyncTransaction, txn_rpc::txn_service_server::TxnServiceServer,
+    anti_entropy::AntiEntropy, auth::interceptor, block::Block, block::BlockID,
+    sync_txn::SyncTransaction, tls::server_builder,
+    txn_rpc::admin_service_server::AdminServiceServer,
+    txn_rpc::txn_service_server::TxnServiceServer,
 };
-use std::{error::Error, fmt::Display};
+use std::fmt::Display;
+use tracing::info;
 
-pub type CRDTResult<T> = Result<T, Box<(dyn Error + Send + Sync)>>;
+pub type CRDTResult<T> = Result<T, CRDTError>;
 
-/// basic error types that can occur when running the tribbler service.
-#[derive(Debug, Clone)]
+/// errors that can occur when editing and syncing a doc,
+/// callers can match on the variant to decide whether to retry or alert
+#[derive(Debug, Clone, PartialEq)]
 pub enum CRDTError {
-    RegisterUserFailed(),
+    // a peer, the relay or a websocket could not be reached, or an rpc failed
+    Transport(String),
+    // a message, file or key could not be (de)serialized
+    Serialization(String),
+    // zookeeper could not register the client or watch the peers of a doc
+    Membership(String),
+    // blocks were rejected instead of integrated (e.g. invalid signatures)
+    Conflict(Vec<BlockID>),
+    // blocks are waiting for their origins, they are integrated once the origins arrive
+    MissingDependency(Vec<BlockID>),
+    // the caller is not authenticated or has no role allowing the request
+    PermissionDenied(String),
+    // a local file (keys, permissions, certificates) could not be read or written
+    Storage(String),
+    // a signing or encryption key is invalid
+    InvalidKey(String),
+    // a node of the xml tree cannot be found, or the edit would break the tree
+    InvalidNode(String),
     Unknown(String),
-    BackgroundSyncFailed(String),
 }
 
 impl Display for CRDTError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let x = match self {
-            CRDTError::RegisterUserFailed() => format!("cannot register user"),
+            CRDTError::Transport(x) => format!("transport error: {}", x),
+            CRDTError::Serialization(x) => format!("serialization error: {}", x),
+            CRDTError::Membership(x) => format!("membership error: {}", x),
+            CRDTError::Conflict(ids) => format!("rejected blocks {:?}", ids),
+            CRDTError::MissingDependency(ids) => format!("blocks {:?} miss their origins", ids),
+            CRDTError::PermissionDenied(x) => format!("permission denied: {}", x),
+            CRDTError::Storage(x) => format!("storage error: {}", x),
+            CRDTError::InvalidKey(x) => format!("invalid key: {}", x),
+            CRDTError::InvalidNode(x) => format!("invalid node: {}", x),
             CRDTError::Unknown(x) => format!("unknown error: {}", x),
-            CRDTError::BackgroundSyncFailed(x) => format!("failed to sync in the background {}", x),
         };
         write!(f, "{}", x)
     }
@@ -30,6 +58,72 @@ impl Display for CRDTError {
 
 impl std::error::Error for CRDTError {}
 
+impl From<std::io::Error> for CRDTError {
+    fn from(e: std::io::Error) -> Self {
+        CRDTError::Storage(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for CRDTError {
+    fn from(e: serde_json::Error) -> Self {
+        CRDTError::Serialization(e.to_string())
+    }
+}
+
+impl From<hex::FromHexError> for CRDTError {
+    fn from(e: hex::FromHexError) -> Self {
+        CRDTError::Serialization(e.to_string())
+    }
+}
+
+impl From<tonic::Status> for CRDTError {
+    fn from(e: tonic::Status) -> Self {
+        match e.code() {
+            tonic::Code::PermissionDenied | tonic::Code::Unauthenticated => {
+                CRDTError::PermissionDenied(e.message().to_string())
+            }
+            _ => CRDTError::Transport(format!("{:?}: {}", e.code(), e.message())),
+        }
+    }
+}
+
+// Status returned by rpc handlers when the request fails locally
+impl From<CRDTError> for tonic::Status {
+    fn from(e: CRDTError) -> Self {
+        let msg = e.to_string();
+        match e {
+            CRDTError::Transport(_) => tonic::Status::unavailable(msg),
+            CRDTError::Serialization(_) | CRDTError::InvalidKey(_) | CRDTError::InvalidNode(_) => {
+                tonic::Status::invalid_argument(msg)
+            }
+            CRDTError::Membership(_) => tonic::Status::not_found(msg),
+            CRDTError::Conflict(_) | CRDTError::MissingDependency(_) => {
+                tonic::Status::failed_precondition(msg)
+            }
+            CRDTError::PermissionDenied(_) => tonic::Status::permission_denied(msg),
+            CRDTError::Storage(_) | CRDTError::Unknown(_) => tonic::Status::internal(msg),
+        }
+    }
+}
+
+impl From<tonic::transport::Error> for CRDTError {
+    fn from(e: tonic::transport::Error) -> Self {
+        CRDTError::Transport(e.to_string())
+    }
+}
+
+impl From<tonic::codegen::http::uri::InvalidUri> for CRDTError {
+    fn from(e: tonic::codegen::http::uri::InvalidUri) -> Self {
+        CRDTError::Transport(e.to_string())
+    }
+}
+
+impl From<zookeeper::ZkError> for CRDTError {
+    fn from(e: zookeeper::ZkError) -> Self {
+        CRDTError::Membership(format!("{:?}", e))
+    }
+}
+
 // general type
 pub type ClientID = u32;
 
@@ -39,17 +133,31 @@ pub type Updates = Vec<Block>;
 pub struct Peer {
     pub client_id: ClientID,
     pub ip_addr: String,
+    // hex encoded Ed25519 key the peer signs its insertions with
+    #[serde(default)]
+    pub public_key: Option<String>,
+    #[serde(default)]
+    pub display: Option<PeerDisplay>,
+}
+
+// PeerDisplay is how a client is shown to the others, e.g. next to its cursor or in a blame view
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct PeerDisplay {
+    pub name: String,
+    // css color
+    #[serde(default)]
+    pub color: Option<String>,
 }
 
 impl Peer {}
 
-// start rpc service
+// start rpc service, returns once receiver gets a message
 pub async fn serve_rpc(
     txn: SyncTransaction,
     txn_bg: SyncTransaction,
     mut receiver: Receiver<()>,
     sender: Sender<()>,
-) {
+) -> CRDTResult<()> {
     let ip = txn.client_ip.clone();
     let doc_name = txn.doc_name.clone();
     let (sender_r, mut receiver_r): (Sender<()>, Receiver<()>) = channel(1);
@@ -59,24 +167,44 @@ pub async fn serve_rpc(
         let _ = txn_bg.zk.background_sync(doc_name, sender).await;
     });
 
-    let txn_rpc = TxnServiceServer::new(txn);
-    let server = tonic::transport::Server::builder().add_service(txn_rpc);
-    let resolved_addr_res = ip.to_socket_addrs().unwrap().next();
-
-    if let Some(resolved_addr) = resolved_addr_res {
-        let res = server
-            .serve_with_shutdown(resolved_addr, async move {
-                println!("started rpc at {:?}", resolved_addr);
-                let _ = sender_r.send(()).await;
-                receiver.recv().await;
-                println!("successfully shut down txn rpc service");
-            })
-            .await;
-        if let Err(e) = res {
-            println!("failed to start rpc service {:?}", e);
-        }
-    } else {
-        println!("cannot resolve ip address");
+    let mut builder = server_builder(&txn.tls)?;
+    // background tasks run as long as the service runs
+    let mut background = vec![];
+    if let Some(config) = txn.anti_entropy.clone() {
+        background.push(tokio::spawn(AntiEntropy::new(txn.clone(), config).run()));
+    }
+    if txn.gossip.is_some() {
+        background.push(tokio::spawn(txn.clone().run_gossip()));
     }
+    if !txn.conn.health_interval.is_zero() {
+        background.push(tokio::spawn(txn.clone().run_health_checks()));
+    }
+    let auth = interceptor(txn.auth.clone());
+    // the admin service shares the replica and the authentication of the txn service
+    let admin_rpc = AdminServiceServer::with_interceptor(txn.clone(), auth.clone());
+    let txn_rpc = TxnServiceServer::with_interceptor(txn, auth);
+    let server = builder.add_service(txn_rpc).add_service(admin_rpc);
+    let resolved_addr = resolve(&ip)?;
+    let res = server
+        .serve_with_shutdown(resolved_addr, async move {
+            info!(addr = %resolved_addr, "started rpc");
+            let _ = sender_r.send(()).await;
+            receiver.recv().await;
+            info!("shut down txn rpc service");
+        })
+        .await;
+    for task in background {
+        task.abort();
+    }
+    res?;
+    Ok(())
     // handle.await.expect("this task being joined has panicked")
 }
+
+// Resolve the address a service listens on
+pub fn resolve(ip: &str) -> CRDTResult<SocketAddr> {
+    ip.to_socket_addrs()
+        .map_err(|e| CRDTError::Transport(format!("cannot resolve {}: {}", ip, e)))?
+        .next()
+        .ok_or_else(|| CRDTError::Transport(format!("cannot resolve {}", ip)))
+}
@@ -0,0 +1,125 @@
+use crate::crdt::doc::{Doc, VectorClock};
+use crate::crdt::snapshot::{delete_set, DeleteSet, Snapshot};
+use crate::crdt::utils::ClientID;
+use serde::{Deserialize, Serialize};
+
+// Version identifies a state of a doc: the characters inserted below its vector clock
+// minus the ones in its delete set
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Version {
+    pub vector_clock: VectorClock,
+    pub delete_set: DeleteSet,
+}
+
+impl Version {
+    // check if the character at clock of client is visible in the version
+    pub fn is_visible(&self, client: ClientID, clock: u32) -> bool {
+        if clock >= self.vector_clock.get(client) {
+            return false;
+        }
+        let ranges = match self.delete_set.get(&client) {
+            Some(ranges) => ranges,
+            None => return true,
+        };
+        // ranges are sorted and don't overlap
+        let i = ranges.partition_point(|(start, _)| *start <= clock);
+        i == 0 || ranges[i - 1].1 <= clock
+    }
+}
+
+impl Snapshot {
+    pub fn version(&self) -> Version {
+        Version {
+            vector_clock: self.vector_clock.clone(),
+            delete_set: self.delete_set.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffKind {
+    Unchanged,
+    Inserted,
+    Deleted,
+}
+
+// DiffSpan is a contiguous range of text written by author that changed the same way between two versions
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DiffSpan {
+    pub kind: DiffKind,
+    pub text: String,
+    pub author: ClientID,
+    // client that deleted the text, only known for deleted spans
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_by: Option<ClientID>,
+}
+
+impl Doc {
+    // Current version of the doc
+    pub async fn version(&self) -> Version {
+        let store = self.block_store.clone();
+        let store_lock = store.lock().await;
+        let mut blocks = vec![];
+        for block in store_lock.total_store.list.iter() {
+            blocks.push(block.lock().await.clone());
+        }
+        Version {
+            vector_clock: self.vector_clock.clone(),
+            delete_set: delete_set(&blocks),
+        }
+    }
+
+    // Spans of text inserted, deleted or left unchanged from version from to version to,
+    // in spatial order; the doc has to contain every character of both versions
+    //
+    // text that is visible in neither version is left out, as is deleted text
+    // whose content is unknown (left out of a snapshot)
+    pub async fn diff(&self, from: &Version, to: &Version) -> Vec<DiffSpan> {
+        let store = self.block_store.clone();
+        let store_lock = store.lock().await;
+        let mut spans: Vec<DiffSpan> = vec![];
+        for block in store_lock.total_store.list.iter() {
+            let block_lock = block.lock().await;
+            if block_lock.elided {
+                continue;
+            }
+            let author = block_lock.id.client;
+            for (offset, c) in block_lock.content.as_str().char_indices() {
+                let clock = block_lock.id.clock + offset as u32;
+                let kind = match (from.is_visible(author, clock), to.is_visible(author, clock)) {
+                    (true, true) => DiffKind::Unchanged,
+                    (false, true) => DiffKind::Inserted,
+                    (true, false) => DiffKind::Deleted,
+                    (false, false) => continue,
+                };
+                let deleted_by = match kind {
+                    DiffKind::Deleted => block_lock.deleted_by,
+                    _ => None,
+                };
+                match spans.last_mut() {
+                    Some(span)
+                        if span.kind == kind
+                            && span.author == author
+                            && span.deleted_by == deleted_by =>
+                    {
+                        span.text.push(c)
+                    }
+                    _ => spans.push(DiffSpan {
+                        kind,
+                        text: c.to_string(),
+                        author,
+                        deleted_by,
+                    }),
+                }
+            }
+        }
+        spans
+    }
+
+    // Changes made since version, e.g. since a user last looked at the doc
+    pub async fn changes_since(&self, version: &Version) -> Vec<DiffSpan> {
+        let now = self.version().await;
+        self.diff(version, &now).await
+    }
+}
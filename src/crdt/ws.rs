@@ -0,0 +1,587 @@
+use crate::crdt::awareness::CursorState;
+use crate::crdt::block::{Block, ChangeID};
+use crate::crdt::doc::VectorClock;
+use crate::crdt::e2e::KeyEpochs;
+use crate::crdt::relay::RelayServer;
+use crate::crdt::signing::Signatures;
+use crate::crdt::sync_txn::SyncTransaction;
+use crate::crdt::utils::{CRDTError, CRDTResult, Updates};
+use futures_util::{Sink, SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::Mutex;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, field, info, instrument, warn, Span};
+
+// how many messages of a doc can be buffered for a slow connection
+const FANOUT_CAPACITY: usize = 1024;
+
+// WsMessage is a message exchanged over a websocket (as json text frames),
+// e.g. {"Update": {"updates": [...]}}
+//
+// the handshake follows y-websocket:
+// 1. both sides send SyncStep1 with their vector clock (and the client its token,
+//    if the relay checks permissions)
+// 2. both sides reply SyncStep2 with the updates the other side is missing
+// 3. afterwards, new local updates are streamed as Update
+// every SyncStep2 and Update is answered with an Ack once it is integrated,
+// cursors are sent as Awareness at any time
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub enum WsMessage {
+    SyncStep1 {
+        doc_name: String,
+        vector_clock: VectorClock,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+    },
+    SyncStep2 {
+        updates: Updates,
+        #[serde(default)]
+        signatures: Signatures,
+        #[serde(default)]
+        key_epochs: KeyEpochs,
+    },
+    Update {
+        updates: Updates,
+        #[serde(default)]
+        signatures: Signatures,
+        #[serde(default)]
+        key_epochs: KeyEpochs,
+    },
+    // clock of the side that integrated a SyncStep2 or Update,
+    // and the changes of its blocks that have been applied (clocks only cover those of whole diffs)
+    Ack {
+        vector_clock: VectorClock,
+        #[serde(default)]
+        changes: Vec<ChangeID>,
+    },
+    Awareness {
+        states: Vec<CursorState>,
+    },
+}
+
+// Seen is what the other side of a connection is known to have
+struct Seen {
+    clock: VectorClock,
+    // changes the clock doesn't cover
+    changes: HashSet<ChangeID>,
+}
+
+impl Seen {
+    // Record that the other side has updates
+    fn record(&mut self, updates: &Updates) {
+        for block in updates {
+            self.clock
+                .advance(block.id.client, block.id.clock + block.content.len() as u32);
+            let clock = &self.clock;
+            self.changes.extend(
+                block
+                    .changes
+                    .iter()
+                    .filter(|change| !clock.has_change(change)),
+            );
+        }
+    }
+
+    // Whether the other side misses the insertion or a change of block
+    fn misses(&self, block: &Block) -> bool {
+        block.id.clock + block.content.len() as u32 > self.clock.get(block.id.client)
+            || block
+                .changes
+                .iter()
+                .any(|change| !self.clock.has_change(change) && !self.changes.contains(change))
+    }
+}
+
+// Remote tracks the updates exchanged with the other side of a connection
+struct Remote {
+    // what the other side acknowledged or sent itself, blocks it didn't integrate
+    // (e.g. quarantined until their signature is known) are sent again with the next updates
+    acked: Seen,
+    // acked and the updates sent since, so that they are not sent twice before an Ack
+    sent: Seen,
+}
+
+impl Remote {
+    fn new(clock: VectorClock) -> Self {
+        Remote {
+            acked: Seen {
+                clock: clock.clone(),
+                changes: HashSet::new(),
+            },
+            sent: Seen {
+                clock,
+                changes: HashSet::new(),
+            },
+        }
+    }
+
+    // Record updates received from the other side
+    fn received(&mut self, updates: &Updates) {
+        self.acked.record(updates);
+        self.sent.record(updates);
+    }
+
+    // Record the clock and applied changes of an Ack
+    fn ack(&mut self, clock: VectorClock, changes: Vec<ChangeID>) {
+        for (client, clock) in clock.clock_map.iter() {
+            self.sent.clock.advance(*client, *clock);
+        }
+        self.sent.clock.merge_changes(&clock);
+        self.sent.changes.extend(changes.iter().copied());
+        self.acked.changes.extend(changes);
+        self.acked
+            .changes
+            .retain(|change| !clock.has_change(change));
+        self.acked.clock = clock;
+    }
+}
+
+// Fanout is published to all connections editing a doc by the connection it comes from
+#[derive(Clone, Debug)]
+enum Fanout {
+    // the replica integrated new updates, each connection sends what its client is missing
+    Updated,
+    Awareness(Vec<CursorState>),
+}
+
+type Topic = broadcast::Sender<(u64, Fanout)>;
+
+// WsServer accepts websocket connections for the docs hosted on a relay,
+// updates received from one connection are integrated and the blocks the
+// replica accepted are sent to all other connections editing the same doc
+#[derive(Clone)]
+pub struct WsServer {
+    pub ip: String,
+    relay: RelayServer,
+    topics: Arc<Mutex<HashMap<String, Topic>>>,
+}
+
+impl WsServer {
+    pub fn new(ip: String, relay: RelayServer) -> Self {
+        WsServer {
+            ip,
+            relay,
+            topics: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    async fn get_topic(&self, doc_name: &str) -> Topic {
+        let mut topics = self.topics.lock().await;
+        topics
+            .entry(doc_name.to_string())
+            .or_insert_with(|| broadcast::channel(FANOUT_CAPACITY).0)
+            .clone()
+    }
+
+    // Serve one websocket connection until it is closed
+    #[instrument(name = "ws_conn", skip_all, fields(doc = field::Empty, conn = field::Empty))]
+    async fn handle_conn(&self, stream: TcpStream) -> CRDTResult<()> {
+        let ws = tokio_tungstenite::accept_async(stream)
+            .await
+            .map_err(ws_error)?;
+        let (mut write, mut read) = ws.split();
+        let conn_id: u64 = rand::random();
+
+        // the first message must be SyncStep1 to tell which doc to edit
+        let (doc_name, remote_clock, token) = match read.next().await {
+            Some(Ok(msg)) => match parse(msg) {
+                Some(WsMessage::SyncStep1 {
+                    doc_name,
+                    vector_clock,
+                    token,
+                }) => (doc_name, vector_clock, token),
+                _ => {
+                    return Err(CRDTError::Transport(
+                        "websocket handshake must start with SyncStep1".to_string(),
+                    ))
+                }
+            },
+            _ => return Ok(()),
+        };
+        let span = Span::current();
+        span.record("doc", doc_name.as_str());
+        span.record("conn", conn_id);
+        debug!("websocket connection opened");
+
+        // like the rpcs of the relay, the client needs a role on the doc to read it,
+        // only editors can create the replica and everything they write is bound to their token
+        let sender = match &self.relay.auth {
+            Some(permissions) => {
+                let client = token.and_then(|token| permissions.authenticate(&token));
+                match client.filter(|client| permissions.role(&doc_name, *client).is_some()) {
+                    Some(client) => Some(client),
+                    None => {
+                        let _ = write.send(Message::Close(None)).await;
+                        return Err(CRDTError::PermissionDenied(format!(
+                            "{:?} has no role on {:?}",
+                            client, doc_name
+                        )));
+                    }
+                }
+            }
+            None => None,
+        };
+        let txn = match sender {
+            Some(client) if !self.relay.can_edit(&doc_name, client) => {
+                self.relay.find_doc(&doc_name).await?
+            }
+            _ => self.relay.get_doc(&doc_name).await,
+        };
+        let topic = self.get_topic(&doc_name).await;
+        let mut fanout = topic.subscribe();
+
+        let local_clock = txn.doc.lock().await.vector_clock.clone();
+        send(
+            &mut write,
+            &WsMessage::SyncStep1 {
+                doc_name: doc_name.clone(),
+                vector_clock: local_clock,
+                token: None,
+            },
+        )
+        .await?;
+        // clock of the client's SyncStep1, its changes are adopted once its SyncStep2 is integrated
+        let mut handshake_clock = Some(remote_clock.clone());
+        let updates = txn.compute_diff(remote_clock.clone()).await;
+        let mut remote = Remote::new(remote_clock);
+        remote.sent.record(&updates);
+        let signatures = txn.signatures_for(&updates).await;
+        let key_epochs = txn.key_epochs().await;
+        send(
+            &mut write,
+            &WsMessage::SyncStep2 {
+                updates,
+                signatures,
+                key_epochs,
+            },
+        )
+        .await?;
+
+        loop {
+            tokio::select! {
+                msg = read.next() => {
+                    let msg = match msg {
+                        Some(Ok(msg)) => msg,
+                        _ => break,
+                    };
+                    if msg.is_close() {
+                        break;
+                    }
+                    match parse(msg) {
+                        Some(WsMessage::SyncStep1 { vector_clock, .. }) => {
+                            let updates = txn.compute_diff(vector_clock.clone()).await;
+                            remote = Remote::new(vector_clock);
+                            remote.sent.record(&updates);
+                            let signatures = txn.signatures_for(&updates).await;
+                            let key_epochs = txn.key_epochs().await;
+                            let msg = WsMessage::SyncStep2 { updates, signatures, key_epochs };
+                            send(&mut write, &msg).await?;
+                        }
+                        Some(WsMessage::SyncStep2 { updates, signatures, key_epochs }) => {
+                            remote.received(&updates);
+                            let changed = changed_blocks(&updates);
+                            let res = match handshake_clock.take() {
+                                Some(client_clock) => {
+                                    txn.integrate_diff(
+                                        sender,
+                                        updates,
+                                        signatures,
+                                        key_epochs,
+                                        &client_clock,
+                                    )
+                                    .await
+                                }
+                                None => txn.update_from(sender, updates, signatures, key_epochs).await,
+                            };
+                            log_refused(res);
+                            send_ack(&txn, &mut write, &changed).await?;
+                            let _ = topic.send((conn_id, Fanout::Updated));
+                        }
+                        Some(WsMessage::Update { updates, signatures, key_epochs }) => {
+                            remote.received(&updates);
+                            let changed = changed_blocks(&updates);
+                            log_refused(txn.update_from(sender, updates, signatures, key_epochs).await);
+                            send_ack(&txn, &mut write, &changed).await?;
+                            // rejected blocks never reach the replica, so they are never fanned out
+                            let _ = topic.send((conn_id, Fanout::Updated));
+                        }
+                        Some(WsMessage::Ack { vector_clock, changes }) => {
+                            remote.ack(vector_clock, changes);
+                        }
+                        Some(WsMessage::Awareness { states }) => {
+                            txn.doc.lock().await.awareness.apply_remote(states.clone());
+                            let _ = topic.send((conn_id, Fanout::Awareness(states)));
+                        }
+                        None => warn!("invalid websocket message"),
+                    }
+                }
+                msg = fanout.recv() => {
+                    match msg {
+                        Ok((from, _)) if from == conn_id => {}
+                        Ok((_, Fanout::Updated)) => {
+                            send_missing(&txn, &mut write, &mut remote).await?;
+                        }
+                        Ok((_, Fanout::Awareness(states))) => {
+                            send(&mut write, &WsMessage::Awareness { states }).await?;
+                        }
+                        // lagged behind, catch up with everything the client is missing
+                        Err(broadcast::error::RecvError::Lagged(_)) => {
+                            send_missing(&txn, &mut write, &mut remote).await?;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    }
+                }
+            }
+        }
+        debug!("websocket connection closed");
+        Ok(())
+    }
+}
+
+// start websocket service, returns once receiver gets a message
+pub async fn serve_ws(
+    server: WsServer,
+    mut receiver: Receiver<()>,
+    sender: Sender<()>,
+) -> CRDTResult<()> {
+    let listener = TcpListener::bind(&server.ip).await.map_err(ws_error)?;
+    info!(addr = %server.ip, "started websocket service");
+    let _ = sender.send(()).await;
+
+    loop {
+        tokio::select! {
+            conn = listener.accept() => {
+                if let Ok((stream, _)) = conn {
+                    let server = server.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = server.handle_conn(stream).await {
+                            warn!(error = %e, "websocket connection failed");
+                        }
+                    });
+                }
+            }
+            _ = receiver.recv() => break,
+        }
+    }
+    info!("shut down websocket service");
+    Ok(())
+}
+
+// Keep the doc of txn in sync with the doc served at url (e.g. ws://127.0.0.1:4301),
+// local updates and cursor moves are sent every interval
+//
+// returns when the connection is closed or receiver gets a message
+#[instrument(skip_all, fields(doc = %txn.doc_name, client = txn.client, url = url))]
+pub async fn sync_ws(
+    txn: &SyncTransaction,
+    url: &str,
+    interval: Duration,
+    mut receiver: Receiver<()>,
+) -> CRDTResult<()> {
+    let (ws, _) = tokio_tungstenite::connect_async(url)
+        .await
+        .map_err(ws_error)?;
+    let (mut write, mut read) = ws.split();
+
+    let local_clock = txn.doc.lock().await.vector_clock.clone();
+    send(
+        &mut write,
+        &WsMessage::SyncStep1 {
+            doc_name: txn.doc_name.clone(),
+            vector_clock: local_clock,
+            token: txn.token.clone(),
+        },
+    )
+    .await?;
+
+    // what the server is known to have, None until its SyncStep1 arrives
+    let mut remote: Option<Remote> = None;
+    // clock of the server's SyncStep1, its changes are adopted once its SyncStep2 is integrated
+    let mut handshake_clock: Option<VectorClock> = None;
+    let mut last_states: Vec<CursorState> = vec![];
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        tokio::select! {
+            msg = read.next() => {
+                let msg = match msg {
+                    Some(Ok(msg)) => msg,
+                    _ => break,
+                };
+                if msg.is_close() {
+                    break;
+                }
+                match parse(msg) {
+                    Some(WsMessage::SyncStep1 { vector_clock, .. }) => {
+                        handshake_clock = Some(vector_clock.clone());
+                        let updates = txn.compute_diff(vector_clock.clone()).await;
+                        let mut server = Remote::new(vector_clock);
+                        server.sent.record(&updates);
+                        let signatures = txn.signatures_for(&updates).await;
+                        let key_epochs = txn.key_epochs().await;
+                        let msg = WsMessage::SyncStep2 { updates, signatures, key_epochs };
+                        send(&mut write, &msg).await?;
+                        remote = Some(server);
+                    }
+                    Some(WsMessage::SyncStep2 { updates, signatures, key_epochs }) => {
+                        if let Some(server) = remote.as_mut() {
+                            server.received(&updates);
+                        }
+                        let changed = changed_blocks(&updates);
+                        let res = match handshake_clock.take() {
+                            Some(server_clock) => {
+                                txn.integrate_diff(None, updates, signatures, key_epochs, &server_clock)
+                                    .await
+                            }
+                            None => txn.update_remote(updates, signatures, key_epochs).await,
+                        };
+                        if let Err(e) = res {
+                            debug!(error = %e, "websocket update not fully integrated");
+                        }
+                        send_ack(txn, &mut write, &changed).await?;
+                    }
+                    Some(WsMessage::Update { updates, signatures, key_epochs }) => {
+                        if let Some(server) = remote.as_mut() {
+                            server.received(&updates);
+                        }
+                        let changed = changed_blocks(&updates);
+                        if let Err(e) = txn.update_remote(updates, signatures, key_epochs).await {
+                            debug!(error = %e, "websocket update not fully integrated");
+                        }
+                        send_ack(txn, &mut write, &changed).await?;
+                    }
+                    Some(WsMessage::Ack { vector_clock, changes }) => {
+                        if let Some(server) = remote.as_mut() {
+                            server.ack(vector_clock, changes);
+                        }
+                    }
+                    Some(WsMessage::Awareness { states }) => {
+                        txn.doc.lock().await.awareness.apply_remote(states);
+                    }
+                    None => warn!("invalid websocket message"),
+                }
+            }
+            _ = ticker.tick() => {
+                if let Some(server) = remote.as_mut() {
+                    send_missing(txn, &mut write, server).await?;
+                }
+
+                let states = txn.doc.lock().await.awareness.get_states();
+                if states != last_states {
+                    send(&mut write, &WsMessage::Awareness { states: states.clone() }).await?;
+                    last_states = states;
+                }
+            }
+            _ = receiver.recv() => {
+                let _ = write.send(Message::Close(None)).await;
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Log the updates of a client that the relay didn't integrate
+fn log_refused(res: CRDTResult<()>) {
+    match res {
+        Err(e @ CRDTError::PermissionDenied(_)) => warn!(error = %e, "refused websocket update"),
+        Err(e) => debug!(error = %e, "websocket update not fully integrated"),
+        Ok(()) => {}
+    }
+}
+
+// Send the updates the other side of the connection is missing as an Update, if any,
+// blocks it didn't acknowledge are sent again along with new ones
+async fn send_missing<S>(
+    txn: &SyncTransaction,
+    write: &mut S,
+    remote: &mut Remote,
+) -> CRDTResult<()>
+where
+    S: Sink<Message> + Unpin,
+    S::Error: Debug,
+{
+    let updates: Updates = txn
+        .compute_diff(remote.acked.clock.clone())
+        .await
+        .into_iter()
+        .filter(|block| remote.acked.misses(block))
+        .collect();
+    if !updates.iter().any(|block| remote.sent.misses(block)) {
+        return Ok(());
+    }
+    remote.sent.record(&updates);
+    let signatures = txn.signatures_for(&updates).await;
+    let key_epochs = txn.key_epochs().await;
+    let msg = WsMessage::Update {
+        updates,
+        signatures,
+        key_epochs,
+    };
+    send(write, &msg).await
+}
+
+// Blocks of updates carrying changes, kept to acknowledge the changes once integrated
+fn changed_blocks(updates: &Updates) -> Updates {
+    updates
+        .iter()
+        .filter(|block| !block.changes.is_empty())
+        .cloned()
+        .collect()
+}
+
+// Acknowledge the updates received from the other side once they are integrated
+async fn send_ack<S>(txn: &SyncTransaction, write: &mut S, changed: &Updates) -> CRDTResult<()>
+where
+    S: Sink<Message> + Unpin,
+    S::Error: Debug,
+{
+    let (vector_clock, changes) = {
+        let local_doc = txn.doc.lock().await;
+        let store_lock = local_doc.block_store.lock().await;
+        (
+            local_doc.vector_clock.clone(),
+            store_lock.applied_changes(changed).await,
+        )
+    };
+    send(
+        write,
+        &WsMessage::Ack {
+            vector_clock,
+            changes,
+        },
+    )
+    .await
+}
+
+async fn send<S>(write: &mut S, msg: &WsMessage) -> CRDTResult<()>
+where
+    S: Sink<Message> + Unpin,
+    S::Error: Debug,
+{
+    let msg_serialized = serde_json::to_string(msg)?;
+    write
+        .send(Message::Text(msg_serialized))
+        .await
+        .map_err(ws_error)?;
+    Ok(())
+}
+
+fn parse(msg: Message) -> Option<WsMessage> {
+    match msg {
+        Message::Text(text) => serde_json::from_str(&text).ok(),
+        Message::Binary(bin) => serde_json::from_slice(&bin).ok(),
+        _ => None,
+    }
+}
+
+fn ws_error<E: Debug>(e: E) -> CRDTError {
+    CRDTError::Transport(format!("websocket error {:?}", e))
+}
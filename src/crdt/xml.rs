@@ -0,0 +1,445 @@
+use crate::crdt::block::{BlockID, Content};
+use crate::crdt::doc::Doc;
+use crate::crdt::embed::{Embed, Payload};
+use crate::crdt::utils::{CRDTError, CRDTResult};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+
+// kinds of the embeds an xml tree is made of, inside an element:
+// - XML_OPEN {"name"} starts the element, its id is the id of the element
+// - XML_ATTR {"key", "value"} sets an attribute of the element it is in
+// - XML_CLOSE {"open"} ends the element started by the embed open
+pub const XML_OPEN: &str = "xml_open";
+pub const XML_ATTR: &str = "xml_attr";
+pub const XML_CLOSE: &str = "xml_close";
+
+// XmlNode is a node of the xml tree of a doc, text between the markers of an element
+// is a text node, other embeds are left out of the tree
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum XmlNode {
+    Element(XmlElement),
+    Text { text: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct XmlElement {
+    // id of the element in the doc, None for an element that hasn't been inserted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<BlockID>,
+    pub name: String,
+    pub attributes: BTreeMap<String, String>,
+    pub children: Vec<XmlNode>,
+}
+
+impl XmlElement {
+    pub fn new(name: &str) -> Self {
+        XmlElement {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+impl XmlNode {
+    pub fn text(text: &str) -> Self {
+        XmlNode::Text {
+            text: text.to_string(),
+        }
+    }
+
+    // Serialize the node, e.g. <p align="left">hello <b>world</b></p>
+    pub fn to_xml(&self) -> String {
+        match self {
+            XmlNode::Text { text } => escape(text),
+            XmlNode::Element(element) => {
+                let mut res = format!("<{}", element.name);
+                for (key, value) in element.attributes.iter() {
+                    res.push_str(&format!(" {}=\"{}\"", key, escape(value)));
+                }
+                if element.children.is_empty() {
+                    return res + "/>";
+                }
+                res.push('>');
+                for child in element.children.iter() {
+                    res.push_str(&child.to_xml());
+                }
+                res + &format!("</{}>", element.name)
+            }
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn marker(kind: &str, payload: serde_json::Value) -> Embed {
+    Embed {
+        kind: kind.to_string(),
+        alt: None,
+        payload: Payload::Json(payload),
+    }
+}
+
+// Item is a visible piece of the doc read as xml, pos is its position in the text
+enum Item {
+    Text(String),
+    Open(BlockID, String),
+    Attr(BlockID, String, String),
+    Close(BlockID),
+}
+
+fn read_item(id: &BlockID, embed: &Embed) -> Option<Item> {
+    let payload = match &embed.payload {
+        Payload::Json(payload) => payload,
+        Payload::Binary(_) | Payload::Sealed(_) => return None,
+    };
+    let field = |name: &str| payload.get(name).and_then(|value| value.as_str());
+    match embed.kind.as_str() {
+        XML_OPEN => Some(Item::Open(id.clone(), field("name")?.to_string())),
+        XML_ATTR => Some(Item::Attr(
+            id.clone(),
+            field("key")?.to_string(),
+            field("value")?.to_string(),
+        )),
+        XML_CLOSE => Some(Item::Close(
+            serde_json::from_value(payload.get("open")?.clone()).ok()?,
+        )),
+        _ => None,
+    }
+}
+
+// Node is a node of the tree together with where it is in the text:
+// [start, end) covers the node and its markers, and children are inserted before inner_end
+struct Node {
+    start: u32,
+    end: u32,
+    inner_end: u32,
+    kind: NodeKind,
+    children: Vec<Node>,
+}
+
+enum NodeKind {
+    Text(String),
+    // id, name, attributes and the markers setting them
+    Element(
+        Option<BlockID>,
+        String,
+        BTreeMap<String, String>,
+        Vec<(String, BlockID)>,
+    ),
+}
+
+impl Node {
+    fn element(id: Option<BlockID>, name: String, start: u32) -> Self {
+        Node {
+            start,
+            end: start,
+            inner_end: start,
+            kind: NodeKind::Element(id, name, BTreeMap::new(), vec![]),
+            children: vec![],
+        }
+    }
+
+    fn id(&self) -> Option<&BlockID> {
+        match &self.kind {
+            NodeKind::Element(id, ..) => id.as_ref(),
+            NodeKind::Text(_) => None,
+        }
+    }
+
+    // Element with id in the subtree of the node (the node itself included)
+    fn find(&self, id: &BlockID) -> Option<&Node> {
+        if self.id() == Some(id) {
+            return Some(self);
+        }
+        self.children.iter().find_map(|child| child.find(id))
+    }
+
+    fn to_xml_node(&self) -> XmlNode {
+        match &self.kind {
+            NodeKind::Text(text) => XmlNode::text(text),
+            NodeKind::Element(id, name, attributes, _) => XmlNode::Element(XmlElement {
+                id: id.clone(),
+                name: name.clone(),
+                attributes: attributes.clone(),
+                children: self
+                    .children
+                    .iter()
+                    .map(|child| child.to_xml_node())
+                    .collect(),
+            }),
+        }
+    }
+}
+
+// Close the innermost open element at pos, end is where its close marker ends
+fn close(stack: &mut Vec<Node>, pos: u32, end: u32) {
+    let mut node = stack.pop().unwrap();
+    node.inner_end = pos;
+    node.end = end;
+    stack.last_mut().unwrap().children.push(node);
+}
+
+impl Doc {
+    // Read the doc as a tree under a root element without id,
+    // markers left unbalanced by concurrent edits are repaired:
+    // a close without its open is ignored, an element without its close ends with its parent,
+    // and an attribute set more than once keeps its first value
+    async fn xml_root(&self) -> Node {
+        let mut items = vec![];
+        let mut len = 0;
+        {
+            let store_lock = self.block_store.lock().await;
+            for block in store_lock.total_store.list.iter() {
+                let block_lock = block.lock().await;
+                if block_lock.is_deleted {
+                    continue;
+                }
+                let item = match &block_lock.content {
+                    Content::Embed(embed) => read_item(&block_lock.id, embed),
+                    Content::Text(text) => Some(Item::Text(text.clone())),
+                };
+                if let Some(item) = item {
+                    items.push((len, item));
+                }
+                len += block_lock.content.len() as u32;
+            }
+        }
+
+        let mut stack = vec![Node::element(None, String::new(), 0)];
+        for (pos, item) in items {
+            match item {
+                Item::Text(text) => {
+                    let end = pos + text.len() as u32;
+                    let children = &mut stack.last_mut().unwrap().children;
+                    match children.last_mut() {
+                        Some(Node {
+                            end: last_end,
+                            kind: NodeKind::Text(last),
+                            ..
+                        }) if *last_end == pos => {
+                            last.push_str(&text);
+                            *last_end = end;
+                        }
+                        _ => children.push(Node {
+                            start: pos,
+                            end,
+                            inner_end: end,
+                            kind: NodeKind::Text(text),
+                            children: vec![],
+                        }),
+                    }
+                }
+                Item::Open(id, name) => stack.push(Node::element(Some(id), name, pos)),
+                Item::Attr(id, key, value) => {
+                    if stack.len() == 1 {
+                        continue;
+                    }
+                    if let NodeKind::Element(_, _, attributes, markers) =
+                        &mut stack.last_mut().unwrap().kind
+                    {
+                        attributes.entry(key.clone()).or_insert(value);
+                        markers.push((key, id));
+                    }
+                }
+                Item::Close(open) => {
+                    let depth = stack.iter().rposition(|node| node.id() == Some(&open));
+                    if let Some(depth) = depth.filter(|depth| *depth > 0) {
+                        while stack.len() > depth + 1 {
+                            close(&mut stack, pos, pos);
+                        }
+                        close(&mut stack, pos, pos + 1);
+                    }
+                }
+            }
+        }
+        while stack.len() > 1 {
+            close(&mut stack, len, len);
+        }
+        let mut root = stack.pop().unwrap();
+        root.inner_end = len;
+        root.end = len;
+        root
+    }
+
+    // Children of the root of the xml tree of the doc
+    pub async fn xml(&self) -> Vec<XmlNode> {
+        self.xml_root()
+            .await
+            .children
+            .iter()
+            .map(|child| child.to_xml_node())
+            .collect()
+    }
+
+    // The xml tree of the doc serialized, children of the root one after the other
+    pub async fn to_xml(&self) -> String {
+        self.xml().await.iter().map(|node| node.to_xml()).collect()
+    }
+
+    // Insert node as the index-th child of the element parent (None for the root),
+    // return the id of the inserted element, None for a text node
+    pub async fn insert_child(
+        &mut self,
+        parent: Option<&BlockID>,
+        index: usize,
+        node: &XmlNode,
+    ) -> CRDTResult<Option<BlockID>> {
+        let root = self.xml_root().await;
+        let parent = find_parent(&root, parent)?;
+        let pos = match parent.children.get(index) {
+            Some(child) => child.start,
+            None if index == parent.children.len() => parent.inner_end,
+            None => {
+                return Err(CRDTError::InvalidNode(format!(
+                    "cannot insert child {} of a node with {} children",
+                    index,
+                    parent.children.len()
+                )))
+            }
+        };
+        let mut pos = pos;
+        self.write_node(&mut pos, node).await
+    }
+
+    // Insert the markers and text of node at pos, pos is moved after them
+    async fn write_node(&mut self, pos: &mut u32, node: &XmlNode) -> CRDTResult<Option<BlockID>> {
+        let element = match node {
+            XmlNode::Text { text } => {
+                if !text.is_empty() {
+                    self.insert_local(Content::Text(text.clone()), *pos).await;
+                    *pos += text.len() as u32;
+                }
+                return Ok(None);
+            }
+            XmlNode::Element(element) => element,
+        };
+        let id = self
+            .insert_embed(marker(XML_OPEN, json!({ "name": element.name })), *pos)
+            .await?;
+        *pos += 1;
+        for (key, value) in element.attributes.iter() {
+            self.insert_embed(
+                marker(XML_ATTR, json!({ "key": key, "value": value })),
+                *pos,
+            )
+            .await?;
+            *pos += 1;
+        }
+        for child in element.children.iter() {
+            // children are written one after the other, so the future is boxed to recurse
+            Box::pin(self.write_node(pos, child)).await?;
+        }
+        self.insert_embed(marker(XML_CLOSE, json!({ "open": id })), *pos)
+            .await?;
+        *pos += 1;
+        Ok(Some(id))
+    }
+
+    // Delete the index-th child of the element parent (None for the root) with its subtree
+    pub async fn delete_child(&mut self, parent: Option<&BlockID>, index: usize) -> CRDTResult<()> {
+        let root = self.xml_root().await;
+        let child = child_of(&root, parent, index)?;
+        self.delete_local(child.start, child.end - child.start)
+            .await;
+        Ok(())
+    }
+
+    // Move the index-th child of parent to the new_index-th child of new_parent
+    // (new_index counts the children once the node has been removed)
+    //
+    // the node is deleted and a copy is inserted, so moved elements get new ids,
+    // a node moved by two clients at the same time ends up at both places
+    // and content inserted into the node at the same time stays at its old place
+    // (as children of the parent it was moved from)
+    pub async fn move_child(
+        &mut self,
+        parent: Option<&BlockID>,
+        index: usize,
+        new_parent: Option<&BlockID>,
+        new_index: usize,
+    ) -> CRDTResult<Option<BlockID>> {
+        let node = {
+            let root = self.xml_root().await;
+            let child = child_of(&root, parent, index)?;
+            if let Some(new_parent) = new_parent {
+                if child.find(new_parent).is_some() {
+                    return Err(CRDTError::InvalidNode(format!(
+                        "cannot move a node into itself ({:?})",
+                        new_parent
+                    )));
+                }
+            }
+            find_parent(&root, new_parent)?;
+            child.to_xml_node()
+        };
+        self.delete_child(parent, index).await?;
+        self.insert_child(new_parent, new_index, &node).await
+    }
+
+    // Set the attribute key of element to value, or remove it if value is None
+    pub async fn set_attribute(
+        &mut self,
+        element: &BlockID,
+        key: &str,
+        value: Option<&str>,
+    ) -> CRDTResult<()> {
+        let root = self.xml_root().await;
+        let node = find_parent(&root, Some(element))?;
+        let markers = match &node.kind {
+            NodeKind::Element(_, _, _, markers) => markers.clone(),
+            NodeKind::Text(_) => vec![],
+        };
+        let old: Vec<BlockID> = markers
+            .into_iter()
+            .filter(|(k, _)| k == key)
+            .map(|(_, id)| id)
+            .collect();
+        if !old.is_empty() {
+            let change = self.next_change();
+            let mut store_lock = self.block_store.lock().await;
+            for id in old {
+                store_lock
+                    .delete_range(id.clone(), 1, Some(self.client), Some(change))
+                    .await;
+                self.signatures.sign_delete(&id, 1, self.client);
+            }
+        }
+        // the new value right after the open marker comes first, so it wins over older ones
+        if let Some(value) = value {
+            self.insert_embed(
+                marker(XML_ATTR, json!({ "key": key, "value": value })),
+                node.start + 1,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+// The element id (the root if None)
+fn find_parent<'a>(root: &'a Node, id: Option<&BlockID>) -> CRDTResult<&'a Node> {
+    match id {
+        None => Ok(root),
+        Some(id) => root
+            .find(id)
+            .ok_or_else(|| CRDTError::InvalidNode(format!("no element {:?}", id))),
+    }
+}
+
+fn child_of<'a>(root: &'a Node, parent: Option<&BlockID>, index: usize) -> CRDTResult<&'a Node> {
+    let parent = find_parent(root, parent)?;
+    parent.children.get(index).ok_or_else(|| {
+        CRDTError::InvalidNode(format!(
+            "no child {} in a node with {} children",
+            index,
+            parent.children.len()
+        ))
+    })
+}
@@ -0,0 +1,667 @@
+use crate::crdt::block::{Block, BlockID, Content};
+use crate::crdt::doc::{Doc, VectorClock};
+use crate::crdt::snapshot::{delete_set, DeleteSet, TOMBSTONE_FILLER};
+use crate::crdt::utils::{CRDTError, CRDTResult, ClientID, Updates};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use tracing::{debug, instrument};
+
+// name of the shared text, i.e. Yjs clients edit ydoc.getText(YTEXT_NAME)
+pub const YTEXT_NAME: &str = "text";
+
+// struct and content refs of the v1 update format
+const REF_GC: u8 = 0;
+const REF_DELETED: u8 = 1;
+const REF_STRING: u8 = 4;
+const REF_SKIP: u8 = 10;
+const REF_MASK: u8 = 0x1f;
+// info flags of an item
+const HAS_ORIGIN: u8 = 0x80;
+const HAS_RIGHT_ORIGIN: u8 = 0x40;
+const HAS_PARENT_SUB: u8 = 0x20;
+
+fn malformed(msg: &str) -> CRDTError {
+    CRDTError::Serialization(format!("malformed yjs update: {}", msg))
+}
+
+// Encoder writes the lib0 encoding used by Yjs
+#[derive(Debug, Default)]
+pub struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Encoder::default()
+    }
+
+    pub fn write_u8(&mut self, n: u8) {
+        self.buf.push(n);
+    }
+
+    // 7 bits per byte, the high bit is set on every byte but the last
+    pub fn write_var_uint(&mut self, mut n: u64) {
+        while n > 0x7f {
+            self.buf.push(0x80 | (n & 0x7f) as u8);
+            n >>= 7;
+        }
+        self.buf.push(n as u8);
+    }
+
+    // utf-8 bytes prefixed with their length
+    pub fn write_var_string(&mut self, s: &str) {
+        self.write_var_uint(s.len() as u64);
+        self.buf.extend_from_slice(s.as_bytes());
+    }
+
+    fn write_id(&mut self, id: &BlockID) {
+        self.write_var_uint(id.client as u64);
+        self.write_var_uint(id.clock as u64);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+// Decoder reads the lib0 encoding used by Yjs
+pub struct Decoder<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Decoder { buf, pos: 0 }
+    }
+
+    pub fn read_u8(&mut self) -> CRDTResult<u8> {
+        let n = *self
+            .buf
+            .get(self.pos)
+            .ok_or_else(|| malformed("unexpected end"))?;
+        self.pos += 1;
+        Ok(n)
+    }
+
+    pub fn read_var_uint(&mut self) -> CRDTResult<u64> {
+        let (mut n, mut shift) = (0u64, 0);
+        loop {
+            let byte = self.read_u8()?;
+            // lib0 numbers have at most 53 bits
+            if shift > 49 {
+                return Err(malformed("varuint overflow"));
+            }
+            n |= ((byte & 0x7f) as u64) << shift;
+            if byte < 0x80 {
+                return Ok(n);
+            }
+            shift += 7;
+        }
+    }
+
+    pub fn read_var_string(&mut self) -> CRDTResult<String> {
+        let len = self.read_var_uint()? as usize;
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.buf.len())
+            .ok_or_else(|| malformed("unexpected end"))?;
+        let s = std::str::from_utf8(&self.buf[self.pos..end])
+            .map_err(|e| CRDTError::Serialization(e.to_string()))?;
+        self.pos = end;
+        Ok(s.to_string())
+    }
+
+    // clocks and client ids are u32 here, Yjs client ids are random u32 as well
+    fn read_u32(&mut self) -> CRDTResult<u32> {
+        let n = self.read_var_uint()?;
+        u32::try_from(n).map_err(|_| malformed("clock or client id out of range"))
+    }
+
+    fn read_id(&mut self) -> CRDTResult<BlockID> {
+        let client = self.read_u32()?;
+        Ok(BlockID::new(client, self.read_u32()?))
+    }
+}
+
+// Decode a Yjs state vector, an empty one means nothing is known
+pub fn decode_state_vector(state_vector: &[u8]) -> CRDTResult<VectorClock> {
+    let mut clocks = VectorClock::new();
+    if state_vector.is_empty() {
+        return Ok(clocks);
+    }
+    let mut decoder = Decoder::new(state_vector);
+    for _ in 0..decoder.read_var_uint()? {
+        let client = decoder.read_u32()?;
+        clocks.advance(client, decoder.read_u32()?);
+    }
+    Ok(clocks)
+}
+
+fn utf16_len(s: &str) -> u32 {
+    s.encode_utf16().count() as u32
+}
+
+// s without its first units utf-16 code units
+fn skip_utf16(s: &str, units: u32) -> String {
+    let mut skipped = 0;
+    s.chars()
+        .skip_while(|c| {
+            let skip = skipped < units;
+            skipped += c.len_utf16() as u32;
+            skip
+        })
+        .collect()
+}
+
+// Utf16Clocks maps clocks here (utf-8 bytes) to Yjs clocks (utf-16 code units) and back,
+// from the content every client wrote in clock order,
+// clocks whose content is unknown count one code unit per byte
+#[derive(Default)]
+struct Utf16Clocks {
+    // (clock, yjs clock, content) runs of every client, in clock order
+    runs: HashMap<ClientID, Vec<(u32, u32, String)>>,
+}
+
+impl Utf16Clocks {
+    fn new(blocks: &[Block]) -> Self {
+        let mut sorted: Vec<&Block> = blocks.iter().collect();
+        sorted.sort_by_key(|block| (block.id.client, block.id.clock));
+        let mut clocks = Utf16Clocks::default();
+        for block in sorted {
+            let (end, yend) = clocks.end(block.id.client);
+            let yclock = yend + block.id.clock.saturating_sub(end);
+            clocks.runs.entry(block.id.client).or_default().push((
+                block.id.clock,
+                yclock,
+                block.content.as_str().to_string(),
+            ));
+        }
+        clocks
+    }
+
+    // end of the content of client, as a clock and as a yjs clock
+    fn end(&self, client: ClientID) -> (u32, u32) {
+        match self.runs.get(&client).and_then(|runs| runs.last()) {
+            Some((clock, yclock, content)) => {
+                (clock + content.len() as u32, yclock + utf16_len(content))
+            }
+            None => (0, 0),
+        }
+    }
+
+    // add content written by client at the yjs clock yclock, after the rest of its content,
+    // and return the clock it starts at
+    fn push_yjs(&mut self, client: ClientID, yclock: u32, content: &str) -> u32 {
+        let (end, yend) = self.end(client);
+        let clock = end + yclock.saturating_sub(yend);
+        self.runs
+            .entry(client)
+            .or_default()
+            .push((clock, yclock, content.to_string()));
+        clock
+    }
+
+    // yjs clock of the character of client at clock, of its last code unit if last is set
+    fn to_yjs(&self, client: ClientID, clock: u32, last: bool) -> u32 {
+        self.convert(client, clock, last, false)
+    }
+
+    // clock of the character of client at the yjs clock yclock
+    fn to_local(&self, client: ClientID, yclock: u32) -> u32 {
+        self.convert(client, yclock, false, true)
+    }
+
+    fn convert(&self, client: ClientID, clock: u32, last: bool, from_yjs: bool) -> u32 {
+        let runs = match self.runs.get(&client) {
+            Some(runs) => runs,
+            None => return clock,
+        };
+        let i = runs.partition_point(|(start, ystart, _)| {
+            if from_yjs {
+                *ystart <= clock
+            } else {
+                *start <= clock
+            }
+        });
+        let (start, ystart, content) = match i.checked_sub(1) {
+            Some(i) => &runs[i],
+            None => return clock,
+        };
+        let (mut offset, mut converted) = match from_yjs {
+            true => (clock - ystart, *start),
+            false => (clock - start, *ystart),
+        };
+        for c in content.chars() {
+            let (len, other) = match from_yjs {
+                true => (c.len_utf16() as u32, c.len_utf8() as u32),
+                false => (c.len_utf8() as u32, c.len_utf16() as u32),
+            };
+            if offset < len {
+                return converted + if last { other - 1 } else { 0 };
+            }
+            offset -= len;
+            converted += other;
+        }
+        // past the content, clocks are counted one to one
+        converted + offset
+    }
+}
+
+// Read the item with id, only text and deleted content of YTEXT_NAME is supported
+fn read_item(decoder: &mut Decoder, id: BlockID, info: u8) -> CRDTResult<Block> {
+    let left_origin = match info & HAS_ORIGIN {
+        0 => None,
+        _ => Some(decoder.read_id()?),
+    };
+    let right_origin = match info & HAS_RIGHT_ORIGIN {
+        0 => None,
+        _ => Some(decoder.read_id()?),
+    };
+    // the parent is only written when it cannot be taken from an origin
+    if left_origin.is_none() && right_origin.is_none() {
+        if decoder.read_var_uint()? != 1 {
+            return Err(CRDTError::Serialization(
+                "nested yjs types are not supported".to_string(),
+            ));
+        }
+        let name = decoder.read_var_string()?;
+        if name != YTEXT_NAME || info & HAS_PARENT_SUB != 0 {
+            return Err(CRDTError::Serialization(format!(
+                "only the yjs text {} is supported, got {}",
+                YTEXT_NAME, name
+            )));
+        }
+    }
+    let (content, is_deleted) = match info & REF_MASK {
+        REF_STRING => (decoder.read_var_string()?, false),
+        REF_DELETED => {
+            let len = decoder.read_u32()?;
+            (TOMBSTONE_FILLER.to_string().repeat(len as usize), true)
+        }
+        content_ref => {
+            return Err(CRDTError::Serialization(format!(
+                "yjs content {} is not supported",
+                content_ref
+            )))
+        }
+    };
+    let block = Block {
+        id,
+        left_origin,
+        right_origin,
+        is_deleted,
+        deleted_by: None,
+        suggestion: None,
+        changes: vec![],
+        sealed: None,
+        // yjs doesn't send the content of deleted items
+        elided: is_deleted,
+        content: Content::Text(content),
+    };
+    Ok(block)
+}
+
+// Decode a v1 update into the blocks it inserts (in clock order for every client)
+// and the clocks it deletes, ids and clocks are yjs clocks (utf-16 code units)
+pub fn decode_update_v1(update: &[u8]) -> CRDTResult<(Updates, DeleteSet)> {
+    let mut decoder = Decoder::new(update);
+    let mut blocks = vec![];
+    for _ in 0..decoder.read_var_uint()? {
+        let structs = decoder.read_var_uint()?;
+        let client = decoder.read_u32()?;
+        let mut clock = decoder.read_u32()?;
+        for _ in 0..structs {
+            let info = decoder.read_u8()?;
+            let len = match info & REF_MASK {
+                // collected and skipped clocks have no position, there is nothing to integrate
+                REF_GC | REF_SKIP => decoder.read_u32()?,
+                _ => {
+                    let block = read_item(&mut decoder, BlockID::new(client, clock), info)?;
+                    let len = utf16_len(block.content.as_str());
+                    blocks.push(block);
+                    len
+                }
+            };
+            clock = clock
+                .checked_add(len)
+                .ok_or_else(|| malformed("clock out of range"))?;
+        }
+    }
+
+    let mut deleted = DeleteSet::new();
+    for _ in 0..decoder.read_var_uint()? {
+        let client = decoder.read_u32()?;
+        let ranges = deleted.entry(client).or_default();
+        for _ in 0..decoder.read_var_uint()? {
+            let clock = decoder.read_u32()?;
+            let len = decoder.read_u32()?;
+            ranges.push((clock, clock.saturating_add(len)));
+        }
+    }
+    Ok((blocks, deleted))
+}
+
+// Yjs origins for blocks in spatial order
+//
+// the origin of a Yjs item is the last character left of it when it was inserted,
+// while a left origin here is the first character of the block left of it,
+// so its end is found by following that block through the blocks it was split into
+// or extended with by the same client, up to the block itself
+fn yjs_origins(blocks: &[Block]) -> Vec<Option<BlockID>> {
+    let len = |i: usize| blocks[i].content.len() as u32;
+    let starts: HashMap<&BlockID, usize> = blocks
+        .iter()
+        .enumerate()
+        .map(|(i, block)| (&block.id, i))
+        .collect();
+    let mut by_client: HashMap<ClientID, Vec<usize>> = HashMap::new();
+    for (i, block) in blocks.iter().enumerate() {
+        by_client.entry(block.id.client).or_default().push(i);
+    }
+    for indices in by_client.values_mut() {
+        indices.sort_by_key(|i| blocks[*i].id.clock);
+    }
+    let containing = |id: &BlockID| -> Option<usize> {
+        let indices = by_client.get(&id.client)?;
+        let pos = indices.partition_point(|i| blocks[*i].id.clock <= id.clock);
+        let i = *indices.get(pos.checked_sub(1)?)?;
+        if id.clock < blocks[i].id.clock + len(i) {
+            Some(i)
+        } else {
+            None
+        }
+    };
+
+    (0..blocks.len())
+        .map(|i| {
+            let left = blocks[i].left_origin.as_ref()?;
+            let mut prev = match containing(left) {
+                Some(j) if j < i => j,
+                _ => return Some(left.clone()),
+            };
+            let mut end = blocks[prev].id.clock + len(prev);
+            while let Some(&next) = starts.get(&BlockID::new(left.client, end)) {
+                let extends = matches!(
+                    &blocks[next].left_origin,
+                    Some(origin) if origin.client == left.client && left.clock <= origin.clock && origin.clock < end
+                );
+                if next <= prev || next >= i || !extends {
+                    break;
+                }
+                end += len(next);
+                prev = next;
+            }
+            Some(BlockID::new(left.client, end - 1))
+        })
+        .collect()
+}
+
+// struct of an update: the part of the block at an index from an offset, or clocks to skip
+enum YStruct {
+    Item(usize, u32),
+    Skip(u32),
+}
+
+// Write the part of block from offset as a Yjs item
+fn write_item(
+    encoder: &mut Encoder,
+    block: &Block,
+    origin: Option<BlockID>,
+    offset: u32,
+    clocks: &Utf16Clocks,
+) {
+    // an item starting inside a block goes right after the previous character
+    let origin = match offset {
+        0 => origin,
+        _ => Some(BlockID::new(block.id.client, block.id.clock + offset - 1)),
+    };
+    // the origin is the last code unit of a character, the right origin the first one
+    let origin = origin.map(|id| BlockID::new(id.client, clocks.to_yjs(id.client, id.clock, true)));
+    let right_origin = block
+        .right_origin
+        .as_ref()
+        .map(|id| BlockID::new(id.client, clocks.to_yjs(id.client, id.clock, false)));
+    let mut info = if block.is_deleted {
+        REF_DELETED
+    } else {
+        REF_STRING
+    };
+    if origin.is_some() {
+        info |= HAS_ORIGIN;
+    }
+    if right_origin.is_some() {
+        info |= HAS_RIGHT_ORIGIN;
+    }
+    encoder.write_u8(info);
+    if let Some(origin) = &origin {
+        encoder.write_id(origin);
+    }
+    if let Some(right_origin) = &right_origin {
+        encoder.write_id(right_origin);
+    }
+    if origin.is_none() && right_origin.is_none() {
+        encoder.write_var_uint(1);
+        encoder.write_var_string(YTEXT_NAME);
+    }
+    let content = &block.content.as_str()[offset as usize..];
+    if block.is_deleted {
+        encoder.write_var_uint(utf16_len(content) as u64);
+    } else {
+        encoder.write_var_string(content);
+    }
+}
+
+impl Doc {
+    // blocks of the doc in spatial order
+    async fn yjs_blocks(&self) -> Updates {
+        let store = self.block_store.clone();
+        let store_lock = store.lock().await;
+        let mut blocks: Updates = vec![];
+        for block in store_lock.total_store.list.iter() {
+            blocks.push(block.lock().await.clone());
+        }
+        blocks
+    }
+
+    // Yjs state vector of the doc, i.e. its vector clock in the v1 encoding
+    pub async fn encode_state_vector(&self) -> Vec<u8> {
+        let utf16 = Utf16Clocks::new(&self.yjs_blocks().await);
+        let mut clocks: Vec<(ClientID, u32)> = self
+            .vector_clock
+            .clock_map
+            .iter()
+            .map(|(client, clock)| (*client, utf16.to_yjs(*client, *clock, false)))
+            .collect();
+        // Yjs writes clients in descending order
+        clocks.sort_unstable_by_key(|(client, _)| Reverse(*client));
+        let mut encoder = Encoder::new();
+        encoder.write_var_uint(clocks.len() as u64);
+        for (client, clock) in clocks {
+            encoder.write_var_uint(client as u64);
+            encoder.write_var_uint(clock as u64);
+        }
+        encoder.into_bytes()
+    }
+
+    // Yjs v1 update with the blocks missing from state_vector (an encoded Yjs state vector,
+    // empty for the whole doc) and every deletion, as Y.encodeStateAsUpdate would write it
+    //
+    // Yjs clients read plaintext, so content is not encrypted
+    pub async fn encode_state_as_update_v1(&self, state_vector: &[u8]) -> CRDTResult<Vec<u8>> {
+        let remote_clocks = decode_state_vector(state_vector)?;
+        let blocks = self.yjs_blocks().await;
+        let clocks = Utf16Clocks::new(&blocks);
+        for block in blocks.iter() {
+            if block.content.embed().is_some() {
+                return Err(CRDTError::Serialization(format!(
+                    "block {:?} is an embed, only text can be shared with yjs",
+                    block.id
+                )));
+            }
+        }
+        let origins = yjs_origins(&blocks);
+
+        let mut by_client: HashMap<ClientID, Vec<usize>> = HashMap::new();
+        for (i, block) in blocks.iter().enumerate() {
+            by_client.entry(block.id.client).or_default().push(i);
+        }
+        // structs of every client from the first clock unknown to the remote
+        let mut clients: Vec<(ClientID, u32, Vec<YStruct>)> = vec![];
+        for (client, mut indices) in by_client {
+            indices.sort_by_key(|i| blocks[*i].id.clock);
+            let mut clock = clocks.to_local(client, remote_clocks.get(client));
+            let (mut start, mut structs) = (None, vec![]);
+            for i in indices {
+                let block = &blocks[i];
+                let end = block.id.clock + block.content.len() as u32;
+                if end <= clock {
+                    continue;
+                }
+                if block.id.clock > clock {
+                    // clocks missing from the doc are skipped, before the first struct there is nothing to skip
+                    if start.is_some() {
+                        let skipped = clocks.to_yjs(client, block.id.clock, false)
+                            - clocks.to_yjs(client, clock, false);
+                        structs.push(YStruct::Skip(skipped));
+                    }
+                    clock = block.id.clock;
+                }
+                start.get_or_insert(clock);
+                structs.push(YStruct::Item(i, clock - block.id.clock));
+                clock = end;
+            }
+            if let Some(start) = start {
+                clients.push((client, clocks.to_yjs(client, start, false), structs));
+            }
+        }
+        clients.sort_unstable_by_key(|(client, _, _)| Reverse(*client));
+
+        let mut encoder = Encoder::new();
+        encoder.write_var_uint(clients.len() as u64);
+        for (client, start, structs) in clients {
+            encoder.write_var_uint(structs.len() as u64);
+            encoder.write_var_uint(client as u64);
+            encoder.write_var_uint(start as u64);
+            for ystruct in structs {
+                match ystruct {
+                    YStruct::Item(i, offset) => write_item(
+                        &mut encoder,
+                        &blocks[i],
+                        origins[i].clone(),
+                        offset,
+                        &clocks,
+                    ),
+                    YStruct::Skip(len) => {
+                        encoder.write_u8(REF_SKIP);
+                        encoder.write_var_uint(len as u64);
+                    }
+                }
+            }
+        }
+
+        let mut deleted: Vec<(ClientID, Vec<(u32, u32)>)> =
+            delete_set(&blocks).into_iter().collect();
+        deleted.sort_unstable_by_key(|(client, _)| Reverse(*client));
+        encoder.write_var_uint(deleted.len() as u64);
+        for (client, ranges) in deleted {
+            encoder.write_var_uint(client as u64);
+            encoder.write_var_uint(ranges.len() as u64);
+            for (start, end) in ranges {
+                let start = clocks.to_yjs(client, start, false);
+                let end = clocks.to_yjs(client, end, false);
+                encoder.write_var_uint(start as u64);
+                encoder.write_var_uint((end - start) as u64);
+            }
+        }
+        Ok(encoder.into_bytes())
+    }
+
+    // Integrate a Yjs v1 update, the known part of every item is skipped and
+    // items whose origins are missing wait in pending_updates like remote blocks
+    //
+    // the update is rejected as a whole if it holds anything else than text of YTEXT_NAME
+    #[instrument(level = "debug", skip_all, fields(doc = %self.name, client = self.client, len = update.len()))]
+    pub async fn apply_update_v1(&mut self, update: &[u8]) -> CRDTResult<()> {
+        let (blocks, deleted) = decode_update_v1(update)?;
+        let mut known_blocks = self.yjs_blocks().await;
+        known_blocks.extend(self.pending_updates.iter().cloned());
+        let mut clocks = Utf16Clocks::new(&known_blocks);
+        let mut rest: Updates = vec![];
+        for mut block in blocks {
+            let client = block.id.client;
+            let known = clocks.to_yjs(client, self.vector_clock.get(client), false);
+            let end = block.id.clock + utf16_len(block.content.as_str());
+            if end <= known {
+                continue;
+            }
+            if block.id.clock < known {
+                let offset = known - block.id.clock;
+                block.content = Content::Text(skip_utf16(block.content.as_str(), offset));
+                block.left_origin = Some(BlockID::new(client, known - 1));
+                block.id.clock = known;
+            }
+            block.id.clock = if block.id.clock < clocks.end(client).1 {
+                // waiting for its origins in pending_updates already
+                clocks.to_local(client, block.id.clock)
+            } else {
+                clocks.push_yjs(client, block.id.clock, block.content.as_str())
+            };
+            rest.push(block);
+        }
+        // origins can be items of the update, they are mapped once every item has a clock
+        let to_local = |id: BlockID| BlockID::new(id.client, clocks.to_local(id.client, id.clock));
+        for block in rest.iter_mut() {
+            block.left_origin = block.left_origin.take().map(to_local);
+            block.right_origin = block.right_origin.take().map(to_local);
+        }
+
+        loop {
+            let mut progress = false;
+            let mut missing = vec![];
+            for block in rest {
+                // a Yjs item goes right after its origin, not after the whole block holding it
+                if let Some(origin) = &block.left_origin {
+                    self.block_store.lock().await.split_after(origin).await;
+                }
+                if self.insert_single_block(&block).await {
+                    progress = true;
+                } else {
+                    missing.push(block);
+                }
+            }
+            rest = missing;
+            if !progress || rest.is_empty() {
+                break;
+            }
+        }
+
+        // Yjs doesn't tell who made the deletions, so they are recorded without an author or a change
+        for (client, ranges) in deleted {
+            for (start, end) in ranges {
+                let start = clocks.to_local(client, start);
+                let end = clocks.to_local(client, end);
+                let mut store_lock = self.block_store.lock().await;
+                store_lock
+                    .delete_range(BlockID::new(client, start), end - start, None, None)
+                    .await;
+                drop(store_lock);
+                for block in rest.iter_mut() {
+                    let block_end = block.id.clock + block.content.len() as u32;
+                    if block.id.client == client && start <= block.id.clock && block_end <= end {
+                        block.delete_by(None);
+                    }
+                }
+            }
+        }
+
+        if rest.is_empty() {
+            return Ok(());
+        }
+        debug!(pending = rest.len(), "yjs items wait for their origins");
+        let ids = rest.iter().map(|block| block.id.clone()).collect();
+        self.pending_updates.extend(rest);
+        Err(CRDTError::MissingDependency(ids))
+    }
+}
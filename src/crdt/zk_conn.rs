@@ -1,15 +1,19 @@
 #![deny(unused_mut)]
 extern crate zookeeper;
 use crate::crdt::{
+    auth::with_token,
+    tls::{endpoint, TlsConfig},
     txn_rpc::{self, txn_service_client::TxnServiceClient},
-    utils::{CRDTError, CRDTResult, ClientID, Peer},
+    utils::{CRDTError, CRDTResult, ClientID, Peer, PeerDisplay},
 };
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::{
     runtime::Runtime,
     sync::mpsc::{channel, Receiver, Sender},
 };
-use tonic::transport::{Channel, Endpoint};
+use tonic::transport::Channel;
+use tracing::{debug, info, info_span, instrument, warn};
 use zookeeper::{Acl, CreateMode, WatchedEvent, WatchedEventType, Watcher, ZooKeeper};
 
 const ZK_ADDR: &'static str = "127.0.0.1:2181";
@@ -18,6 +22,7 @@ struct RegisterWatcher {
     pub channel: Channel,
     pub sender: Sender<()>,
     pub id: u32, // used for debugging
+    pub token: Option<String>,
 }
 
 impl RegisterWatcher {
@@ -26,22 +31,26 @@ impl RegisterWatcher {
         let mut client = TxnServiceClient::new(self.channel.clone());
         let peer_list_serialized = serde_json::to_string(&peers);
         if let Ok(peer_list_serialized) = peer_list_serialized {
-            let req = tonic::Request::new(txn_rpc::RegisterRequest {
-                peer_list: peer_list_serialized,
-            });
+            let req = with_token(
+                txn_rpc::RegisterRequest {
+                    peer_list: peer_list_serialized,
+                },
+                &self.token,
+            );
             let resp = client.sync_peer_list(req).await;
             match resp {
-                Ok(_) => println!("successfully send new user to peer's list"),
-                Err(e) => println!("failed to send new user to peer's list {:?}", e),
+                Ok(_) => info!(peers = peers.len(), "sent new peer list"),
+                Err(e) => warn!(error = ?e, "failed to send new peer list"),
             }
         } else {
-            println!("watcher failed to serialize peer list");
+            warn!("watcher failed to serialize peer list");
         }
     }
 }
 
 impl Watcher for RegisterWatcher {
     fn handle(&self, e: WatchedEvent) {
+        let _span = info_span!("membership_event", client = self.id, path = ?e.path).entered();
         let mut peers_remote = vec![];
         match e.event_type {
             WatchedEventType::NodeChildrenChanged => {
@@ -56,32 +65,42 @@ impl Watcher for RegisterWatcher {
                                 match peer_id {
                                     Ok(peer_id) => {
                                         let child_path = format!("{}/{}", path, peer);
-                                        let ip_addr_res = zk.get_data(&child_path[..], false);
-                                        if let Ok(ip_addr) = ip_addr_res {
-                                            peers_remote.push(Peer {
-                                                client_id: peer_id,
-                                                ip_addr: String::from_utf8(ip_addr.0.clone())
-                                                    .unwrap(),
-                                            });
+                                        let data_res = zk.get_data(&child_path[..], false);
+                                        if let Ok(data) = data_res {
+                                            peers_remote.push(parse_node_data(peer_id, &data.0));
                                         }
                                     }
-                                    Err(_) => println!("invalid client id"),
+                                    Err(_) => warn!(node = %peer, "invalid client id"),
                                 }
                             }
                             Runtime::new()
                                 .unwrap()
                                 .block_on(self.register_new_user(&peers_remote));
                         } else {
-                            println!("can't retrieve full peer list");
+                            warn!("can't retrieve full peer list");
                         }
                     }
                 }
             }
-            _ => println!("unsupported event type"),
+            _ => debug!(event = ?e.event_type, "unsupported event type"),
         }
     }
 }
 
+// Node data of a client is the json of its Peer,
+// older clients only store their ip address
+fn parse_node_data(client_id: ClientID, data: &[u8]) -> Peer {
+    match serde_json::from_slice::<Peer>(data) {
+        Ok(peer) => Peer { client_id, ..peer },
+        Err(_) => Peer {
+            client_id,
+            ip_addr: String::from_utf8_lossy(data).to_string(),
+            public_key: None,
+            display: None,
+        },
+    }
+}
+
 struct DefaultWatcher;
 impl Watcher for DefaultWatcher {
     fn handle(&self, _: WatchedEvent) {
@@ -89,13 +108,22 @@ impl Watcher for DefaultWatcher {
     }
 }
 
+#[derive(Clone)]
 pub struct ZooKeeperConnection {
     pub client_ip: String,
+    pub client: ClientID,
+    // token used by the watcher to call this client's rpc service
+    pub token: Option<String>,
+    pub tls: Option<Arc<TlsConfig>>,
+    // published to peers together with the ip address
+    pub public_key: Option<String>,
+    pub display: Option<PeerDisplay>,
 }
 
 impl ZooKeeperConnection {
+    #[instrument(skip_all, fields(doc = %doc, client = self.client))]
     pub async fn background_sync(&self, doc: String, sender: Sender<()>) -> CRDTResult<()> {
-        println!("background sync process started!");
+        info!("background sync process started");
         let path = format!("/{}", doc);
         let zk = ZooKeeper::connect(&*ZK_ADDR, Duration::from_secs(15), DefaultWatcher);
         let (sender_block, mut receiver_block): (Sender<()>, Receiver<()>) = channel(1);
@@ -107,7 +135,7 @@ impl ZooKeeperConnection {
                 Ok(exists) => {
                     if let None = exists {
                         // this file does not exist, create one
-                        println!("creating the doc directory");
+                        info!("creating the doc directory");
                         let create_res = zk.create(
                             &path[..],
                             "".as_bytes().to_vec(),
@@ -115,13 +143,15 @@ impl ZooKeeperConnection {
                             CreateMode::Persistent,
                         );
                         if let Err(e) = create_res {
-                            println!("{:?}", e);
+                            warn!(error = ?e, "failed to create the doc directory");
                         }
                     }
                 }
                 Err(e) => {
-                    println!("{:?}", e);
-                    return Err(Box::new(CRDTError::BackgroundSyncFailed(path)));
+                    return Err(CRDTError::Membership(format!(
+                        "cannot find doc {}: {:?}",
+                        path, e
+                    )));
                 }
             }
 
@@ -129,9 +159,7 @@ impl ZooKeeperConnection {
             let _ = sender.send(()).await;
 
             loop {
-                let http_path = format!("http://{}", self.client_ip.clone());
-                let endpoint = Endpoint::from_shared(http_path);
-                match endpoint {
+                match endpoint(&self.client_ip, &self.tls, self.client) {
                     Ok(ep) => {
                         let temp = ep.connect().await;
                         match temp {
@@ -141,62 +169,59 @@ impl ZooKeeperConnection {
                                     RegisterWatcher {
                                         channel: ch,
                                         sender: sender_block.clone(),
-                                        id: 0,
+                                        id: self.client,
+                                        token: self.token.clone(),
                                     },
                                 );
                             }
-                            Err(_) => {
-                                return Err(Box::new(CRDTError::BackgroundSyncFailed(
-                                    "zookeepeer failed to connect to local node".to_string(),
+                            Err(e) => {
+                                return Err(CRDTError::Transport(format!(
+                                    "zookeepeer failed to connect to local node: {}",
+                                    e
                                 )));
                             }
                         }
                     }
-                    Err(_) => {
-                        return Err(Box::new(CRDTError::BackgroundSyncFailed(
-                            "zookeepeer failed to connect to endpoint".to_string(),
-                        )));
-                    }
+                    Err(e) => return Err(e),
                 }
                 let _ = receiver_block.recv().await;
             }
         } else {
-            return Err(Box::new(CRDTError::BackgroundSyncFailed(
+            return Err(CRDTError::Membership(
                 "failed to start zookeeper".to_string(),
-            )));
+            ));
         }
     }
 
     // add a user for a doc
+    #[instrument(skip(self))]
     pub async fn register(&self, doc: String, client: ClientID) -> CRDTResult<()> {
-        let zk = ZooKeeper::connect(&*ZK_ADDR, Duration::from_secs(15), DefaultWatcher);
+        let zk = ZooKeeper::connect(ZK_ADDR, Duration::from_secs(15), DefaultWatcher)?;
+        debug!(addr = ZK_ADDR, "connected to zookeeper");
+        // create the child node
+        let child_path = format!("/{}/{}", doc, client);
+        let node_data = serde_json::to_vec(&Peer {
+            client_id: client,
+            ip_addr: self.client_ip.clone(),
+            public_key: self.public_key.clone(),
+            display: self.display.clone(),
+        })?;
+        let res = zk.create(
+            &child_path[..],
+            node_data,
+            Acl::open_unsafe().clone(),
+            CreateMode::Persistent,
+        );
 
-        match zk {
-            Ok(zk) => {
-                println!("connected to {:?}", ZK_ADDR);
-                // create the child node
-                let child_path = format!("/{}/{}", doc, client);
-                println!("the child path is {:?}", child_path);
-                let res = zk.create(
-                    &child_path[..],
-                    self.client_ip.as_bytes().to_vec(),
-                    Acl::open_unsafe().clone(),
-                    CreateMode::Persistent,
-                );
-
-                match res {
-                    Ok(_) => {
-                        println!("successfully created node for this client");
-                        return Ok(());
-                    }
-                    Err(e) => {
-                        println!("cannot create node for this client because {:?}", e);
-                        return Err(Box::new(CRDTError::RegisterUserFailed()));
-                    }
-                }
+        match res {
+            Ok(_) => {
+                info!(path = %child_path, "registered client");
+                Ok(())
             }
-            Err(_) => println!("failed to connect to zk"),
+            Err(e) => Err(CRDTError::Membership(format!(
+                "cannot create node {} for this client: {:?}",
+                child_path, e
+            ))),
         }
-        Err(Box::new(CRDTError::RegisterUserFailed()))
     }
 }
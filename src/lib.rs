@@ -1,6 +1,66 @@
 pub mod crdt;
+pub mod tui;
 pub mod wasm;
 
+#[cfg(test)]
+mod test_utils {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use crate::crdt::block::Content;
+    use crate::crdt::doc::Doc;
+    use crate::crdt::sync_txn::SyncTransaction;
+    use crate::crdt::utils::{CRDTResult, ClientID, Peer};
+    use tokio::sync::Mutex;
+
+    // transaction of client on a new doc, serving rpcs at ip once serve_rpc is called
+    pub fn init_txn(doc_name: &str, client_id: ClientID, ip: &str) -> SyncTransaction {
+        let doc = Arc::new(Mutex::new(Doc::new(doc_name.to_string(), client_id)));
+        SyncTransaction::new(
+            doc_name.to_string(),
+            client_id,
+            doc,
+            Arc::new(Mutex::new(HashMap::new())),
+            ip.to_string(),
+        )
+    }
+
+    // peer of client listening on port of localhost
+    pub fn peer(client_id: ClientID, port: u32) -> Peer {
+        Peer {
+            client_id,
+            ip_addr: format!("127.0.0.1:{}", port),
+            public_key: None,
+            display: None,
+        }
+    }
+
+    // send everything txn_from has to txn_to, together with the signatures of the blocks
+    pub async fn send(txn_from: &SyncTransaction, txn_to: &SyncTransaction) -> CRDTResult<()> {
+        let clock = txn_to.doc.lock().await.vector_clock.clone();
+        let updates = txn_from.compute_diff(clock).await;
+        let signatures = txn_from.signatures_for(&updates).await;
+        txn_to
+            .update_remote(updates, signatures, HashMap::new())
+            .await
+    }
+
+    // send doc_from everything doc_to hasn't seen
+    pub async fn exchange(doc_from: &Doc, doc_to: &mut Doc) {
+        let updates = doc_from.compute_diff(&doc_to.vector_clock).await;
+        doc_to.insert_remote(updates).await;
+    }
+
+    pub async fn insert(txn: &SyncTransaction, content: &str, pos: u32) {
+        insert_doc(&mut *txn.doc.lock().await, content, pos).await;
+    }
+
+    pub async fn insert_doc(doc: &mut Doc, content: &str, pos: u32) {
+        doc.insert_local(Content::Text(content.to_string()), pos)
+            .await;
+    }
+}
+
 #[cfg(test)]
 mod local_tests {
     use crate::crdt::block::Content;
@@ -14,41 +74,17 @@ mod local_tests {
         let cid = 1 as ClientID;
         let mut doc = Doc::new("text".to_string(), cid);
 
-        doc.insert_local(
-            Content {
-                content: "1".to_string(),
-            },
-            0,
-        )
-        .await;
+        doc.insert_local(Content::Text("1".to_string()), 0).await;
         assert_eq!(doc.to_string().await, "1".to_string());
 
-        doc.insert_local(
-            Content {
-                content: "2".to_string(),
-            },
-            1,
-        )
-        .await;
+        doc.insert_local(Content::Text("2".to_string()), 1).await;
         assert_eq!(doc.to_string().await, "12".to_string());
 
         // Insert pos is larger than length
-        doc.insert_local(
-            Content {
-                content: "3".to_string(),
-            },
-            10,
-        )
-        .await;
+        doc.insert_local(Content::Text("3".to_string()), 10).await;
         assert_eq!(doc.to_string().await, "123".to_string());
 
-        doc.insert_local(
-            Content {
-                content: "4".to_string(),
-            },
-            1,
-        )
-        .await;
+        doc.insert_local(Content::Text("4".to_string()), 1).await;
         assert_eq!(doc.to_string().await, "1423".to_string());
     }
 
@@ -59,41 +95,17 @@ mod local_tests {
         let cid = 1 as ClientID;
         let mut doc = Doc::new("text".to_string(), cid);
 
-        doc.insert_local(
-            Content {
-                content: "123".to_string(),
-            },
-            0,
-        )
-        .await;
+        doc.insert_local(Content::Text("123".to_string()), 0).await;
         assert_eq!(doc.to_string().await, "123".to_string());
 
-        doc.insert_local(
-            Content {
-                content: "45".to_string(),
-            },
-            1,
-        )
-        .await;
+        doc.insert_local(Content::Text("45".to_string()), 1).await;
         assert_eq!(doc.to_string().await, "14523".to_string());
 
         // Insert pos is larger than length
-        doc.insert_local(
-            Content {
-                content: "6".to_string(),
-            },
-            10,
-        )
-        .await;
+        doc.insert_local(Content::Text("6".to_string()), 10).await;
         assert_eq!(doc.to_string().await, "145236".to_string());
 
-        doc.insert_local(
-            Content {
-                content: "789".to_string(),
-            },
-            4,
-        )
-        .await;
+        doc.insert_local(Content::Text("789".to_string()), 4).await;
         assert_eq!(doc.to_string().await, "145278936".to_string());
     }
 
@@ -103,13 +115,7 @@ mod local_tests {
         let cid = 1 as ClientID;
         let mut doc = Doc::new("text".to_string(), cid);
 
-        doc.insert_local(
-            Content {
-                content: "123".to_string(),
-            },
-            0,
-        )
-        .await;
+        doc.insert_local(Content::Text("123".to_string()), 0).await;
         assert_eq!(doc.to_string().await, "123".to_string());
 
         // Remove a whole block
@@ -123,13 +129,8 @@ mod local_tests {
         let cid = 1 as ClientID;
         let mut doc = Doc::new("text".to_string(), cid);
 
-        doc.insert_local(
-            Content {
-                content: "12345".to_string(),
-            },
-            0,
-        )
-        .await;
+        doc.insert_local(Content::Text("12345".to_string()), 0)
+            .await;
         assert_eq!(doc.to_string().await, "12345".to_string());
 
         // Delete part of a block from the start
@@ -145,13 +146,7 @@ mod local_tests {
         assert_eq!(doc.to_string().await, "4".to_string());
 
         // Deleting characters out of bound should take no effect either
-        doc.insert_local(
-            Content {
-                content: "567".to_string(),
-            },
-            1,
-        )
-        .await;
+        doc.insert_local(Content::Text("567".to_string()), 1).await;
         assert_eq!(doc.to_string().await, "4567".to_string());
         doc.delete_local(2, 10).await;
         assert_eq!(doc.to_string().await, "45".to_string());
@@ -163,22 +158,10 @@ mod local_tests {
         let cid = 1 as ClientID;
         let mut doc = Doc::new("text".to_string(), cid);
 
-        doc.insert_local(
-            Content {
-                content: "123".to_string(),
-            },
-            0,
-        )
-        .await;
+        doc.insert_local(Content::Text("123".to_string()), 0).await;
         assert_eq!(doc.to_string().await, "123".to_string());
 
-        doc.insert_local(
-            Content {
-                content: "456".to_string(),
-            },
-            3,
-        )
-        .await;
+        doc.insert_local(Content::Text("456".to_string()), 3).await;
         assert_eq!(doc.to_string().await, "123456".to_string());
 
         // Delete part of a block from the start
@@ -194,19 +177,15 @@ mod remote_test {
     use crate::crdt::block::Content;
     use crate::crdt::doc::Doc;
     use crate::crdt::utils::ClientID;
+    use crate::test_utils::insert_doc;
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
     async fn remote_insert_none() {
         let cid = 1 as ClientID;
         let mut doc1 = Doc::new("text".to_string(), cid);
 
-        doc1.insert_local(
-            Content {
-                content: "1234567".to_string(),
-            },
-            0,
-        )
-        .await;
+        doc1.insert_local(Content::Text("1234567".to_string()), 0)
+            .await;
 
         let mut updates = vec![];
         let new_block: Block = Block {
@@ -217,9 +196,12 @@ mod remote_test {
             left_origin: None,
             right_origin: None,
             is_deleted: false,
-            content: Content {
-                content: "NEW2".to_string(),
-            },
+            deleted_by: None,
+            suggestion: None,
+            changes: vec![],
+            sealed: None,
+            elided: false,
+            content: Content::Text("NEW2".to_string()),
         };
         updates.push(new_block);
 
@@ -233,13 +215,8 @@ mod remote_test {
         let cid = 1 as ClientID;
         let mut doc1 = Doc::new("text".to_string(), cid);
 
-        doc1.insert_local(
-            Content {
-                content: "1234567".to_string(),
-            },
-            0,
-        )
-        .await;
+        doc1.insert_local(Content::Text("1234567".to_string()), 0)
+            .await;
         let store = doc1.block_store.clone();
         let store_lock = store.lock().await;
         let id = store_lock.kv_store.get(&1).unwrap().list[0]
@@ -262,9 +239,12 @@ mod remote_test {
                 clock: id.clock + 2,
             }),
             is_deleted: false,
-            content: Content {
-                content: "NEW2".to_string(),
-            },
+            deleted_by: None,
+            suggestion: None,
+            changes: vec![],
+            sealed: None,
+            elided: false,
+            content: Content::Text("NEW2".to_string()),
         };
         updates.push(new_block);
 
@@ -277,20 +257,10 @@ mod remote_test {
     async fn remote_insert_split_with_squash() {
         let cid = 1 as ClientID;
         let mut doc1 = Doc::new("text".to_string(), cid);
-        doc1.insert_local(
-            Content {
-                content: "1234567".to_string(),
-            },
-            0,
-        )
-        .await;
-        doc1.insert_local(
-            Content {
-                content: "aabbccdd".to_string(),
-            },
-            7,
-        )
-        .await;
+        doc1.insert_local(Content::Text("1234567".to_string()), 0)
+            .await;
+        doc1.insert_local(Content::Text("aabbccdd".to_string()), 7)
+            .await;
         assert_eq!(doc1.to_string().await, "1234567aabbccdd");
 
         let cid2 = 2 as ClientID;
@@ -304,21 +274,19 @@ mod remote_test {
             left_origin: None,
             right_origin: None,
             is_deleted: false,
-            content: Content {
-                content: "1234567aabbccdd".to_string(),
-            },
+            deleted_by: None,
+            suggestion: None,
+            changes: vec![],
+            sealed: None,
+            elided: false,
+            content: Content::Text("1234567aabbccdd".to_string()),
         };
         updates_1_to_2.push(new_block);
         doc2.insert_remote(updates_1_to_2).await;
         assert_eq!(doc2.to_string().await, "1234567aabbccdd");
 
-        doc2.insert_local(
-            Content {
-                content: "NEW2".to_string(),
-            },
-            7,
-        )
-        .await;
+        doc2.insert_local(Content::Text("NEW2".to_string()), 7)
+            .await;
         assert_eq!(doc2.to_string().await, "1234567NEW2aabbccdd");
 
         let mut updates_2_to_1: Vec<Block> = vec![];
@@ -336,9 +304,12 @@ mod remote_test {
                 clock: 7,
             }),
             is_deleted: false,
-            content: Content {
-                content: "NEW2".to_string(),
-            },
+            deleted_by: None,
+            suggestion: None,
+            changes: vec![],
+            sealed: None,
+            elided: false,
+            content: Content::Text("NEW2".to_string()),
         };
         updates_2_to_1.push(new_block);
         doc1.insert_remote(updates_2_to_1).await;
@@ -350,13 +321,8 @@ mod remote_test {
         let cid = 1 as ClientID;
         let mut doc1 = Doc::new("text".to_string(), cid);
 
-        doc1.insert_local(
-            Content {
-                content: "1234567".to_string(),
-            },
-            0,
-        )
-        .await;
+        doc1.insert_local(Content::Text("1234567".to_string()), 0)
+            .await;
         let store = doc1.block_store.clone();
         let store_lock = store.lock().await;
         let left = store_lock.kv_store.get(&1).unwrap().list[0]
@@ -376,9 +342,12 @@ mod remote_test {
             left_origin: Some(left.clone()),
             right_origin: None,
             is_deleted: false,
-            content: Content {
-                content: "NEW2".to_string(),
-            },
+            deleted_by: None,
+            suggestion: None,
+            changes: vec![],
+            sealed: None,
+            elided: false,
+            content: Content::Text("NEW2".to_string()),
         };
         updates.push(new_block);
         doc1.insert_remote(updates).await;
@@ -392,9 +361,12 @@ mod remote_test {
             left_origin: Some(left),
             right_origin: None,
             is_deleted: false,
-            content: Content {
-                content: "FROM14".to_string(),
-            },
+            deleted_by: None,
+            suggestion: None,
+            changes: vec![],
+            sealed: None,
+            elided: false,
+            content: Content::Text("FROM14".to_string()),
         };
         updates.push(new_block);
 
@@ -408,20 +380,10 @@ mod remote_test {
         let cid = 1 as ClientID;
         let mut doc1 = Doc::new("text".to_string(), cid);
 
-        doc1.insert_local(
-            Content {
-                content: "1234567".to_string(),
-            },
-            0,
-        )
-        .await;
-        doc1.insert_local(
-            Content {
-                content: "aabbccdd".to_string(),
-            },
-            7,
-        )
-        .await;
+        doc1.insert_local(Content::Text("1234567".to_string()), 0)
+            .await;
+        doc1.insert_local(Content::Text("aabbccdd".to_string()), 7)
+            .await;
         let left = BlockID {
             client: cid,
             clock: 0,
@@ -440,9 +402,12 @@ mod remote_test {
             left_origin: Some(left.clone()),
             right_origin: Some(right.clone()),
             is_deleted: true,
-            content: Content {
-                content: "NEW2".to_string(),
-            },
+            deleted_by: None,
+            suggestion: None,
+            changes: vec![],
+            sealed: None,
+            elided: false,
+            content: Content::Text("NEW2".to_string()),
         };
         updates.push(new_block);
         doc1.delete_remote(updates).await;
@@ -456,9 +421,12 @@ mod remote_test {
             left_origin: Some(left),
             right_origin: Some(right),
             is_deleted: false,
-            content: Content {
-                content: "NEW2".to_string(),
-            },
+            deleted_by: None,
+            suggestion: None,
+            changes: vec![],
+            sealed: None,
+            elided: false,
+            content: Content::Text("NEW2".to_string()),
         };
         updates.push(new_block);
 
@@ -466,156 +434,4204 @@ mod remote_test {
 
         assert_eq!(doc1.to_string().await, "1234567aabbccdd".to_string());
     }
-}
 
-#[cfg(test)]
-mod zk_test {
-    use std::collections::HashMap;
-    use std::sync::Arc;
+    // A concurrent block is integrated past blocks whose left origin lies
+    // between its own origins (here "c", whose left origin is "b")
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn remote_insert_after_chain() {
+        let mut docs: Vec<Doc> = (1..=3).map(|c| Doc::new("text".to_string(), c)).collect();
+        insert_doc(&mut docs[0], "a", 0).await;
+        let updates = docs[0].compute_diff(&docs[1].vector_clock).await;
+        docs[1].insert_remote(updates.clone()).await;
+        docs[2].insert_remote(updates).await;
 
-    use crate::crdt::doc::Doc;
-    use crate::crdt::sync_txn::SyncTransaction;
-    use crate::crdt::utils::{serve_rpc, ClientID};
-    use std::{thread, time};
-    use tokio::sync::mpsc::channel;
-    use tokio::sync::mpsc::{Receiver, Sender};
-    use tokio::sync::Mutex;
+        insert_doc(&mut docs[1], "b", 1).await;
+        insert_doc(&mut docs[1], "c", 2).await;
+        insert_doc(&mut docs[2], "X", 1).await;
+        let from2 = docs[1].compute_diff(&docs[2].vector_clock).await;
+        let from3 = docs[2].compute_diff(&docs[1].vector_clock).await;
+        docs[1].insert_remote(from3).await;
+        docs[2].insert_remote(from2).await;
+
+        assert_eq!(docs[1].to_string().await, "abcX".to_string());
+        assert_eq!(docs[2].to_string().await, "abcX".to_string());
+    }
 
+    // Remote blocks whose range or origins fall inside a multi-byte character are dropped
     #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
-    async fn zk_register_test() {
-        // init channels used for rpc and user service communication
-        let (sender1, receiver1): (Sender<()>, Receiver<()>) = channel(1);
-        let (init_sender1, mut init_receiver1): (Sender<()>, Receiver<()>) = channel(1);
-        let (sender2, receiver2): (Sender<()>, Receiver<()>) = channel(1);
-        let (init_sender2, mut init_receiver2): (Sender<()>, Receiver<()>) = channel(1);
+    async fn remote_range_inside_char() {
+        let mut doc1 = Doc::new("text".to_string(), 1);
+        insert_doc(&mut doc1, "é", 0).await;
 
-        let (txn_rpc1, txn_service1, txn_bg1) =
-            init_txn_w_rpc("doc".to_string(), 1, "127.0.0.1:4001".to_string()).await;
-        let (txn_rpc2, txn_service2, txn_bg2) =
-            init_txn_w_rpc("doc".to_string(), 2, "127.0.0.1:4002".to_string()).await;
+        let content = Content::Text("a".to_string());
+        let mut tombstone = Block::new(BlockID::new(1, 1), None, None, content.clone());
+        tombstone.is_deleted = true;
+        doc1.insert_remote(vec![tombstone.clone()]).await;
+        doc1.delete_remote(vec![tombstone]).await;
+        let inside = Block::new(BlockID::new(2, 0), Some(BlockID::new(1, 1)), None, content);
+        doc1.insert_remote(vec![inside]).await;
 
-        // start rpc services
-        tokio::spawn(async move {
-            serve_rpc(txn_rpc1, txn_bg1, receiver1, init_sender1).await;
-        });
-        let wait = time::Duration::from_secs(2);
-        thread::sleep(wait);
-        tokio::spawn(async move {
-            serve_rpc(txn_rpc2, txn_bg2, receiver2, init_sender2).await;
-        });
+        assert_eq!(doc1.to_string().await, "é".to_string());
+        assert!(doc1.pending_updates.is_empty());
+    }
+}
 
-        // start user operation
-        let _ = init_receiver1.recv().await;
-        let _ = init_receiver2.recv().await;
+#[cfg(test)]
+mod awareness_test {
+    use crate::crdt::block::Block;
+    use crate::crdt::block::BlockID;
+    use crate::crdt::block::Content;
+    use crate::crdt::doc::Doc;
+    use crate::crdt::utils::ClientID;
 
-        println!("----------- start op --------------");
-        tokio::spawn(async move {
-            let succ = txn_service1.register().await;
-            assert_eq!(true, succ);
-        });
-        tokio::spawn(async move {
-            let succ = txn_service2.register().await;
-            assert_eq!(true, succ);
-        });
+    // The cursor should stay after the same character when remote insertions happen before it
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn cursor_follows_remote_insert() {
+        let cid = 1 as ClientID;
+        let mut doc1 = Doc::new("text".to_string(), cid);
 
-        // wait for all operations to finish
-        let wait = time::Duration::from_secs(2);
-        thread::sleep(wait);
+        doc1.insert_local(Content::Text("12345".to_string()), 0)
+            .await;
+        doc1.set_cursor(3).await;
+        assert_eq!(doc1.cursor_positions().await[&cid], 3);
 
-        // shutdown rpc service
-        let _ = sender1.send(()).await;
-        let _ = sender2.send(()).await;
+        let mut updates = vec![];
+        let new_block: Block = Block {
+            id: BlockID {
+                client: 2,
+                clock: 0,
+            },
+            left_origin: None,
+            right_origin: Some(BlockID {
+                client: cid,
+                clock: 0,
+            }),
+            is_deleted: false,
+            deleted_by: None,
+            suggestion: None,
+            changes: vec![],
+            sealed: None,
+            elided: false,
+            content: Content::Text("AB".to_string()),
+        };
+        updates.push(new_block);
+        doc1.insert_remote(updates).await;
 
-        // wait for rpc to shutdown
-        let wait = time::Duration::from_secs(2);
-        thread::sleep(wait);
+        assert_eq!(doc1.to_string().await, "AB12345".to_string());
+        assert_eq!(doc1.cursor_positions().await[&cid], 5);
     }
 
-    // is it possible to make sure the order of registration?
+    // The cursor should collapse to where its anchor was when the anchor is deleted
     #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
-    async fn zk_register_test_more_nodes() {
-        let (sender1, receiver1): (Sender<()>, Receiver<()>) = channel(1);
-        let (init_sender1, mut init_receiver1): (Sender<()>, Receiver<()>) = channel(1);
-        let (sender2, receiver2): (Sender<()>, Receiver<()>) = channel(1);
-        let (init_sender2, mut init_receiver2): (Sender<()>, Receiver<()>) = channel(1);
-        let (sender3, receiver3): (Sender<()>, Receiver<()>) = channel(1);
-        let (init_sender3, mut init_receiver3): (Sender<()>, Receiver<()>) = channel(1);
-        let (sender4, receiver4): (Sender<()>, Receiver<()>) = channel(1);
-        let (init_sender4, mut init_receiver4): (Sender<()>, Receiver<()>) = channel(1);
+    async fn cursor_anchor_deleted() {
+        let cid = 1 as ClientID;
+        let mut doc1 = Doc::new("text".to_string(), cid);
 
-        let (txn_rpc1, txn_service1, txn_bg1) =
-            init_txn_w_rpc("doc".to_string(), 1, "127.0.0.1:4001".to_string()).await;
-        let (txn_rpc2, txn_service2, txn_bg2) =
-            init_txn_w_rpc("doc".to_string(), 2, "127.0.0.1:4002".to_string()).await;
-        let (txn_rpc3, txn_service3, txn_bg3) =
-            init_txn_w_rpc("doc".to_string(), 3, "127.0.0.1:4003".to_string()).await;
-        let (txn_rpc4, txn_service4, txn_bg4) =
-            init_txn_w_rpc("doc".to_string(), 4, "127.0.0.1:4004".to_string()).await;
+        doc1.insert_local(Content::Text("12345".to_string()), 0)
+            .await;
+        doc1.set_cursor(3).await;
+        doc1.delete_local(1, 2).await;
 
-        // start 1 and 2
-        tokio::spawn(async move {
-            serve_rpc(txn_rpc1, txn_bg1, receiver1, init_sender1).await;
-        });
-        tokio::spawn(async move {
-            serve_rpc(txn_rpc2, txn_bg2, receiver2, init_sender2).await;
-        });
+        assert_eq!(doc1.to_string().await, "145".to_string());
+        assert_eq!(doc1.cursor_positions().await[&cid], 1);
+    }
+}
 
-        let _ = init_receiver1.recv().await;
-        let _ = init_receiver2.recv().await;
-        tokio::spawn(async move {
-            let succ = txn_service1.register().await;
-            assert_eq!(true, succ);
-        });
-        tokio::spawn(async move {
-            let succ = txn_service2.register().await;
-            assert_eq!(true, succ);
-        });
+#[cfg(test)]
+mod sync_test {
+    use crate::crdt::block::Content;
+    use crate::crdt::doc::Doc;
+    use crate::crdt::utils::ClientID;
+    use crate::test_utils::exchange;
 
-        // start 3 and 4
-        tokio::spawn(async move {
-            serve_rpc(txn_rpc3, txn_bg3, receiver3, init_sender3).await;
-        });
-        tokio::spawn(async move {
-            serve_rpc(txn_rpc4, txn_bg4, receiver4, init_sender4).await;
-        });
+    // Concurrent insertions should converge after exchanging diffs in both directions
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn diff_concurrent_insert() {
+        let mut doc1 = Doc::new("text".to_string(), 1 as ClientID);
+        let mut doc2 = Doc::new("text".to_string(), 2 as ClientID);
 
-        let _ = init_receiver3.recv().await;
-        let _ = init_receiver4.recv().await;
+        doc1.insert_local(Content::Text("hello".to_string()), 0)
+            .await;
+        doc2.insert_local(Content::Text("world".to_string()), 0)
+            .await;
 
-        // delay the op, see if it got propagated
-        let wait = time::Duration::from_secs(2);
-        thread::sleep(wait);
+        exchange(&doc1, &mut doc2).await;
+        exchange(&doc2, &mut doc1).await;
+        assert_eq!(doc1.to_string().await, doc2.to_string().await);
 
-        // delay the op, see if it got propagated
-        let wait = time::Duration::from_secs(2);
-        thread::sleep(wait);
+        // Nothing new should be sent the second time except tombstones
+        assert!(doc1.compute_diff(&doc2.vector_clock).await.is_empty());
 
-        tokio::spawn(async move {
-            let succ = txn_service3.register().await;
-            assert_eq!(true, succ);
-        });
-        tokio::spawn(async move {
-            let succ = txn_service4.register().await;
-            assert_eq!(true, succ);
-        });
+        doc1.insert_local(Content::Text("!".to_string()), 10).await;
+        exchange(&doc1, &mut doc2).await;
+        assert_eq!(doc1.to_string().await, doc2.to_string().await);
+        assert!(doc2.to_string().await.ends_with("!"));
+    }
 
-        // wait for all operations to finish
-        let wait = time::Duration::from_secs(2);
-        thread::sleep(wait);
+    // Deletions should reach both peers that have and haven't seen the deleted blocks
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn diff_delete() {
+        let mut doc1 = Doc::new("text".to_string(), 1 as ClientID);
+        let mut doc2 = Doc::new("text".to_string(), 2 as ClientID);
+        let mut doc3 = Doc::new("text".to_string(), 3 as ClientID);
 
-        // shutdown rpc service
-        let _ = sender1.send(()).await;
-        let _ = sender2.send(()).await;
-        let _ = sender3.send(()).await;
-        let _ = sender4.send(()).await;
+        doc1.insert_local(Content::Text("12345".to_string()), 0)
+            .await;
+        exchange(&doc1, &mut doc2).await;
+        assert_eq!(doc2.to_string().await, "12345".to_string());
 
-        // wait for rpc to shutdown
-        let wait = time::Duration::from_secs(2);
-        thread::sleep(wait);
+        doc1.delete_local(1, 2).await;
+        exchange(&doc1, &mut doc2).await;
+        exchange(&doc1, &mut doc3).await;
+        assert_eq!(doc2.to_string().await, "145".to_string());
+        assert_eq!(doc3.to_string().await, "145".to_string());
     }
 
-    // create a transaction (one is rpc service, one is the real service that a client use)
-    pub async fn init_txn_w_rpc(
+    // A tombstone is sent until the peer has adopted the change that deleted it
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn diff_tombstone_sent_once() {
+        let mut doc1 = Doc::new("text".to_string(), 1 as ClientID);
+        let mut doc2 = Doc::new("text".to_string(), 2 as ClientID);
+
+        doc1.insert_local(Content::Text("12345".to_string()), 0)
+            .await;
+        exchange(&doc1, &mut doc2).await;
+        doc2.vector_clock.merge_changes(&doc1.vector_clock);
+        assert!(doc1.compute_diff(&doc2.vector_clock).await.is_empty());
+
+        doc1.delete_local(1, 2).await;
+        doc2.delete_local(4, 1).await;
+        let diff = doc1.compute_diff(&doc2.vector_clock).await;
+        assert_eq!(diff.len(), 1);
+        assert!(diff[0].is_deleted);
+
+        // both sides integrate the whole diff of the other, then adopt its changes
+        let clock1 = doc1.vector_clock.clone();
+        let clock2 = doc2.vector_clock.clone();
+        exchange(&doc1, &mut doc2).await;
+        exchange(&doc2, &mut doc1).await;
+        doc1.vector_clock.merge_changes(&clock2);
+        doc2.vector_clock.merge_changes(&clock1);
+        assert_eq!(doc1.to_string().await, "14".to_string());
+        assert_eq!(doc2.to_string().await, "14".to_string());
+        assert!(doc1.compute_diff(&doc2.vector_clock).await.is_empty());
+        assert!(doc2.compute_diff(&doc1.vector_clock).await.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod relay_test {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use crate::crdt::block::Content;
+    use crate::crdt::doc::Doc;
+    use crate::crdt::relay::{serve_relay, RelayServer};
+    use crate::crdt::sync_txn::SyncTransaction;
+    use crate::crdt::utils::ClientID;
+    use tokio::sync::mpsc::channel;
+    use tokio::sync::mpsc::{Receiver, Sender};
+    use tokio::sync::Mutex;
+
+    fn init_txn_w_relay(doc_name: &str, client_id: ClientID, relay_ip: &str) -> SyncTransaction {
+        let doc = Arc::new(Mutex::new(Doc::new(doc_name.to_string(), client_id)));
+        let mut txn = SyncTransaction::new(
+            doc_name.to_string(),
+            client_id,
+            doc,
+            Arc::new(Mutex::new(HashMap::new())),
+            "".to_string(),
+        );
+        txn.set_relay(relay_ip.to_string());
+        txn
+    }
+
+    // Two clients that only connect to the relay should see each other's updates
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn relay_two_clients() {
+        let relay_ip = "127.0.0.1:4201";
+        let (sender, receiver): (Sender<()>, Receiver<()>) = channel(1);
+        let (init_sender, mut init_receiver): (Sender<()>, Receiver<()>) = channel(1);
+        tokio::spawn(async move {
+            serve_relay(
+                RelayServer::new(relay_ip.to_string()),
+                receiver,
+                init_sender,
+            )
+            .await
+            .unwrap();
+        });
+        let _ = init_receiver.recv().await;
+
+        let txn1 = init_txn_w_relay("doc", 1, relay_ip);
+        let txn2 = init_txn_w_relay("doc", 2, relay_ip);
+        let other = init_txn_w_relay("other", 3, relay_ip);
+
+        txn1.doc
+            .lock()
+            .await
+            .insert_local(Content::Text("abc".to_string()), 0)
+            .await;
+        txn1.sync().await.unwrap();
+        txn2.sync().await.unwrap();
+        assert_eq!(txn2.doc.lock().await.to_string().await, "abc".to_string());
+
+        txn2.doc
+            .lock()
+            .await
+            .insert_local(Content::Text("d".to_string()), 3)
+            .await;
+        txn2.doc.lock().await.delete_local(0, 1).await;
+        txn2.sync().await.unwrap();
+        txn1.sync().await.unwrap();
+        assert_eq!(txn1.doc.lock().await.to_string().await, "bcd".to_string());
+
+        // Docs hosted on the same relay are independent
+        other.sync().await.unwrap();
+        assert_eq!(other.doc.lock().await.to_string().await, "".to_string());
+
+        let _ = sender.send(()).await;
+    }
+}
+
+#[cfg(test)]
+mod ws_test {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::crdt::block::{Block, BlockID, Content};
+    use crate::crdt::doc::{Doc, VectorClock};
+    use crate::crdt::relay::RelayServer;
+    use crate::crdt::signing::{Signatures, Signer};
+    use crate::crdt::sync_txn::SyncTransaction;
+    use crate::crdt::utils::ClientID;
+    use crate::crdt::ws::{serve_ws, sync_ws, WsMessage, WsServer};
+    use crate::test_utils::insert_doc;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::sync::mpsc::channel;
+    use tokio::sync::mpsc::{Receiver, Sender};
+    use tokio::sync::Mutex;
+    use tokio_tungstenite::tungstenite::Message;
+
+    // start syncing a new doc over websocket in the background
+    fn init_txn_w_ws(client_id: ClientID, url: &str) -> (Arc<Mutex<Doc>>, Sender<()>) {
+        let doc = Arc::new(Mutex::new(Doc::new("doc".to_string(), client_id)));
+        let txn = SyncTransaction::new(
+            "doc".to_string(),
+            client_id,
+            doc.clone(),
+            Arc::new(Mutex::new(HashMap::new())),
+            "".to_string(),
+        );
+        let (sender, receiver): (Sender<()>, Receiver<()>) = channel(1);
+        let url = url.to_string();
+        tokio::spawn(async move {
+            let res = sync_ws(&txn, &url, Duration::from_millis(50), receiver).await;
+            assert!(res.is_ok());
+        });
+        (doc, sender)
+    }
+
+    // Updates and cursors of one websocket client should reach the other
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn ws_two_clients() {
+        let (sender, receiver): (Sender<()>, Receiver<()>) = channel(1);
+        let (init_sender, mut init_receiver): (Sender<()>, Receiver<()>) = channel(1);
+        let server = WsServer::new(
+            "127.0.0.1:4301".to_string(),
+            RelayServer::new("".to_string()),
+        );
+        tokio::spawn(async move {
+            serve_ws(server, receiver, init_sender).await.unwrap();
+        });
+        let _ = init_receiver.recv().await;
+
+        let (doc1, stop1) = init_txn_w_ws(1, "ws://127.0.0.1:4301");
+        doc1.lock()
+            .await
+            .insert_local(Content::Text("hello".to_string()), 0)
+            .await;
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        // a client joining later gets the whole doc during the handshake
+        let (doc2, stop2) = init_txn_w_ws(2, "ws://127.0.0.1:4301");
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(doc2.lock().await.to_string().await, "hello".to_string());
+
+        {
+            let mut doc2_lock = doc2.lock().await;
+            doc2_lock.delete_local(0, 1).await;
+            doc2_lock.set_cursor(2).await;
+        }
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let doc1_lock = doc1.lock().await;
+        assert_eq!(doc1_lock.to_string().await, "ello".to_string());
+        assert_eq!(doc1_lock.cursor_positions().await[&2], 2);
+        drop(doc1_lock);
+
+        let _ = stop1.send(()).await;
+        let _ = stop2.send(()).await;
+        let _ = sender.send(()).await;
+    }
+
+    // Blocks a client didn't integrate are sent again with the next updates,
+    // here once the client knows the key that signed them
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn ws_resend_unacked() {
+        let (sender, receiver): (Sender<()>, Receiver<()>) = channel(1);
+        let (init_sender, mut init_receiver): (Sender<()>, Receiver<()>) = channel(1);
+        let server = WsServer::new(
+            "127.0.0.1:4303".to_string(),
+            RelayServer::new("".to_string()),
+        );
+        tokio::spawn(async move {
+            serve_ws(server, receiver, init_sender).await.unwrap();
+        });
+        let _ = init_receiver.recv().await;
+
+        let signer = Arc::new(Signer::generate());
+        let (doc1, stop1) = init_txn_w_ws(1, "ws://127.0.0.1:4303");
+        doc1.lock().await.signatures.set_signer(1, signer.clone());
+        let (doc2, stop2) = init_txn_w_ws(2, "ws://127.0.0.1:4303");
+        doc2.lock()
+            .await
+            .signatures
+            .set_signer(2, Arc::new(Signer::generate()));
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        insert_doc(&mut *doc1.lock().await, "hello", 0).await;
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(doc2.lock().await.to_string().await, "".to_string());
+
+        doc2.lock()
+            .await
+            .signatures
+            .add_public_key(1, signer.public_key());
+        insert_doc(&mut *doc1.lock().await, "!", 5).await;
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(doc2.lock().await.to_string().await, "hello!".to_string());
+
+        let _ = stop1.send(()).await;
+        let _ = stop2.send(()).await;
+        let _ = sender.send(()).await;
+    }
+
+    // Blocks are fanned out once the relay integrated them, not as they were received
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn ws_fanout_integrated() {
+        let (sender, receiver): (Sender<()>, Receiver<()>) = channel(1);
+        let (init_sender, mut init_receiver): (Sender<()>, Receiver<()>) = channel(1);
+        let server = WsServer::new(
+            "127.0.0.1:4302".to_string(),
+            RelayServer::new("".to_string()),
+        );
+        tokio::spawn(async move {
+            serve_ws(server, receiver, init_sender).await.unwrap();
+        });
+        let _ = init_receiver.recv().await;
+        let (doc2, stop2) = init_txn_w_ws(2, "ws://127.0.0.1:4302");
+
+        let (ws, _) = tokio_tungstenite::connect_async("ws://127.0.0.1:4302")
+            .await
+            .unwrap();
+        let (mut write, _read) = ws.split();
+        let send = |msg: WsMessage| Message::Text(serde_json::to_string(&msg).unwrap());
+        let update = |block: Block| {
+            send(WsMessage::Update {
+                updates: vec![block],
+                signatures: Signatures::default(),
+                key_epochs: HashMap::new(),
+            })
+        };
+        let origin = Block::new(
+            BlockID::new(3, 0),
+            None,
+            None,
+            Content::Text("hello".to_string()),
+        );
+        let child = Block::new(
+            BlockID::new(3, 5),
+            Some(BlockID::new(3, 4)),
+            None,
+            Content::Text("!".to_string()),
+        );
+        write
+            .send(send(WsMessage::SyncStep1 {
+                doc_name: "doc".to_string(),
+                vector_clock: VectorClock::new(),
+                token: None,
+            }))
+            .await
+            .unwrap();
+
+        // the relay waits for the origin, so doc2 doesn't get the block yet
+        write.send(update(child)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(doc2.lock().await.pending_updates.is_empty());
+
+        write.send(update(origin)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let doc2_lock = doc2.lock().await;
+        assert_eq!(doc2_lock.to_string().await, "hello!".to_string());
+        assert!(doc2_lock.pending_updates.is_empty());
+        drop(doc2_lock);
+
+        let _ = stop2.send(()).await;
+        let _ = sender.send(()).await;
+    }
+}
+
+#[cfg(test)]
+mod auth_test {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use std::time::Duration;
+
+    use crate::crdt::auth::{with_token, Permissions, Role};
+    use crate::crdt::block::{Block, BlockID, Content};
+    use crate::crdt::doc::Doc;
+    use crate::crdt::relay::{serve_relay, RelayServer};
+    use crate::crdt::signing::Signatures;
+    use crate::crdt::sync_txn::SyncTransaction;
+    use crate::crdt::txn_rpc::{self, txn_service_client::TxnServiceClient};
+    use crate::crdt::utils::{serve_rpc, CRDTError, ClientID};
+    use crate::crdt::ws::{serve_ws, sync_ws, WsServer};
+    use crate::test_utils::{init_txn, insert, insert_doc};
+    use tokio::sync::mpsc::channel;
+    use tokio::sync::mpsc::{Receiver, Sender};
+    use tokio::sync::Mutex;
+
+    // client 1 owns "doc", client 2 can only view it, client 3 has no access
+    fn init_permissions() -> Arc<Permissions> {
+        let mut permissions = Permissions::default();
+        for client in 1..=3 as ClientID {
+            permissions
+                .tokens
+                .insert(format!("token{}", client), client);
+        }
+        let mut roles = HashMap::new();
+        roles.insert(1, Role::Owner);
+        roles.insert(2, Role::Viewer);
+        permissions.docs.insert("doc".to_string(), roles);
+        Arc::new(permissions)
+    }
+
+    fn init_txn_w_token(client_id: ClientID, token: Option<&str>) -> SyncTransaction {
+        let mut txn = init_txn("doc", client_id, "127.0.0.1:4402");
+        if let Some(token) = token {
+            txn.set_token(token.to_string());
+        }
+        txn
+    }
+
+    // Only clients with edit access can change the doc hosted on a relay
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn relay_roles() {
+        let relay_ip = "127.0.0.1:4401";
+        let mut relay = RelayServer::new(relay_ip.to_string());
+        relay.auth = Some(init_permissions());
+        let (sender, receiver): (Sender<()>, Receiver<()>) = channel(1);
+        let (init_sender, mut init_receiver): (Sender<()>, Receiver<()>) = channel(1);
+        tokio::spawn(async move {
+            serve_relay(relay, receiver, init_sender).await.unwrap();
+        });
+        let _ = init_receiver.recv().await;
+
+        let mut owner = init_txn_w_token(1, Some("token1"));
+        let mut viewer = init_txn_w_token(2, Some("token2"));
+        let mut stranger = init_txn_w_token(3, Some("token3"));
+        let mut anonymous = init_txn_w_token(4, None);
+        for txn in [&mut owner, &mut viewer, &mut stranger, &mut anonymous] {
+            txn.set_relay(relay_ip.to_string());
+        }
+
+        insert(&owner, "abc", 0).await;
+        owner.sync().await.unwrap();
+        viewer.sync().await.unwrap();
+        assert_eq!(viewer.doc.lock().await.to_string().await, "abc".to_string());
+
+        // changes of a viewer stay local
+        insert(&viewer, "x", 0).await;
+        viewer.doc.lock().await.delete_local(1, 1).await;
+        assert!(matches!(
+            viewer.sync().await,
+            Err(CRDTError::PermissionDenied(_))
+        ));
+        owner.sync().await.unwrap();
+        assert_eq!(owner.doc.lock().await.to_string().await, "abc".to_string());
+
+        // clients without a role or without a token cannot read the doc
+        for txn in [&stranger, &anonymous] {
+            assert!(matches!(
+                txn.sync().await,
+                Err(CRDTError::PermissionDenied(_))
+            ));
+        }
+        assert_eq!(stranger.doc.lock().await.to_string().await, "".to_string());
+        assert_eq!(anonymous.doc.lock().await.to_string().await, "".to_string());
+
+        let _ = sender.send(()).await;
+    }
+
+    // The peer list of a client can only be replaced by the client itself
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn sync_peer_list_denied() {
+        let mut txn_rpc = init_txn_w_token(1, Some("token1"));
+        let mut txn_bg = init_txn_w_token(1, Some("token1"));
+        txn_rpc.set_permissions(init_permissions());
+        txn_bg.set_permissions(init_permissions());
+        let (sender, receiver): (Sender<()>, Receiver<()>) = channel(1);
+        let (init_sender, mut init_receiver): (Sender<()>, Receiver<()>) = channel(1);
+        tokio::spawn(async move {
+            serve_rpc(txn_rpc, txn_bg, receiver, init_sender)
+                .await
+                .unwrap();
+        });
+        let _ = init_receiver.recv().await;
+
+        let mut client = TxnServiceClient::connect("http://127.0.0.1:4402")
+            .await
+            .unwrap();
+        let request = |token: Option<String>| {
+            with_token(
+                txn_rpc::RegisterRequest {
+                    peer_list: "[]".to_string(),
+                },
+                &token,
+            )
+        };
+        let res = client.sync_peer_list(request(None)).await;
+        assert_eq!(res.unwrap_err().code(), tonic::Code::Unauthenticated);
+        let res = client
+            .sync_peer_list(request(Some("token2".to_string())))
+            .await;
+        assert_eq!(res.unwrap_err().code(), tonic::Code::PermissionDenied);
+        let res = client
+            .sync_peer_list(request(Some("token1".to_string())))
+            .await;
+        assert!(res.is_ok());
+
+        let _ = sender.send(()).await;
+    }
+
+    // Tombstones are checked against the client that deleted them, whoever relays them
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn deletion_checked_against_deleter() {
+        let mut txn = init_txn("doc", 3, "");
+        txn.set_permissions(init_permissions());
+        let block = Block::new(
+            BlockID::new(1, 0),
+            None,
+            None,
+            Content::Text("abc".to_string()),
+        );
+        txn.update_remote(vec![block.clone()], Signatures::default(), HashMap::new())
+            .await
+            .unwrap();
+
+        // deleted by a viewer, or by nobody in particular
+        for deleter in [Some(2), None] {
+            let mut tombstone = block.clone();
+            tombstone.delete_by(deleter);
+            txn.update_remote(vec![tombstone], Signatures::default(), HashMap::new())
+                .await
+                .unwrap();
+            assert_eq!(txn.doc.lock().await.to_string().await, "abc".to_string());
+        }
+
+        let mut tombstone = block.clone();
+        tombstone.delete_by(Some(1));
+        txn.update_remote(vec![tombstone], Signatures::default(), HashMap::new())
+            .await
+            .unwrap();
+        assert_eq!(txn.doc.lock().await.to_string().await, "".to_string());
+    }
+
+    // An editor only pushes its own insertions and deletions, whatever client the blocks claim
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn push_bound_to_caller() {
+        let relay_ip = "127.0.0.1:4403";
+        let mut permissions = (*init_permissions()).clone();
+        permissions.tokens.insert("token5".to_string(), 5);
+        permissions
+            .docs
+            .get_mut("doc")
+            .unwrap()
+            .insert(5, Role::Editor);
+        let mut relay = RelayServer::new(relay_ip.to_string());
+        relay.auth = Some(Arc::new(permissions));
+        let (sender, receiver): (Sender<()>, Receiver<()>) = channel(1);
+        let (init_sender, mut init_receiver): (Sender<()>, Receiver<()>) = channel(1);
+        tokio::spawn(async move {
+            serve_relay(relay, receiver, init_sender).await.unwrap();
+        });
+        let _ = init_receiver.recv().await;
+
+        let mut owner = init_txn_w_token(1, Some("token1"));
+        owner.set_relay(relay_ip.to_string());
+        insert(&owner, "abc", 0).await;
+        owner.sync().await.unwrap();
+
+        let mut client = TxnServiceClient::connect(format!("http://{}", relay_ip))
+            .await
+            .unwrap();
+        let push = |updates: Vec<Block>| {
+            with_token(
+                txn_rpc::PushRequest {
+                    doc_name: "doc".to_string(),
+                    client_id: 5,
+                    updates: serde_json::to_string(&updates).unwrap(),
+                    ..Default::default()
+                },
+                &Some("token5".to_string()),
+            )
+        };
+        let block = |client: ClientID, clock: u32, left: Option<BlockID>, content: &str| {
+            Block::new(
+                BlockID::new(client, clock),
+                left,
+                None,
+                Content::Text(content.to_string()),
+            )
+        };
+
+        // an insertion and a deletion on behalf of the owner
+        let forged = block(1, 3, Some(BlockID::new(1, 2)), "x");
+        let mut deleted = block(1, 0, None, "abc");
+        deleted.delete_by(Some(1));
+        for updates in [vec![forged], vec![deleted]] {
+            let res = client.push_updates(push(updates)).await;
+            assert_eq!(res.unwrap_err().code(), tonic::Code::PermissionDenied);
+        }
+
+        let own = block(5, 0, Some(BlockID::new(1, 2)), "!");
+        assert!(client.push_updates(push(vec![own])).await.is_ok());
+        owner.sync().await.unwrap();
+        assert_eq!(owner.doc.lock().await.to_string().await, "abc!".to_string());
+
+        let _ = sender.send(()).await;
+    }
+
+    // Websocket clients need a role on the doc, and viewers cannot write
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn ws_roles() {
+        let url = "ws://127.0.0.1:4303";
+        let mut relay = RelayServer::new("".to_string());
+        relay.auth = Some(init_permissions());
+        let server = WsServer::new("127.0.0.1:4303".to_string(), relay);
+        let (sender, receiver): (Sender<()>, Receiver<()>) = channel(1);
+        let (init_sender, mut init_receiver): (Sender<()>, Receiver<()>) = channel(1);
+        tokio::spawn(async move {
+            serve_ws(server, receiver, init_sender).await.unwrap();
+        });
+        let _ = init_receiver.recv().await;
+
+        let connect = |client_id: ClientID| -> (Arc<Mutex<Doc>>, Sender<()>) {
+            let txn = init_txn_w_token(client_id, Some(&format!("token{}", client_id)));
+            let doc = txn.doc.clone();
+            let (sender, receiver): (Sender<()>, Receiver<()>) = channel(1);
+            tokio::spawn(async move {
+                let _ = sync_ws(&txn, url, Duration::from_millis(50), receiver).await;
+            });
+            (doc, sender)
+        };
+        let (owner, stop_owner) = connect(1);
+        insert_doc(&mut *owner.lock().await, "abc", 0).await;
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        let (viewer, stop_viewer) = connect(2);
+        let (stranger, _) = connect(3);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(viewer.lock().await.to_string().await, "abc".to_string());
+        assert_eq!(stranger.lock().await.to_string().await, "".to_string());
+
+        // the relay refuses the insertion of the viewer, so the owner never gets it
+        insert_doc(&mut *viewer.lock().await, "x", 0).await;
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(owner.lock().await.to_string().await, "abc".to_string());
+
+        let _ = stop_owner.send(()).await;
+        let _ = stop_viewer.send(()).await;
+        let _ = sender.send(()).await;
+    }
+}
+
+#[cfg(test)]
+mod tls_test {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use crate::crdt::block::Content;
+    use crate::crdt::doc::Doc;
+    use crate::crdt::sync_txn::SyncTransaction;
+    use crate::crdt::tls::{endpoint, peer_name, TlsConfig};
+    use crate::crdt::txn_rpc::{self, txn_service_client::TxnServiceClient};
+    use crate::crdt::utils::{serve_rpc, ClientID};
+    use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa};
+    use tokio::sync::mpsc::channel;
+    use tokio::sync::mpsc::{Receiver, Sender};
+    use tokio::sync::Mutex;
+
+    // issue a certificate for every client, signed by a fresh ca
+    fn init_certs(name: &str, clients: &[ClientID]) -> HashMap<ClientID, Arc<TlsConfig>> {
+        let dir = std::env::temp_dir().join(format!("codoc-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut ca_params = CertificateParams::new(vec![]);
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = Certificate::from_params(ca_params).unwrap();
+        let ca_path = dir.join("ca.pem");
+        std::fs::write(&ca_path, ca.serialize_pem().unwrap()).unwrap();
+
+        let mut configs = HashMap::new();
+        for client in clients {
+            let cert =
+                Certificate::from_params(CertificateParams::new(vec![peer_name(*client)])).unwrap();
+            let cert_path = dir.join(format!("{}.pem", client));
+            let key_path = dir.join(format!("{}.key", client));
+            std::fs::write(&cert_path, cert.serialize_pem_with_signer(&ca).unwrap()).unwrap();
+            std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+            let config = TlsConfig::new(
+                cert_path.to_string_lossy().to_string(),
+                key_path.to_string_lossy().to_string(),
+                ca_path.to_string_lossy().to_string(),
+                true,
+            );
+            configs.insert(*client, Arc::new(config));
+        }
+        configs
+    }
+
+    fn pull(client_id: ClientID) -> txn_rpc::PullRequest {
+        txn_rpc::PullRequest {
+            client_id,
+            vector_clock: "{\"clock_map\":{}}".to_string(),
+            doc_name: "doc".to_string(),
+            after: String::new(),
+            limit: 0,
+        }
+    }
+
+    // Peers should only talk to each other over mutual tls, using their own identity
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn mutual_tls() {
+        let ip = "127.0.0.1:4501";
+        let certs = init_certs("mutual_tls", &[1, 2]);
+        let doc = Arc::new(Mutex::new(Doc::new("doc".to_string(), 1)));
+        doc.lock()
+            .await
+            .insert_local(Content::Text("abc".to_string()), 0)
+            .await;
+        let new_txn = || {
+            let mut txn = SyncTransaction::new(
+                "doc".to_string(),
+                1,
+                doc.clone(),
+                Arc::new(Mutex::new(HashMap::new())),
+                ip.to_string(),
+            );
+            txn.set_tls(certs[&1].clone());
+            txn
+        };
+        let (txn_rpc, txn_bg) = (new_txn(), new_txn());
+        let (sender, receiver): (Sender<()>, Receiver<()>) = channel(1);
+        let (init_sender, mut init_receiver): (Sender<()>, Receiver<()>) = channel(1);
+        tokio::spawn(async move {
+            serve_rpc(txn_rpc, txn_bg, receiver, init_sender)
+                .await
+                .unwrap();
+        });
+        let _ = init_receiver.recv().await;
+
+        let tls = Some(certs[&2].clone());
+        let channel = endpoint(ip, &tls, 1).unwrap().connect().await.unwrap();
+        let mut client = TxnServiceClient::new(channel);
+        let res = client.get_remote_updates(pull(2)).await.unwrap();
+        assert!(res.into_inner().updates.contains("abc"));
+
+        // client 2 cannot pretend to be another client
+        let res = client.get_remote_updates(pull(3)).await;
+        assert_eq!(res.unwrap_err().code(), tonic::Code::PermissionDenied);
+
+        // the server must hold the certificate of the client it is expected to be
+        assert!(endpoint(ip, &tls, 2).unwrap().connect().await.is_err());
+        // plaintext connections are refused
+        let plain = endpoint(ip, &None, 1).unwrap().connect().await;
+        if let Ok(plain) = plain {
+            let res = TxnServiceClient::new(plain)
+                .get_remote_updates(pull(2))
+                .await;
+            assert!(res.is_err());
+        }
+
+        let _ = sender.send(()).await;
+    }
+}
+
+#[cfg(test)]
+mod signing_test {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use crate::crdt::block::{Block, BlockID, Content};
+    use crate::crdt::doc::{Doc, VectorClock};
+    use crate::crdt::signing::{Signatures, Signer};
+    use crate::crdt::sync_txn::SyncTransaction;
+    use crate::crdt::utils::{CRDTError, ClientID};
+    use crate::test_utils::{init_txn, insert, send};
+    use tokio::sync::Mutex;
+
+    // create a transaction for every client, each of them knows the public keys of the others
+    async fn init_txns(clients: &[ClientID]) -> Vec<SyncTransaction> {
+        let signers: Vec<Arc<Signer>> = clients
+            .iter()
+            .map(|_| Arc::new(Signer::generate()))
+            .collect();
+        let mut txns = vec![];
+        for client in clients {
+            let doc = Arc::new(Mutex::new(Doc::new("doc".to_string(), *client)));
+            let mut txn = SyncTransaction::new(
+                "doc".to_string(),
+                *client,
+                doc,
+                Arc::new(Mutex::new(HashMap::new())),
+                "".to_string(),
+            );
+            txn.set_signer(signers[txns.len()].clone()).await;
+            for (other, signer) in clients.iter().zip(&signers) {
+                txn.doc
+                    .lock()
+                    .await
+                    .signatures
+                    .add_public_key(*other, signer.public_key());
+            }
+            txns.push(txn);
+        }
+        txns
+    }
+
+    // Blocks split by other clients can still be verified against the original insertion
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn verify_split_blocks() {
+        let txns = init_txns(&[1, 2, 3]).await;
+        insert(&txns[0], "hello", 0).await;
+        send(&txns[0], &txns[1]).await.unwrap();
+        insert(&txns[1], "--", 2).await;
+        txns[1].doc.lock().await.delete_local(4, 1).await;
+
+        // client 3 only gets the split blocks from client 2
+        send(&txns[1], &txns[2]).await.unwrap();
+        let doc3 = txns[2].doc.lock().await;
+        assert_eq!(doc3.to_string().await, "he--lo".to_string());
+        assert!(doc3.signatures.quarantine.is_empty());
+    }
+
+    // Blocks that are not signed by the client in their id are never integrated
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn quarantine_forged_blocks() {
+        let txns = init_txns(&[1, 2]).await;
+        let forged = Block::new(
+            BlockID::new(1, 0),
+            None,
+            None,
+            Content::Text("evil".to_string()),
+        );
+
+        let rejected = Err(CRDTError::Conflict(vec![forged.id.clone()]));
+        // missing signature
+        let res = txns[1]
+            .update_remote(vec![forged.clone()], Signatures::default(), HashMap::new())
+            .await;
+        assert_eq!(res, rejected);
+        // signed by someone else
+        let mallory = Signer::generate();
+        let signatures = Signatures {
+            inserts: vec![mallory.sign(&forged.id, "evil")],
+            ..Default::default()
+        };
+        let res = txns[1]
+            .update_remote(vec![forged.clone()], signatures, HashMap::new())
+            .await;
+        assert_eq!(res, rejected);
+        // signature of client 1 over different content
+        insert(&txns[0], "good", 0).await;
+        let signatures = txns[0]
+            .signatures_for(&txns[0].compute_diff(VectorClock::new()).await)
+            .await;
+        let res = txns[1]
+            .update_remote(vec![forged], signatures, HashMap::new())
+            .await;
+        assert_eq!(res, rejected);
+
+        let doc2 = txns[1].doc.lock().await;
+        assert_eq!(doc2.to_string().await, "".to_string());
+        assert_eq!(doc2.signatures.quarantine.len(), 1);
+        drop(doc2);
+
+        // the real block of client 1 is accepted
+        send(&txns[0], &txns[1]).await.unwrap();
+        assert_eq!(
+            txns[1].doc.lock().await.to_string().await,
+            "good".to_string()
+        );
+    }
+
+    // Signed blocks moved to other origins are quarantined
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn quarantine_forged_origins() {
+        let txns = init_txns(&[1, 2]).await;
+        insert(&txns[0], "ab", 0).await;
+        send(&txns[0], &txns[1]).await.unwrap();
+        insert(&txns[0], "X", 2).await;
+
+        let clock = txns[1].doc.lock().await.vector_clock.clone();
+        let mut updates = txns[0].compute_diff(clock).await;
+        let signatures = txns[0].signatures_for(&updates).await;
+        assert_eq!(updates.len(), 1);
+        // "X" was signed after "b", moving it between "a" and "b" breaks the signature
+        updates[0].left_origin = Some(BlockID::new(1, 0));
+        updates[0].right_origin = Some(BlockID::new(1, 1));
+        let res = txns[1]
+            .update_remote(updates.clone(), signatures, HashMap::new())
+            .await;
+        assert_eq!(res, Err(CRDTError::Conflict(vec![updates[0].id.clone()])));
+        assert_eq!(txns[1].doc.lock().await.to_string().await, "ab".to_string());
+
+        send(&txns[0], &txns[1]).await.unwrap();
+        assert_eq!(
+            txns[1].doc.lock().await.to_string().await,
+            "abX".to_string()
+        );
+    }
+
+    // Tombstones are only integrated with a signature of the client they are attributed to
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn quarantine_forged_tombstones() {
+        let txns = init_txns(&[1, 2, 3]).await;
+        insert(&txns[0], "abc", 0).await;
+        send(&txns[0], &txns[1]).await.unwrap();
+        send(&txns[0], &txns[2]).await.unwrap();
+
+        // client 3 deletes the text in the name of client 1
+        let mut tombstone = txns[0].compute_diff(VectorClock::new()).await[0].clone();
+        let signatures = txns[0].signatures_for(&vec![tombstone.clone()]).await;
+        tombstone.delete_by(Some(1));
+        let res = txns[1]
+            .update_remote(vec![tombstone.clone()], signatures, HashMap::new())
+            .await;
+        assert_eq!(res, Err(CRDTError::Conflict(vec![tombstone.id])));
+        assert_eq!(
+            txns[1].doc.lock().await.to_string().await,
+            "abc".to_string()
+        );
+
+        // the signed deletion of client 3 is accepted, also when relayed by client 1
+        txns[2].doc.lock().await.delete_local(1, 1).await;
+        send(&txns[2], &txns[0]).await.unwrap();
+        send(&txns[0], &txns[1]).await.unwrap();
+        let doc2 = txns[1].doc.lock().await;
+        assert_eq!(doc2.to_string().await, "ac".to_string());
+        assert_eq!(doc2.signatures.quarantine.len(), 1);
+    }
+
+    // Signatures received before the key of their client are kept aside,
+    // so a forged one sent first doesn't take the place of the real one
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn signatures_before_keys() {
+        let signer = Arc::new(Signer::generate());
+        let mut txn1 = init_txn("doc", 1, "");
+        txn1.set_signer(signer.clone()).await;
+        let mut txn2 = init_txn("doc", 2, "");
+        txn2.set_signer(Arc::new(Signer::generate())).await;
+        insert(&txn1, "good", 0).await;
+        let updates = txn1.compute_diff(VectorClock::new()).await;
+
+        let mallory = Signer::generate();
+        let forged = Signatures {
+            inserts: vec![mallory.sign(&updates[0].id, "good")],
+            forks: vec![mallory.sign_fork(9, 7)],
+            ..Default::default()
+        };
+        let res = txn2
+            .update_remote(updates.clone(), forged, HashMap::new())
+            .await;
+        assert!(res.is_err());
+        let signatures = Signatures {
+            forks: vec![signer.sign_fork(1, 7)],
+            ..txn1.signatures_for(&updates).await
+        };
+        let res = txn2
+            .update_remote(updates.clone(), signatures, HashMap::new())
+            .await;
+        assert!(res.is_err());
+
+        txn2.doc
+            .lock()
+            .await
+            .signatures
+            .add_public_key(1, signer.public_key());
+        txn2.update_remote(updates, Signatures::default(), HashMap::new())
+            .await
+            .unwrap();
+        let doc2 = txn2.doc.lock().await;
+        assert_eq!(doc2.to_string().await, "good".to_string());
+        // the fork signed by client 1 isn't replaced by one whose parent key is unknown
+        assert_eq!(doc2.signatures.owner_of(7), 1);
+    }
+}
+
+#[cfg(test)]
+mod e2e_test {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use crate::crdt::auth::{Permissions, Role};
+    use crate::crdt::block::{BlockID, Content};
+    use crate::crdt::doc::{Doc, VectorClock};
+    use crate::crdt::e2e::{KeyRing, SEALED_FILLER};
+    use crate::crdt::relay::{serve_relay, RelayServer};
+    use crate::crdt::sync_txn::SyncTransaction;
+    use crate::crdt::utils::{serve_rpc, CRDTError, ClientID, Peer};
+    use crate::test_utils::{init_txn, insert, insert_doc};
+    use tokio::sync::mpsc::channel;
+    use tokio::sync::mpsc::{Receiver, Sender};
+    use tokio::sync::Mutex;
+
+    // The relay merges encrypted blocks without being able to read them
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn relay_stores_ciphertext() {
+        let relay_ip = "127.0.0.1:4601";
+        let relay = RelayServer::new(relay_ip.to_string());
+        let (sender, receiver): (Sender<()>, Receiver<()>) = channel(1);
+        let (init_sender, mut init_receiver): (Sender<()>, Receiver<()>) = channel(1);
+        let relay_handle = relay.clone();
+        tokio::spawn(async move {
+            serve_relay(relay_handle, receiver, init_sender)
+                .await
+                .unwrap();
+        });
+        let _ = init_receiver.recv().await;
+
+        let keys = KeyRing::generate();
+        let mut txn1 = init_txn("doc", 1, "");
+        let mut txn2 = init_txn("doc", 2, "");
+        for txn in [&mut txn1, &mut txn2] {
+            txn.set_relay(relay_ip.to_string());
+            txn.enable_encryption(Some(keys.clone())).await;
+        }
+
+        insert(&txn1, "secret", 0).await;
+        txn1.sync().await.unwrap();
+        txn2.sync().await.unwrap();
+        insert(&txn2, "--", 3).await;
+        txn2.sync().await.unwrap();
+        txn1.sync().await.unwrap();
+        assert_eq!(
+            txn1.doc.lock().await.to_string().await,
+            "sec--ret".to_string()
+        );
+        let stored = relay
+            .get_doc("doc")
+            .await
+            .doc
+            .lock()
+            .await
+            .to_string()
+            .await;
+        assert_eq!(stored.len(), 8);
+        assert!(!stored.contains("sec") && !stored.contains("ret"));
+
+        // content written after a rotation is only readable with the new version
+        let version = txn1.rotate_key().await;
+        insert(&txn1, "!", 8).await;
+        txn1.sync().await.unwrap();
+        txn2.sync().await.unwrap();
+        assert_eq!(
+            txn2.doc.lock().await.to_string().await,
+            "sec--ret".to_string()
+        );
+        let new_keys = txn1.doc.lock().await.encryption.keys.clone();
+        assert!(new_keys.keys.contains_key(&version));
+        txn2.enable_encryption(Some(new_keys)).await;
+        txn2.sync().await.unwrap();
+        assert_eq!(
+            txn2.doc.lock().await.to_string().await,
+            "sec--ret!".to_string()
+        );
+
+        let _ = sender.send(()).await;
+    }
+
+    // Blocks split from a sealed insertion are opened on their own,
+    // blocks whose ciphertext has been tampered with are left out
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn open_sealed_blocks() {
+        let keys = KeyRing::generate();
+        let mut docs = vec![];
+        for client in 1..=3 {
+            let mut doc = Doc::new("doc".to_string(), client);
+            doc.encryption.enable(Some(keys.clone()));
+            docs.push(doc);
+        }
+        insert_doc(&mut docs[0], "secret", 0).await;
+        let epochs = docs[0].encryption.epochs.clone();
+        let block = docs[0].compute_diff(&VectorClock::new()).await.remove(0);
+        assert_eq!(block.content.as_str(), SEALED_FILLER.to_string().repeat(6));
+        assert!(block.sealed.is_some());
+
+        // a relay split the block before sending it on
+        let mut left = block.clone();
+        left.content = Content::text(&block.content.as_str()[..2]);
+        let mut right = block.clone();
+        right.id = BlockID::new(1, 2);
+        right.left_origin = Some(BlockID::new(1, 1));
+        right.content = Content::text(&block.content.as_str()[..4]);
+        docs[1].encryption.merge_epochs(epochs.clone());
+        docs[1].insert_remote(vec![right, left]).await;
+        assert_eq!(docs[1].to_string().await, "secret".to_string());
+
+        let mut forged = block;
+        forged.sealed.as_mut().unwrap().ciphertext[0] ^= 1;
+        docs[2].encryption.merge_epochs(epochs);
+        docs[2].insert_remote(vec![forged]).await;
+        assert_eq!(docs[2].to_string().await, "".to_string());
+    }
+
+    // Live blocks that cannot be sealed are never sent, and filler is never taken for text
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn unsealable_blocks() {
+        let keys = KeyRing::generate();
+        let mut doc = Doc::new("doc".to_string(), 1);
+        insert_doc(&mut doc, "plain", 0).await;
+        doc.encryption.enable(Some(keys.clone()));
+        insert_doc(&mut doc, "secret", 5).await;
+        let diff = doc.compute_diff(&VectorClock::new()).await;
+        assert!(diff.iter().all(|block| block.sealed.is_some()));
+        assert!(!serde_json::to_string(&diff).unwrap().contains("plain"));
+
+        let mut peer = Doc::new("doc".to_string(), 2);
+        peer.encryption.enable(Some(keys));
+        peer.encryption.merge_epochs(doc.encryption.epochs.clone());
+        let mut stripped = diff[0].clone();
+        stripped.sealed = None;
+        let mut elided = diff[0].clone();
+        elided.sealed = None;
+        elided.elided = true;
+        peer.insert_remote(vec![stripped, elided]).await;
+        assert_eq!(peer.to_string().await, "".to_string());
+    }
+
+    // A new peer gets the doc key from a peer that has it, if it is allowed to read the doc
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn share_key() {
+        let ip = "127.0.0.1:4602";
+        let mut permissions = Permissions::default();
+        for client in 1..=3 as ClientID {
+            permissions
+                .tokens
+                .insert(format!("token{}", client), client);
+        }
+        let mut roles = HashMap::new();
+        roles.insert(1, Role::Owner);
+        roles.insert(2, Role::Viewer);
+        permissions.docs.insert("doc".to_string(), roles);
+        let permissions = Arc::new(permissions);
+
+        let doc = Arc::new(Mutex::new(Doc::new("doc".to_string(), 1)));
+        let new_txn = || {
+            let mut txn = SyncTransaction::new(
+                "doc".to_string(),
+                1,
+                doc.clone(),
+                Arc::new(Mutex::new(HashMap::new())),
+                ip.to_string(),
+            );
+            txn.set_permissions(permissions.clone());
+            txn
+        };
+        let (txn_rpc, txn_bg) = (new_txn(), new_txn());
+        txn_rpc.enable_encryption(Some(KeyRing::generate())).await;
+        let (sender, receiver): (Sender<()>, Receiver<()>) = channel(1);
+        let (init_sender, mut init_receiver): (Sender<()>, Receiver<()>) = channel(1);
+        tokio::spawn(async move {
+            serve_rpc(txn_rpc, txn_bg, receiver, init_sender)
+                .await
+                .unwrap();
+        });
+        let _ = init_receiver.recv().await;
+
+        let peer = Peer {
+            client_id: 1,
+            ip_addr: ip.to_string(),
+            public_key: None,
+            display: None,
+        };
+        let mut viewer = init_txn("doc", 2, "");
+        let mut stranger = init_txn("doc", 3, "");
+        for (txn, token) in [(&mut viewer, "token2"), (&mut stranger, "token3")] {
+            txn.set_token(token.to_string());
+            txn.enable_encryption(None).await;
+            txn.doc.lock().await.peers.push(peer.clone());
+        }
+        viewer.fetch_keys().await.unwrap();
+        assert!(matches!(
+            stranger.fetch_keys().await,
+            Err(CRDTError::PermissionDenied(_))
+        ));
+        let expected = doc.lock().await.encryption.keys.clone();
+        assert_eq!(viewer.doc.lock().await.encryption.keys, expected);
+        assert!(!stranger.doc.lock().await.encryption.has_key());
+
+        let _ = sender.send(()).await;
+    }
+}
+
+#[cfg(test)]
+mod error_test {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use crate::crdt::block::{Block, BlockID, Content};
+    use crate::crdt::doc::Doc;
+    use crate::crdt::signing::Signatures;
+    use crate::crdt::sync_txn::SyncTransaction;
+    use crate::crdt::utils::{CRDTError, CRDTResult};
+    use tokio::sync::Mutex;
+
+    // Blocks whose origins are unknown are buffered and reported
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn missing_dependency() {
+        let doc = Arc::new(Mutex::new(Doc::new("doc".to_string(), 1)));
+        let txn = SyncTransaction::new(
+            "doc".to_string(),
+            1,
+            doc,
+            Arc::new(Mutex::new(HashMap::new())),
+            "".to_string(),
+        );
+        let block = |clock, content: &str, left_origin| {
+            Block::new(
+                BlockID::new(2, clock),
+                left_origin,
+                None,
+                Content::Text(content.to_string()),
+            )
+        };
+        let orphan = block(1, "b", Some(BlockID::new(2, 0)));
+
+        let res = txn
+            .update_remote(vec![orphan.clone()], Signatures::default(), HashMap::new())
+            .await;
+        assert_eq!(res, Err(CRDTError::MissingDependency(vec![orphan.id])));
+        assert_eq!(txn.doc.lock().await.to_string().await, "".to_string());
+
+        // the buffered block is integrated once its origin arrives
+        let res = txn
+            .update_remote(
+                vec![block(0, "a", None)],
+                Signatures::default(),
+                HashMap::new(),
+            )
+            .await;
+        assert_eq!(res, Ok(()));
+        assert_eq!(txn.doc.lock().await.to_string().await, "ab".to_string());
+    }
+
+    // Rpc failures keep telling authorization apart from transport problems
+    #[test]
+    fn status_conversion() {
+        let denied: CRDTResult<()> = Err(tonic::Status::permission_denied("no role").into());
+        assert_eq!(
+            denied,
+            Err(CRDTError::PermissionDenied("no role".to_string()))
+        );
+        let unavailable: CRDTError = tonic::Status::unavailable("down").into();
+        assert!(matches!(unavailable, CRDTError::Transport(_)));
+        let invalid: CRDTError = serde_json::from_str::<u32>("x").unwrap_err().into();
+        assert!(matches!(invalid, CRDTError::Serialization(_)));
+    }
+}
+
+#[cfg(test)]
+mod metrics_test {
+    use std::collections::HashMap;
+
+    use crate::crdt::block::Content;
+    use crate::crdt::block_store::StoreStats;
+    use crate::crdt::doc::Doc;
+    use crate::crdt::metrics::serve_metrics;
+    use crate::crdt::signing::Signatures;
+    use crate::test_utils::{init_txn, insert_doc};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio::sync::mpsc::channel;
+    use tokio::sync::mpsc::{Receiver, Sender};
+
+    async fn scrape(ip: &str) -> String {
+        let mut stream = TcpStream::connect(ip).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp).await.unwrap();
+        resp
+    }
+
+    // Doc gauges are exported once remote updates are integrated
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn export_doc_metrics() {
+        let ip = "127.0.0.1:4701";
+        let (sender, receiver): (Sender<()>, Receiver<()>) = channel(1);
+        let (init_sender, mut init_receiver): (Sender<()>, Receiver<()>) = channel(1);
+        tokio::spawn(async move {
+            serve_metrics(ip.to_string(), receiver, init_sender)
+                .await
+                .unwrap();
+        });
+        let _ = init_receiver.recv().await;
+
+        let (txn1, txn2) = (
+            init_txn("metrics_doc", 1, ""),
+            init_txn("metrics_doc", 2, ""),
+        );
+        txn1.doc
+            .lock()
+            .await
+            .insert_local(Content::Text("hello".to_string()), 0)
+            .await;
+        txn1.doc.lock().await.delete_local(0, 2).await;
+        let updates = txn1
+            .compute_diff(txn2.doc.lock().await.vector_clock.clone())
+            .await;
+        txn2.update_remote(updates, Signatures::default(), HashMap::new())
+            .await
+            .unwrap();
+
+        let resp = scrape(ip).await;
+        assert!(resp.starts_with("HTTP/1.1 200"));
+        assert!(resp.contains("crdt_doc_length{doc=\"metrics_doc\"} 3"));
+        assert!(resp.contains("crdt_blocks{doc=\"metrics_doc\"} 2"));
+        assert!(resp.contains("crdt_tombstones{doc=\"metrics_doc\"} 1"));
+        assert!(resp.contains("crdt_pending_updates{doc=\"metrics_doc\"} 0"));
+        assert!(resp.contains("crdt_block_integration_seconds_count{doc=\"metrics_doc\"}"));
+
+        let _ = sender.send(()).await;
+    }
+
+    // The counters of a store match its blocks through splits, deletions and compaction
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn store_stats_follow_changes() {
+        async fn walk(doc: &Doc) -> StoreStats {
+            let store = doc.block_store.lock().await;
+            let mut stats = StoreStats::default();
+            for block in &store.total_store.list {
+                let block = block.lock().await;
+                stats.blocks += 1;
+                if block.is_deleted {
+                    stats.tombstones += 1;
+                } else {
+                    stats.length += block.content.len() as i64;
+                }
+            }
+            stats
+        }
+
+        let mut doc = Doc::new("stats_doc".to_string(), 1);
+        insert_doc(&mut doc, "hello", 0).await;
+        insert_doc(&mut doc, "world", 2).await;
+        doc.delete_local(1, 6).await;
+        doc.suggest_insert(Content::Text("xyz".to_string()), 0)
+            .await;
+        let id = doc.suggestions().await[0].id.clone();
+        doc.reject_suggestion(&id, 3).await;
+        let stats = doc.block_store.lock().await.stats;
+        assert_eq!(stats, walk(&doc).await);
+
+        doc.block_store.lock().await.compact().await;
+        let stats = doc.block_store.lock().await.stats;
+        assert_eq!(stats, walk(&doc).await);
+        assert_eq!(stats.length, doc.to_string().await.len() as i64);
+    }
+}
+
+#[cfg(test)]
+mod admin_test {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use crate::crdt::admin::{PeerState, PendingUpdate};
+    use crate::crdt::auth::{with_token, Permissions, Role};
+    use crate::crdt::block::{Block, BlockID, Content};
+    use crate::crdt::doc::VectorClock;
+    use crate::crdt::relay::{serve_relay, RelayServer};
+    use crate::crdt::signing::Signatures;
+    use crate::crdt::txn_rpc::admin_service_client::AdminServiceClient;
+    use crate::crdt::txn_rpc::admin_service_server::AdminService;
+    use crate::crdt::txn_rpc::{self, AdminRequest};
+    use crate::crdt::utils::CRDTError;
+    use crate::test_utils::{init_txn, insert, peer};
+    use tokio::sync::mpsc::channel;
+    use tokio::sync::mpsc::{Receiver, Sender};
+
+    fn doc_request() -> tonic::Request<AdminRequest> {
+        tonic::Request::new(AdminRequest {
+            doc_name: "admin_doc".to_string(),
+        })
+    }
+
+    // Owners of a doc hosted on the relay can inspect its replica, nobody else can
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn relay_introspection() {
+        let relay_ip = "127.0.0.1:4801";
+        let mut permissions = Permissions::default();
+        permissions.tokens.insert("token1".to_string(), 1);
+        permissions.tokens.insert("token2".to_string(), 2);
+        let mut roles = HashMap::new();
+        roles.insert(1, Role::Owner);
+        roles.insert(2, Role::Editor);
+        permissions.docs.insert("admin_doc".to_string(), roles);
+        let mut relay = RelayServer::new(relay_ip.to_string());
+        relay.auth = Some(Arc::new(permissions));
+        let (sender, receiver): (Sender<()>, Receiver<()>) = channel(1);
+        let (init_sender, mut init_receiver): (Sender<()>, Receiver<()>) = channel(1);
+        tokio::spawn(async move {
+            serve_relay(relay, receiver, init_sender).await.unwrap();
+        });
+        let _ = init_receiver.recv().await;
+
+        let mut txn = init_txn("admin_doc", 2, "");
+        txn.set_token("token2".to_string());
+        txn.set_relay(relay_ip.to_string());
+        txn.doc
+            .lock()
+            .await
+            .insert_local(Content::Text("hello".to_string()), 0)
+            .await;
+        txn.sync().await.unwrap();
+
+        let mut client = AdminServiceClient::connect(format!("http://{}", relay_ip))
+            .await
+            .unwrap();
+        let owner = Some("token1".to_string());
+        let request = |doc_name: &str, token: &Option<String>| {
+            with_token(
+                AdminRequest {
+                    doc_name: doc_name.to_string(),
+                },
+                token,
+            )
+        };
+        let resp = client
+            .get_vector_clock(request("admin_doc", &owner))
+            .await
+            .unwrap()
+            .into_inner();
+        let clock: VectorClock = serde_json::from_str(&resp.result).unwrap();
+        assert_eq!(clock.get(2), 5);
+
+        let resp = client
+            .dump_blocks(with_token(
+                txn_rpc::BlockRangeRequest {
+                    doc_name: "admin_doc".to_string(),
+                    start: 0,
+                    end: 0,
+                },
+                &owner,
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        let blocks: Vec<Block> = serde_json::from_str(&resp.result).unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].content.as_str(), "hello");
+
+        // editors cannot inspect the doc, docs that are not hosted are not created
+        let denied = client
+            .get_vector_clock(request("admin_doc", &Some("token2".to_string())))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            CRDTError::from(denied),
+            CRDTError::PermissionDenied(
+                "2 needs to be at least Owner on \"admin_doc\"".to_string()
+            )
+        );
+        let missing = client
+            .list_pending(request("other_doc", &owner))
+            .await
+            .unwrap_err();
+        assert_eq!(missing.code(), tonic::Code::NotFound);
+
+        let _ = sender.send(()).await;
+    }
+
+    // Pending updates are listed with the origins they wait for,
+    // compaction merges split blocks without changing the doc
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn pending_and_compact() {
+        let txn = init_txn("admin_doc", 1, "");
+        txn.doc.lock().await.peers.push(peer(2, 4802));
+        txn.doc
+            .lock()
+            .await
+            .insert_local(Content::Text("hello".to_string()), 0)
+            .await;
+        let orphan = Block::new(
+            BlockID::new(2, 3),
+            Some(BlockID::new(2, 2)),
+            Some(BlockID::new(1, 0)),
+            Content::Text("x".to_string()),
+        );
+        assert_eq!(
+            txn.update_remote(vec![orphan.clone()], Signatures::default(), HashMap::new())
+                .await,
+            Err(CRDTError::MissingDependency(vec![orphan.id.clone()]))
+        );
+
+        let resp = AdminService::list_pending(&txn, doc_request())
+            .await
+            .unwrap()
+            .into_inner();
+        let pending: Vec<PendingUpdate> = serde_json::from_str(&resp.result).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].block.id, orphan.id);
+        assert_eq!(pending[0].missing, vec![BlockID::new(2, 2)]);
+
+        let resp = AdminService::list_peers(&txn, doc_request())
+            .await
+            .unwrap()
+            .into_inner();
+        let peers: Vec<PeerState> = serde_json::from_str(&resp.result).unwrap();
+        assert_eq!(peers.len(), 1);
+        assert!(!peers[0].connected);
+
+        // split "hello" as a remote insertion at "he|llo" would
+        txn.doc
+            .lock()
+            .await
+            .block_store
+            .lock()
+            .await
+            .split(BlockID::new(1, 0), 2)
+            .await;
+        assert_eq!(txn.blocks_in(0, usize::MAX).await.len(), 2);
+        let resp = AdminService::compact(&txn, doc_request())
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(resp.result, "1".to_string());
+        let blocks = txn.blocks_in(0, usize::MAX).await;
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].content.as_str(), "hello");
+        assert_eq!(txn.doc.lock().await.to_string().await, "hello".to_string());
+    }
+
+    // Tombstones are only collected once the relay has reported seeing their deletion,
+    // they keep their place in the doc without their content
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn collect_tombstones() {
+        let relay_ip = "127.0.0.1:4803";
+        let relay = RelayServer::new(relay_ip.to_string());
+        let (sender, receiver): (Sender<()>, Receiver<()>) = channel(1);
+        let (init_sender, mut init_receiver): (Sender<()>, Receiver<()>) = channel(1);
+        tokio::spawn(async move {
+            serve_relay(relay, receiver, init_sender).await.unwrap();
+        });
+        let _ = init_receiver.recv().await;
+
+        let mut txn1 = init_txn("admin_doc", 1, "");
+        let mut txn2 = init_txn("admin_doc", 2, "");
+        for txn in [&mut txn1, &mut txn2] {
+            txn.set_relay(relay_ip.to_string());
+        }
+        assert_eq!(txn1.collect_tombstones().await, 0);
+        insert(&txn1, "hello world", 0).await;
+        txn1.sync().await.unwrap();
+        txn1.doc.lock().await.delete_local(0, 6).await;
+        // the relay reported its clock before the deletion was pushed
+        txn1.sync().await.unwrap();
+        assert_eq!(txn1.collect_tombstones().await, 0);
+
+        txn1.sync().await.unwrap();
+        let resp = AdminService::collect_tombstones(&txn1, doc_request())
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(resp.result, "1".to_string());
+        let blocks = txn1.blocks_in(0, usize::MAX).await;
+        assert_eq!(blocks.len(), 2);
+        assert!(blocks[0].elided);
+        assert_eq!(blocks[0].content.as_str(), "\0".repeat(6));
+        assert_eq!(txn1.doc.lock().await.to_string().await, "world".to_string());
+
+        // later insertions next to the tombstone still integrate everywhere
+        insert(&txn1, "big ", 0).await;
+        txn1.sync().await.unwrap();
+        txn2.sync().await.unwrap();
+        assert_eq!(
+            txn2.doc.lock().await.to_string().await,
+            "big world".to_string()
+        );
+
+        let _ = sender.send(()).await;
+    }
+}
+
+#[cfg(test)]
+mod anti_entropy_test {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use crate::crdt::anti_entropy::{AntiEntropyConfig, PeerSelection};
+    use crate::crdt::block::BlockID;
+    use crate::crdt::signing::Signatures;
+    use crate::crdt::utils::{serve_rpc, ClientID, Peer};
+    use crate::test_utils::{init_txn, insert, peer};
+    use tokio::sync::mpsc::channel;
+    use tokio::sync::mpsc::{Receiver, Sender};
+
+    // A large diff is split in pages, each page resumes after the last block id of the previous one,
+    // so edits made between pages don't make the puller skip blocks
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn diff_pages() {
+        let (txn1, txn2) = (init_txn("ae_doc", 1, ""), init_txn("ae_doc", 2, ""));
+        insert(&txn1, "a", 0).await;
+        insert(&txn1, "c", 1).await;
+        insert(&txn1, "b", 1).await;
+
+        let clock = txn2.doc.lock().await.vector_clock.clone();
+        let (page, mut next) = txn1.compute_diff_page(clock, None, 2).await;
+        let content: Vec<&str> = page.iter().map(|b| b.content.as_str()).collect();
+        assert_eq!(content, vec!["a", "c"]);
+        assert_eq!(next, Some(BlockID::new(1, 1)));
+        txn2.update_remote(page, Signatures::default(), HashMap::new())
+            .await
+            .unwrap();
+
+        // shifts every spatial position by one
+        insert(&txn1, "Z", 0).await;
+        let mut pages = 1;
+        while next.is_some() {
+            let clock = txn2.doc.lock().await.vector_clock.clone();
+            let (page, after) = txn1.compute_diff_page(clock, next, 2).await;
+            let _ = txn2
+                .update_remote(page, Signatures::default(), HashMap::new())
+                .await;
+            next = after;
+            pages += 1;
+        }
+        assert!(pages <= 3);
+        assert_eq!(txn2.doc.lock().await.to_string().await, "Zabc".to_string());
+    }
+
+    // Replicas converge without anyone calling sync,
+    // an unreachable peer doesn't stop the others from syncing
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn background_convergence() {
+        let peers: Vec<Peer> = [4901, 4902, 4903, 4909]
+            .iter()
+            .enumerate()
+            .map(|(i, port)| peer(i as ClientID + 1, *port))
+            .collect();
+        let mut txns = vec![];
+        let mut senders = vec![];
+        for (i, peer) in peers.iter().take(3).enumerate() {
+            let mut txn_rpc = init_txn("ae_doc", peer.client_id, &peer.ip_addr);
+            let txn_bg = init_txn("ae_doc", peer.client_id, &peer.ip_addr);
+            txn_rpc.doc.lock().await.peers = peers.clone();
+            txn_rpc.set_anti_entropy(AntiEntropyConfig {
+                interval: Duration::from_millis(20),
+                fanout: 1,
+                selection: if i == 0 {
+                    PeerSelection::RoundRobin
+                } else {
+                    PeerSelection::Random
+                },
+                page_size: 1,
+                ..AntiEntropyConfig::default()
+            });
+            txns.push(txn_rpc.clone());
+            let (sender, receiver): (Sender<()>, Receiver<()>) = channel(1);
+            let (init_sender, mut init_receiver): (Sender<()>, Receiver<()>) = channel(1);
+            tokio::spawn(async move {
+                serve_rpc(txn_rpc, txn_bg, receiver, init_sender)
+                    .await
+                    .unwrap();
+            });
+            let _ = init_receiver.recv().await;
+            senders.push(sender);
+        }
+
+        insert(&txns[0], "hello", 0).await;
+        insert(&txns[1], "world", 0).await;
+        insert(&txns[0], "!", 5).await;
+        insert(&txns[2], " ", 0).await;
+
+        let mut converged = false;
+        for _ in 0..200 {
+            tokio::time::sleep(Duration::from_millis(25)).await;
+            let mut contents = vec![];
+            for txn in &txns {
+                contents.push(txn.doc.lock().await.to_string().await);
+            }
+            if contents[0].len() == 12 && contents.iter().all(|c| *c == contents[0]) {
+                converged = true;
+                break;
+            }
+        }
+        assert!(converged);
+
+        for sender in senders {
+            let _ = sender.send(()).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod gossip_test {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::crdt::auth::{with_token, Permissions, Role};
+    use crate::crdt::block::{Block, BlockID, ChangeID, Content};
+    use crate::crdt::doc::{Doc, VectorClock};
+    use crate::crdt::gossip::{Gossip, GossipConfig};
+    use crate::crdt::sync_txn::SyncTransaction;
+    use crate::crdt::txn_rpc::txn_service_client::TxnServiceClient;
+    use crate::crdt::txn_rpc::GossipRequest;
+    use crate::crdt::utils::{serve_rpc, ClientID, Peer};
+    use crate::test_utils::{insert, peer};
+    use tokio::sync::mpsc::channel;
+    use tokio::sync::mpsc::{Receiver, Sender};
+    use tokio::sync::Mutex;
+
+    // Serve peers connected as a chain, every peer only knows its neighbours,
+    // with permissions every peer authenticates with the token "token<client>"
+    async fn serve_chain(
+        ports: &[u32],
+        config: GossipConfig,
+        permissions: Option<Permissions>,
+    ) -> (Vec<SyncTransaction>, Vec<Sender<()>>) {
+        let peers: Vec<Peer> = ports
+            .iter()
+            .enumerate()
+            .map(|(i, port)| peer(i as ClientID + 1, *port))
+            .collect();
+        let (mut txns, mut senders) = (vec![], vec![]);
+        for (i, local) in peers.iter().enumerate() {
+            let doc = Arc::new(Mutex::new(Doc::new(
+                "gossip_doc".to_string(),
+                local.client_id,
+            )));
+            doc.lock().await.peers = peers
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j + 1 == i || *j == i + 1)
+                .map(|(_, peer)| peer.clone())
+                .collect();
+            let mut txn_rpc = SyncTransaction::new(
+                "gossip_doc".to_string(),
+                local.client_id,
+                doc,
+                Arc::new(Mutex::new(HashMap::new())),
+                local.ip_addr.clone(),
+            );
+            txn_rpc.set_gossip(Arc::new(Gossip::new(config.clone())));
+            if let Some(permissions) = &permissions {
+                txn_rpc.set_permissions(Arc::new(permissions.clone()));
+                txn_rpc.set_token(format!("token{}", local.client_id));
+            }
+            let txn_bg = txn_rpc.clone();
+            txns.push(txn_rpc.clone());
+            let (sender, receiver): (Sender<()>, Receiver<()>) = channel(1);
+            let (init_sender, mut init_receiver): (Sender<()>, Receiver<()>) = channel(1);
+            tokio::spawn(async move {
+                serve_rpc(txn_rpc, txn_bg, receiver, init_sender)
+                    .await
+                    .unwrap();
+            });
+            let _ = init_receiver.recv().await;
+            senders.push(sender);
+        }
+        (txns, senders)
+    }
+
+    async fn wait_for(txns: &[SyncTransaction], expected: &str) -> bool {
+        for _ in 0..200 {
+            let mut done = true;
+            for txn in txns {
+                done &= txn.doc.lock().await.to_string().await == expected;
+            }
+            if done {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(25)).await;
+        }
+        false
+    }
+
+    // An update is disseminated once, its deletion is a new update,
+    // updates covered by the vector clock are forgotten
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn fresh_updates() {
+        let gossip = Gossip::new(GossipConfig::default());
+        let mut clock = VectorClock::new();
+        let mut block = Block::new(
+            BlockID::new(1, 0),
+            None,
+            None,
+            Content::Text("a".to_string()),
+        );
+        assert_eq!(gossip.fresh(vec![block.clone()], &clock).await.len(), 1);
+        assert_eq!(gossip.fresh(vec![block.clone()], &clock).await.len(), 0);
+        clock.advance(1, 1);
+        assert_eq!(gossip.fresh(vec![block.clone()], &clock).await.len(), 0);
+        assert_eq!(gossip.remembered().await, 0);
+        block.delete();
+        block.stamp(ChangeID::new(2, 0));
+        assert_eq!(
+            gossip
+                .fresh(vec![block.clone(), block.clone()], &clock)
+                .await
+                .len(),
+            1
+        );
+        clock.change_map.insert(2, 1);
+        assert_eq!(gossip.fresh(vec![block], &clock).await.len(), 0);
+        assert_eq!(gossip.remembered().await, 0);
+    }
+
+    // Updates are relayed from peer to peer without anyone calling sync
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn forward_along_chain() {
+        let config = GossipConfig {
+            fanout: 1,
+            hops: 3,
+            interval: Duration::from_millis(20),
+            // digests would also repair the chain
+            digest_interval: Duration::from_secs(3600),
+        };
+        let (txns, senders) = serve_chain(&[5001, 5002, 5003, 5004], config, None).await;
+        txns[0]
+            .doc
+            .lock()
+            .await
+            .insert_local(Content::Text("hello".to_string()), 0)
+            .await;
+        assert!(wait_for(&txns, "hello").await);
+
+        txns[3].doc.lock().await.delete_local(0, 1).await;
+        assert!(wait_for(&txns, "ello").await);
+
+        for sender in senders {
+            let _ = sender.send(()).await;
+        }
+    }
+
+    // Updates that run out of hops are repaired by digest exchanges
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn digest_repair() {
+        let config = GossipConfig {
+            fanout: 1,
+            hops: 1,
+            interval: Duration::from_millis(20),
+            digest_interval: Duration::from_millis(30),
+        };
+        let (txns, senders) = serve_chain(&[5011, 5012, 5013, 5014], config, None).await;
+        txns[0]
+            .doc
+            .lock()
+            .await
+            .insert_local(Content::Text("hello".to_string()), 0)
+            .await;
+        assert!(wait_for(&txns, "hello").await);
+
+        for sender in senders {
+            let _ = sender.send(()).await;
+        }
+    }
+
+    // With authentication, digests repair updates written by other clients,
+    // and only editors can gossip
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn authenticated_gossip() {
+        let mut permissions = Permissions::default();
+        let mut roles = HashMap::new();
+        for client in 1..=4 {
+            permissions
+                .tokens
+                .insert(format!("token{}", client), client);
+            roles.insert(client, Role::Editor);
+        }
+        roles.insert(4, Role::Viewer);
+        permissions.docs.insert("gossip_doc".to_string(), roles);
+        // updates only travel through the digests exchanged below
+        let config = GossipConfig {
+            fanout: 1,
+            hops: 1,
+            interval: Duration::from_secs(3600),
+            digest_interval: Duration::from_secs(3600),
+        };
+        let (txns, senders) = serve_chain(&[5021, 5022, 5023], config, Some(permissions)).await;
+        insert(&txns[2], "hello", 0).await;
+        txns[1].doc.lock().await.peers = vec![peer(3, 5023)];
+        txns[1].exchange_digest().await.unwrap();
+        // the blocks of 3 are sent to 1 by 2
+        txns[1].doc.lock().await.peers = vec![peer(1, 5021)];
+        txns[1].exchange_digest().await.unwrap();
+        assert_eq!(txns[0].doc.lock().await.to_string().await, "hello");
+
+        let mut client = TxnServiceClient::connect("http://127.0.0.1:5021")
+            .await
+            .unwrap();
+        let req = GossipRequest {
+            doc_name: "gossip_doc".to_string(),
+            client_id: 4,
+            updates: "[]".to_string(),
+            hops: 1,
+            ..Default::default()
+        };
+        let denied = client
+            .gossip_updates(with_token(req, &Some("token4".to_string())))
+            .await
+            .unwrap_err();
+        assert_eq!(denied.code(), tonic::Code::PermissionDenied);
+
+        for sender in senders {
+            let _ = sender.send(()).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod conn_test {
+    use std::time::Duration;
+
+    use crate::crdt::block::Content;
+    use crate::crdt::conn::{ConnConfig, Connection};
+    use crate::crdt::sync_txn::SyncTransaction;
+    use crate::crdt::txn_rpc::txn_service_server::TxnService;
+    use crate::crdt::txn_rpc::RegisterRequest;
+    use crate::crdt::utils::{serve_rpc, CRDTError, Peer};
+    use crate::test_utils::{init_txn, insert, peer};
+    use tokio::sync::mpsc::channel;
+    use tokio::sync::mpsc::{Receiver, Sender};
+
+    async fn init_txn_w_peers(local: &Peer, peers: Vec<Peer>) -> SyncTransaction {
+        let mut txn = init_txn("conn_doc", local.client_id, &local.ip_addr);
+        txn.doc.lock().await.peers = peers;
+        txn.set_conn_config(ConnConfig {
+            connect_timeout: Duration::from_millis(500),
+            min_backoff: Duration::from_millis(100),
+            // checked by the tests themselves
+            health_interval: Duration::ZERO,
+            ..ConnConfig::default()
+        });
+        txn
+    }
+
+    async fn serve(txn: SyncTransaction) -> Sender<()> {
+        let txn_bg = txn.clone();
+        let (sender, receiver): (Sender<()>, Receiver<()>) = channel(1);
+        let (init_sender, mut init_receiver): (Sender<()>, Receiver<()>) = channel(1);
+        tokio::spawn(async move {
+            serve_rpc(txn, txn_bg, receiver, init_sender).await.unwrap();
+        });
+        let _ = init_receiver.recv().await;
+        sender
+    }
+
+    // The backoff doubles with every failure, up to the maximum
+    #[test]
+    fn backoff() {
+        let config = ConnConfig {
+            min_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(300),
+            ..ConnConfig::default()
+        };
+        let mut conn = Connection::default();
+        assert_eq!(conn.failed(&config), Duration::from_millis(100));
+        assert_eq!(conn.failed(&config), Duration::from_millis(200));
+        assert_eq!(conn.failed(&config), Duration::from_millis(300));
+        assert_eq!(conn.failures, 3);
+        assert!(!conn.is_connected());
+    }
+
+    // An unreachable peer is not reconnected before its backoff is over
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn reconnect_after_backoff() {
+        let down = peer(2, 5109);
+        let txn = init_txn_w_peers(&peer(1, 5101), vec![down.clone()]).await;
+        assert!(txn.sync_with(2).await.is_err());
+        // retried too early, the connection isn't even attempted
+        match txn.sync_with(2).await {
+            Err(CRDTError::Transport(msg)) => assert!(msg.contains("is down")),
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert_eq!(txn.channels.lock().await[&2].failures, 1);
+
+        // the peer comes up, it is reached once the backoff is over
+        let _sender = serve(init_txn_w_peers(&down, vec![]).await).await;
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        txn.sync_with(2).await.unwrap();
+        let channels = txn.channels.lock().await;
+        assert!(channels[&2].is_connected());
+        assert_eq!(channels[&2].failures, 0);
+    }
+
+    // A peer that doesn't host the doc fails the pull, only a relay is assumed to have seen nothing
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn peer_without_doc() {
+        let remote = peer(2, 5126);
+        let txn = init_txn_w_peers(&peer(1, 5125), vec![remote.clone()]).await;
+        let mut other = init_txn("other_doc", remote.client_id, &remote.ip_addr);
+        other.set_conn_config(txn.conn.clone());
+        let sender = serve(other).await;
+        // a doc with content pulls the diff instead of streaming the history
+        insert(&txn, "a", 0).await;
+        match txn.sync_with(2).await {
+            Err(CRDTError::Transport(msg)) => assert!(msg.contains("NotFound")),
+            res => panic!("unexpected result {:?}", res),
+        }
+        let _ = sender.send(()).await;
+    }
+
+    // A peer that stopped answering fails its health check and its channel is dropped
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn health_check() {
+        let remote = peer(2, 5112);
+        let txn = init_txn_w_peers(&peer(1, 5111), vec![remote.clone()]).await;
+        let sender = serve(init_txn_w_peers(&remote, vec![]).await).await;
+        txn.sync_with(2).await.unwrap();
+        assert_eq!(txn.check_health().await, 1);
+
+        sender.send(()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(txn.check_health().await, 0);
+        let channels = txn.channels.lock().await;
+        assert!(!channels[&2].is_connected());
+        assert_eq!(channels[&2].failures, 1);
+    }
+
+    // The peer list replaces the known peers, the channels of peers that left are closed
+    // and a peer that moved is reached at its new address
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn peer_list_changes() {
+        let local = peer(1, 5121);
+        let (moving, leaving) = (peer(2, 5122), peer(3, 5123));
+        let txn = init_txn_w_peers(&local, vec![moving.clone(), leaving.clone()]).await;
+        let _senders = [
+            serve(init_txn_w_peers(&moving, vec![]).await).await,
+            serve(init_txn_w_peers(&leaving, vec![]).await).await,
+        ];
+        txn.sync_with(2).await.unwrap();
+        txn.sync_with(3).await.unwrap();
+        assert_eq!(txn.channels.lock().await.len(), 2);
+
+        // peer 2 restarted elsewhere with some content, peer 3 left
+        let moved = peer(2, 5124);
+        let moved_txn = init_txn_w_peers(&moved, vec![]).await;
+        moved_txn
+            .doc
+            .lock()
+            .await
+            .insert_local(Content::Text("moved".to_string()), 0)
+            .await;
+        let _moved_sender = serve(moved_txn).await;
+        let peer_list = serde_json::to_string(&vec![local, moved.clone()]).unwrap();
+        TxnService::sync_peer_list(&txn, tonic::Request::new(RegisterRequest { peer_list }))
+            .await
+            .unwrap();
+        assert_eq!(txn.doc.lock().await.peers, vec![peer(1, 5121), moved]);
+        assert!(!txn.channels.lock().await.contains_key(&3));
+
+        txn.sync_with(2).await.unwrap();
+        assert_eq!(txn.doc.lock().await.to_string().await, "moved");
+        assert_eq!(txn.channels.lock().await[&2].ip_addr, "127.0.0.1:5124");
+    }
+}
+
+#[cfg(test)]
+mod stream_test {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use crate::crdt::block::{Block, BlockID, Content};
+    use crate::crdt::doc::VectorClock;
+    use crate::crdt::signing::{Signatures, Signer};
+    use crate::crdt::stream::{causal_order, chunks, MAX_CHUNK_BYTES};
+    use crate::crdt::sync_txn::SyncTransaction;
+    use crate::crdt::txn_rpc::txn_service_server::TxnService;
+    use crate::crdt::txn_rpc::{StreamChunk, StreamRequest};
+    use crate::crdt::utils::{serve_rpc, CRDTError, ClientID, Updates};
+    use crate::test_utils::{init_txn, insert, peer, send};
+    use tokio::sync::mpsc::channel;
+    use tokio::sync::mpsc::{Receiver, Sender};
+    use tokio_stream::StreamExt;
+
+    // integrate a chunk, return the number of blocks it holds
+    async fn apply(txn: &SyncTransaction, chunk: &StreamChunk) -> usize {
+        let updates: Updates = serde_json::from_str(&chunk.updates).unwrap();
+        let len = updates.len();
+        txn.update_remote(updates, Signatures::default(), HashMap::new())
+            .await
+            .unwrap();
+        len
+    }
+
+    fn block(client: ClientID, clock: u32, left_origin: Option<BlockID>) -> Block {
+        Block::new(
+            BlockID::new(client, clock),
+            left_origin,
+            None,
+            Content::Text("x".to_string()),
+        )
+    }
+
+    // Origins come before the blocks inserted next to them, whatever their ids
+    #[test]
+    fn causal_order_of_blocks() {
+        let a = block(2, 0, None);
+        let b = block(1, 0, Some(BlockID::new(2, 0)));
+        let c = block(2, 1, Some(BlockID::new(1, 0)));
+        let ids: Vec<BlockID> = causal_order(vec![c.clone(), b.clone(), a.clone()])
+            .into_iter()
+            .map(|block| block.id)
+            .collect();
+        assert_eq!(ids, vec![a.id, b.id, c.id]);
+    }
+
+    // Chunks are bounded by their number of blocks and their content
+    #[test]
+    fn bounded_chunks() {
+        let mut blocks: Updates = (0..5).map(|clock| block(1, clock, None)).collect();
+        let lens = |chunks: Vec<Updates>| chunks.iter().map(Vec::len).collect::<Vec<_>>();
+        assert_eq!(lens(chunks(blocks.clone(), 2)), vec![2, 2, 1]);
+        blocks[1].content = Content::Text("x".repeat(MAX_CHUNK_BYTES));
+        assert_eq!(lens(chunks(blocks, 10)), vec![2, 3]);
+    }
+
+    // A peer joining a doc edited by several clients streams it without pending blocks
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn join_large_doc() {
+        let (txn1, txn3) = (
+            init_txn("stream_doc", 1, "127.0.0.1:5201"),
+            init_txn("stream_doc", 3, ""),
+        );
+        for _ in 0..260 {
+            insert(&txn1, "a", 0).await;
+        }
+        send(&txn1, &txn3).await.unwrap();
+        for i in 0..260 {
+            insert(&txn3, "b", i * 2 + 1).await;
+        }
+        send(&txn3, &txn1).await.unwrap();
+        // blocks of client 1 now depend on blocks of client 3
+        for i in 0..40 {
+            insert(&txn1, "c", i * 3 + 1).await;
+        }
+        for _ in 0..20 {
+            txn1.doc.lock().await.delete_local(0, 1).await;
+        }
+        // every origin is sent before the blocks depending on it
+        let (blocks, _) = txn1.causal_diff(&VectorClock::new(), None).await;
+        for (i, block) in blocks.iter().enumerate() {
+            for origin in [&block.left_origin, &block.right_origin]
+                .into_iter()
+                .flatten()
+            {
+                assert!(blocks[..i]
+                    .iter()
+                    .any(|sent| sent.id.client == origin.client
+                        && sent.id.clock <= origin.clock
+                        && origin.clock < sent.id.clock + sent.content.len() as u32));
+            }
+        }
+
+        let txn1_bg = txn1.clone();
+        let (_sender, receiver): (Sender<()>, Receiver<()>) = channel(1);
+        let (init_sender, mut init_receiver): (Sender<()>, Receiver<()>) = channel(1);
+        let txn_rpc = txn1.clone();
+        tokio::spawn(async move {
+            serve_rpc(txn_rpc, txn1_bg, receiver, init_sender)
+                .await
+                .unwrap();
+        });
+        let _ = init_receiver.recv().await;
+
+        let txn2 = init_txn("stream_doc", 2, "");
+        txn2.doc.lock().await.peers = vec![peer(1, 5201)];
+        txn2.sync_with(1).await.unwrap();
+        let (doc1, doc2) = (txn1.doc.lock().await, txn2.doc.lock().await);
+        assert_eq!(doc2.to_string().await, doc1.to_string().await);
+        assert!(doc2.pending_updates.is_empty());
+    }
+
+    // An interrupted stream resumes from the clock of the receiver and the last tombstone it got
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn resume_stream() {
+        let (txn1, txn2) = (init_txn("stream_doc", 1, ""), init_txn("stream_doc", 2, ""));
+        for _ in 0..20 {
+            insert(&txn1, "a", 0).await;
+        }
+        send(&txn1, &txn2).await.unwrap();
+        for _ in 0..12 {
+            txn1.doc.lock().await.delete_local(0, 1).await;
+        }
+        for _ in 0..10 {
+            insert(&txn1, "b", 0).await;
+        }
+        let request = |clock: VectorClock, after: String| {
+            tonic::Request::new(StreamRequest {
+                doc_name: "stream_doc".to_string(),
+                client_id: 2,
+                vector_clock: serde_json::to_string(&clock).unwrap(),
+                chunk_size: 5,
+                after,
+            })
+        };
+
+        // the stream breaks after the new blocks and the first tombstones
+        let clock = txn2.doc.lock().await.vector_clock.clone();
+        let mut stream = TxnService::stream_updates(&txn1, request(clock, String::new()))
+            .await
+            .unwrap()
+            .into_inner();
+        let mut after = String::new();
+        for _ in 0..3 {
+            let chunk = stream.next().await.unwrap().unwrap();
+            assert_eq!(apply(&txn2, &chunk).await, 5);
+            after = chunk.after;
+        }
+        drop(stream);
+
+        let clock = txn2.doc.lock().await.vector_clock.clone();
+        let stream = TxnService::stream_updates(&txn1, request(clock, after))
+            .await
+            .unwrap()
+            .into_inner();
+        let chunks: Vec<StreamChunk> = stream.map(Result::unwrap).collect().await;
+        let mut resumed = 0;
+        for chunk in &chunks {
+            resumed += apply(&txn2, chunk).await;
+        }
+        assert_eq!(resumed, 7);
+        assert_eq!(
+            txn2.doc.lock().await.to_string().await,
+            txn1.doc.lock().await.to_string().await
+        );
+    }
+
+    // A refused chunk doesn't stop the rest of the stream, its error is returned at the end
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn refused_chunk() {
+        let (txn1, txn2, txn3) = (
+            init_txn("stream_doc", 1, "127.0.0.1:5202"),
+            init_txn("stream_doc", 2, ""),
+            init_txn("stream_doc", 3, ""),
+        );
+        let signer2 = Arc::new(Signer::generate());
+        txn2.doc
+            .lock()
+            .await
+            .signatures
+            .set_signer(2, signer2.clone());
+        {
+            let mut local_doc = txn3.doc.lock().await;
+            local_doc
+                .signatures
+                .set_signer(3, Arc::new(Signer::generate()));
+            local_doc.signatures.add_public_key(2, signer2.public_key());
+        }
+        // the unsigned block of 1 fills the first chunk on its own
+        insert(&txn1, &"a".repeat(MAX_CHUNK_BYTES), 0).await;
+        insert(&txn2, "tail", 0).await;
+        let updates = txn2.compute_diff(VectorClock::new()).await;
+        let signatures = txn2.signatures_for(&updates).await;
+        txn1.update_remote(updates, signatures, HashMap::new())
+            .await
+            .unwrap();
+
+        let txn1_bg = txn1.clone();
+        let (sender, receiver): (Sender<()>, Receiver<()>) = channel(1);
+        let (init_sender, mut init_receiver): (Sender<()>, Receiver<()>) = channel(1);
+        tokio::spawn(async move {
+            serve_rpc(txn1, txn1_bg, receiver, init_sender)
+                .await
+                .unwrap();
+        });
+        let _ = init_receiver.recv().await;
+
+        let res = txn3.stream_from(&peer(1, 5202)).await;
+        assert!(matches!(res, Err(CRDTError::Conflict(_))));
+        assert_eq!(txn3.doc.lock().await.to_string().await, "tail".to_string());
+        let _ = sender.send(()).await;
+    }
+}
+
+#[cfg(test)]
+mod snapshot_test {
+    use std::sync::Arc;
+
+    use crate::crdt::block::BlockID;
+    use crate::crdt::doc::VectorClock;
+    use crate::crdt::e2e::KeyRing;
+    use crate::crdt::signing::{Signatures, Signer};
+    use crate::crdt::snapshot::{Bootstrap, Snapshot};
+    use crate::crdt::sync_txn::SyncTransaction;
+    use crate::crdt::utils::{serve_rpc, CRDTError};
+    use crate::crdt::version::Version;
+    use crate::test_utils::{init_txn, insert, peer, send};
+    use tokio::sync::mpsc::channel;
+    use tokio::sync::mpsc::{Receiver, Sender};
+
+    async fn block_count(txn: &SyncTransaction) -> usize {
+        let local_doc = txn.doc.lock().await;
+        let store_lock = local_doc.block_store.lock().await;
+        store_lock.total_store.list.len()
+    }
+
+    // A snapshot merges split blocks, leaves deleted content out,
+    // and the doc loading it keeps integrating concurrent updates
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn encode_and_load() {
+        let (txn1, txn2) = (
+            init_txn("snapshot_doc", 1, ""),
+            init_txn("snapshot_doc", 2, ""),
+        );
+        insert(&txn1, "hello world", 0).await;
+        txn1.doc.lock().await.delete_local(5, 6).await;
+        // split "hello" as a remote insertion at "he|llo" would
+        txn1.doc
+            .lock()
+            .await
+            .block_store
+            .lock()
+            .await
+            .split(BlockID::new(1, 0), 2)
+            .await;
+        assert_eq!(block_count(&txn1).await, 3);
+
+        let snapshot = txn1.doc.lock().await.snapshot().await;
+        assert_eq!(snapshot.blocks.len(), 2);
+        assert_eq!(snapshot.blocks[0].content, "hello".to_string());
+        assert!(snapshot.blocks[1].content.is_empty());
+        assert_eq!(snapshot.blocks[1].len, 6);
+        assert_eq!(snapshot.delete_set[&1], vec![(5, 11)]);
+        assert_eq!(snapshot.vector_clock.get(1), 11);
+        let encoded = serde_json::to_string(&snapshot).unwrap();
+        let snapshot: Snapshot = serde_json::from_str(&encoded).unwrap();
+
+        txn2.doc.lock().await.load_snapshot(snapshot).await.unwrap();
+        assert_eq!(txn2.doc.lock().await.to_string().await, "hello");
+        assert!(txn2
+            .doc
+            .lock()
+            .await
+            .load_snapshot(Snapshot::default())
+            .await
+            .is_err());
+
+        // edits made on both sides after the snapshot converge
+        insert(&txn1, "!", 5).await;
+        insert(&txn2, "oh ", 0).await;
+        send(&txn1, &txn2).await.unwrap();
+        send(&txn2, &txn1).await.unwrap();
+        let expected = "oh hello!".to_string();
+        assert_eq!(txn1.doc.lock().await.to_string().await, expected);
+        assert_eq!(txn2.doc.lock().await.to_string().await, expected);
+    }
+
+    // A peer joining with snapshot bootstrap only gets the current state,
+    // then pulls the updates made since like any other peer
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn bootstrap_from_snapshot() {
+        let txn1 = init_txn("snapshot_doc", 1, "127.0.0.1:5301");
+        for _ in 0..30 {
+            insert(&txn1, "ab", 0).await;
+            txn1.doc.lock().await.delete_local(0, 1).await;
+        }
+        let txn1_bg = txn1.clone();
+        let txn_rpc = txn1.clone();
+        let (_sender, receiver): (Sender<()>, Receiver<()>) = channel(1);
+        let (init_sender, mut init_receiver): (Sender<()>, Receiver<()>) = channel(1);
+        tokio::spawn(async move {
+            serve_rpc(txn_rpc, txn1_bg, receiver, init_sender)
+                .await
+                .unwrap();
+        });
+        let _ = init_receiver.recv().await;
+
+        let mut txn2 = init_txn("snapshot_doc", 2, "");
+        txn2.set_bootstrap(Bootstrap::Snapshot);
+        txn2.doc.lock().await.peers = vec![peer(1, 5301)];
+        txn2.sync_with(1).await.unwrap();
+        assert_eq!(txn2.doc.lock().await.to_string().await, "b".repeat(30));
+        assert!(txn2.doc.lock().await.pending_updates.is_empty());
+        // the deleted content has been left out, it doesn't show up as deleted text
+        {
+            let doc2 = txn2.doc.lock().await;
+            assert!(doc2.deleted_spans().await.is_empty());
+            let diff = doc2.changes_since(&Version::default()).await;
+            assert_eq!(diff.len(), 1);
+            assert_eq!(diff[0].text, "b".repeat(30));
+        }
+
+        insert(&txn1, "c", 30).await;
+        txn2.sync_with(1).await.unwrap();
+        assert_eq!(
+            txn2.doc.lock().await.to_string().await,
+            format!("{}c", "b".repeat(30))
+        );
+    }
+
+    // A snapshot failing its checks leaves the doc as it was, signatures and key epochs included
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn rejected_snapshot() {
+        let txn1 = init_txn("snapshot_doc", 1, "127.0.0.1:5302");
+        txn1.doc
+            .lock()
+            .await
+            .signatures
+            .set_signer(1, Arc::new(Signer::generate()));
+        txn1.enable_encryption(Some(KeyRing::generate())).await;
+        insert(&txn1, "hello", 0).await;
+        let txn1_bg = txn1.clone();
+        let txn_rpc = txn1.clone();
+        let (sender, receiver): (Sender<()>, Receiver<()>) = channel(1);
+        let (init_sender, mut init_receiver): (Sender<()>, Receiver<()>) = channel(1);
+        tokio::spawn(async move {
+            serve_rpc(txn_rpc, txn1_bg, receiver, init_sender)
+                .await
+                .unwrap();
+        });
+        let _ = init_receiver.recv().await;
+
+        // the key of 1 is unknown, so its blocks cannot be verified
+        let txn2 = init_txn("snapshot_doc", 2, "");
+        txn2.doc
+            .lock()
+            .await
+            .signatures
+            .set_signer(2, Arc::new(Signer::generate()));
+        let res = txn2.load_snapshot_from(&peer(1, 5302)).await;
+        assert!(matches!(res, Err(CRDTError::Conflict(_))));
+        let updates = txn1.compute_diff(VectorClock::new()).await;
+        let doc2 = txn2.doc.lock().await;
+        assert!(doc2.encryption.epochs.is_empty());
+        assert_eq!(
+            doc2.signatures.signatures_for(&updates),
+            Signatures::default()
+        );
+        assert_eq!(doc2.to_string().await, "".to_string());
+        drop(doc2);
+        let _ = sender.send(()).await;
+    }
+}
+
+#[cfg(test)]
+mod yjs_test {
+    use crate::crdt::doc::Doc;
+    use crate::crdt::utils::CRDTError;
+    use crate::crdt::yjs::{decode_state_vector, Decoder, Encoder};
+    use crate::test_utils::insert_doc;
+
+    // ydoc.clientID = 1; ydoc.getText("text").insert(0, "abc")
+    const INSERT_TEXT: &[u8] = include_bytes!("../fixtures/yjs/insert_text.bin");
+    // then client 2 inserts "X" at 2 ("abXc") and "a" is deleted, with gc on
+    const CONCURRENT_EDITS: &[u8] = include_bytes!("../fixtures/yjs/concurrent_edits.bin");
+
+    #[test]
+    fn lib0_encoding() {
+        let mut encoder = Encoder::new();
+        encoder.write_var_uint(127);
+        encoder.write_var_uint(300);
+        encoder.write_var_uint(u32::MAX as u64);
+        encoder.write_var_string("text");
+        let bytes = encoder.into_bytes();
+        assert_eq!(bytes[..3], [0x7f, 0xac, 0x02]);
+
+        let mut decoder = Decoder::new(&bytes);
+        assert_eq!(decoder.read_var_uint().unwrap(), 127);
+        assert_eq!(decoder.read_var_uint().unwrap(), 300);
+        assert_eq!(decoder.read_var_uint().unwrap(), u32::MAX as u64);
+        assert_eq!(decoder.read_var_string().unwrap(), "text");
+        assert!(decoder.read_u8().is_err());
+    }
+
+    // Updates written by Yjs are integrated and written back byte for byte
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn import_yjs_updates() {
+        let mut doc = Doc::new("yjs_doc".to_string(), 3);
+        doc.apply_update_v1(INSERT_TEXT).await.unwrap();
+        assert_eq!(doc.to_string().await, "abc");
+        assert_eq!(
+            doc.encode_state_as_update_v1(&[]).await.unwrap(),
+            INSERT_TEXT
+        );
+
+        // the items of client 1 are known, only "X" and the deletion are new
+        doc.apply_update_v1(CONCURRENT_EDITS).await.unwrap();
+        assert_eq!(doc.to_string().await, "bXc");
+        assert_eq!(doc.encode_state_vector().await, vec![2, 2, 1, 1, 3]);
+        // the deletion is not attributed to the local client
+        assert_eq!(doc.vector_clock.changes(3), 0);
+        let store = doc.block_store.lock().await;
+        for block in &store.total_store.list {
+            let block = block.lock().await;
+            if block.is_deleted {
+                assert_eq!(block.deleted_by, None);
+                assert!(block.changes.is_empty());
+            }
+        }
+        drop(store);
+
+        let mut fresh = Doc::new("yjs_doc".to_string(), 4);
+        fresh.apply_update_v1(CONCURRENT_EDITS).await.unwrap();
+        assert_eq!(fresh.to_string().await, "bXc");
+        assert_eq!(
+            fresh.encode_state_as_update_v1(&[]).await.unwrap(),
+            CONCURRENT_EDITS
+        );
+        // applying an update twice changes nothing
+        fresh.apply_update_v1(CONCURRENT_EDITS).await.unwrap();
+        assert_eq!(fresh.to_string().await, "bXc");
+    }
+
+    // Edits made here are written the way Yjs writes the same edits
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn export_yjs_updates() {
+        let mut doc1 = Doc::new("yjs_doc".to_string(), 1);
+        insert_doc(&mut doc1, "abc", 0).await;
+        assert_eq!(
+            doc1.encode_state_as_update_v1(&[]).await.unwrap(),
+            INSERT_TEXT
+        );
+
+        let mut doc2 = Doc::new("yjs_doc".to_string(), 2);
+        let diff = doc1.compute_diff(&doc2.vector_clock).await;
+        doc2.insert_remote(diff).await;
+        insert_doc(&mut doc2, "X", 2).await;
+        doc2.delete_local(0, 1).await;
+        assert_eq!(doc2.to_string().await, "bXc");
+        assert_eq!(
+            doc2.encode_state_as_update_v1(&[]).await.unwrap(),
+            CONCURRENT_EDITS
+        );
+
+        // a peer knowing "ab" only gets "c" and "X", and every deletion
+        let state_vector = [1, 1, 2];
+        assert_eq!(decode_state_vector(&state_vector).unwrap().get(1), 2);
+        let update = doc2.encode_state_as_update_v1(&state_vector).await.unwrap();
+        assert_eq!(
+            update,
+            vec![2, 1, 2, 0, 196, 1, 1, 1, 2, 1, 88, 1, 1, 2, 132, 1, 1, 1, 99, 1, 1, 1, 0, 1]
+        );
+    }
+
+    // Unsupported content and truncated updates are rejected
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn unsupported_updates() {
+        let mut doc = Doc::new("yjs_doc".to_string(), 1);
+        // ytext.format(0, 1, { bold: true }) inserts a ContentFormat item
+        let mut format = vec![1, 1, 1, 0, 6, 1, 4];
+        format.extend_from_slice(b"text");
+        format.extend_from_slice(&[4, b'b', b'o', b'l', b'd', 4, b't', b'r', b'u', b'e', 0]);
+        assert!(matches!(
+            doc.apply_update_v1(&format).await,
+            Err(CRDTError::Serialization(_))
+        ));
+        let mut other_type = INSERT_TEXT.to_vec();
+        other_type[8] = b'T';
+        assert!(doc.apply_update_v1(&other_type).await.is_err());
+        assert!(doc
+            .apply_update_v1(&INSERT_TEXT[..INSERT_TEXT.len() - 3])
+            .await
+            .is_err());
+        assert_eq!(doc.to_string().await, "");
+    }
+
+    // Yjs clocks count utf-16 code units, they are mapped to the bytes of the text
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn non_ascii_text() {
+        let mut doc1 = Doc::new("yjs_doc".to_string(), 1);
+        insert_doc(&mut doc1, "caf\u{e9} \u{1F600}!", 0).await;
+        assert_eq!(doc1.vector_clock.get(1), 11);
+        assert_eq!(doc1.encode_state_vector().await, vec![1, 1, 8]);
+
+        let mut doc2 = Doc::new("yjs_doc".to_string(), 2);
+        let update = doc1.encode_state_as_update_v1(&[]).await.unwrap();
+        doc2.apply_update_v1(&update).await.unwrap();
+        assert_eq!(doc2.to_string().await, "caf\u{e9} \u{1F600}!");
+        assert_eq!(doc2.vector_clock.get(1), 11);
+
+        // an insertion right after the emoji and the deletion of the accented letter
+        insert_doc(&mut doc2, "X", 10).await;
+        doc2.delete_local(3, 2).await;
+        let state_vector = doc1.encode_state_vector().await;
+        let update = doc2.encode_state_as_update_v1(&state_vector).await.unwrap();
+        doc1.apply_update_v1(&update).await.unwrap();
+        assert_eq!(doc1.to_string().await, "caf \u{1F600}X!");
+        assert_eq!(doc1.to_string().await, doc2.to_string().await);
+    }
+}
+
+#[cfg(test)]
+mod history_test {
+    use crate::crdt::block::BlockID;
+    use crate::crdt::doc::Doc;
+    use crate::crdt::history::{Change, History, TextOp};
+    use crate::test_utils::{exchange, insert_doc};
+
+    // The change log format is stable, split blocks are logged as their insertion
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn change_log() {
+        let mut doc = Doc::new("history_doc".to_string(), 1);
+        insert_doc(&mut doc, "abc", 0).await;
+        doc.delete_local(1, 1).await;
+        let history = doc.history().await;
+        assert_eq!(
+            serde_json::to_string(&history).unwrap(),
+            concat!(
+                r#"{"version":1,"doc":"history_doc","changes":["#,
+                r#"{"op":"insert","id":{"client":1,"clock":0},"left_origin":null,"right_origin":null,"content":"abc"},"#,
+                r#"{"op":"delete","id":{"client":1,"clock":1},"len":1}]}"#
+            )
+        );
+        assert_eq!(
+            history.text_ops().await,
+            vec![
+                TextOp::Insert {
+                    index: 0,
+                    text: "abc".to_string()
+                },
+                TextOp::Delete { index: 1, len: 1 }
+            ]
+        );
+        let json = serde_json::to_string(&history).unwrap();
+        assert_eq!(serde_json::from_str::<History>(&json).unwrap(), history);
+    }
+
+    // Replaying the text operations of concurrent edits gives the text of the doc
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn replay_text_ops() {
+        let mut doc1 = Doc::new("history_doc".to_string(), 1);
+        let mut doc2 = Doc::new("history_doc".to_string(), 2);
+        insert_doc(&mut doc1, "hello world", 0).await;
+        exchange(&doc1, &mut doc2).await;
+        insert_doc(&mut doc1, " big", 5).await;
+        insert_doc(&mut doc2, "!!", 11).await;
+        doc2.delete_local(0, 1).await;
+        exchange(&doc1, &mut doc2).await;
+        exchange(&doc2, &mut doc1).await;
+        insert_doc(&mut doc2, "H", 0).await;
+        doc2.delete_local(5, 4).await;
+        exchange(&doc2, &mut doc1).await;
+        let text = doc1.to_string().await;
+        assert_eq!(text, "Hello world!!");
+        assert_eq!(text, doc2.to_string().await);
+
+        let history = doc1.history().await;
+        let insertions: Vec<&BlockID> = history
+            .changes
+            .iter()
+            .filter_map(|change| match change {
+                Change::Insert { id, .. } => Some(id),
+                _ => None,
+            })
+            .collect();
+        // " world" has been split from "hello world" before "h" was split off "hello",
+        // the parts cannot be told apart from an insertion after "hello" and are logged separately
+        assert_eq!(insertions.len(), 5);
+        assert!(matches!(
+            history.changes.last(),
+            Some(Change::Delete { .. })
+        ));
+
+        let mut replayed = String::new();
+        for op in history.text_ops().await {
+            op.apply(&mut replayed);
+        }
+        assert_eq!(replayed, text);
+    }
+
+    // Insertions of a client are only merged with the blocks split from them,
+    // an insertion in the middle of an earlier one keeps its origins
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn interleaved_insertions() {
+        let mut doc = Doc::new("history_doc".to_string(), 1);
+        insert_doc(&mut doc, "abc", 0).await;
+        insert_doc(&mut doc, "e", 3).await;
+        insert_doc(&mut doc, "Z", 2).await;
+        let text = doc.to_string().await;
+        assert_eq!(text, "abZce");
+
+        let history = doc.history().await;
+        let insertions: Vec<(&str, &Option<BlockID>)> = history
+            .changes
+            .iter()
+            .filter_map(|change| match change {
+                Change::Insert {
+                    content,
+                    left_origin,
+                    ..
+                } => Some((content.as_str(), left_origin)),
+                _ => None,
+            })
+            .collect();
+        let origin = Some(BlockID::new(1, 0));
+        assert_eq!(
+            insertions,
+            vec![("abc", &None), ("e", &origin), ("Z", &origin)]
+        );
+
+        let mut replayed = String::new();
+        for op in history.text_ops().await {
+            op.apply(&mut replayed);
+        }
+        assert_eq!(replayed, text);
+    }
+}
+
+#[cfg(test)]
+mod blame_test {
+    use crate::crdt::blame::{BlameLine, Span};
+    use crate::crdt::doc::Doc;
+    use crate::crdt::utils::{Peer, PeerDisplay};
+    use crate::test_utils::{exchange, insert_doc};
+
+    // Visible text is attributed to its authors and lines are blamed on them
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn attributed_spans_and_blame() {
+        let mut doc1 = Doc::new("blame_doc".to_string(), 1);
+        let mut doc2 = Doc::new("blame_doc".to_string(), 2);
+        insert_doc(&mut doc1, "first line\n", 0).await;
+        exchange(&doc1, &mut doc2).await;
+        insert_doc(&mut doc2, "second line\n", 11).await;
+        insert_doc(&mut doc2, "very ", 6).await;
+        exchange(&doc2, &mut doc1).await;
+        assert_eq!(doc1.to_string().await, "first very line\nsecond line\n");
+
+        let spans = doc1.attributed_spans().await;
+        assert_eq!(spans, doc2.attributed_spans().await);
+        let expected = [
+            ("first ", 1),
+            ("very ", 2),
+            ("line\n", 1),
+            ("second line\n", 2),
+        ];
+        let mut start = 0;
+        for (span, (text, author)) in spans.iter().zip(expected.iter()) {
+            assert_eq!(
+                *span,
+                Span {
+                    start,
+                    text: text.to_string(),
+                    author: *author
+                }
+            );
+            start += text.len() as u32;
+        }
+        assert_eq!(spans.len(), expected.len());
+
+        assert_eq!(
+            doc1.blame().await,
+            vec![
+                BlameLine {
+                    line: 0,
+                    text: "first very line".to_string(),
+                    authors: vec![1, 2]
+                },
+                BlameLine {
+                    line: 1,
+                    text: "second line".to_string(),
+                    authors: vec![2]
+                }
+            ]
+        );
+
+        doc1.peers = vec![Peer {
+            client_id: 2,
+            ip_addr: "".to_string(),
+            public_key: None,
+            display: Some(PeerDisplay {
+                name: "bob".to_string(),
+                color: Some("#00ff00".to_string()),
+            }),
+        }];
+        assert_eq!(doc1.display_of(2).unwrap().name, "bob");
+        assert!(doc1.display_of(1).is_none());
+    }
+
+    // Deletions are attributed to the client that made them on every peer,
+    // concurrent deletions of the same text to the smallest client
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn deleted_by() {
+        let mut doc1 = Doc::new("blame_doc".to_string(), 1);
+        let mut doc2 = Doc::new("blame_doc".to_string(), 2);
+        let mut doc3 = Doc::new("blame_doc".to_string(), 3);
+        insert_doc(&mut doc1, "hello world", 0).await;
+        exchange(&doc1, &mut doc2).await;
+        exchange(&doc1, &mut doc3).await;
+        // both delete the space
+        doc2.delete_local(0, 6).await;
+        doc3.delete_local(5, 6).await;
+        exchange(&doc2, &mut doc1).await;
+        exchange(&doc3, &mut doc1).await;
+        exchange(&doc1, &mut doc2).await;
+        exchange(&doc1, &mut doc3).await;
+        assert_eq!(doc1.to_string().await, "");
+
+        let deleted = doc1.deleted_spans().await;
+        assert_eq!(deleted, doc2.deleted_spans().await);
+        assert_eq!(deleted, doc3.deleted_spans().await);
+        let attribution: Vec<(&str, u32, Option<u32>)> = deleted
+            .iter()
+            .map(|span| (span.text.as_str(), span.author, span.deleted_by))
+            .collect();
+        assert_eq!(
+            attribution,
+            vec![("hello ", 1, Some(2)), ("world", 1, Some(3))]
+        );
+    }
+}
+
+#[cfg(test)]
+mod version_test {
+    use crate::crdt::doc::Doc;
+    use crate::crdt::version::{DiffKind, DiffSpan, Version};
+    use crate::test_utils::{exchange, insert_doc};
+
+    fn span(kind: DiffKind, text: &str, author: u32, deleted_by: Option<u32>) -> DiffSpan {
+        DiffSpan {
+            kind,
+            text: text.to_string(),
+            author,
+            deleted_by,
+        }
+    }
+
+    // The diff between two versions follows the edits, not a text diff
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn diff_versions() {
+        let mut doc1 = Doc::new("version_doc".to_string(), 1);
+        let mut doc2 = Doc::new("version_doc".to_string(), 2);
+        insert_doc(&mut doc1, "hello world", 0).await;
+        exchange(&doc1, &mut doc2).await;
+        let seen = doc1.version().await;
+        let encoded = serde_json::to_string(&seen).unwrap();
+
+        // "world" is replaced by the same text, a text diff would see no change
+        doc1.delete_local(6, 5).await;
+        insert_doc(&mut doc1, "world", 6).await;
+        insert_doc(&mut doc2, "!", 11).await;
+        exchange(&doc2, &mut doc1).await;
+        assert_eq!(doc1.to_string().await, "hello world!");
+
+        let seen: Version = serde_json::from_str(&encoded).unwrap();
+        assert_eq!(
+            doc1.changes_since(&seen).await,
+            vec![
+                span(DiffKind::Unchanged, "hello ", 1, None),
+                span(DiffKind::Inserted, "world", 1, None),
+                span(DiffKind::Deleted, "world", 1, Some(1)),
+                span(DiffKind::Inserted, "!", 2, None),
+            ]
+        );
+        // nothing changed between a version and itself
+        let now = doc1.version().await;
+        assert!(doc1
+            .diff(&now, &now)
+            .await
+            .iter()
+            .all(|span| span.kind == DiffKind::Unchanged));
+        assert!(doc1
+            .diff(&Version::default(), &seen)
+            .await
+            .iter()
+            .all(|span| span.kind == DiffKind::Inserted));
+    }
+}
+
+#[cfg(test)]
+mod fork_test {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use crate::crdt::auth::{Permissions, Role};
+    use crate::crdt::doc::{Doc, VectorClock};
+    use crate::crdt::e2e::{KeyRing, SEALED_FILLER};
+    use crate::crdt::signing::Signer;
+    use crate::test_utils::{init_txn, insert_doc};
+
+    // A fork is edited on its own and merged back without conflicts
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn fork_and_merge() {
+        let mut doc = Doc::new("fork_doc".to_string(), 1);
+        insert_doc(&mut doc, "hello world", 0).await;
+        let mut draft = doc.fork("fork_doc_draft".to_string()).await;
+        assert_eq!(draft.name, "fork_doc_draft");
+        assert_ne!(draft.client, doc.client);
+        assert_eq!(draft.to_string().await, "hello world");
+
+        insert_doc(&mut draft, " big", 5).await;
+        draft.delete_local(10, 5).await;
+        insert_doc(&mut doc, "!", 11).await;
+        assert_eq!(draft.to_string().await, "hello big ");
+        assert_eq!(doc.to_string().await, "hello world!");
+
+        // merging twice changes nothing
+        doc.merge_from(&draft).await.unwrap();
+        doc.merge_from(&draft).await.unwrap();
+        assert_eq!(doc.to_string().await, "hello big !");
+        draft.merge_from(&doc).await.unwrap();
+        assert_eq!(draft.to_string().await, "hello big !");
+
+        // peers of the original get the merged edits like any other update
+        let mut peer = Doc::new("fork_doc".to_string(), 2);
+        peer.merge_from(&doc).await.unwrap();
+        assert_eq!(peer.to_string().await, "hello big !");
+    }
+
+    // Insertions of the original and of the fork never share an id
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn fork_clocks() {
+        let mut doc = Doc::new("fork_doc".to_string(), 1);
+        insert_doc(&mut doc, "ab", 0).await;
+        let mut draft = doc.fork_as("fork_doc_draft".to_string(), 7).await;
+        insert_doc(&mut doc, "c", 2).await;
+        insert_doc(&mut draft, "d", 2).await;
+        assert_eq!(draft.vector_clock.get(1), 2);
+        assert_eq!(draft.vector_clock.get(7), 1);
+
+        doc.merge_from(&draft).await.unwrap();
+        draft.merge_from(&doc).await.unwrap();
+        let text = doc.to_string().await;
+        assert_eq!(text.len(), 4);
+        assert_eq!(text, draft.to_string().await);
+    }
+
+    // A fork signs with the key of its parent, which signs the fork so that peers
+    // verify its updates and give it the role of the parent
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn signed_fork() {
+        let signers = [Arc::new(Signer::generate()), Arc::new(Signer::generate())];
+        let mut doc = Doc::new("fork_doc".to_string(), 1);
+        doc.signatures.set_signer(1, signers[0].clone());
+        insert_doc(&mut doc, "hello world", 0).await;
+        let mut draft = doc.fork_as("fork_doc_draft".to_string(), 7).await;
+        insert_doc(&mut draft, " big", 5).await;
+        draft.delete_local(10, 5).await;
+        doc.merge_from(&draft).await.unwrap();
+        assert_eq!(doc.to_string().await, "hello big ");
+
+        let updates = doc.compute_diff(&VectorClock::new()).await;
+        let signatures = doc.signatures.signatures_for(&updates);
+        assert_eq!(signatures.forks.len(), 1);
+        let mut permissions = Permissions::default();
+        permissions.docs.insert(
+            "fork_doc".to_string(),
+            HashMap::from([(1, Role::Editor), (2, Role::Editor), (3, Role::Editor)]),
+        );
+        let permissions = Arc::new(permissions);
+
+        // the peers only know the key of client 1
+        for (client, forks) in [(2, signatures.forks.clone()), (3, vec![])] {
+            let mut txn = init_txn("fork_doc", client, "");
+            txn.set_permissions(permissions.clone());
+            txn.set_signer(signers[1].clone()).await;
+            txn.doc
+                .lock()
+                .await
+                .signatures
+                .add_public_key(1, signers[0].public_key());
+            let mut signatures = signatures.clone();
+            signatures.forks = forks;
+            let _ = txn
+                .update_remote(updates.clone(), signatures, HashMap::new())
+                .await;
+            let text = txn.doc.lock().await.to_string().await;
+            if client == 2 {
+                assert_eq!(text, "hello big ");
+            } else {
+                // without the signed fork, its client has neither a key nor a role
+                assert!(!text.contains("big"));
+            }
+        }
+    }
+
+    // Insertions of a fork are sealed like those of the original, the sealed blocks
+    // of a doc without the key are forked and merged as they are
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn encrypted_fork() {
+        let keys = KeyRing::generate();
+        let mut doc = Doc::new("fork_doc".to_string(), 1);
+        doc.encryption.enable(Some(keys.clone()));
+        insert_doc(&mut doc, "hello", 0).await;
+        let mut draft = doc.fork_as("fork_doc_draft".to_string(), 7).await;
+        insert_doc(&mut draft, " world", 5).await;
+        let diff = draft.compute_diff(&doc.vector_clock).await;
+        assert!(diff.iter().all(|block| block.sealed.is_some()));
+        doc.merge_from(&draft).await.unwrap();
+        assert_eq!(doc.to_string().await, "hello world");
+
+        let mut relay = Doc::new("fork_doc".to_string(), 2);
+        relay.merge_from(&doc).await.unwrap();
+        assert_eq!(
+            relay.to_string().await,
+            SEALED_FILLER.to_string().repeat(11)
+        );
+        let copy = relay.fork_as("fork_doc_copy".to_string(), 8).await;
+        let mut peer = Doc::new("fork_doc".to_string(), 3);
+        peer.encryption.enable(Some(keys));
+        peer.merge_from(&copy).await.unwrap();
+        assert_eq!(peer.to_string().await, "hello world");
+    }
+}
+
+#[cfg(test)]
+mod suggestion_test {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use crate::crdt::auth::{Permissions, Role};
+    use crate::crdt::block::{BlockID, Content};
+    use crate::crdt::doc::Doc;
+    use crate::crdt::signing::Signer;
+    use crate::crdt::suggestion::{SuggestionKind, SuggestionSpan, SuggestionState};
+    use crate::crdt::utils::CRDTError;
+    use crate::test_utils::{init_txn, insert_doc};
+
+    async fn suggest(doc: &mut Doc, content: &str, pos: u32) -> BlockID {
+        doc.suggest_insert(Content::Text(content.to_string()), pos)
+            .await
+    }
+
+    // Suggestions are rendered with markers on every peer until an owner resolves them
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn suggest_and_resolve() {
+        let mut doc1 = Doc::new("suggestion_doc".to_string(), 1);
+        let mut doc2 = Doc::new("suggestion_doc".to_string(), 2);
+        insert_doc(&mut doc1, "hello world", 0).await;
+        doc2.merge_from(&doc1).await.unwrap();
+
+        let id = suggest(&mut doc2, " big", 5).await;
+        assert_eq!(id, BlockID::new(2, 0));
+        doc2.suggest_delete(10, 5).await;
+        assert_eq!(doc2.to_string().await, "hello big world");
+        assert_eq!(
+            doc2.render_suggestions().await,
+            "hello{++ big++} {--world--}"
+        );
+
+        doc1.merge_from(&doc2).await.unwrap();
+        assert_eq!(
+            doc1.render_suggestions().await,
+            "hello{++ big++} {--world--}"
+        );
+        let suggestions = doc1.suggestions().await;
+        assert_eq!(
+            suggestions,
+            vec![
+                SuggestionSpan {
+                    id: BlockID::new(2, 0),
+                    len: 4,
+                    pos: 5,
+                    kind: SuggestionKind::Insert,
+                    author: 2,
+                    text: " big".to_string(),
+                },
+                SuggestionSpan {
+                    id: BlockID::new(1, 6),
+                    len: 5,
+                    pos: 10,
+                    kind: SuggestionKind::Delete,
+                    author: 2,
+                    text: "world".to_string(),
+                },
+            ]
+        );
+
+        assert!(doc1.accept_suggestion(&suggestions[0].id, 4).await);
+        assert!(doc1.accept_suggestion(&suggestions[1].id, 5).await);
+        assert_eq!(doc1.render_suggestions().await, "hello big ");
+        assert!(doc1.suggestions().await.is_empty());
+        assert_eq!(doc1.deleted_spans().await[0].deleted_by, Some(2));
+
+        doc2.merge_from(&doc1).await.unwrap();
+        assert_eq!(doc2.render_suggestions().await, "hello big ");
+
+        // a rejected insertion is deleted, a rejected deletion keeps the text
+        suggest(&mut doc2, "!", 10).await;
+        doc2.suggest_delete(0, 5).await;
+        assert_eq!(doc2.render_suggestions().await, "{--hello--} big {++!++}");
+        doc1.merge_from(&doc2).await.unwrap();
+        for span in doc1.suggestions().await {
+            assert!(doc1.reject_suggestion(&span.id, span.len).await);
+        }
+        doc2.merge_from(&doc1).await.unwrap();
+        assert_eq!(doc1.render_suggestions().await, "hello big ");
+        assert_eq!(doc2.render_suggestions().await, "hello big ");
+    }
+
+    // Concurrent resolutions converge to the one removing text,
+    // and suggesting the deletion of an own suggestion withdraws it
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_resolutions() {
+        let mut doc1 = Doc::new("suggestion_doc".to_string(), 1);
+        let mut doc2 = Doc::new("suggestion_doc".to_string(), 2);
+        insert_doc(&mut doc1, "abc", 0).await;
+        doc2.merge_from(&doc1).await.unwrap();
+        let id = suggest(&mut doc2, "X", 3).await;
+        doc1.merge_from(&doc2).await.unwrap();
+
+        assert!(doc1.accept_suggestion(&id, 1).await);
+        assert!(doc2.reject_suggestion(&id, 1).await);
+        assert_eq!(doc1.to_string().await, "abcX");
+        assert_eq!(doc2.to_string().await, "abc");
+        doc1.merge_from(&doc2).await.unwrap();
+        doc2.merge_from(&doc1).await.unwrap();
+        for doc in [&doc1, &doc2] {
+            assert_eq!(doc.to_string().await, "abc");
+            let store = doc.block_store.lock().await;
+            let block = store.block_map[&id].lock().await;
+            let suggestion = block.suggestion.unwrap();
+            assert_eq!(suggestion.state, SuggestionState::Rejected);
+            assert_eq!(suggestion.resolved_by, Some(2));
+        }
+
+        suggest(&mut doc2, "Y", 0).await;
+        doc2.suggest_delete(0, 1).await;
+        assert_eq!(doc2.render_suggestions().await, "abc");
+        assert!(doc2.suggestions().await.is_empty());
+    }
+
+    // Suggesting and resolving are changes, they are sent to the peers that haven't seen them only
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn resolutions_sent_once() {
+        let mut doc1 = Doc::new("suggestion_doc".to_string(), 1);
+        let mut doc2 = Doc::new("suggestion_doc".to_string(), 2);
+        insert_doc(&mut doc1, "abc", 0).await;
+        doc2.merge_from(&doc1).await.unwrap();
+        let id = suggest(&mut doc2, "X", 3).await;
+        doc2.suggest_delete(0, 1).await;
+        assert_eq!(doc2.compute_diff(&doc1.vector_clock).await.len(), 2);
+        doc1.merge_from(&doc2).await.unwrap();
+        assert!(doc1.compute_diff(&doc2.vector_clock).await.is_empty());
+
+        assert!(doc1.accept_suggestion(&id, 1).await);
+        let diff = doc1.compute_diff(&doc2.vector_clock).await;
+        assert_eq!(diff.len(), 1);
+        assert_eq!(diff[0].id, id);
+        doc2.merge_from(&doc1).await.unwrap();
+        assert!(doc1.compute_diff(&doc2.vector_clock).await.is_empty());
+        assert_eq!(doc2.render_suggestions().await, "{--a--}bcX");
+    }
+
+    // A resolution is only trusted if it is signed by the client it is attributed to
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn forged_resolution() {
+        let signers: Vec<Arc<Signer>> = (0..3).map(|_| Arc::new(Signer::generate())).collect();
+        let mut doc1 = Doc::new("suggestion_doc".to_string(), 1);
+        let mut doc2 = Doc::new("suggestion_doc".to_string(), 2);
+        for (doc, client) in [(&mut doc1, 1), (&mut doc2, 2)] {
+            doc.signatures
+                .set_signer(client, signers[client as usize - 1].clone());
+            doc.signatures
+                .add_public_key(3 - client, signers[2 - client as usize].public_key());
+        }
+        insert_doc(&mut doc1, "abc", 0).await;
+        doc2.merge_from(&doc1).await.unwrap();
+        let id = suggest(&mut doc2, "X", 3).await;
+
+        let mut permissions = Permissions::default();
+        permissions.docs.insert(
+            "suggestion_doc".to_string(),
+            HashMap::from([(1, Role::Owner), (2, Role::Editor), (3, Role::Editor)]),
+        );
+        let mut txn = init_txn("suggestion_doc", 3, "");
+        txn.set_permissions(Arc::new(permissions));
+        txn.set_signer(signers[2].clone()).await;
+        for (client, signer) in [(1, &signers[0]), (2, &signers[1])] {
+            txn.doc
+                .lock()
+                .await
+                .signatures
+                .add_public_key(client, signer.public_key());
+        }
+
+        // the editor claims the owner accepted its suggestion
+        let mut updates = doc2.compute_diff(&Default::default()).await;
+        for block in updates.iter_mut().filter(|block| block.id == id) {
+            let suggestion = block.suggestion.as_mut().unwrap();
+            suggestion.state = SuggestionState::Accepted;
+            suggestion.resolved_by = Some(1);
+        }
+        let signatures = doc2.signatures.signatures_for(&updates);
+        let res = txn.update_remote(updates, signatures, HashMap::new()).await;
+        assert_eq!(res, Err(CRDTError::Conflict(vec![id.clone()])));
+
+        doc1.merge_from(&doc2).await.unwrap();
+        assert!(doc1.accept_suggestion(&id, 1).await);
+        let updates = doc1.compute_diff(&Default::default()).await;
+        let signatures = doc1.signatures.signatures_for(&updates);
+        assert_eq!(signatures.resolutions.len(), 1);
+        txn.update_remote(updates, signatures, HashMap::new())
+            .await
+            .unwrap();
+        let text = txn.doc.lock().await.render_suggestions().await;
+        assert_eq!(text, "abcX");
+    }
+}
+
+#[cfg(test)]
+mod comment_test {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use crate::crdt::auth::{Permissions, Role};
+    use crate::crdt::comment::{Comment, CommentID, Resolution};
+    use crate::crdt::doc::Doc;
+    use crate::crdt::e2e::KeyRing;
+    use crate::crdt::sync_txn::SyncTransaction;
+    use crate::crdt::utils::{serve_rpc, ClientID};
+    use crate::test_utils::{insert_doc, peer};
+    use tokio::sync::mpsc::channel;
+    use tokio::sync::mpsc::{Receiver, Sender};
+    use tokio::sync::Mutex;
+
+    // Threads follow their text through concurrent edits and become orphaned once it is deleted
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn threads_follow_text() {
+        let mut doc1 = Doc::new("comment_doc".to_string(), 1);
+        let mut doc2 = Doc::new("comment_doc".to_string(), 2);
+        insert_doc(&mut doc1, "hello world", 0).await;
+        let id = doc1
+            .add_comment(6, 5, "which world?".to_string())
+            .await
+            .unwrap();
+        assert!(doc1.add_comment(6, 0, "".to_string()).await.is_none());
+        assert!(doc1.add_comment(20, 1, "".to_string()).await.is_none());
+        doc2.merge_from(&doc1).await.unwrap();
+
+        // concurrent replies and edits
+        let reply2 = doc2.reply_to(&id, "this one".to_string()).unwrap();
+        let reply1 = doc1.reply_to(&id, "ok".to_string()).unwrap();
+        insert_doc(&mut doc2, "oh ", 0).await;
+        doc1.merge_from(&doc2).await.unwrap();
+        doc1.reply_to(&reply2, "thanks".to_string()).unwrap();
+        doc2.merge_from(&doc1).await.unwrap();
+
+        let threads = doc1.comment_threads().await;
+        assert_eq!(threads, doc2.comment_threads().await);
+        assert_eq!(threads.len(), 1);
+        assert_eq!(threads[0].comment.id, id);
+        assert_eq!(threads[0].range, Some((9, 14)));
+        let replies: Vec<(&str, ClientID)> = threads[0]
+            .replies
+            .iter()
+            .map(|reply| (reply.text.as_str(), reply.author()))
+            .collect();
+        assert_eq!(replies, vec![("ok", 1), ("this one", 2), ("thanks", 1)]);
+        assert_eq!(threads[0].replies[0].id, reply1);
+
+        // the latest resolution wins, replies resolve their thread
+        doc1.resolve_comment(&id, true);
+        doc2.resolve_comment(&reply2, true);
+        doc2.resolve_comment(&id, false);
+        doc1.merge_from(&doc2).await.unwrap();
+        doc2.merge_from(&doc1).await.unwrap();
+        assert!(!doc1.comment_threads().await[0].is_resolved());
+        assert!(!doc2.comment_threads().await[0].is_resolved());
+
+        doc1.delete_local(9, 2).await;
+        assert_eq!(doc1.comment_threads().await[0].range, Some((9, 12)));
+        doc1.delete_local(9, 3).await;
+        assert_eq!(doc1.to_string().await, "oh hello ");
+        let threads = doc1.comment_threads().await;
+        assert!(threads[0].is_orphaned());
+        assert_eq!(threads[0].replies.len(), 3);
+    }
+
+    // Comments are exchanged with peers, the ones of clients without comment access are dropped
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn sync_comments() {
+        let mut permissions = Permissions::default();
+        for client in 1..=3 as ClientID {
+            permissions
+                .tokens
+                .insert(format!("token{}", client), client);
+        }
+        let mut roles = HashMap::new();
+        roles.insert(1, Role::Owner);
+        roles.insert(2, Role::Commenter);
+        roles.insert(3, Role::Viewer);
+        permissions.docs.insert("comment_doc".to_string(), roles);
+        let permissions = Arc::new(permissions);
+
+        let init_txn = |client_id: ClientID, ip: &str| {
+            let doc = Arc::new(Mutex::new(Doc::new("comment_doc".to_string(), client_id)));
+            let mut txn = SyncTransaction::new(
+                "comment_doc".to_string(),
+                client_id,
+                doc,
+                Arc::new(Mutex::new(HashMap::new())),
+                ip.to_string(),
+            );
+            txn.set_permissions(permissions.clone());
+            txn.set_token(format!("token{}", client_id));
+            txn
+        };
+        let txn1 = init_txn(1, "127.0.0.1:5401");
+        insert_doc(&mut *txn1.doc.lock().await, "hello world", 0).await;
+        let id1 = txn1
+            .doc
+            .lock()
+            .await
+            .add_comment(0, 5, "greeting".to_string())
+            .await
+            .unwrap();
+        let txn1_bg = txn1.clone();
+        let txn_rpc = txn1.clone();
+        let (_sender, receiver): (Sender<()>, Receiver<()>) = channel(1);
+        let (init_sender, mut init_receiver): (Sender<()>, Receiver<()>) = channel(1);
+        tokio::spawn(async move {
+            serve_rpc(txn_rpc, txn1_bg, receiver, init_sender)
+                .await
+                .unwrap();
+        });
+        let _ = init_receiver.recv().await;
+
+        let txn2 = init_txn(2, "");
+        {
+            let mut doc2 = txn2.doc.lock().await;
+            doc2.peers = vec![peer(1, 5401)];
+            doc2.merge_from(&*txn1.doc.lock().await).await.unwrap();
+            doc2.comments.comments.clear();
+            doc2.add_comment(6, 5, "planet?".to_string()).await.unwrap();
+            // a viewer cannot comment, even through a peer
+            let forged = CommentID { client: 3, seq: 0 };
+            doc2.comments.apply_remote(vec![Comment {
+                id: forged,
+                parent: None,
+                anchor: None,
+                text: "spam".to_string(),
+                resolution: Resolution {
+                    resolved: false,
+                    version: 0,
+                    by: 3,
+                },
+                sealed: None,
+            }]);
+            // nor can a commenter resolve the thread of the owner on its behalf
+            let mut greeting = txn1.doc.lock().await.comments.comments[&id1].clone();
+            greeting.resolution = Resolution {
+                resolved: true,
+                version: 5,
+                by: 1,
+            };
+            doc2.comments.apply_remote(vec![greeting]);
+        }
+        txn2.sync_comments().await.unwrap();
+
+        let texts = |doc: &Doc| -> Vec<String> {
+            doc.comments
+                .get_comments()
+                .into_iter()
+                .map(|comment| comment.text)
+                .collect()
+        };
+        let expected = vec!["greeting".to_string(), "planet?".to_string()];
+        assert_eq!(texts(&*txn1.doc.lock().await), expected);
+        assert_eq!(texts(&*txn2.doc.lock().await)[..2], expected[..]);
+        let threads = txn1.doc.lock().await.comment_threads().await;
+        assert_eq!(threads[0].comment.id, id1);
+        assert!(!threads[0].is_resolved());
+
+        // the next sync only sends what changed since (and what has been refused)
+        let clock = txn2.comment_clocks.lock().await[&1].clone();
+        let missing: Vec<CommentID> = txn2
+            .doc
+            .lock()
+            .await
+            .comments
+            .missing_from(&clock)
+            .into_iter()
+            .map(|comment| comment.id)
+            .collect();
+        assert_eq!(missing, vec![id1, CommentID { client: 3, seq: 0 }]);
+        assert!(txn1.doc.lock().await.resolve_comment(&id1, true));
+        let missing = txn1.doc.lock().await.comments.missing_from(&clock);
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].id, id1);
+        txn2.sync_comments().await.unwrap();
+        let resolution = txn1.doc.lock().await.comments.comments[&id1].resolution;
+        assert_eq!(resolution.version, 1);
+
+        // a viewer cannot sync comments at all
+        let txn3 = init_txn(3, "");
+        txn3.doc.lock().await.peers = txn2.doc.lock().await.peers.clone();
+        assert!(txn3.sync_comments().await.is_err());
+    }
+
+    // Replies that answer each other belong to no thread
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn looping_replies() {
+        let mut doc = Doc::new("comment_doc".to_string(), 1);
+        let ids = [
+            CommentID { client: 2, seq: 0 },
+            CommentID { client: 2, seq: 1 },
+        ];
+        doc.comments.apply_remote(
+            (0..2)
+                .map(|i| Comment {
+                    id: ids[i],
+                    parent: Some(ids[1 - i]),
+                    anchor: None,
+                    text: "loop".to_string(),
+                    resolution: Resolution {
+                        resolved: false,
+                        version: 0,
+                        by: 2,
+                    },
+                    sealed: None,
+                })
+                .collect(),
+        );
+        assert_eq!(doc.comments.thread_of(&ids[0]), None);
+        assert!(!doc.resolve_comment(&ids[1], true));
+        assert!(doc.comment_threads().await.is_empty());
+    }
+
+    // Texts of comments are sealed like the text of the doc, nodes without the key keep them sealed
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn sealed_comments() {
+        let keys = KeyRing::generate();
+        let mut doc1 = Doc::new("comment_doc".to_string(), 1);
+        doc1.encryption.enable(Some(keys.clone()));
+        insert_doc(&mut doc1, "hello world", 0).await;
+        let id = doc1
+            .add_comment(0, 5, "greeting".to_string())
+            .await
+            .unwrap();
+        let sealed = doc1.encryption.seal_comments(doc1.comments.get_comments());
+        assert_eq!(sealed[0].text, "");
+        assert!(sealed[0].sealed.is_some());
+
+        // a relay keeps them as they are
+        let mut relay = Doc::new("comment_doc".to_string(), 2);
+        relay.comments.apply_remote(sealed.clone());
+        assert_eq!(
+            relay
+                .encryption
+                .seal_comments(relay.comments.get_comments()),
+            sealed
+        );
+
+        let mut doc3 = Doc::new("comment_doc".to_string(), 3);
+        doc3.encryption.enable(Some(keys));
+        let opened = doc3.encryption.open_comments(sealed.clone());
+        assert_eq!(opened[0].text, "greeting");
+        assert_eq!(opened[0].id, id);
+
+        // a peer without the key version gets them once it has the key
+        let mut doc4 = Doc::new("comment_doc".to_string(), 4);
+        doc4.encryption.enable(None);
+        assert!(doc4.encryption.open_comments(sealed.clone()).is_empty());
+        assert!(doc4.encryption.needs_keys());
+        let mut forged = sealed;
+        forged[0].sealed.as_mut().unwrap().ciphertext[0] ^= 1;
+        assert!(doc3.encryption.open_comments(forged).is_empty());
+    }
+}
+
+#[cfg(test)]
+mod embed_test {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use crate::crdt::block::{Block, BlockID, Content};
+    use crate::crdt::doc::{Doc, VectorClock};
+    use crate::crdt::e2e::KeyRing;
+    use crate::crdt::embed::{DeltaInsert, DeltaOp, Embed, EmbedPolicy, Payload, SEALED_EMBED};
+    use crate::crdt::signing::{Signatures, Signer};
+    use crate::test_utils::{init_txn, insert_doc};
+    use serde_json::json;
+
+    fn image(src: &str) -> Embed {
+        Embed {
+            kind: "image".to_string(),
+            alt: None,
+            payload: Payload::Json(json!({ "src": src })),
+        }
+    }
+
+    fn mention(name: &str) -> Embed {
+        Embed {
+            kind: "mention".to_string(),
+            alt: Some(format!("@{}", name)),
+            payload: Payload::Binary(name.as_bytes().to_vec()),
+        }
+    }
+
+    // Embeds are ordered, deleted and synchronized like characters and rendered by policy
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn embeds_in_text() {
+        let mut doc1 = Doc::new("embed_doc".to_string(), 1);
+        insert_doc(&mut doc1, "hello world", 0).await;
+        let id = doc1.insert_embed(image("a.png"), 5).await.unwrap();
+        assert_eq!(id, BlockID::new(1, 11));
+        doc1.insert_embed(mention("bob"), 6).await.unwrap();
+        doc1.insert_embed(mention("eve"), 7).await.unwrap();
+        insert_doc(&mut doc1, "!", 8).await;
+        assert_eq!(doc1.to_string().await, "hello\u{1A}\u{1A}\u{1A}! world");
+        assert_eq!(
+            doc1.to_string_with(EmbedPolicy::Alt).await,
+            "hello[image]@bob@eve! world"
+        );
+
+        // embeds are never merged, even with the embeds next to them
+        doc1.block_store.lock().await.compact().await;
+        let mut doc2 = Doc::new("embed_doc".to_string(), 2);
+        doc2.merge_from(&doc1).await.unwrap();
+        doc2.delete_local(7, 1).await;
+        doc1.merge_from(&doc2).await.unwrap();
+        for doc in [&doc1, &doc2] {
+            assert_eq!(doc.to_string_with(EmbedPolicy::Skip).await, "hello! world");
+            assert_eq!(
+                doc.delta().await,
+                vec![
+                    DeltaOp {
+                        insert: DeltaInsert::Text("hello".to_string())
+                    },
+                    DeltaOp {
+                        insert: DeltaInsert::Embed(image("a.png"))
+                    },
+                    DeltaOp {
+                        insert: DeltaInsert::Embed(mention("bob"))
+                    },
+                    DeltaOp {
+                        insert: DeltaInsert::Text("! world".to_string())
+                    },
+                ]
+            );
+        }
+
+        let delta = serde_json::to_value(doc1.delta().await).unwrap();
+        assert_eq!(
+            delta,
+            json!([
+                { "insert": "hello" },
+                { "insert": { "kind": "image", "payload": { "type": "json", "data": { "src": "a.png" } } } },
+                { "insert": { "kind": "mention", "alt": "@bob", "payload": { "type": "binary", "data": "626f62" } } },
+                { "insert": "! world" },
+            ])
+        );
+        let decoded: Vec<DeltaOp> = serde_json::from_value(delta).unwrap();
+        assert_eq!(decoded, doc1.delta().await);
+
+        // the change log replays embeds
+        let mut text = String::new();
+        for op in doc1.history().await.text_ops().await {
+            op.apply(&mut text);
+        }
+        assert_eq!(text, doc1.to_string().await);
+    }
+
+    // The payload of an embed is covered by the signature of its insertion
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn signed_embeds() {
+        let mut doc = Doc::new("embed_doc".to_string(), 1);
+        doc.signatures.set_signer(1, Arc::new(Signer::generate()));
+        insert_doc(&mut doc, "ab", 0).await;
+        let id = doc.insert_embed(image("a.png"), 1).await.unwrap();
+        let block = doc.block_store.lock().await.block_map[&id]
+            .lock()
+            .await
+            .clone();
+        assert!(doc.signatures.verify_block(&block));
+
+        let mut forged = block.clone();
+        forged.content = Content::Embed(image("b.png"));
+        assert!(!doc.signatures.verify_block(&forged));
+        forged.content = Content::text("\u{1A}");
+        assert!(!doc.signatures.verify_block(&forged));
+
+        // sealed payloads only come from the encryption of the doc
+        let sealed = Embed {
+            kind: SEALED_EMBED.to_string(),
+            alt: None,
+            payload: Payload::Sealed(vec![0; 16]),
+        };
+        assert!(doc.insert_embed(sealed, 0).await.is_err());
+    }
+
+    // Embeds of an encrypted doc leave it sealed, nodes without the key relay them as they are
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn encrypted_embeds() {
+        let keys = KeyRing::generate();
+        let mut doc = Doc::new("embed_doc".to_string(), 1);
+        doc.signatures.set_signer(1, Arc::new(Signer::generate()));
+        doc.encryption.enable(Some(keys.clone()));
+        insert_doc(&mut doc, "ab", 0).await;
+        doc.insert_embed(image("a.png"), 1).await.unwrap();
+        let diff = doc.compute_diff(&VectorClock::new()).await;
+        let embed = diff
+            .iter()
+            .find(|block| block.content.embed().is_some())
+            .unwrap();
+        assert_eq!(embed.content.embed().unwrap().kind, SEALED_EMBED);
+        assert!(!serde_json::to_string(&diff).unwrap().contains("a.png"));
+        assert!(doc.signatures.verify_block(embed));
+
+        let mut relay = Doc::new("embed_doc".to_string(), 2);
+        relay.merge_from(&doc).await.unwrap();
+        let kinds: Vec<String> = relay
+            .delta()
+            .await
+            .into_iter()
+            .filter_map(|op| match op.insert {
+                DeltaInsert::Embed(embed) => Some(embed.kind),
+                DeltaInsert::Text(_) => None,
+            })
+            .collect();
+        assert_eq!(kinds, vec![SEALED_EMBED.to_string()]);
+
+        let mut peer = Doc::new("embed_doc".to_string(), 3);
+        peer.encryption.enable(Some(keys.clone()));
+        peer.merge_from(&relay).await.unwrap();
+        assert_eq!(peer.delta().await, doc.delta().await);
+
+        // a tampered payload cannot be opened
+        let mut other = Doc::new("embed_doc".to_string(), 4);
+        other.encryption.enable(Some(keys));
+        other.encryption.merge_epochs(doc.encryption.epochs.clone());
+        let mut forged = diff.clone();
+        for block in forged.iter_mut() {
+            if let Content::Embed(Embed {
+                payload: Payload::Sealed(ciphertext),
+                ..
+            }) = &mut block.content
+            {
+                ciphertext[0] ^= 1;
+            }
+        }
+        other.insert_remote(forged).await;
+        assert_eq!(other.to_string().await, "ab");
+    }
+
+    // An embed whose key is gone is held back rather than sent in the clear
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn unsealable_embeds() {
+        let mut doc = Doc::new("embed_doc".to_string(), 1);
+        doc.encryption.enable(Some(KeyRing::generate()));
+        insert_doc(&mut doc, "ab", 0).await;
+        doc.insert_embed(image("a.png"), 1).await.unwrap();
+        doc.encryption.keys.keys.clear();
+        let diff = doc.compute_diff(&VectorClock::new()).await;
+        assert!(diff.iter().all(|block| block.content.embed().is_none()));
+        assert!(!serde_json::to_string(&diff).unwrap().contains("a.png"));
+    }
+
+    // An embed is a single character, a block claiming a longer or empty one cannot be decoded
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn malformed_embeds() {
+        let mut doc = Doc::new("embed_doc".to_string(), 1);
+        doc.insert_embed(image("a.png"), 0).await.unwrap();
+        let embed = doc.compute_diff(&VectorClock::new()).await.remove(0);
+        assert_eq!(embed.content.len(), 1);
+
+        let mut encoded = serde_json::to_value(&embed).unwrap();
+        for content in [
+            json!({"text": "\u{1A}\u{1A}", "embed": image("a.png")}),
+            json!({"embed": "ab"}),
+            json!(""),
+        ] {
+            encoded["content"] = content;
+            assert!(serde_json::from_value::<Block>(encoded.clone()).is_err());
+        }
+
+        let txn = init_txn("embed_doc", 2, "");
+        txn.update_remote(vec![embed], Signatures::default(), HashMap::new())
+            .await
+            .unwrap();
+        let delta = txn.doc.lock().await.delta().await;
+        assert_eq!(
+            delta,
+            vec![DeltaOp {
+                insert: DeltaInsert::Embed(image("a.png"))
+            }]
+        );
+    }
+}
+
+#[cfg(test)]
+mod xml_test {
+    use std::collections::BTreeMap;
+
+    use crate::crdt::doc::Doc;
+    use crate::crdt::e2e::KeyRing;
+    use crate::crdt::xml::{XmlElement, XmlNode};
+    use crate::test_utils::insert_doc;
+
+    fn element(name: &str, attributes: &[(&str, &str)], children: Vec<XmlNode>) -> XmlNode {
+        XmlNode::Element(XmlElement {
+            id: None,
+            name: name.to_string(),
+            attributes: attributes
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect::<BTreeMap<_, _>>(),
+            children,
+        })
+    }
+
+    // Children are inserted, moved and deleted and attributes are set through the tree
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn edit_tree() {
+        let mut doc = Doc::new("xml_doc".to_string(), 1);
+        let root = doc
+            .insert_child(
+                None,
+                0,
+                &element(
+                    "doc",
+                    &[],
+                    vec![element(
+                        "p",
+                        &[("align", "left")],
+                        vec![XmlNode::text("hello")],
+                    )],
+                ),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(doc.to_xml().await, "<doc><p align=\"left\">hello</p></doc>");
+
+        let list = doc
+            .insert_child(Some(&root), 1, &element("ul", &[], vec![]))
+            .await
+            .unwrap()
+            .unwrap();
+        for (i, item) in ["one", "two"].iter().enumerate() {
+            doc.insert_child(
+                Some(&list),
+                i,
+                &element("li", &[], vec![XmlNode::text(item)]),
+            )
+            .await
+            .unwrap();
+        }
+        doc.insert_child(Some(&root), 0, &XmlNode::text("a < b"))
+            .await
+            .unwrap();
+        assert_eq!(
+            doc.to_xml().await,
+            "<doc>a &lt; b<p align=\"left\">hello</p><ul><li>one</li><li>two</li></ul></doc>"
+        );
+
+        // the paragraph goes to the end of the list, then the text is dropped
+        doc.move_child(Some(&root), 1, Some(&list), 2)
+            .await
+            .unwrap();
+        doc.delete_child(Some(&root), 0).await.unwrap();
+        doc.set_attribute(&list, "class", Some("todo"))
+            .await
+            .unwrap();
+        assert_eq!(
+            doc.to_xml().await,
+            "<doc><ul class=\"todo\"><li>one</li><li>two</li><p align=\"left\">hello</p></ul></doc>"
+        );
+
+        let tree = doc.xml().await;
+        let paragraph = match &tree[0] {
+            XmlNode::Element(doc) => match &doc.children[0] {
+                XmlNode::Element(list) => list.children[2].clone(),
+                _ => panic!("expected the list"),
+            },
+            _ => panic!("expected the root"),
+        };
+        let paragraph = match paragraph {
+            XmlNode::Element(paragraph) => paragraph.id.unwrap(),
+            _ => panic!("expected the paragraph"),
+        };
+        doc.set_attribute(&paragraph, "align", Some("right"))
+            .await
+            .unwrap();
+        doc.set_attribute(&list, "class", None).await.unwrap();
+        assert_eq!(
+            doc.to_xml().await,
+            "<doc><ul><li>one</li><li>two</li><p align=\"right\">hello</p></ul></doc>"
+        );
+
+        // nodes cannot be moved into themselves, and children must exist
+        assert!(doc
+            .move_child(Some(&root), 0, Some(&list), 0)
+            .await
+            .is_err());
+        assert!(doc.delete_child(Some(&list), 3).await.is_err());
+        assert!(doc
+            .insert_child(Some(&list), 4, &XmlNode::text("x"))
+            .await
+            .is_err());
+    }
+
+    // Concurrent edits of the tree converge and a node deleted under a concurrent edit
+    // leaves the new content to its parent
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_edits() {
+        let mut doc1 = Doc::new("xml_doc".to_string(), 1);
+        let root = doc1
+            .insert_child(None, 0, &element("doc", &[], vec![]))
+            .await
+            .unwrap()
+            .unwrap();
+        let quote = doc1
+            .insert_child(Some(&root), 0, &element("quote", &[], vec![]))
+            .await
+            .unwrap()
+            .unwrap();
+        let mut doc2 = Doc::new("xml_doc".to_string(), 2);
+        doc2.merge_from(&doc1).await.unwrap();
+
+        doc1.insert_child(Some(&quote), 0, &XmlNode::text("kept"))
+            .await
+            .unwrap();
+        doc1.set_attribute(&root, "lang", Some("en")).await.unwrap();
+        doc2.delete_child(Some(&root), 0).await.unwrap();
+        doc2.insert_child(
+            Some(&root),
+            0,
+            &element("h1", &[], vec![XmlNode::text("title")]),
+        )
+        .await
+        .unwrap();
+        doc2.set_attribute(&root, "lang", Some("fr")).await.unwrap();
+
+        doc1.merge_from(&doc2).await.unwrap();
+        doc2.merge_from(&doc1).await.unwrap();
+        let xml = doc1.to_xml().await;
+        assert_eq!(xml, doc2.to_xml().await);
+        assert_eq!(doc1.xml().await, doc2.xml().await);
+        assert!(xml.contains("<h1>title</h1>"));
+        assert!(xml.contains("kept") && !xml.contains("quote"));
+        assert!(xml.starts_with("<doc lang="));
+    }
+
+    // A move is a deletion and an insertion, so text inserted into the moved node
+    // at the same time stays at its old place, in the parent it was moved from
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_move() {
+        let mut doc1 = Doc::new("xml_doc".to_string(), 1);
+        let root = doc1
+            .insert_child(
+                None,
+                0,
+                &element(
+                    "doc",
+                    &[],
+                    vec![
+                        element("p", &[], vec![XmlNode::text("hello")]),
+                        element("ul", &[], vec![]),
+                    ],
+                ),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        let mut doc2 = Doc::new("xml_doc".to_string(), 2);
+        doc2.merge_from(&doc1).await.unwrap();
+
+        let list = match &doc1.xml().await[0] {
+            XmlNode::Element(doc) => match &doc.children[1] {
+                XmlNode::Element(list) => list.id.clone().unwrap(),
+                _ => panic!("expected the list"),
+            },
+            _ => panic!("expected the root"),
+        };
+        doc1.move_child(Some(&root), 0, Some(&list), 0)
+            .await
+            .unwrap();
+        let pos = doc2.to_string().await.find("hello").unwrap() as u32 + 5;
+        insert_doc(&mut doc2, " world", pos).await;
+
+        doc1.merge_from(&doc2).await.unwrap();
+        doc2.merge_from(&doc1).await.unwrap();
+        assert_eq!(doc1.to_xml().await, doc2.to_xml().await);
+        assert_eq!(
+            doc1.to_xml().await,
+            "<doc> world<ul><p>hello</p></ul></doc>"
+        );
+    }
+
+    // The tree of an encrypted doc is sealed, nodes without the key only see its text length
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn encrypted_tree() {
+        let keys = KeyRing::generate();
+        let mut doc = Doc::new("xml_doc".to_string(), 1);
+        doc.encryption.enable(Some(keys.clone()));
+        let root = doc
+            .insert_child(None, 0, &element("doc", &[("lang", "en")], vec![]))
+            .await
+            .unwrap()
+            .unwrap();
+        doc.insert_child(
+            Some(&root),
+            0,
+            &element("p", &[], vec![XmlNode::text("hello")]),
+        )
+        .await
+        .unwrap();
+        doc.move_child(Some(&root), 0, None, 1).await.unwrap();
+        let xml = "<doc lang=\"en\"/><p>hello</p>";
+        assert_eq!(doc.to_xml().await, xml);
+
+        let mut relay = Doc::new("xml_doc".to_string(), 2);
+        relay.merge_from(&doc).await.unwrap();
+        let sealed = relay.to_xml().await;
+        assert!(!sealed.contains("doc") && !sealed.contains("hello"));
+
+        let mut peer = Doc::new("xml_doc".to_string(), 3);
+        peer.encryption.enable(Some(keys));
+        peer.merge_from(&relay).await.unwrap();
+        assert_eq!(peer.to_xml().await, xml);
+    }
+}
+
+#[cfg(test)]
+mod editor_test {
+    use std::time::Duration;
+
+    use crate::crdt::block::Content;
+    use crate::crdt::utils::{serve_rpc, ClientID, Peer};
+    use crate::test_utils::{init_txn, peer};
+    use crate::tui::editor::Editor;
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+    use tokio::sync::mpsc::channel;
+    use tokio::sync::mpsc::{Receiver, Sender};
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    // Text typed by a peer shows up in the editor after a sync,
+    // and the cursor moves over non-ascii text char by char
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn remote_edit() {
+        let peers: Vec<Peer> = [5021, 5022]
+            .iter()
+            .enumerate()
+            .map(|(i, port)| peer(i as ClientID + 1, *port))
+            .collect();
+        let mut txns = vec![];
+        let mut senders = vec![];
+        for peer in peers.iter() {
+            let txn_rpc = init_txn("editor_doc", peer.client_id, &peer.ip_addr);
+            let txn_bg = init_txn("editor_doc", peer.client_id, &peer.ip_addr);
+            txn_rpc.doc.lock().await.peers = peers.clone();
+            txns.push(txn_rpc.clone());
+            let (sender, receiver): (Sender<()>, Receiver<()>) = channel(1);
+            let (init_sender, mut init_receiver): (Sender<()>, Receiver<()>) = channel(1);
+            tokio::spawn(async move {
+                serve_rpc(txn_rpc, txn_bg, receiver, init_sender)
+                    .await
+                    .unwrap();
+            });
+            let _ = init_receiver.recv().await;
+            senders.push(sender);
+        }
+
+        let mut editor = Editor::new(txns[0].clone(), Duration::from_secs(1));
+        editor.handle_key(key(KeyCode::Char('!'))).await;
+        txns[1]
+            .doc
+            .lock()
+            .await
+            .insert_local(Content::Text("héllo ".to_string()), 0)
+            .await;
+        editor.sync().await;
+        // concurrent insertions at the same position are ordered by client
+        assert_eq!(txns[0].doc.lock().await.to_string().await, "!héllo ");
+
+        // the cursor is still right after '!'
+        editor.handle_key(key(KeyCode::Right)).await;
+        editor.handle_key(key(KeyCode::Right)).await;
+        editor.handle_key(key(KeyCode::Backspace)).await;
+        editor.handle_key(key(KeyCode::Char('ê'))).await;
+        editor.handle_key(key(KeyCode::End)).await;
+        editor.handle_key(key(KeyCode::Char('?'))).await;
+        assert_eq!(txns[0].doc.lock().await.to_string().await, "!hêllo ?");
+
+        for sender in senders {
+            let _ = sender.send(()).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod zk_test {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use crate::crdt::doc::Doc;
+    use crate::crdt::sync_txn::SyncTransaction;
+    use crate::crdt::utils::{serve_rpc, ClientID};
+    use std::{thread, time};
+    use tokio::sync::mpsc::channel;
+    use tokio::sync::mpsc::{Receiver, Sender};
+    use tokio::sync::Mutex;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn zk_register_test() {
+        // init channels used for rpc and user service communication
+        let (sender1, receiver1): (Sender<()>, Receiver<()>) = channel(1);
+        let (init_sender1, mut init_receiver1): (Sender<()>, Receiver<()>) = channel(1);
+        let (sender2, receiver2): (Sender<()>, Receiver<()>) = channel(1);
+        let (init_sender2, mut init_receiver2): (Sender<()>, Receiver<()>) = channel(1);
+
+        let (txn_rpc1, txn_service1, txn_bg1) =
+            init_txn_w_rpc("doc".to_string(), 1, "127.0.0.1:4001".to_string()).await;
+        let (txn_rpc2, txn_service2, txn_bg2) =
+            init_txn_w_rpc("doc".to_string(), 2, "127.0.0.1:4002".to_string()).await;
+
+        // start rpc services
+        tokio::spawn(async move {
+            serve_rpc(txn_rpc1, txn_bg1, receiver1, init_sender1)
+                .await
+                .unwrap();
+        });
+        let wait = time::Duration::from_secs(2);
+        thread::sleep(wait);
+        tokio::spawn(async move {
+            serve_rpc(txn_rpc2, txn_bg2, receiver2, init_sender2)
+                .await
+                .unwrap();
+        });
+
+        // start user operation
+        let _ = init_receiver1.recv().await;
+        let _ = init_receiver2.recv().await;
+
+        println!("----------- start op --------------");
+        tokio::spawn(async move {
+            let succ = txn_service1.register().await;
+            assert_eq!(Ok(()), succ);
+        });
+        tokio::spawn(async move {
+            let succ = txn_service2.register().await;
+            assert_eq!(Ok(()), succ);
+        });
+
+        // wait for all operations to finish
+        let wait = time::Duration::from_secs(2);
+        thread::sleep(wait);
+
+        // shutdown rpc service
+        let _ = sender1.send(()).await;
+        let _ = sender2.send(()).await;
+
+        // wait for rpc to shutdown
+        let wait = time::Duration::from_secs(2);
+        thread::sleep(wait);
+    }
+
+    // is it possible to make sure the order of registration?
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn zk_register_test_more_nodes() {
+        let (sender1, receiver1): (Sender<()>, Receiver<()>) = channel(1);
+        let (init_sender1, mut init_receiver1): (Sender<()>, Receiver<()>) = channel(1);
+        let (sender2, receiver2): (Sender<()>, Receiver<()>) = channel(1);
+        let (init_sender2, mut init_receiver2): (Sender<()>, Receiver<()>) = channel(1);
+        let (sender3, receiver3): (Sender<()>, Receiver<()>) = channel(1);
+        let (init_sender3, mut init_receiver3): (Sender<()>, Receiver<()>) = channel(1);
+        let (sender4, receiver4): (Sender<()>, Receiver<()>) = channel(1);
+        let (init_sender4, mut init_receiver4): (Sender<()>, Receiver<()>) = channel(1);
+
+        let (txn_rpc1, txn_service1, txn_bg1) =
+            init_txn_w_rpc("doc".to_string(), 1, "127.0.0.1:4001".to_string()).await;
+        let (txn_rpc2, txn_service2, txn_bg2) =
+            init_txn_w_rpc("doc".to_string(), 2, "127.0.0.1:4002".to_string()).await;
+        let (txn_rpc3, txn_service3, txn_bg3) =
+            init_txn_w_rpc("doc".to_string(), 3, "127.0.0.1:4003".to_string()).await;
+        let (txn_rpc4, txn_service4, txn_bg4) =
+            init_txn_w_rpc("doc".to_string(), 4, "127.0.0.1:4004".to_string()).await;
+
+        // start 1 and 2
+        tokio::spawn(async move {
+            serve_rpc(txn_rpc1, txn_bg1, receiver1, init_sender1)
+                .await
+                .unwrap();
+        });
+        tokio::spawn(async move {
+            serve_rpc(txn_rpc2, txn_bg2, receiver2, init_sender2)
+                .await
+                .unwrap();
+        });
+
+        let _ = init_receiver1.recv().await;
+        let _ = init_receiver2.recv().await;
+        tokio::spawn(async move {
+            let succ = txn_service1.register().await;
+            assert_eq!(Ok(()), succ);
+        });
+        tokio::spawn(async move {
+            let succ = txn_service2.register().await;
+            assert_eq!(Ok(()), succ);
+        });
+
+        // start 3 and 4
+        tokio::spawn(async move {
+            serve_rpc(txn_rpc3, txn_bg3, receiver3, init_sender3)
+                .await
+                .unwrap();
+        });
+        tokio::spawn(async move {
+            serve_rpc(txn_rpc4, txn_bg4, receiver4, init_sender4)
+                .await
+                .unwrap();
+        });
+
+        let _ = init_receiver3.recv().await;
+        let _ = init_receiver4.recv().await;
+
+        // delay the op, see if it got propagated
+        let wait = time::Duration::from_secs(2);
+        thread::sleep(wait);
+
+        // delay the op, see if it got propagated
+        let wait = time::Duration::from_secs(2);
+        thread::sleep(wait);
+
+        tokio::spawn(async move {
+            let succ = txn_service3.register().await;
+            assert_eq!(Ok(()), succ);
+        });
+        tokio::spawn(async move {
+            let succ = txn_service4.register().await;
+            assert_eq!(Ok(()), succ);
+        });
+
+        // wait for all operations to finish
+        let wait = time::Duration::from_secs(2);
+        thread::sleep(wait);
+
+        // shutdown rpc service
+        let _ = sender1.send(()).await;
+        let _ = sender2.send(()).await;
+        let _ = sender3.send(()).await;
+        let _ = sender4.send(()).await;
+
+        // wait for rpc to shutdown
+        let wait = time::Duration::from_secs(2);
+        thread::sleep(wait);
+    }
+
+    // create a transaction (one is rpc service, one is the real service that a client use)
+    pub async fn init_txn_w_rpc(
         doc_name: String,
         client_id: ClientID,
         client_ip: String,
@@ -694,13 +4710,8 @@ mod perf_tests {
                 .map(char::from)
                 .collect();
 
-            doc.insert_local(
-                Content {
-                    content: rand_string.clone(),
-                },
-                rand_pos as u32,
-            )
-            .await;
+            doc.insert_local(Content::Text(rand_string.clone()), rand_pos as u32)
+                .await;
             ref_string.insert_str(rand_pos, &rand_string);
             assert_eq!(doc.to_string().await, ref_string);
         }
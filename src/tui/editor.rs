@@ -0,0 +1,364 @@
+use crate::crdt::block::Content;
+use crate::crdt::sync_txn::SyncTransaction;
+use crate::crdt::utils::{CRDTError, CRDTResult, ClientID};
+use crossterm::{
+    cursor::MoveTo,
+    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
+    execute, queue,
+    style::{Attribute, Color, Print, ResetColor, SetAttribute, SetBackgroundColor},
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{channel, Receiver};
+
+// colors used to draw the cursors of other clients
+const CURSOR_COLORS: [Color; 6] = [
+    Color::Red,
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+];
+
+// how long to wait for a key before checking for remote updates
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Editor is a terminal front end of a Doc,
+// keystrokes are translated into local insertions and deletions at the cursor,
+// the doc is synced with peers every sync_interval in the background and redrawn
+pub struct Editor {
+    txn: SyncTransaction,
+    sync_interval: Duration,
+    // local cursor (position in the visible text, in bytes like positions of Doc,
+    // always on a char boundary)
+    cursor: u32,
+    // first row of the text that is displayed
+    scroll: u16,
+    last_sync: Option<Instant>,
+    // error of the last sync, shown in the status line
+    sync_error: Option<CRDTError>,
+    quit: bool,
+}
+
+impl Editor {
+    pub fn new(txn: SyncTransaction, sync_interval: Duration) -> Self {
+        Editor {
+            txn,
+            sync_interval,
+            cursor: 0,
+            scroll: 0,
+            last_sync: None,
+            sync_error: None,
+            quit: false,
+        }
+    }
+
+    // Run the editor until the user quits (Esc or Ctrl-Q)
+    pub async fn run(&mut self) -> io::Result<()> {
+        let mut stdout = io::stdout();
+        terminal::enable_raw_mode()?;
+        execute!(stdout, EnterAlternateScreen)?;
+
+        let res = self.event_loop(&mut stdout).await;
+
+        execute!(stdout, LeaveAlternateScreen)?;
+        terminal::disable_raw_mode()?;
+        res
+    }
+
+    async fn event_loop(&mut self, stdout: &mut io::Stdout) -> io::Result<()> {
+        // syncing takes a network round trip, so it runs in the background
+        // and the loop only picks up its results
+        let (sync_sender, mut sync_receiver) = channel(1);
+        let txn = self.txn.clone();
+        let sync_interval = self.sync_interval;
+        let syncer = tokio::spawn(async move {
+            loop {
+                if sync_sender.send(sync_round(&txn).await).await.is_err() {
+                    break;
+                }
+                tokio::time::sleep(sync_interval).await;
+            }
+        });
+
+        let res = self.edit(stdout, &mut sync_receiver).await;
+        syncer.abort();
+        res
+    }
+
+    async fn edit(
+        &mut self,
+        stdout: &mut io::Stdout,
+        sync_receiver: &mut Receiver<CRDTResult<()>>,
+    ) -> io::Result<()> {
+        while !self.quit {
+            if event::poll(POLL_INTERVAL)? {
+                if let Event::Key(key) = event::read()? {
+                    self.handle_key(key).await;
+                }
+            }
+
+            while let Ok(res) = sync_receiver.try_recv() {
+                self.synced(res).await;
+            }
+
+            self.render(stdout).await?;
+        }
+        Ok(())
+    }
+
+    // Pull remote updates and cursors right away, without waiting for the background sync
+    pub async fn sync(&mut self) {
+        let res = sync_round(&self.txn).await;
+        self.synced(res).await;
+    }
+
+    // Record the result of a sync,
+    // the local cursor follows its anchor if remote edits happened before it
+    async fn synced(&mut self, res: CRDTResult<()>) {
+        self.sync_error = res.err();
+        self.last_sync = Some(Instant::now());
+
+        let doc = self.txn.doc.lock().await;
+        if let Some(pos) = doc.cursor_positions().await.get(&self.txn.client) {
+            self.cursor = *pos;
+        }
+    }
+
+    pub(crate) async fn handle_key(&mut self, key: KeyEvent) {
+        let text = self.txn.doc.lock().await.to_string().await;
+        self.cursor = floor_char_boundary(&text, self.cursor as usize) as u32;
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        // length in bytes of the chars before and after the cursor
+        let before = text[..self.cursor as usize]
+            .chars()
+            .next_back()
+            .map_or(0, |c| c.len_utf8() as u32);
+        let after = text[self.cursor as usize..]
+            .chars()
+            .next()
+            .map_or(0, |c| c.len_utf8() as u32);
+
+        match key.code {
+            KeyCode::Esc => self.quit = true,
+            KeyCode::Char('q') if ctrl => self.quit = true,
+            KeyCode::Char(c) if !ctrl && !c.is_control() => {
+                self.insert(c.to_string()).await;
+            }
+            KeyCode::Enter => self.insert("\n".to_string()).await,
+            KeyCode::Backspace if before > 0 => {
+                self.cursor -= before;
+                let mut doc = self.txn.doc.lock().await;
+                doc.delete_local(self.cursor, before).await;
+            }
+            KeyCode::Delete if after > 0 => {
+                let mut doc = self.txn.doc.lock().await;
+                doc.delete_local(self.cursor, after).await;
+            }
+            KeyCode::Left => self.cursor -= before,
+            KeyCode::Right => self.cursor += after,
+            KeyCode::Home => self.cursor = self.line_bounds().await.0,
+            KeyCode::End => self.cursor = self.line_bounds().await.1,
+            KeyCode::Up => self.move_vertical(-1).await,
+            KeyCode::Down => self.move_vertical(1).await,
+            _ => {}
+        }
+
+        let mut doc = self.txn.doc.lock().await;
+        doc.set_cursor(self.cursor).await;
+    }
+
+    async fn insert(&mut self, content: String) {
+        let len = content.len() as u32;
+        let mut doc = self.txn.doc.lock().await;
+        doc.insert_local(Content::Text(content), self.cursor).await;
+        self.cursor += len;
+    }
+
+    // Start and end position of the line the cursor is on
+    async fn line_bounds(&self) -> (u32, u32) {
+        let text = self.txn.doc.lock().await.to_string().await;
+        let cursor = floor_char_boundary(&text, self.cursor as usize);
+        let start = text[..cursor].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let end = text[cursor..]
+            .find('\n')
+            .map(|i| cursor + i)
+            .unwrap_or(text.len());
+        (start as u32, end as u32)
+    }
+
+    // Move the cursor up (-1) or down (1) one screen row, keeping the column if possible
+    async fn move_vertical(&mut self, delta: i32) {
+        let text = self.txn.doc.lock().await.to_string().await;
+        let (width, _) = terminal::size().unwrap_or((80, 24));
+        let layout = layout(&text, width);
+        let (x, y) = layout[char_index(&text, self.cursor as usize)];
+        let target = y as i32 + delta;
+        if target < 0 {
+            return;
+        }
+
+        // byte offset of every char, and of the end of the text
+        let offsets = text
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(std::iter::once(text.len()));
+        let mut best = None;
+        for (pos, (px, py)) in offsets.zip(layout.iter()) {
+            if *py as i32 == target && *px <= x {
+                best = Some(pos);
+            }
+        }
+        if let Some(best) = best {
+            self.cursor = best as u32;
+        }
+    }
+
+    async fn render(&mut self, stdout: &mut io::Stdout) -> io::Result<()> {
+        let (text, cursors, peers, pending, name) = {
+            let doc = self.txn.doc.lock().await;
+            (
+                doc.to_string().await,
+                doc.cursor_positions().await,
+                doc.peers
+                    .iter()
+                    .filter(|peer| peer.client_id != self.txn.client)
+                    .count(),
+                doc.pending_updates.len(),
+                doc.name.clone(),
+            )
+        };
+        let (width, height) = terminal::size()?;
+        let text_rows = height.saturating_sub(1);
+        self.cursor = floor_char_boundary(&text, self.cursor as usize) as u32;
+
+        let layout = layout(&text, width);
+        let (cursor_x, cursor_y) = layout[char_index(&text, self.cursor as usize)];
+
+        // keep the local cursor on screen
+        if cursor_y < self.scroll {
+            self.scroll = cursor_y;
+        } else if text_rows > 0 && cursor_y >= self.scroll + text_rows {
+            self.scroll = cursor_y - text_rows + 1;
+        }
+        let visible = |y: u16| y >= self.scroll && y < self.scroll + text_rows;
+
+        queue!(stdout, Clear(ClearType::All))?;
+        for (pos, c) in text.chars().enumerate() {
+            let (x, y) = layout[pos];
+            if c != '\n' && visible(y) {
+                queue!(stdout, MoveTo(x, y - self.scroll), Print(c))?;
+            }
+        }
+
+        // cursors of other clients
+        for (client, pos) in remote_cursors(&cursors, self.txn.client, &text) {
+            let (x, y) = layout[char_index(&text, pos)];
+            if !visible(y) {
+                continue;
+            }
+            let under = match text[pos..].chars().next() {
+                Some(c) if c != '\n' => c,
+                _ => ' ',
+            };
+            queue!(
+                stdout,
+                MoveTo(x, y - self.scroll),
+                SetBackgroundColor(cursor_color(client)),
+                Print(under),
+                ResetColor
+            )?;
+        }
+
+        // status line
+        let sync_state = match (&self.sync_error, self.last_sync) {
+            (Some(e), _) => format!("sync failed: {}", e),
+            (None, Some(last_sync)) => format!("synced {}s ago", last_sync.elapsed().as_secs()),
+            (None, None) => "not synced".to_string(),
+        };
+        let status = format!(
+            " {} | client {} | {} peer(s) | {} | {} pending | Esc to quit",
+            name, self.txn.client, peers, sync_state, pending
+        );
+        let status: String = status.chars().take(width as usize).collect();
+        queue!(
+            stdout,
+            MoveTo(0, text_rows),
+            SetAttribute(Attribute::Reverse),
+            Print(format!("{:width$}", status, width = width as usize)),
+            SetAttribute(Attribute::Reset),
+            MoveTo(cursor_x, cursor_y.saturating_sub(self.scroll))
+        )?;
+        stdout.flush()
+    }
+}
+
+// Pull remote updates and cursors once
+async fn sync_round(txn: &SyncTransaction) -> CRDTResult<()> {
+    let res = txn.sync().await;
+    let awareness_res = txn.sync_awareness().await;
+    res.and(awareness_res)
+}
+
+// Compute the screen position (column, row) of every char of text,
+// including the position right after the last one
+fn layout(text: &str, width: u16) -> Vec<(u16, u16)> {
+    let width = width.max(1);
+    let mut res = Vec::with_capacity(text.chars().count() + 1);
+    let (mut x, mut y) = (0u16, 0u16);
+    for c in text.chars() {
+        if x >= width {
+            x = 0;
+            y += 1;
+        }
+        res.push((x, y));
+        if c == '\n' {
+            x = 0;
+            y += 1;
+        } else {
+            x += 1;
+        }
+    }
+    if x >= width {
+        x = 0;
+        y += 1;
+    }
+    res.push((x, y));
+    res
+}
+
+// Largest char boundary of text at or before pos, clamped to the text
+fn floor_char_boundary(text: &str, pos: usize) -> usize {
+    let mut pos = pos.min(text.len());
+    while !text.is_char_boundary(pos) {
+        pos -= 1;
+    }
+    pos
+}
+
+// Number of chars of text before the byte offset pos, i.e. its index in the layout
+fn char_index(text: &str, pos: usize) -> usize {
+    text[..floor_char_boundary(text, pos)].chars().count()
+}
+
+// Cursors of all clients except the local one, clamped to the text and its char boundaries
+fn remote_cursors(
+    cursors: &HashMap<ClientID, u32>,
+    local: ClientID,
+    text: &str,
+) -> Vec<(ClientID, usize)> {
+    let mut res: Vec<(ClientID, usize)> = cursors
+        .iter()
+        .filter(|(client, _)| **client != local)
+        .map(|(client, pos)| (*client, floor_char_boundary(text, *pos as usize)))
+        .collect();
+    res.sort();
+    res
+}
+
+fn cursor_color(client: ClientID) -> Color {
+    CURSOR_COLORS[client as usize % CURSOR_COLORS.len()]
+}
@@ -67,7 +67,9 @@ impl WasmTransaction {
         let (txn_rpc, txn_service, txn_bg) =
             WasmTransaction::start_helper(doc_name, client_id, client_ip, doc);
         tokio::spawn(async move {
-            serve_rpc(txn_rpc, txn_bg, receiver, init_sender).await;
+            if let Err(e) = serve_rpc(txn_rpc, txn_bg, receiver, init_sender).await {
+                tracing::error!(error = %e, "rpc service failed");
+            }
         });
         let _ = init_receiver.recv().await;
 